@@ -321,60 +321,6 @@ function runDbSetup(libraryPath: string) {
       }
     }
 
-    const hasFileHashIndex = runWatcherDbCommand(
-      'query-one',
-      { sql: "SELECT 1 AS present FROM sqlite_master WHERE type = 'index' AND name = 'books_file_hash_unique' LIMIT 1" },
-      env,
-    ).row;
-
-    if (!hasFileHashIndex) {
-      console.log('[Electron] Missing unique indexes on books table, applying fix...');
-
-      runWatcherDbCommand(
-        'execute',
-        {
-          sql: `
-            DELETE FROM books WHERE id IN (
-              SELECT b.id FROM books b
-              INNER JOIN (
-                SELECT file_hash, MIN(added_at) AS min_added
-                FROM books GROUP BY file_hash HAVING COUNT(*) > 1
-              ) d ON b.file_hash = d.file_hash AND b.added_at > d.min_added
-            )
-          `,
-        },
-        env,
-      );
-
-      runWatcherDbCommand(
-        'execute',
-        {
-          sql: `
-            DELETE FROM books WHERE id IN (
-              SELECT b.id FROM books b
-              INNER JOIN (
-                SELECT file_path, MIN(added_at) AS min_added
-                FROM books GROUP BY file_path HAVING COUNT(*) > 1
-              ) d ON b.file_path = d.file_path AND b.added_at > d.min_added
-            )
-          `,
-        },
-        env,
-      );
-
-      runWatcherDbCommand(
-        'execute',
-        { sql: 'CREATE UNIQUE INDEX IF NOT EXISTS `books_file_path_unique` ON `books` (`file_path`)' },
-        env,
-      );
-      runWatcherDbCommand(
-        'execute',
-        { sql: 'CREATE UNIQUE INDEX IF NOT EXISTS `books_file_hash_unique` ON `books` (`file_hash`)' },
-        env,
-      );
-      console.log('[Electron] Unique indexes created and duplicates cleaned up');
-    }
-
     const adminEmail = 'admin@localhost';
     const existingAdmin = runWatcherDbCommand(
       'query-one',
@@ -111,56 +111,8 @@ function main() {
     console.log('[db:push] Schema already present.');
   }
 
-  const hasHashIndex = runWatcherDb(
-    binaryPath,
-    dbPath,
-    'query-one',
-    "SELECT 1 AS present FROM sqlite_master WHERE type = 'index' AND name = 'books_file_hash_unique' LIMIT 1",
-  ).row;
-
-  if (!hasHashIndex) {
-    console.log('[db:push] Missing unique book indexes, applying dedupe + index fix...');
-    runWatcherDb(
-      binaryPath,
-      dbPath,
-      'execute',
-      `
-        DELETE FROM books WHERE id IN (
-          SELECT b.id FROM books b
-          INNER JOIN (
-            SELECT file_hash, MIN(added_at) AS min_added
-            FROM books GROUP BY file_hash HAVING COUNT(*) > 1
-          ) d ON b.file_hash = d.file_hash AND b.added_at > d.min_added
-        )
-      `,
-    );
-    runWatcherDb(
-      binaryPath,
-      dbPath,
-      'execute',
-      `
-        DELETE FROM books WHERE id IN (
-          SELECT b.id FROM books b
-          INNER JOIN (
-            SELECT file_path, MIN(added_at) AS min_added
-            FROM books GROUP BY file_path HAVING COUNT(*) > 1
-          ) d ON b.file_path = d.file_path AND b.added_at > d.min_added
-        )
-      `,
-    );
-    runWatcherDb(
-      binaryPath,
-      dbPath,
-      'execute',
-      'CREATE UNIQUE INDEX IF NOT EXISTS `books_file_path_unique` ON `books` (`file_path`)',
-    );
-    runWatcherDb(
-      binaryPath,
-      dbPath,
-      'execute',
-      'CREATE UNIQUE INDEX IF NOT EXISTS `books_file_hash_unique` ON `books` (`file_hash`)',
-    );
-  }
+  // Duplicate books are merged and the unique indexes created by the
+  // watcher's own migrations, which run whenever it opens the database.
 
   // --- S3 source columns migration (0001) ---
   const hasSourceColumn = runWatcherDb(
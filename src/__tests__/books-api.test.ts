@@ -63,6 +63,14 @@ const books = [
   },
 ];
 
+// Soft-deleted by the watcher: its file and cover are still on disk, so only
+// the `missing_at` filter keeps the by-id routes from serving it.
+const missingBook = {
+  id: "book-missing",
+  filePath: path.join(testDbDir, "gone.epub"),
+  coverPath: path.join(testDbDir, "gone.jpg"),
+};
+
 async function initSchema() {
   await execute(`
     CREATE TABLE IF NOT EXISTS users (
@@ -88,6 +96,8 @@ async function initSchema() {
       file_hash TEXT NOT NULL,
       cover_path TEXT,
       page_count INTEGER,
+      source TEXT NOT NULL DEFAULT 'local',
+      missing_at INTEGER,
       added_at INTEGER NOT NULL,
       updated_at INTEGER NOT NULL
     )
@@ -151,6 +161,27 @@ async function resetData() {
     );
   }
 
+  fs.writeFileSync(missingBook.filePath, "epub");
+  fs.writeFileSync(missingBook.coverPath, "jpeg");
+  await execute(
+    `
+      INSERT INTO books (id, title, file_type, file_path, file_size, file_hash, cover_path, missing_at, added_at, updated_at)
+      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+    `,
+    [
+      missingBook.id,
+      "Gone Book",
+      "epub",
+      missingBook.filePath,
+      4,
+      "hash-gone",
+      missingBook.coverPath,
+      1700000300,
+      1700000003,
+      1700000003,
+    ]
+  );
+
   await execute(
     `
       INSERT INTO reading_progress (id, user_id, book_id, current_page, total_pages, percent_complete, status, last_read_at)
@@ -225,5 +256,39 @@ describe("Books API", () => {
 
     expect(res.status).toBe(404);
   });
-});
 
+  it("returns 404 for books whose file has gone missing", async () => {
+    const params = { params: Promise.resolve({ id: missingBook.id }) };
+    const url = `http://localhost/api/books/${missingBook.id}`;
+
+    const book = await import("@/app/api/books/[id]/route");
+    expect((await book.GET(new Request(url), params)).status).toBe(404);
+
+    const file = await import("@/app/api/books/[id]/file/route");
+    expect((await file.GET(new Request(`${url}/file`), params)).status).toBe(404);
+
+    const epub = await import("@/app/api/books/[id]/book.epub/route");
+    expect((await epub.GET(new Request(`${url}/book.epub`), params)).status).toBe(404);
+
+    const cover = await import("@/app/api/books/[id]/cover/route");
+    expect((await cover.GET(new Request(`${url}/cover`), params)).status).toBe(404);
+  });
+
+  it("does not read or record progress for books whose file has gone missing", async () => {
+    const { GET, PUT } = await import("@/app/api/books/[id]/progress/route");
+    const params = { params: Promise.resolve({ id: missingBook.id }) };
+    const url = `http://localhost/api/books/${missingBook.id}/progress`;
+
+    expect((await GET(new Request(url), params)).status).toBe(404);
+
+    const res = await PUT(
+      new Request(url, {
+        method: "PUT",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ epubLocation: "epubcfi(/6/2)", percentComplete: 10 }),
+      }),
+      params
+    );
+    expect(res.status).toBe(404);
+  });
+});
@@ -75,6 +75,7 @@ async function initSchema() {
       file_hash TEXT NOT NULL,
       cover_path TEXT,
       page_count INTEGER,
+      missing_at INTEGER,
       added_at INTEGER NOT NULL,
       updated_at INTEGER NOT NULL
     )
@@ -302,6 +303,39 @@ describe("Collections API", () => {
     expect(Number(remaining?.total ?? 0)).toBe(0);
   });
 
+  it("does not add books whose file has gone missing", async () => {
+    const { POST } = await import("@/app/api/collections/[id]/books/route");
+
+    await execute(
+      `
+        INSERT INTO collections (id, user_id, name, description, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+      `,
+      ["col-5", user.id, "Queue", null, 1700000000]
+    );
+    await execute("UPDATE books SET missing_at = ?1 WHERE id = ?2", [1700000100, book.id]);
+
+    const res = await POST(
+      new Request("http://localhost/api/collections/col-5/books", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ bookId: book.id }),
+      }),
+      { params: Promise.resolve({ id: "col-5" }) }
+    );
+    expect(res.status).toBe(404);
+
+    const added = await queryOne<{ total: number }>(
+      `
+        SELECT COUNT(*) AS total
+        FROM collection_books
+        WHERE collection_id = ?1
+      `,
+      ["col-5"]
+    );
+    expect(Number(added?.total ?? 0)).toBe(0);
+  });
+
   it("returns 404 for collections owned by another user", async () => {
     const { GET } = await import("@/app/api/collections/[id]/route");
 
@@ -16,14 +16,14 @@ jest.mock("child_process", () => {
 });
 
 type MockChildProcess = EventEmitter & {
-  stdout: EventEmitter;
+  stdout: EventEmitter & { pause: jest.Mock<void, []>; resume: jest.Mock<void, []> };
   stderr: EventEmitter;
   kill: jest.Mock<void, []>;
 };
 
 function createMockChildProcess(): MockChildProcess {
   const child = new EventEmitter() as MockChildProcess;
-  child.stdout = new EventEmitter();
+  child.stdout = Object.assign(new EventEmitter(), { pause: jest.fn(), resume: jest.fn() });
   child.stderr = new EventEmitter();
   child.kill = jest.fn();
   return child;
@@ -150,12 +150,15 @@ describe("serveBookFile", () => {
             `${JSON.stringify({
               content_type: "application/pdf",
               content_length: 5,
+              total_size: 100,
               status: 206,
               range_start: 10,
               range_end: 14,
-            })}\nhello`,
+              content_range: "bytes 10-14/100",
+            })}\nhel`,
           ),
         );
+        child.stdout.emit("data", Buffer.from("lo"));
         child.emit("close", 0);
       });
       return child as never;
@@ -174,10 +177,96 @@ describe("serveBookFile", () => {
     expect(args).toEqual(["s3-stream", "--key", "s3/book.pdf", "--range", "bytes=10-14"]);
 
     expect(response.status).toBe(206);
-    expect(response.headers.get("content-range")).toBe("bytes 10-14/5");
+    expect(response.headers.get("content-range")).toBe("bytes 10-14/100");
+    expect(response.headers.get("content-length")).toBe("5");
     await expect(response.text()).resolves.toBe("hello");
   });
 
+  it("forwards conditional and HEAD requests to S3 stream", async () => {
+    setS3Env();
+    spawnMock.mockImplementation(() => {
+      const child = createMockChildProcess();
+      setImmediate(() => {
+        child.stdout.emit(
+          "data",
+          Buffer.from(
+            `${JSON.stringify({
+              content_type: "application/pdf",
+              content_length: 0,
+              total_size: 100,
+              status: 304,
+              etag: '"abc"',
+            })}\n`,
+          ),
+        );
+        child.emit("close", 0);
+      });
+      return child as never;
+    });
+
+    const { serveBookFile } = await import("@/lib/files/serve-book-file");
+    const response = await serveBookFile(
+      { filePath: "s3/book.pdf", fileType: "pdf", source: "s3" },
+      new Request("http://localhost/api/books/1/file", {
+        method: "HEAD",
+        headers: {
+          "if-none-match": '"abc"',
+          "if-modified-since": "Wed, 21 Oct 2015 07:28:00 GMT",
+        },
+      }),
+    );
+
+    const [, args] = spawnMock.mock.calls[0];
+    expect(args).toEqual([
+      "s3-stream",
+      "--key",
+      "s3/book.pdf",
+      "--if-none-match",
+      '"abc"',
+      "--if-modified-since",
+      "Wed, 21 Oct 2015 07:28:00 GMT",
+      "--head",
+    ]);
+    expect(response.status).toBe(304);
+    expect(response.headers.get("etag")).toBe('"abc"');
+    expect(response.headers.get("content-length")).toBeNull();
+  });
+
+  it("proxies covers kept in a bucket through S3 stream", async () => {
+    spawnMock.mockImplementation(() => {
+      const child = createMockChildProcess();
+      setImmediate(() => {
+        child.stdout.emit(
+          "data",
+          Buffer.from(
+            `${JSON.stringify({
+              content_type: "image/jpeg",
+              content_length: 4,
+              total_size: 4,
+              status: 200,
+            })}\njpeg`,
+          ),
+        );
+        child.emit("close", 0);
+      });
+      return child as never;
+    });
+
+    const { serveS3Cover } = await import("@/lib/files/serve-book-file");
+    const response = await serveS3Cover(
+      "s3://books/covers/1.jpg",
+      new Request("http://localhost/api/books/1/cover"),
+      "public, max-age=86400",
+    );
+
+    const [, args] = spawnMock.mock.calls[0];
+    expect(args).toEqual(["s3-stream", "--key", "s3://books/covers/1.jpg"]);
+    expect(response.status).toBe(200);
+    expect(response.headers.get("content-type")).toBe("image/jpeg");
+    expect(response.headers.get("cache-control")).toBe("public, max-age=86400");
+    await expect(response.text()).resolves.toBe("jpeg");
+  });
+
   it("returns 500 when S3 stream header cannot be parsed", async () => {
     setS3Env();
     spawnMock.mockImplementation(() => {
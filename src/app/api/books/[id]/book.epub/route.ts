@@ -27,6 +27,7 @@ export async function GET(
         source
       FROM books
       WHERE id = ?1
+        AND missing_at IS NULL
       LIMIT 1
     `,
     [id]
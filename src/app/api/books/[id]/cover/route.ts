@@ -1,5 +1,6 @@
 import { NextResponse } from "next/server";
 import fs from "fs";
+import { isS3Uri, serveS3Cover } from "@/lib/files/serve-book-file";
 import { authSession as auth } from "@/lib/auth/config";
 import { queryOne } from "@/lib/db/rust";
 
@@ -23,7 +24,7 @@ function placeholderResponse() {
 }
 
 export async function GET(
-  _req: Request,
+  req: Request,
   { params }: { params: Promise<{ id: string }> },
 ) {
   const session = await auth();
@@ -38,12 +39,22 @@ export async function GET(
       SELECT cover_path AS coverPath
       FROM books
       WHERE id = ?1
+        AND missing_at IS NULL
       LIMIT 1
     `,
     [id]
   );
 
-  if (!book?.coverPath || !fs.existsSync(book.coverPath)) {
+  if (!book) {
+    return NextResponse.json({ error: "Book not found" }, { status: 404 });
+  }
+
+  if (book.coverPath && isS3Uri(book.coverPath)) {
+    const response = await serveS3Cover(book.coverPath, req, "public, max-age=86400");
+    return response.ok || response.status === 304 ? response : placeholderResponse();
+  }
+
+  if (!book.coverPath || !fs.existsSync(book.coverPath)) {
     return placeholderResponse();
   }
 
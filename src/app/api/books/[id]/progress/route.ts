@@ -21,6 +21,7 @@ export async function GET(
       SELECT id
       FROM books
       WHERE id = ?1
+        AND missing_at IS NULL
       LIMIT 1
     `,
     [id]
@@ -91,6 +92,7 @@ export async function PUT(
         file_type AS fileType
       FROM books
       WHERE id = ?1
+        AND missing_at IS NULL
       LIMIT 1
     `,
     [id]
@@ -60,6 +60,7 @@ export async function GET(
         ON rp.book_id = b.id
        AND rp.user_id = ?1
       WHERE b.id = ?2
+        AND b.missing_at IS NULL
       LIMIT 1
     `,
     [session.user.id, id]
@@ -39,6 +39,7 @@ export async function GET() {
       INNER JOIN books b ON rp.book_id = b.id
       WHERE rp.user_id = ?1
         AND rp.status = 'reading'
+        AND b.missing_at IS NULL
       ORDER BY rp.last_read_at DESC
     `,
     [session.user.id]
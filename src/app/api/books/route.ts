@@ -19,7 +19,7 @@ export async function GET(req: Request) {
   const limit = Math.max(1, Math.min(100, Number(url.searchParams.get("limit")) || 24));
   const offset = (page - 1) * limit;
 
-  const whereClauses: string[] = [];
+  const whereClauses: string[] = ["b.missing_at IS NULL"];
   const whereParams: SqlParam[] = [];
 
   if (q) {
@@ -48,6 +48,7 @@ export async function POST(
       SELECT id
       FROM books
       WHERE id = ?1
+        AND missing_at IS NULL
       LIMIT 1
     `,
     [bookId]
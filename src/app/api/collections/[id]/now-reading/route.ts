@@ -61,6 +61,7 @@ export async function GET(
        AND rp.user_id = ?1
       WHERE cb.collection_id = ?2
         AND rp.status = 'reading'
+        AND b.missing_at IS NULL
       ORDER BY rp.last_read_at DESC
     `,
     [session.user.id, id]
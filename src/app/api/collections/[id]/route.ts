@@ -51,8 +51,10 @@ export async function GET(
   const countRow = await queryOne<{ total: number }>(
     `
       SELECT COUNT(*) AS total
-      FROM collection_books
-      WHERE collection_id = ?1
+      FROM collection_books cb
+      INNER JOIN books b ON b.id = cb.book_id
+      WHERE cb.collection_id = ?1
+        AND b.missing_at IS NULL
     `,
     [id]
   );
@@ -90,6 +92,7 @@ export async function GET(
         ON rp.book_id = b.id
        AND rp.user_id = ?1
       WHERE cb.collection_id = ?2
+        AND b.missing_at IS NULL
       ORDER BY b.title ASC
       LIMIT ?3
       OFFSET ?4
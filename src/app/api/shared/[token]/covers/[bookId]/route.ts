@@ -1,5 +1,6 @@
 import { NextResponse } from "next/server";
 import fs from "fs";
+import { isS3Uri, serveS3Cover } from "@/lib/files/serve-book-file";
 import { getSharedBook } from "@/lib/shared";
 
 export const dynamic = "force-dynamic";
@@ -22,7 +23,7 @@ function placeholderResponse() {
 }
 
 export async function GET(
-  _req: Request,
+  req: Request,
   { params }: { params: Promise<{ token: string; bookId: string }> }
 ) {
   const { token, bookId } = await params;
@@ -33,6 +34,12 @@ export async function GET(
     return NextResponse.json({ error: "Book not found" }, { status: 404 });
   }
 
+  // Covers uploaded to a bucket are proxied from it
+  if (book.coverPath && isS3Uri(book.coverPath)) {
+    const response = await serveS3Cover(book.coverPath, req, "public, max-age=86400");
+    return response.ok || response.status === 304 ? response : placeholderResponse();
+  }
+
   // Serve placeholder if no cover exists
   if (!book.coverPath || !fs.existsSync(book.coverPath)) {
     return placeholderResponse();
@@ -26,8 +26,10 @@ export async function GET(
   const totalResult = await queryOne<{ count: number }>(
     `
       SELECT COUNT(*) AS count
-      FROM collection_books
-      WHERE collection_id = ?1
+      FROM collection_books cb
+      INNER JOIN books b ON b.id = cb.book_id
+      WHERE cb.collection_id = ?1
+        AND b.missing_at IS NULL
     `,
     [collection.id]
   );
@@ -53,6 +55,7 @@ export async function GET(
       FROM books b
       INNER JOIN collection_books cb ON b.id = cb.book_id
       WHERE cb.collection_id = ?1
+        AND b.missing_at IS NULL
       LIMIT ?2
       OFFSET ?3
     `,
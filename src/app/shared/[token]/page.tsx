@@ -41,8 +41,10 @@ export default async function SharedCollectionPage({ params }: PageProps) {
   const totalResult = await queryOne<{ count: number }>(
     `
       SELECT COUNT(*) AS count
-      FROM collection_books
-      WHERE collection_id = ?1
+      FROM collection_books cb
+      INNER JOIN books b ON b.id = cb.book_id
+      WHERE cb.collection_id = ?1
+        AND b.missing_at IS NULL
     `,
     [collection.id]
   );
@@ -68,6 +70,7 @@ export default async function SharedCollectionPage({ params }: PageProps) {
       FROM books b
       INNER JOIN collection_books cb ON b.id = cb.book_id
       WHERE cb.collection_id = ?1
+        AND b.missing_at IS NULL
       LIMIT ?2
     `,
     [collection.id, limit]
@@ -1,15 +1,39 @@
 import fs from "node:fs";
 import path from "node:path";
-import { spawn } from "node:child_process";
+import { spawn, type ChildProcessWithoutNullStreams } from "node:child_process";
 
-export type SqlParam = string | number | boolean | null;
+/** BLOB values travel as base64 with a type tag, in both directions. */
+export interface SqlBlob {
+  $type: "blob";
+  base64: string;
+}
+
+export type SqlParam = string | number | boolean | null | SqlBlob;
+/** Positional (`?1`) params as an array, or named (`:name`) params as an object. */
+export type SqlParams = SqlParam[] | Record<string, SqlParam>;
 export type SqlRow = Record<string, unknown>;
 
-type DbMode = "query-all" | "query-one" | "execute";
+type DbMode = "query-all" | "query-one" | "execute" | "transaction";
 
 interface SqlRequest {
   sql: string;
-  params: SqlParam[];
+  params: SqlParams;
+}
+
+export interface TransactionStatement {
+  mode: "query-all" | "query-one" | "execute";
+  sql: string;
+  params?: SqlParams;
+}
+
+interface TransactionRequest {
+  statements: TransactionStatement[];
+}
+
+export type TransactionResult = QueryAllResponse | QueryOneResponse | ExecuteResponse;
+
+interface TransactionResponse {
+  results: TransactionResult[];
 }
 
 interface QueryAllResponse {
@@ -68,69 +92,160 @@ async function resolveBinaryPath(): Promise<string> {
   return binaryPathPromise;
 }
 
-async function callRustDb<T>(mode: DbMode, request: SqlRequest): Promise<T> {
-  const binaryPath = await resolveBinaryPath();
-
-  return new Promise((resolve, reject) => {
-    const child = spawn(binaryPath, ["db", mode], {
-      cwd: process.cwd(),
-      env: process.env,
-      stdio: ["pipe", "pipe", "pipe"],
-    });
+interface PendingCall {
+  resolve: (response: Record<string, unknown>) => void;
+  reject: (error: Error) => void;
+}
 
-    let stdout = "";
-    let stderr = "";
+interface ServeProcess {
+  child: ChildProcessWithoutNullStreams;
+  pending: Map<number, PendingCall>;
+}
 
-    child.stdout.setEncoding("utf8");
-    child.stderr.setEncoding("utf8");
+let serveProcessPromise: Promise<ServeProcess> | null = null;
+let currentServer: ServeProcess | null = null;
+let nextRequestId = 1;
 
-    child.stdout.on("data", (chunk: string) => {
-      stdout += chunk;
+/**
+ * One long-lived `watcher-rs db serve` process answers every query over
+ * NDJSON on stdin/stdout, so a query costs a pipe round trip instead of a
+ * process spawn. It is restarted on the next call if it exits.
+ */
+function serveProcess(): Promise<ServeProcess> {
+  if (!serveProcessPromise) {
+    serveProcessPromise = startServeProcess().catch((error) => {
+      serveProcessPromise = null;
+      throw error;
     });
+  }
+  return serveProcessPromise;
+}
 
-    child.stderr.on("data", (chunk: string) => {
-      stderr += chunk;
-    });
+async function startServeProcess(): Promise<ServeProcess> {
+  const binaryPath = await resolveBinaryPath();
+  const child = spawn(binaryPath, ["db", "serve"], {
+    cwd: process.cwd(),
+    env: process.env,
+    stdio: ["pipe", "pipe", "pipe"],
+  });
+  const server: ServeProcess = { child, pending: new Map() };
 
-    child.on("error", (error) => {
-      reject(error);
-    });
+  let stdout = "";
+  let stderr = "";
+  child.stdout.setEncoding("utf8");
+  child.stderr.setEncoding("utf8");
 
-    child.on("close", (code) => {
-      if (code !== 0) {
-        reject(
-          new Error(
-            `[rust-db] watcher-rs exited with code ${code}\n${stderr.trim() || "(no stderr)"}`
-          )
-        );
-        return;
+  child.stdout.on("data", (chunk: string) => {
+    stdout += chunk;
+    let newline = stdout.indexOf("\n");
+    while (newline !== -1) {
+      const line = stdout.slice(0, newline).trim();
+      stdout = stdout.slice(newline + 1);
+      if (line) {
+        handleResponse(server, line);
       }
+      newline = stdout.indexOf("\n");
+    }
+  });
 
-      const payload = stdout.trim();
-      if (!payload) {
-        reject(new Error("[rust-db] watcher-rs returned empty output"));
-        return;
-      }
+  child.stderr.on("data", (chunk: string) => {
+    stderr = (stderr + chunk).slice(-4096);
+  });
 
-      try {
-        resolve(JSON.parse(payload) as T);
-      } catch (error) {
-        reject(
-          new Error(
-            `[rust-db] Failed to parse watcher-rs JSON output: ${(error as Error).message}\nOutput: ${payload}`
-          )
-        );
-      }
-    });
+  const fail = (error: Error) => {
+    if (currentServer === server) {
+      currentServer = null;
+      serveProcessPromise = null;
+    }
+    for (const call of server.pending.values()) {
+      call.reject(error);
+    }
+    server.pending.clear();
+  };
+
+  child.on("error", fail);
+  child.on("close", (code) => {
+    fail(
+      new Error(
+        `[rust-db] watcher-rs db serve exited with code ${code}\n${stderr.trim() || "(no stderr)"}`
+      )
+    );
+  });
+  // Writes after the process died surface through the close handler.
+  child.stdin.on("error", () => {});
+
+  currentServer = server;
+  setIdle(server, true);
+  return server;
+}
+
+type Refable = { ref?: () => void; unref?: () => void };
+
+/** An idle server must not keep the Node process alive on its own. */
+function setIdle(server: ServeProcess, idle: boolean) {
+  const { child } = server;
+  const streams: Refable[] = [child, child.stdout, child.stderr, child.stdin] as unknown as Refable[];
+  for (const stream of streams) {
+    if (idle) {
+      stream.unref?.();
+    } else {
+      stream.ref?.();
+    }
+  }
+}
 
-    child.stdin.write(JSON.stringify(request));
-    child.stdin.end();
+function handleResponse(server: ServeProcess, line: string) {
+  let response: Record<string, unknown>;
+  try {
+    response = JSON.parse(line) as Record<string, unknown>;
+  } catch (error) {
+    console.error(`[rust-db] Failed to parse watcher-rs JSON output: ${(error as Error).message}\nOutput: ${line}`);
+    return;
+  }
+
+  const id = response.id;
+  const call = typeof id === "number" ? server.pending.get(id) : undefined;
+  if (!call) {
+    console.error(`[rust-db] Response for unknown request: ${line}`);
+    return;
+  }
+  server.pending.delete(id as number);
+  if (server.pending.size === 0) {
+    setIdle(server, true);
+  }
+
+  if (typeof response.error === "string") {
+    call.reject(new Error(`[rust-db] ${response.error}`));
+  } else {
+    call.resolve(response);
+  }
+}
+
+async function callRustDb<T>(
+  mode: DbMode,
+  request: SqlRequest | TransactionRequest
+): Promise<T> {
+  const server = await serveProcess();
+  const id = nextRequestId++;
+  // Reads run on a read-only connection, so a bad query can never write through them.
+  const readOnly = mode === "query-all" || mode === "query-one";
+  const line = JSON.stringify({ id, mode, ...(readOnly ? { read_only: true } : {}), ...request });
+
+  return new Promise<T>((resolve, reject) => {
+    if (server.pending.size === 0) {
+      setIdle(server, false);
+    }
+    server.pending.set(id, {
+      resolve: (response) => resolve(response as T),
+      reject,
+    });
+    server.child.stdin.write(`${line}\n`);
   });
 }
 
 export async function queryAll<T extends SqlRow = SqlRow>(
   sql: string,
-  params: SqlParam[] = []
+  params: SqlParams = []
 ): Promise<T[]> {
   const result = await callRustDb<QueryAllResponse>("query-all", { sql, params });
   return result.rows as T[];
@@ -138,13 +253,19 @@ export async function queryAll<T extends SqlRow = SqlRow>(
 
 export async function queryOne<T extends SqlRow = SqlRow>(
   sql: string,
-  params: SqlParam[] = []
+  params: SqlParams = []
 ): Promise<T | null> {
   const result = await callRustDb<QueryOneResponse>("query-one", { sql, params });
   return result.row as T | null;
 }
 
-export async function execute(sql: string, params: SqlParam[] = []): Promise<number> {
+export async function execute(sql: string, params: SqlParams = []): Promise<number> {
   const result = await callRustDb<ExecuteResponse>("execute", { sql, params });
   return result.changes;
 }
+
+/** Run all statements in one SQLite transaction; any failure rolls back every statement. */
+export async function transaction(statements: TransactionStatement[]): Promise<TransactionResult[]> {
+  const result = await callRustDb<TransactionResponse>("transaction", { statements });
+  return result.results;
+}
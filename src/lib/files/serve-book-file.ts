@@ -90,6 +90,25 @@ export async function serveBookFile(
   return await handler.stream(context);
 }
 
+/**
+ * Serve a cover uploaded to a bucket (recorded as `s3://bucket/key`) through
+ * `watcher-rs s3-stream`, so it stays behind the route's access checks.
+ */
+export async function serveS3Cover(
+  uri: string,
+  req: Request,
+  cacheControl: string
+): Promise<NextResponse> {
+  return await streamFromS3({ filePath: uri, fileType: "jpg", source: "s3" }, req, {
+    cacheControl,
+    filenameOverride: "cover.jpg",
+  });
+}
+
+export function isS3Uri(value: string) {
+  return value.startsWith("s3://");
+}
+
 function normalizeSource(source: string | null | undefined) {
   if (!source) return "local";
   return source.toLowerCase();
@@ -214,6 +233,19 @@ function resolveWatcherBinaryPath() {
   return binaryName;
 }
 
+/** The JSON line `watcher-rs s3-stream` writes before the body. */
+interface S3StreamHeader {
+  status?: number;
+  content_type?: string;
+  /** Body bytes that follow the header. */
+  content_length?: number;
+  total_size?: number;
+  etag?: string;
+  last_modified?: string;
+  /** Ready-made `Content-Range` for 206 and 416 responses. */
+  content_range?: string;
+}
+
 async function streamFromS3(
   book: BookFileRecord,
   req: Request,
@@ -236,6 +268,17 @@ async function streamFromS3(
   if (rangeHeader) {
     args.push("--range", rangeHeader);
   }
+  const ifNoneMatch = req.headers.get("if-none-match");
+  if (ifNoneMatch) {
+    args.push("--if-none-match", ifNoneMatch);
+  }
+  const ifModifiedSince = req.headers.get("if-modified-since");
+  if (ifModifiedSince) {
+    args.push("--if-modified-since", ifModifiedSince);
+  }
+  if (req.method === "HEAD") {
+    args.push("--head");
+  }
 
   return await new Promise((resolve) => {
     let settled = false;
@@ -252,24 +295,28 @@ async function streamFromS3(
     });
 
     let stderr = "";
-    let headerParsed = false;
     let headerBuf = Buffer.alloc(0);
-    let meta: {
-      content_type?: string;
-      content_length?: number;
-      status?: number;
-      range_start?: number;
-      range_end?: number;
-    } | null = null;
-    const bodyChunks: Uint8Array[] = [];
+    // Set once the header line is in; the rest of stdout is the body.
+    let body: ReadableStreamDefaultController<Uint8Array> | null = null;
 
     child.stderr.on("data", (chunk: Buffer) => {
       stderr += chunk.toString("utf8");
     });
 
+    const enqueue = (chunk: Uint8Array) => {
+      if (!body) return;
+      body.enqueue(chunk);
+      if ((body.desiredSize ?? 1) <= 0) {
+        child.stdout.pause();
+      }
+    };
+
     child.stdout.on("data", (chunk: Buffer) => {
-      if (headerParsed) {
-        bodyChunks.push(new Uint8Array(chunk));
+      if (body) {
+        enqueue(new Uint8Array(chunk));
+        return;
+      }
+      if (settled) {
         return;
       }
 
@@ -279,64 +326,91 @@ async function streamFromS3(
         return;
       }
 
-      headerParsed = true;
       const headerLine = headerBuf.subarray(0, newlineIdx).toString("utf8").trim();
       const remaining = headerBuf.subarray(newlineIdx + 1);
-      if (remaining.length > 0) {
-        bodyChunks.push(new Uint8Array(remaining));
-      }
 
+      let meta: S3StreamHeader;
       try {
-        meta = JSON.parse(headerLine) as {
-          content_type?: string;
-          content_length?: number;
-          status?: number;
-          range_start?: number;
-          range_end?: number;
-        };
+        meta = JSON.parse(headerLine) as S3StreamHeader;
       } catch {
+        child.kill();
         finish(
           NextResponse.json(
             { error: "Failed to parse S3 stream header", details: headerLine },
             { status: 500 }
           )
         );
-      }
-    });
-
-    child.on("close", (code) => {
-      if (!headerParsed || !meta) {
-        finish(
-          NextResponse.json(
-            {
-              error: "S3 stream failed before response header",
-              exitCode: code,
-              details: stderr.trim() || undefined,
-            },
-            { status: 502 }
-          )
-        );
         return;
       }
 
-      const body = concatUint8Arrays(bodyChunks);
-      const normalizedBody = new Uint8Array(body.length);
-      normalizedBody.set(body);
-      const responseBody = new Blob([normalizedBody]);
       const status = meta.status || 200;
-      const headers = buildResponseHeaders(book, body.length, options, status);
+      if (status === 404) {
+        finish(NextResponse.json({ error: "File not found in S3" }, { status: 404 }));
+        return;
+      }
 
-      if (status === 206 && meta.range_start !== undefined) {
-        headers["Content-Range"] = `bytes ${meta.range_start}-${meta.range_end}/${meta.content_length}`;
+      const headers = buildResponseHeaders(book, meta.content_length ?? 0, options, status);
+      if ((status === 206 || status === 416) && meta.content_range) {
+        headers["Content-Range"] = meta.content_range;
       } else {
         delete headers["Content-Range"];
       }
-
       if (!options.contentTypeOverride && meta.content_type) {
         headers["Content-Type"] = meta.content_type;
       }
+      if (meta.etag) {
+        headers["ETag"] = meta.etag;
+      }
+      if (meta.last_modified) {
+        headers["Last-Modified"] = meta.last_modified;
+      }
+
+      if (status === 304) {
+        delete headers["Content-Length"];
+      }
+      if (status === 304 || status === 416 || req.method === "HEAD") {
+        finish(new NextResponse(null, { status, headers }));
+        return;
+      }
+
+      const stream = new ReadableStream<Uint8Array>({
+        start(controller) {
+          body = controller;
+          if (remaining.length > 0) {
+            enqueue(new Uint8Array(remaining));
+          }
+        },
+        pull() {
+          child.stdout.resume();
+        },
+        cancel() {
+          body = null;
+          child.kill();
+        },
+      });
+      finish(new NextResponse(stream, { status, headers }));
+    });
 
-      finish(new NextResponse(responseBody, { status, headers }));
+    child.on("close", (code) => {
+      if (body) {
+        // A failure after the header can only cut the body short.
+        if (code === 0) {
+          body.close();
+        } else {
+          body.error(new Error(stderr.trim() || `S3 stream exited with code ${code}`));
+        }
+        return;
+      }
+      finish(
+        NextResponse.json(
+          {
+            error: "S3 stream failed before response header",
+            exitCode: code,
+            details: stderr.trim() || undefined,
+          },
+          { status: 502 }
+        )
+      );
     });
 
     child.on("error", (error) => {
@@ -348,22 +422,12 @@ async function streamFromS3(
       );
     });
 
+    // Only the header has to arrive in time; a long download may take longer.
     const timeoutId = setTimeout(() => {
-      child.kill();
-      if (!headerParsed) {
+      if (!settled) {
+        child.kill();
         finish(NextResponse.json({ error: "S3 stream timeout" }, { status: 504 }));
       }
     }, 30_000);
   });
 }
-
-function concatUint8Arrays(arrays: Uint8Array[]): Uint8Array {
-  const totalLength = arrays.reduce((sum, arr) => sum + arr.length, 0);
-  const result = new Uint8Array(totalLength);
-  let offset = 0;
-  for (const arr of arrays) {
-    result.set(arr, offset);
-    offset += arr.length;
-  }
-  return result;
-}
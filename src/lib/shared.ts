@@ -97,6 +97,7 @@ export async function getSharedBook(token: string, bookId: string): Promise<Shar
         INNER JOIN collection_books cb ON b.id = cb.book_id
         WHERE cb.collection_id = ?1
           AND b.id = ?2
+          AND b.missing_at IS NULL
         LIMIT 1
       `,
       [collection.id, bookId]
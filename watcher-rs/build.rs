@@ -19,11 +19,11 @@ fn main() {
     let target_os = env::var("CARGO_CFG_TARGET_OS").expect("missing target os");
 
     // Prefer a true static archive on macOS when available.
-    if target_os == "macos" {
-        if let Ok(lib_dir) = ensure_macos_static_pdfium() {
-            link_static_pdfium(&lib_dir);
-            return;
-        }
+    if target_os == "macos"
+        && let Ok(lib_dir) = ensure_macos_static_pdfium()
+    {
+        link_static_pdfium(&lib_dir);
+        return;
     }
 
     // Fallback: download the official platform binary and link it at build time.
@@ -39,7 +39,7 @@ fn main() {
     }
 
     println!("cargo:rustc-link-search=native={}", search_dir.display());
-    println!("cargo:rustc-link-lib={}={}", library_kind, "pdfium");
+    println!("cargo:rustc-link-lib={}=pdfium", library_kind);
 
     let target_family = env::var("CARGO_CFG_TARGET_FAMILY").unwrap_or_default();
     if target_family == "unix" {
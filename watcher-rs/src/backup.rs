@@ -0,0 +1,403 @@
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, DatabaseName, OpenFlags, params};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::db::migrations;
+
+/// Bumped when the archive layout changes in a way older restores can't read.
+pub const ARCHIVE_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.json";
+const DATABASE_NAME: &str = "library.db";
+const COVERS_NAME: &str = "covers";
+
+/// Describes a backup archive. Written first so a restore can check what it
+/// is about to unpack.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format_version: u32,
+    pub schema_version: i64,
+    pub watcher_version: String,
+    pub created_at: i64,
+    /// Library and covers directories at backup time; restore uses them to
+    /// rewrite paths stored in `books`.
+    pub library_path: Option<String>,
+    pub covers_path: String,
+    pub counts: Counts,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Counts {
+    pub books: i64,
+    pub users: i64,
+    pub collections: i64,
+    pub collection_books: i64,
+    pub reading_progress: i64,
+    pub covers: u64,
+}
+
+pub struct RestoreOptions<'a> {
+    pub db_path: &'a Path,
+    pub covers_dir: &'a Path,
+    /// New root for local book files, replacing the manifest's `library_path`.
+    pub library_root: Option<&'a Path>,
+    /// Replace an existing database instead of refusing.
+    pub force: bool,
+}
+
+/// Write a `.tar.zst` archive holding a consistent snapshot of the database
+/// (taken with the SQLite online backup API, so it is safe while the watcher
+/// writes), the covers directory and a [`Manifest`].
+pub fn backup(
+    db_path: &Path,
+    covers_dir: &Path,
+    library_path: Option<&Path>,
+    out: &Path,
+) -> Result<Manifest> {
+    let snapshot = sibling_path(out, "db-snapshot");
+    let partial = sibling_path(out, "partial");
+
+    let result = (|| {
+        let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database at {}", db_path.display()))?;
+        source.busy_timeout(std::time::Duration::from_secs(5))?;
+        source
+            .backup(DatabaseName::Main, &snapshot, None)
+            .context("Failed to snapshot database")?;
+
+        // One listing for both the manifest count and the archive, so they
+        // agree even if a watcher writes covers meanwhile.
+        let covers = cover_files(covers_dir)?;
+        let snapshot_conn = Connection::open(&snapshot)?;
+        let manifest = Manifest {
+            format_version: ARCHIVE_FORMAT_VERSION,
+            schema_version: migrations::user_version(&snapshot_conn)?,
+            watcher_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: crate::db::unix_now(),
+            library_path: library_path.map(|p| p.to_string_lossy().into_owned()),
+            covers_path: covers_dir.to_string_lossy().into_owned(),
+            counts: Counts {
+                covers: covers.len() as u64,
+                ..table_counts(&snapshot_conn)?
+            },
+        };
+        drop(snapshot_conn);
+
+        let encoder = zstd::Encoder::new(File::create(&partial)?, 0)?;
+        let mut archive = tar::Builder::new(encoder);
+        let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(manifest_json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(manifest.created_at as u64);
+        archive.append_data(&mut header, MANIFEST_NAME, manifest_json.as_slice())?;
+        archive.append_path_with_name(&snapshot, DATABASE_NAME)?;
+        for cover in &covers {
+            let name = Path::new(COVERS_NAME).join(cover.file_name().context("Invalid cover")?);
+            archive.append_path_with_name(cover, name)?;
+        }
+        archive.into_inner()?.finish()?.flush()?;
+
+        fs::rename(&partial, out).with_context(|| format!("Failed to write {}", out.display()))?;
+        Ok(manifest)
+    })();
+
+    let _ = fs::remove_file(&snapshot);
+    let _ = fs::remove_file(&partial);
+    result
+}
+
+/// Restore an archive written by [`backup`]. The manifest is checked against
+/// the unpacked database before anything at `db_path` is touched. Stop the
+/// watcher first; the database is replaced, not merged.
+pub fn restore(archive: &Path, options: &RestoreOptions) -> Result<Manifest> {
+    if options.db_path.exists() && !options.force {
+        bail!(
+            "{} already exists; pass --force to replace it",
+            options.db_path.display()
+        );
+    }
+
+    let staging = sibling_path(options.db_path, "restore");
+    let result = (|| {
+        fs::create_dir_all(&staging)?;
+        let file =
+            File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+        tar::Archive::new(zstd::Decoder::new(file)?)
+            .unpack(&staging)
+            .context("Failed to unpack backup archive")?;
+
+        let manifest = read_manifest(&staging)?;
+        let staged_db = staging.join(DATABASE_NAME);
+        let staged_covers = staging.join(COVERS_NAME);
+        validate(&manifest, &staged_db, &staged_covers)?;
+
+        {
+            let conn = Connection::open(&staged_db)?;
+            migrations::run(&conn)?;
+            relink(&conn, &manifest, options)?;
+        }
+
+        fs::create_dir_all(options.covers_dir)?;
+        for cover in cover_files(&staged_covers)? {
+            let name = cover.file_name().context("Invalid cover")?;
+            fs::copy(&cover, options.covers_dir.join(name))?;
+        }
+
+        for suffix in ["-wal", "-shm"] {
+            let _ = fs::remove_file(sibling_suffix(options.db_path, suffix));
+        }
+        fs::rename(&staged_db, options.db_path)
+            .with_context(|| format!("Failed to replace {}", options.db_path.display()))?;
+        Ok(manifest)
+    })();
+
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn read_manifest(dir: &Path) -> Result<Manifest> {
+    let bytes = fs::read(dir.join(MANIFEST_NAME)).context("Backup has no manifest")?;
+    serde_json::from_slice(&bytes).context("Backup manifest is invalid")
+}
+
+/// Check the unpacked files against the manifest so a truncated or
+/// mismatched archive is refused instead of half-restored.
+fn validate(manifest: &Manifest, db_path: &Path, covers_dir: &Path) -> Result<()> {
+    if manifest.format_version != ARCHIVE_FORMAT_VERSION {
+        bail!(
+            "Unsupported backup format version {} (expected {})",
+            manifest.format_version,
+            ARCHIVE_FORMAT_VERSION
+        );
+    }
+    if manifest.schema_version > migrations::SCHEMA_VERSION {
+        bail!(
+            "Backup schema version {} is newer than this watcher supports ({}); upgrade watcher-rs",
+            manifest.schema_version,
+            migrations::SCHEMA_VERSION
+        );
+    }
+    if !db_path.is_file() {
+        bail!("Backup has no {DATABASE_NAME}");
+    }
+
+    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if integrity != "ok" {
+        bail!("Backup database failed integrity check: {integrity}");
+    }
+    if migrations::user_version(&conn)? != manifest.schema_version {
+        bail!("Backup database schema version does not match its manifest");
+    }
+
+    let found = Counts {
+        covers: cover_files(covers_dir)?.len() as u64,
+        ..table_counts(&conn)?
+    };
+    if found != manifest.counts {
+        bail!(
+            "Backup contents do not match its manifest (expected {:?}, found {:?})",
+            manifest.counts,
+            found
+        );
+    }
+    Ok(())
+}
+
+/// Rewrite stored paths for the restore location: local book files under the
+/// new library root, and covers under the new covers directory.
+fn relink(conn: &Connection, manifest: &Manifest, options: &RestoreOptions) -> Result<()> {
+    if let Some(root) = options.library_root {
+        let old_root = manifest
+            .library_path
+            .as_deref()
+            .context("Backup does not record a library path to re-link from")?;
+        let moved = replace_prefix(
+            conn,
+            "file_path",
+            "source = 'local'",
+            old_root,
+            &root.to_string_lossy(),
+        )?;
+        crate::log::log(&format!(
+            "[RESTORE] Re-linked {} book(s) under {}",
+            moved,
+            root.display()
+        ));
+    }
+
+    let covers_dir = options.covers_dir.to_string_lossy();
+    if manifest.covers_path != covers_dir {
+        replace_prefix(
+            conn,
+            "cover_path",
+            "cover_path IS NOT NULL",
+            &manifest.covers_path,
+            &covers_dir,
+        )?;
+    }
+    Ok(())
+}
+
+fn replace_prefix(
+    conn: &Connection,
+    column: &str,
+    filter: &str,
+    old: &str,
+    new: &str,
+) -> Result<usize> {
+    let old = old.trim_end_matches('/');
+    let new = new.trim_end_matches('/');
+    let changed = conn.execute(
+        &format!(
+            "UPDATE books SET {column} = ?2 || substr({column}, length(?1) + 1)
+             WHERE {filter} AND substr({column}, 1, length(?1) + 1) = ?1 || '/'"
+        ),
+        params![old, new],
+    )?;
+    Ok(changed)
+}
+
+fn table_counts(conn: &Connection) -> Result<Counts> {
+    let count = |table: &str| -> Result<i64> {
+        Ok(
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })?,
+        )
+    };
+    Ok(Counts {
+        books: count("books")?,
+        users: count("users")?,
+        collections: count("collections")?,
+        collection_books: count("collection_books")?,
+        reading_progress: count("reading_progress")?,
+        covers: 0,
+    })
+}
+
+fn cover_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// `path` with `.{extension}` appended, for temporary files next to it.
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    sibling_suffix(path, &format!(".{extension}"))
+}
+
+fn sibling_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RestoreOptions, backup, restore};
+    use crate::db::{COVER_SOURCE_GENERATED, Database, NewBook};
+    use std::fs;
+
+    fn seed(db: &Database, cover_path: &str) {
+        db.insert_book(&NewBook {
+            id: "book-1",
+            title: "Kept",
+            author: None,
+            description: None,
+            file_type: "pdf",
+            file_path: "/old/library/shelf/kept.pdf",
+            file_size: 10,
+            file_hash: "hash-kept",
+            cover_path: Some(cover_path),
+            cover_source: COVER_SOURCE_GENERATED,
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "local",
+            s3_source: None,
+            s3_bucket: None,
+            s3_etag: None,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn backup_round_trips_and_relinks_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("library.db");
+        let covers = dir.path().join("covers");
+        fs::create_dir_all(&covers).unwrap();
+        fs::write(covers.join("book-1.jpg"), b"jpeg").unwrap();
+        let db = Database::open(db_path.to_str().unwrap()).unwrap();
+        seed(&db, covers.join("book-1.jpg").to_str().unwrap());
+
+        let archive = dir.path().join("backup.tar.zst");
+        let manifest = backup(&db_path, &covers, Some("/old/library".as_ref()), &archive).unwrap();
+        assert_eq!(manifest.counts.books, 1);
+        assert_eq!(manifest.counts.covers, 1);
+
+        let target = dir.path().join("restored");
+        fs::create_dir_all(&target).unwrap();
+        let new_db = target.join("library.db");
+        let new_covers = target.join("covers");
+        let restored = restore(
+            &archive,
+            &RestoreOptions {
+                db_path: &new_db,
+                covers_dir: &new_covers,
+                library_root: Some("/new/root".as_ref()),
+                force: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(restored, manifest);
+
+        let db = Database::open(new_db.to_str().unwrap()).unwrap();
+        let book = db.find_by_id("book-1").unwrap().unwrap();
+        assert_eq!(book.file_path, "/new/root/shelf/kept.pdf");
+        let cover = new_covers.join("book-1.jpg");
+        assert_eq!(book.cover_path.as_deref(), cover.to_str());
+        assert_eq!(fs::read(cover).unwrap(), b"jpeg");
+    }
+
+    #[test]
+    fn restore_refuses_to_overwrite_without_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("library.db");
+        Database::open(db_path.to_str().unwrap()).unwrap();
+        let archive = dir.path().join("backup.tar.zst");
+        backup(&db_path, &dir.path().join("covers"), None, &archive).unwrap();
+
+        let options = RestoreOptions {
+            db_path: &db_path,
+            covers_dir: &dir.path().join("covers"),
+            library_root: None,
+            force: false,
+        };
+        let error = restore(&archive, &options).unwrap_err();
+        assert!(error.to_string().contains("--force"));
+
+        restore(
+            &archive,
+            &RestoreOptions {
+                force: true,
+                ..options
+            },
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::db::Database;
+use crate::log::log;
+use crate::s3::S3Config;
+use crate::s3::webhook::WebhookConfig;
+use crate::{s3, watcher};
+
+/// Run the local watcher and the S3 watcher in one process until `shutdown`
+/// is set. Must be called from within a tokio runtime.
+///
+/// Both share `db`, whose connection serializes their writes, and
+/// `shutdown`: when either watcher stops, with an error or otherwise, the
+/// other is told to stop too. The local watcher runs on a blocking thread;
+/// the S3 watcher runs on the calling task and does the housekeeping for
+/// both, so books are purged and the database maintained once.
+pub async fn run(
+    library_path: PathBuf,
+    covers_path: PathBuf,
+    sources: Vec<S3Config>,
+    db: Database,
+    housekeeping: watcher::Housekeeping,
+    webhook: Option<WebhookConfig>,
+    shutdown: Arc<AtomicBool>,
+) -> Result<()> {
+    let db = Arc::new(db);
+
+    let local = {
+        let db = Arc::clone(&db);
+        let covers_path = covers_path.clone();
+        let shutdown = Arc::clone(&shutdown);
+        tokio::task::spawn_blocking(move || {
+            let result = watcher::run(library_path, covers_path, &db, None, Arc::clone(&shutdown));
+            shutdown.store(true, Ordering::Relaxed);
+            result
+        })
+    };
+
+    let s3_result = s3::watcher::run(
+        sources,
+        &covers_path,
+        &db,
+        housekeeping,
+        webhook,
+        Arc::clone(&shutdown),
+    )
+    .await;
+    shutdown.store(true, Ordering::Relaxed);
+
+    let local_result = local.await.context("Local watcher thread panicked")?;
+    match (s3_result, local_result) {
+        (Err(e), Err(local)) => {
+            log(&format!("[ERROR] Local watcher failed: {}", local));
+            Err(e)
+        }
+        (s3_result, local_result) => s3_result.and(local_result),
+    }
+}
@@ -1,9 +1,11 @@
-use image::ImageFormat;
+use super::svg;
+use image::{DynamicImage, ImageFormat};
 use quick_xml::Reader;
 use quick_xml::events::Event;
 use std::collections::HashMap;
 use std::io::Read;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 
 pub fn extract_epub_cover(file_path: &Path, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
     let file = std::fs::File::open(file_path).ok()?;
@@ -21,6 +23,15 @@ pub fn extract_epub_cover_from_bytes(
     extract_cover_from_archive(&mut archive, book_id, covers_dir)
 }
 
+pub fn extract_epub_cover_from_reader<R: Read + std::io::Seek>(
+    reader: R,
+    book_id: &str,
+    covers_dir: &Path,
+) -> Option<PathBuf> {
+    let mut archive = zip::ZipArchive::new(reader).ok()?;
+    extract_cover_from_archive(&mut archive, book_id, covers_dir)
+}
+
 fn extract_cover_from_archive<R: Read + std::io::Seek>(
     archive: &mut zip::ZipArchive<R>,
     book_id: &str,
@@ -32,12 +43,9 @@ fn extract_cover_from_archive<R: Read + std::io::Seek>(
     let cover_href = parse_opf_for_cover_href(archive, &opf_path)?;
 
     let relative_cover_path = resolve_relative_path(&opf_path, &cover_href);
-    let mut cover_file = archive.by_name(&relative_cover_path).ok()?;
-
-    let mut bytes = Vec::new();
-    cover_file.read_to_end(&mut bytes).ok()?;
+    let bytes = read_entry(archive, &relative_cover_path)?;
 
-    let decoded = image::load_from_memory(&bytes).ok()?;
+    let decoded = decode_cover(archive, &relative_cover_path, &bytes)?;
     let cover_path = covers_dir.join(format!("{book_id}.jpg"));
 
     decoded
@@ -48,6 +56,104 @@ fn extract_cover_from_archive<R: Read + std::io::Seek>(
     Some(cover_path)
 }
 
+/// Decode a cover entry: a raster image, a standalone SVG, or an XHTML page
+/// that wraps either an inline `<svg>` or an `<img>`.
+fn decode_cover<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    entry_path: &str,
+    bytes: &[u8],
+) -> Option<DynamicImage> {
+    if let Ok(decoded) = image::load_from_memory(bytes) {
+        return Some(decoded);
+    }
+
+    let text = std::str::from_utf8(bytes).ok()?;
+    match find_wrapped_cover(text)? {
+        WrappedCover::Svg(svg) => rasterize_svg(archive, entry_path, &svg),
+        WrappedCover::Image(href) => {
+            let image_path = resolve_relative_path(entry_path, &href);
+            let image_bytes = read_entry(archive, &image_path)?;
+            image::load_from_memory(&image_bytes).ok()
+        }
+    }
+}
+
+/// Rasterize SVG markup found in `entry_path`, loading any linked raster
+/// images from the archive relative to that entry.
+fn rasterize_svg<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    entry_path: &str,
+    svg_text: &str,
+) -> Option<DynamicImage> {
+    let mut images = HashMap::new();
+    for href in svg::image_hrefs(svg_text) {
+        let image_path = resolve_relative_path(entry_path, &href);
+        if let Some(data) = read_entry(archive, &image_path) {
+            images.insert(href, Arc::new(data));
+        }
+    }
+
+    svg::rasterize(svg_text, &images)
+}
+
+enum WrappedCover {
+    Svg(String),
+    Image(String),
+}
+
+/// Locate the cover inside an SVG or XHTML document. A document whose root is
+/// `<svg>` is returned whole; otherwise the first `<svg>` subtree wins, then
+/// the first `<img>`.
+fn find_wrapped_cover(text: &str) -> Option<WrappedCover> {
+    let mut reader = Reader::from_str(text);
+    let mut svg_start: Option<usize> = None;
+    let mut svg_depth = 0usize;
+    let mut first_img: Option<String> = None;
+
+    loop {
+        let position = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) if e.local_name().as_ref() == b"svg" => {
+                if svg_start.is_none() {
+                    svg_start = Some(position);
+                }
+                svg_depth += 1;
+            }
+            Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e))
+                if e.local_name().as_ref() == b"img" && first_img.is_none() =>
+            {
+                first_img = e
+                    .attributes()
+                    .flatten()
+                    .find(|attr| attr.key.local_name().as_ref() == b"src")
+                    .map(|attr| String::from_utf8_lossy(attr.value.as_ref()).to_string());
+            }
+            Ok(Event::End(ref e)) if e.local_name().as_ref() == b"svg" && svg_depth > 0 => {
+                svg_depth -= 1;
+                if svg_depth == 0 {
+                    let start = svg_start?;
+                    let end = reader.buffer_position() as usize;
+                    return Some(WrappedCover::Svg(text.get(start..end)?.to_string()));
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    first_img.map(WrappedCover::Image)
+}
+
+fn read_entry<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    path: &str,
+) -> Option<Vec<u8>> {
+    let mut file = archive.by_name(path).ok()?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
 fn parse_container_xml<R: Read + std::io::Seek>(
     archive: &mut zip::ZipArchive<R>,
 ) -> Option<String> {
@@ -60,12 +166,12 @@ fn parse_container_xml<R: Read + std::io::Seek>(
 
     loop {
         match reader.read_event_into(&mut buf) {
-            Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e)) => {
-                if e.local_name().as_ref() == b"rootfile" {
-                    for attr in e.attributes().flatten() {
-                        if attr.key.local_name().as_ref() == b"full-path" {
-                            return Some(String::from_utf8_lossy(attr.value.as_ref()).to_string());
-                        }
+            Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e))
+                if e.local_name().as_ref() == b"rootfile" =>
+            {
+                for attr in e.attributes().flatten() {
+                    if attr.key.local_name().as_ref() == b"full-path" {
+                        return Some(String::from_utf8_lossy(attr.value.as_ref()).to_string());
                     }
                 }
             }
@@ -92,6 +198,7 @@ fn parse_opf_for_cover_href<R: Read + std::io::Seek>(
 
     let mut cover_id: Option<String> = None;
     let mut cover_href_from_props: Option<String> = None;
+    let mut cover_href_from_guide: Option<String> = None;
     let mut item_hrefs: HashMap<String, String> = HashMap::new();
 
     loop {
@@ -139,11 +246,30 @@ fn parse_opf_for_cover_href<R: Read + std::io::Seek>(
                         item_hrefs.insert(id, href_value);
                     }
 
-                    if let (Some(prop), Some(href_value)) = (properties.as_deref(), href) {
-                        if prop.split_whitespace().any(|token| token == "cover-image") {
-                            cover_href_from_props = Some(href_value);
+                    if let (Some(prop), Some(href_value)) = (properties.as_deref(), href)
+                        && prop.split_whitespace().any(|token| token == "cover-image")
+                    {
+                        cover_href_from_props = Some(href_value);
+                    }
+                } else if local_name.as_ref() == b"reference" {
+                    // EPUB 2 guide entry, usually an XHTML page wrapping the cover.
+                    let mut ref_type: Option<String> = None;
+                    let mut href: Option<String> = None;
+
+                    for attr in e.attributes().flatten() {
+                        let key = attr.key.local_name();
+                        let value = String::from_utf8_lossy(attr.value.as_ref()).to_string();
+
+                        if key.as_ref() == b"type" {
+                            ref_type = Some(value);
+                        } else if key.as_ref() == b"href" {
+                            href = Some(value);
                         }
                     }
+
+                    if ref_type.as_deref() == Some("cover") {
+                        cover_href_from_guide = href;
+                    }
                 }
             }
             Ok(Event::Eof) => break,
@@ -157,14 +283,30 @@ fn parse_opf_for_cover_href<R: Read + std::io::Seek>(
         return Some(href);
     }
 
-    cover_id.and_then(|id| item_hrefs.get(&id).cloned())
+    cover_id
+        .and_then(|id| item_hrefs.get(&id).cloned())
+        .or(cover_href_from_guide)
 }
 
-fn resolve_relative_path(opf_path: &str, href: &str) -> String {
-    let base = Path::new(opf_path)
+/// Resolve `href` against the directory of the archive entry `base_path`,
+/// dropping any URL fragment and collapsing `.`/`..` segments.
+fn resolve_relative_path(base_path: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let base = Path::new(base_path)
         .parent()
         .map(PathBuf::from)
         .unwrap_or_default();
 
-    base.join(href).to_string_lossy().replace('\\', "/")
+    let mut parts: Vec<String> = Vec::new();
+    for component in base.join(href).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+
+    parts.join("/")
 }
@@ -1,10 +1,9 @@
+use super::{COVER_HEIGHT as HEIGHT, COVER_WIDTH as WIDTH};
 use ab_glyph::{FontArc, PxScale};
 use image::{ImageFormat, Rgb, RgbImage};
 use imageproc::drawing::{draw_text_mut, text_size};
 use std::path::{Path, PathBuf};
 
-const WIDTH: u32 = 400;
-const HEIGHT: u32 = 600;
 const TITLE_FONT_SIZE: f32 = 34.0;
 const AUTHOR_FONT_SIZE: f32 = 20.0;
 
@@ -14,7 +13,7 @@ const ACCENT: [u8; 3] = [0x63, 0x66, 0xf1];
 const TITLE_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
 const AUTHOR_COLOR: [u8; 3] = [0xa5, 0xb4, 0xfc];
 
-const FONT_BYTES: &[u8] = include_bytes!("fonts/NotoSans-VF.ttf");
+pub(super) const FONT_BYTES: &[u8] = include_bytes!("fonts/NotoSans-VF.ttf");
 
 pub fn generate_synthetic_cover(
     book_id: &str,
@@ -1,9 +1,22 @@
 pub mod epub;
 mod fallback;
 mod pdf;
+pub mod renderer;
+mod svg;
+mod user;
+pub mod worker;
 
+use std::io::{Read, Seek};
 use std::path::{Path, PathBuf};
 
+use renderer::ReadSeek;
+
+pub use user::{find_sidecar_cover, import_cover_image, is_sidecar_cover_name};
+
+/// Dimensions every generated cover is sized to.
+const COVER_WIDTH: u32 = 400;
+const COVER_HEIGHT: u32 = 600;
+
 pub fn default_covers_dir() -> PathBuf {
     std::env::var("COVERS_PATH")
         .map(PathBuf::from)
@@ -32,6 +45,23 @@ pub fn generate_pdf_cover_from_bytes(
         .or_else(|| fallback::generate_synthetic_cover(book_id, title, author, covers_dir))
 }
 
+/// Like [`generate_pdf_cover_from_bytes`], but pdfium reads from `reader` on
+/// demand instead of needing the whole file in memory. Errors when a read
+/// fails or the renderer is unavailable, instead of settling for a synthetic
+/// cover.
+pub fn generate_pdf_cover_from_reader(
+    reader: Box<dyn ReadSeek>,
+    book_id: &str,
+    title: &str,
+    author: Option<&str>,
+    covers_dir: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    Ok(
+        pdf::render_pdf_cover_from_reader(reader, book_id, covers_dir)?
+            .or_else(|| fallback::generate_synthetic_cover(book_id, title, author, covers_dir)),
+    )
+}
+
 pub fn render_pdf_cover_primary(
     file_path: &Path,
     book_id: &str,
@@ -62,6 +92,17 @@ pub fn generate_epub_cover_from_bytes(
         .or_else(|| fallback::generate_synthetic_cover(book_id, title, author, covers_dir))
 }
 
+pub fn generate_epub_cover_from_reader<R: Read + Seek>(
+    reader: R,
+    book_id: &str,
+    title: &str,
+    author: Option<&str>,
+    covers_dir: &Path,
+) -> Option<PathBuf> {
+    epub::extract_epub_cover_from_reader(reader, book_id, covers_dir)
+        .or_else(|| fallback::generate_synthetic_cover(book_id, title, author, covers_dir))
+}
+
 pub fn generate_fallback_cover(
     book_id: &str,
     title: &str,
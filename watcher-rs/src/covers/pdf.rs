@@ -1,13 +1,29 @@
-use image::ImageFormat;
+use super::renderer::{PdfRenderer, PdfSource, ReadSeek, RenderParams, RenderUnavailable};
+use super::worker::render_page_image;
+use image::{DynamicImage, GrayImage, ImageFormat};
 use pdfium_render::prelude::*;
 use std::path::{Path, PathBuf};
 
-pub fn render_pdf_cover(file_path: &Path, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
-    std::fs::create_dir_all(covers_dir).ok()?;
+/// Resolution pages are rendered at when picking a cover.
+const COVER_DPI: f32 = 150.0;
+
+/// How many leading pages to consider when looking for a usable cover.
+const MAX_COVER_CANDIDATES: u16 = 5;
+/// Pages whose share of non-background pixels falls below this are treated
+/// as blank ("intentionally left blank", library stamps, scanner noise).
+const MIN_INK_RATIO: f64 = 0.005;
+/// Greyscale distance from the background colour that counts as ink.
+const INK_THRESHOLD: u8 = 40;
+/// Padding kept around the content box when trimming margins, as a fraction
+/// of the page's shorter side.
+const TRIM_PADDING_RATIO: f64 = 0.02;
 
-    let pdfium = Pdfium::new(Pdfium::bind_to_statically_linked_library().ok()?);
-    let document = pdfium.load_pdf_from_file(file_path, None).ok()?;
-    render_first_page(&document, book_id, covers_dir)
+pub fn render_pdf_cover(file_path: &Path, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
+    render_cover(
+        PdfSource::File(file_path.to_path_buf()),
+        book_id,
+        covers_dir,
+    )
 }
 
 pub fn render_pdf_cover_from_bytes(
@@ -15,26 +31,213 @@ pub fn render_pdf_cover_from_bytes(
     book_id: &str,
     covers_dir: &Path,
 ) -> Option<PathBuf> {
-    std::fs::create_dir_all(covers_dir).ok()?;
+    render_cover(PdfSource::Bytes(bytes.to_vec()), book_id, covers_dir)
+}
 
-    let pdfium = Pdfium::new(Pdfium::bind_to_statically_linked_library().ok()?);
-    let document = pdfium.load_pdf_from_byte_slice(bytes, None).ok()?;
-    render_first_page(&document, book_id, covers_dir)
+/// Like [`render_pdf_cover`], but a failed read or an unavailable renderer is
+/// an error rather than "no cover", so the caller can retry later.
+pub fn render_pdf_cover_from_reader(
+    reader: Box<dyn ReadSeek>,
+    book_id: &str,
+    covers_dir: &Path,
+) -> anyhow::Result<Option<PathBuf>> {
+    match PdfRenderer::shared().cover(PdfSource::Reader(reader)) {
+        Ok(image) => Ok(image.and_then(|image| save_cover(image, book_id, covers_dir))),
+        Err(e) if e.is::<RenderUnavailable>() => Err(e),
+        Err(_) => Ok(None),
+    }
 }
 
-fn render_first_page(document: &PdfDocument, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
-    let page = document.pages().get(0).ok()?;
+fn render_cover(source: PdfSource, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
+    let image = PdfRenderer::shared().cover(source).ok()??;
+    save_cover(image, book_id, covers_dir)
+}
 
-    let render = page
-        .render_with_config(&PdfRenderConfig::new().scale_page_by_factor(150.0 / 72.0))
-        .ok()?;
+fn save_cover(image: DynamicImage, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
+    std::fs::create_dir_all(covers_dir).ok()?;
 
-    let image = render.as_image().into_rgb8();
     let cover_path = covers_dir.join(format!("{book_id}.jpg"));
-
-    image
+    trim_margins(image)
+        .into_rgb8()
         .save_with_format(&cover_path, ImageFormat::Jpeg)
         .ok()?;
 
     Some(cover_path)
 }
+
+/// Pick the cover image for a document: an embedded `/Thumb` on the first
+/// page if it carries content, otherwise the first of the leading pages that
+/// isn't near-blank (falling back to the inkiest one). Runs in the PDF
+/// worker.
+pub(super) fn select_cover_image(document: &PdfDocument) -> Option<DynamicImage> {
+    let pages = document.pages();
+    let params = RenderParams::dpi(COVER_DPI);
+
+    let thumbnail = pages.get(0).ok().and_then(|page| {
+        let image = page.embedded_thumbnail().ok()?.as_image();
+        (ink_ratio(&image.to_luma8()) >= MIN_INK_RATIO).then_some(image)
+    });
+    if thumbnail.is_some() {
+        return thumbnail;
+    }
+
+    let mut best: Option<(f64, DynamicImage)> = None;
+
+    for index in 0..pages.len().min(MAX_COVER_CANDIDATES) {
+        let Ok(page) = pages.get(index) else {
+            continue;
+        };
+        // A page with no objects at all is blank; skip the render.
+        if page.objects().is_empty() {
+            continue;
+        }
+        let Ok(image) = render_page_image(&page, &params) else {
+            continue;
+        };
+
+        let ratio = ink_ratio(&image.to_luma8());
+        if ratio >= MIN_INK_RATIO {
+            return Some(image);
+        }
+        if best
+            .as_ref()
+            .is_none_or(|(best_ratio, _)| ratio > *best_ratio)
+        {
+            best = Some((ratio, image));
+        }
+    }
+
+    match best {
+        Some((_, image)) => Some(image),
+        None => render_page_image(&pages.get(0).ok()?, &params).ok(),
+    }
+}
+
+/// Estimate the background as the most common of the four corner shades.
+fn background_level(gray: &GrayImage) -> u8 {
+    let (w, h) = gray.dimensions();
+    let corners = [
+        gray.get_pixel(0, 0).0[0],
+        gray.get_pixel(w - 1, 0).0[0],
+        gray.get_pixel(0, h - 1).0[0],
+        gray.get_pixel(w - 1, h - 1).0[0],
+    ];
+
+    *corners
+        .iter()
+        .max_by_key(|&&level| {
+            corners
+                .iter()
+                .filter(|&&other| other.abs_diff(level) <= INK_THRESHOLD)
+                .count()
+        })
+        .unwrap_or(&255)
+}
+
+/// Share of pixels that differ noticeably from the page background.
+fn ink_ratio(gray: &GrayImage) -> f64 {
+    let total = gray.width() as u64 * gray.height() as u64;
+    if total == 0 {
+        return 0.0;
+    }
+
+    let background = background_level(gray);
+    let ink = gray
+        .pixels()
+        .filter(|pixel| pixel.0[0].abs_diff(background) > INK_THRESHOLD)
+        .count() as u64;
+
+    ink as f64 / total as f64
+}
+
+/// Crop away uniform borders around the page content, keeping a little
+/// padding. Images with no detectable content are returned unchanged.
+fn trim_margins(image: DynamicImage) -> DynamicImage {
+    let gray = image.to_luma8();
+    let (w, h) = gray.dimensions();
+    if w == 0 || h == 0 {
+        return image;
+    }
+
+    let background = background_level(&gray);
+    let mut min_x = w;
+    let mut min_y = h;
+    let mut max_x = 0;
+    let mut max_y = 0;
+
+    for (x, y, pixel) in gray.enumerate_pixels() {
+        if pixel.0[0].abs_diff(background) > INK_THRESHOLD {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    if min_x > max_x || min_y > max_y {
+        return image;
+    }
+
+    let padding = (w.min(h) as f64 * TRIM_PADDING_RATIO).round() as u32;
+    let left = min_x.saturating_sub(padding);
+    let top = min_y.saturating_sub(padding);
+    let right = (max_x + padding).min(w - 1);
+    let bottom = (max_y + padding).min(h - 1);
+
+    image.crop_imm(left, top, right - left + 1, bottom - top + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MIN_INK_RATIO, ink_ratio, trim_margins};
+    use image::{DynamicImage, GrayImage, Luma};
+
+    fn page_with_box(w: u32, h: u32, x0: u32, y0: u32, x1: u32, y1: u32) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| {
+            if (x0..x1).contains(&x) && (y0..y1).contains(&y) {
+                Luma([10])
+            } else {
+                Luma([250])
+            }
+        })
+    }
+
+    #[test]
+    fn ink_ratio_flags_blank_and_stamped_pages() {
+        let blank = GrayImage::from_pixel(200, 300, Luma([255]));
+        assert!(ink_ratio(&blank) < MIN_INK_RATIO);
+
+        let stamped = page_with_box(200, 300, 90, 140, 96, 146);
+        assert!(ink_ratio(&stamped) < MIN_INK_RATIO);
+
+        let title_page = page_with_box(200, 300, 40, 60, 160, 120);
+        assert!(ink_ratio(&title_page) >= MIN_INK_RATIO);
+    }
+
+    #[test]
+    fn ink_ratio_handles_dark_backgrounds() {
+        let dark_blank = GrayImage::from_pixel(200, 300, Luma([5]));
+        assert!(ink_ratio(&dark_blank) < MIN_INK_RATIO);
+    }
+
+    #[test]
+    fn trim_margins_crops_to_content_with_padding() {
+        let page = page_with_box(200, 300, 50, 100, 150, 200);
+        let trimmed = trim_margins(DynamicImage::ImageLuma8(page));
+
+        // 2% of the 200px short side = 4px padding on each side.
+        assert_eq!(trimmed.width(), 108);
+        assert_eq!(trimmed.height(), 108);
+    }
+
+    #[test]
+    fn trim_margins_leaves_full_bleed_and_empty_pages_alone() {
+        let full_bleed = page_with_box(200, 300, 0, 0, 200, 300);
+        let trimmed = trim_margins(DynamicImage::ImageLuma8(full_bleed));
+        assert_eq!((trimmed.width(), trimmed.height()), (200, 300));
+
+        let blank = GrayImage::from_pixel(200, 300, Luma([255]));
+        let trimmed = trim_margins(DynamicImage::ImageLuma8(blank));
+        assert_eq!((trimmed.width(), trimmed.height()), (200, 300));
+    }
+}
@@ -0,0 +1,482 @@
+use crate::log::log;
+use anyhow::{Context, Result, anyhow, bail};
+use image::{DynamicImage, RgbImage};
+use pdfium_render::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::worker::{self, JobSource, Reply, Request, Task};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+/// Upper bound on the default worker count; each worker is a process with
+/// its own copy of pdfium and the documents it has open.
+const DEFAULT_MAX_WORKERS: usize = 4;
+/// How long a new worker has to bind pdfium and report ready.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+static WORKER_PROGRAM: OnceLock<PathBuf> = OnceLock::new();
+
+/// A seekable byte stream that can be handed to the pdfium thread.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where a render job reads its PDF from.
+pub enum PdfSource {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    /// Read on demand; pdfium only touches the parts of the file it needs.
+    Reader(Box<dyn ReadSeek>),
+}
+
+/// Output resolution for a rendered page. The page is scaled to `dpi` (or to
+/// exactly `width` pixels wide, if given), then shrunk keeping its aspect
+/// ratio to fit any maximum dimension.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RenderParams {
+    pub dpi: f32,
+    pub width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl RenderParams {
+    pub fn dpi(dpi: f32) -> Self {
+        Self {
+            dpi,
+            width: None,
+            max_width: None,
+            max_height: None,
+        }
+    }
+
+    pub(super) fn config(&self) -> PdfRenderConfig {
+        let mut config = match self.width {
+            Some(width) => PdfRenderConfig::new().set_target_width(width as Pixels),
+            None => PdfRenderConfig::new().scale_page_by_factor(self.dpi / 72.0),
+        };
+        if let Some(width) = self.max_width {
+            config = config.set_maximum_width(width as Pixels);
+        }
+        if let Some(height) = self.max_height {
+            config = config.set_maximum_height(height as Pixels);
+        }
+        config
+    }
+}
+
+/// Document properties read by [`PdfRenderer::info`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PdfInfo {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub pages: u32,
+}
+
+/// A job failed for reasons unrelated to the document: it timed out, the
+/// worker died or couldn't start, or the PDF's bytes couldn't be read. The
+/// same job may well succeed later.
+#[derive(Debug)]
+pub struct RenderUnavailable(String);
+
+impl fmt::Display for RenderUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RenderUnavailable {}
+
+/// Use `program` (a `watcher-rs` binary) for PDF workers instead of the
+/// current executable, e.g. from test binaries. Only the first call counts.
+pub fn set_worker_program(program: impl Into<PathBuf>) {
+    let _ = WORKER_PROGRAM.set(program.into());
+}
+
+enum Output {
+    Info(PdfInfo),
+    Image(DynamicImage),
+    NoCover,
+}
+
+struct Job {
+    source: PdfSource,
+    task: Task,
+    reply: mpsc::SyncSender<Result<Output>>,
+}
+
+/// pdfium, run in a `watcher-rs pdfium-worker` child process.
+///
+/// pdfium is not thread-safe, `pdfium-render` holds a process-wide lock for
+/// as long as a `Pdfium` value lives, and a call that hangs inside pdfium
+/// cannot be interrupted, so a hung job would otherwise stall every render
+/// in the process for good. Instead each job gets `timeout` to finish once
+/// it reaches a worker (time spent queued doesn't count); a worker that
+/// overruns it or dies is killed and a fresh one is started for the next
+/// job. Being separate processes, several workers can render at once.
+/// [`PdfSource::Reader`] sources are read in this process and handed to the
+/// worker as it asks for them.
+pub struct PdfRenderer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl PdfRenderer {
+    /// The process-wide renderer. The timeout comes from `PDF_RENDER_TIMEOUT`
+    /// (seconds, default 30) and the number of workers from
+    /// `PDF_RENDER_WORKERS` (default: the number of CPUs, at most 4). Workers
+    /// are started on first use.
+    pub fn shared() -> &'static PdfRenderer {
+        static SHARED: OnceLock<PdfRenderer> = OnceLock::new();
+        SHARED.get_or_init(|| {
+            let timeout = std::env::var("PDF_RENDER_TIMEOUT")
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_TIMEOUT_SECS);
+            let workers = std::env::var("PDF_RENDER_WORKERS")
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or_else(|| {
+                    thread::available_parallelism()
+                        .map_or(1, |n| n.get())
+                        .min(DEFAULT_MAX_WORKERS)
+                });
+            PdfRenderer::spawn(None, Duration::from_secs(timeout), workers)
+        })
+    }
+
+    /// Run jobs on up to `workers` workers started from `program`, or from
+    /// [`worker_program`] when `None`.
+    fn spawn(program: Option<PathBuf>, timeout: Duration, workers: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for index in 0..workers.max(1) {
+            let queue = Arc::clone(&queue);
+            let program = program.clone();
+            thread::Builder::new()
+                .name(format!("pdfium-{index}"))
+                .spawn(move || dispatch(&queue, program.as_deref(), timeout))
+                .expect("failed to spawn pdfium thread");
+        }
+
+        Self { jobs }
+    }
+
+    fn submit(&self, source: PdfSource, task: Task) -> Result<Output> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Job {
+                source,
+                task,
+                reply,
+            })
+            .map_err(|_| RenderUnavailable("PDF renderer has stopped".to_string()))?;
+        result
+            .recv()
+            .map_err(|_| RenderUnavailable("PDF render job was dropped".to_string()))?
+    }
+
+    /// Title, author and page count of `source`.
+    pub fn info(&self, source: PdfSource) -> Result<PdfInfo> {
+        match self.submit(source, Task::Info)? {
+            Output::Info(info) => Ok(info),
+            _ => bail!("PDF worker sent the wrong reply"),
+        }
+    }
+
+    /// The cover image of `source`, or `None` when no page could be
+    /// rendered. See [`select_cover_image`](super::pdf).
+    pub fn cover(&self, source: PdfSource) -> Result<Option<DynamicImage>> {
+        match self.submit(source, Task::Cover)? {
+            Output::Image(image) => Ok(Some(image)),
+            Output::NoCover => Ok(None),
+            Output::Info(_) => bail!("PDF worker sent the wrong reply"),
+        }
+    }
+
+    /// Render a single page (zero-based) of `source`.
+    pub fn render_page(
+        &self,
+        source: PdfSource,
+        page_index: u16,
+        params: RenderParams,
+    ) -> Result<DynamicImage> {
+        let task = Task::Page {
+            index: page_index,
+            params,
+        };
+        match self.submit(source, task)? {
+            Output::Image(image) => Ok(image),
+            _ => bail!("PDF worker sent the wrong reply"),
+        }
+    }
+}
+
+/// Run queued jobs one at a time on this thread's worker, (re)starting it as
+/// needed.
+fn dispatch(queue: &Mutex<mpsc::Receiver<Job>>, program: Option<&Path>, timeout: Duration) {
+    let mut worker: Option<Worker> = None;
+    loop {
+        let job = match queue.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let result = run_job(&mut worker, program, job.source, job.task, timeout);
+        let _ = job.reply.send(result);
+    }
+}
+
+fn run_job(
+    slot: &mut Option<Worker>,
+    program: Option<&Path>,
+    source: PdfSource,
+    task: Task,
+    timeout: Duration,
+) -> Result<Output> {
+    let worker = match slot {
+        Some(worker) => worker,
+        None => match Worker::start(program) {
+            Ok(worker) => slot.insert(worker),
+            Err(e) => {
+                log(&format!("[ERROR] Failed to start PDF worker: {e:#}"));
+                return Err(RenderUnavailable(format!("PDF worker unavailable: {e}")).into());
+            }
+        },
+    };
+
+    match worker.run(source, task, timeout) {
+        Ok(output) => Ok(output),
+        Err(JobError::Failed(message)) => Err(anyhow!(message)),
+        Err(JobError::Unavailable(message)) => Err(RenderUnavailable(message).into()),
+        Err(JobError::Broken(message)) => {
+            log(&format!("[WARN] {message}; restarting the PDF worker"));
+            *slot = None;
+            Err(RenderUnavailable(message).into())
+        }
+    }
+}
+
+enum JobError {
+    /// pdfium couldn't handle the document.
+    Failed(String),
+    /// Not the document's fault; the worker is still usable.
+    Unavailable(String),
+    /// The worker hung, died or broke protocol and has to be replaced.
+    Broken(String),
+}
+
+/// The executable workers are started from: the one passed to
+/// [`set_worker_program`], otherwise this one.
+fn worker_program() -> Result<PathBuf> {
+    if let Some(program) = WORKER_PROGRAM.get() {
+        return Ok(program.clone());
+    }
+    let exe = std::env::current_exe().context("Failed to locate the current executable")?;
+    // Unit tests run from target/<profile>/deps; the binary sits one level up.
+    #[cfg(test)]
+    let exe = exe
+        .parent()
+        .and_then(Path::parent)
+        .map(|dir| dir.join(format!("watcher-rs{}", std::env::consts::EXE_SUFFIX)))
+        .unwrap_or(exe);
+    Ok(exe)
+}
+
+/// A running worker process. Dropping it kills the process.
+struct Worker {
+    child: Child,
+    input: ChildStdin,
+    replies: mpsc::Receiver<Result<Option<Reply>>>,
+}
+
+impl Worker {
+    fn start(program: Option<&Path>) -> Result<Self> {
+        let program = match program {
+            Some(program) => program.to_path_buf(),
+            None => worker_program()?,
+        };
+        let mut child = Command::new(&program)
+            .arg(worker::COMMAND)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", program.display()))?;
+        let input = child.stdin.take().context("PDF worker has no stdin")?;
+        let mut output = child.stdout.take().context("PDF worker has no stdout")?;
+
+        let (replies_tx, replies) = mpsc::channel();
+        thread::Builder::new()
+            .name("pdfium-replies".to_string())
+            .spawn(move || {
+                loop {
+                    let reply = worker::read_frame::<Reply>(&mut output);
+                    let done = !matches!(reply, Ok(Some(_)));
+                    if replies_tx.send(reply).is_err() || done {
+                        break;
+                    }
+                }
+            })?;
+
+        let worker = Worker {
+            child,
+            input,
+            replies,
+        };
+        match worker.replies.recv_timeout(STARTUP_TIMEOUT) {
+            Ok(Ok(Some(Reply::Ready))) => Ok(worker),
+            Ok(Ok(Some(Reply::Failed { message, .. }))) => bail!(message),
+            _ => bail!("{} did not start a PDF worker", program.display()),
+        }
+    }
+
+    fn run(
+        &mut self,
+        source: PdfSource,
+        task: Task,
+        timeout: Duration,
+    ) -> Result<Output, JobError> {
+        let deadline = Instant::now() + timeout;
+        let (source, mut reader) = match source {
+            PdfSource::File(path) => (JobSource::File(path), None),
+            PdfSource::Bytes(bytes) => (JobSource::Bytes(bytes), None),
+            PdfSource::Reader(mut reader) => {
+                let size = reader
+                    .seek(SeekFrom::End(0))
+                    .map_err(|e| JobError::Unavailable(format!("Failed to read PDF: {e}")))?;
+                (JobSource::Reader { size }, Some(reader))
+            }
+        };
+        self.send(&Request::Job { source, task })?;
+
+        let mut read_error = None;
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let reply = match self.replies.recv_timeout(wait) {
+                Ok(Ok(Some(reply))) => reply,
+                Ok(Ok(None)) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    return Err(JobError::Broken("PDF worker exited".to_string()));
+                }
+                Ok(Err(e)) => return Err(JobError::Broken(format!("PDF worker failed: {e}"))),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    return Err(JobError::Broken(format!(
+                        "PDF job timed out after {}s",
+                        timeout.as_secs()
+                    )));
+                }
+            };
+
+            match reply {
+                Reply::Read { offset, len } => {
+                    let request = match reader.as_mut().map(|r| read_chunk(r, offset, len)) {
+                        Some(Ok(data)) => Request::Data(data),
+                        Some(Err(e)) => {
+                            read_error = Some(e);
+                            Request::ReadFailed
+                        }
+                        None => Request::ReadFailed,
+                    };
+                    self.send(&request)?;
+                }
+                Reply::Info(info) => return Ok(Output::Info(info)),
+                Reply::Image { width, height, rgb } => {
+                    return RgbImage::from_raw(width, height, rgb)
+                        .map(|image| Output::Image(DynamicImage::ImageRgb8(image)))
+                        .ok_or_else(|| {
+                            JobError::Broken("PDF worker sent a bad image".to_string())
+                        });
+                }
+                Reply::NoCover => return Ok(Output::NoCover),
+                Reply::Failed {
+                    message,
+                    unavailable,
+                } => {
+                    return Err(match read_error {
+                        Some(e) => JobError::Unavailable(format!("Failed to read PDF: {e}")),
+                        None if unavailable => JobError::Unavailable(message),
+                        None => JobError::Failed(message),
+                    });
+                }
+                Reply::Ready => {
+                    return Err(JobError::Broken("PDF worker restarted mid-job".to_string()));
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, request: &Request) -> Result<(), JobError> {
+        worker::write_frame(&mut self.input, request)
+            .map_err(|e| JobError::Broken(format!("PDF worker stopped reading: {e}")))
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Up to `len` bytes of `reader` at `offset`; fewer only at the end.
+fn read_chunk(reader: &mut dyn ReadSeek, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(len as usize);
+    reader.take(len as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PdfRenderer, PdfSource, RenderParams, RenderUnavailable};
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn failed_jobs_report_errors_and_keep_the_worker_alive() {
+        let renderer = PdfRenderer::shared();
+
+        for _ in 0..2 {
+            let missing = PdfSource::File(PathBuf::from("/nonexistent/book.pdf"));
+            let err = renderer
+                .render_page(missing, 0, RenderParams::dpi(72.0))
+                .unwrap_err();
+            assert!(!err.is::<RenderUnavailable>(), "{err:#}");
+        }
+
+        let garbage = PdfSource::Bytes(b"not a pdf".to_vec());
+        let err = renderer.info(garbage).unwrap_err();
+        assert!(!err.is::<RenderUnavailable>(), "{err:#}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_workers_are_killed_and_replaced() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Says it is ready, then never answers.
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("hung-worker");
+        std::fs::write(
+            &program,
+            "#!/bin/sh\nprintf '\\004\\000\\000\\000\\000\\000\\000\\000'\nexec sleep 60\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let renderer = PdfRenderer::spawn(Some(program), Duration::from_millis(500), 1);
+
+        for _ in 0..2 {
+            let started = Instant::now();
+            let err = renderer
+                .info(PdfSource::Bytes(b"%PDF-1.4".to_vec()))
+                .unwrap_err();
+            assert!(err.is::<RenderUnavailable>(), "{err:#}");
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use super::fallback::FONT_BYTES;
+use super::{COVER_HEIGHT, COVER_WIDTH};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{self, ImageHrefResolver, ImageKind};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+/// Collect the `href`/`xlink:href` targets of every `<image>` element in an SVG
+/// document, skipping inline `data:` URIs (usvg decodes those itself).
+pub fn image_hrefs(svg: &str) -> Vec<String> {
+    let mut reader = Reader::from_str(svg);
+    let mut buf = Vec::new();
+    let mut hrefs = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e))
+                if e.local_name().as_ref() == b"image" =>
+            {
+                for attr in e.attributes().flatten() {
+                    if attr.key.local_name().as_ref() == b"href" {
+                        let href = String::from_utf8_lossy(attr.value.as_ref()).to_string();
+                        if !href.starts_with("data:") {
+                            hrefs.push(href);
+                        }
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    hrefs
+}
+
+/// Rasterize an SVG document onto a white background, scaled to fit the
+/// standard cover size. Linked images are looked up by their raw href in
+/// `images`; nothing is ever read from the local filesystem.
+pub fn rasterize(svg: &str, images: &HashMap<String, Arc<Vec<u8>>>) -> Option<DynamicImage> {
+    let mut options = usvg::Options {
+        font_family: "Noto Sans".to_string(),
+        image_href_resolver: ImageHrefResolver {
+            resolve_data: ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(|href: &str, _: &usvg::Options| {
+                let data = images.get(href)?;
+                match image::guess_format(data).ok()? {
+                    ImageFormat::Jpeg => Some(ImageKind::JPEG(Arc::clone(data))),
+                    ImageFormat::Png => Some(ImageKind::PNG(Arc::clone(data))),
+                    ImageFormat::Gif => Some(ImageKind::GIF(Arc::clone(data))),
+                    ImageFormat::WebP => Some(ImageKind::WEBP(Arc::clone(data))),
+                    _ => None,
+                }
+            }),
+        },
+        ..usvg::Options::default()
+    };
+
+    let fontdb = options.fontdb_mut();
+    fontdb.load_font_data(FONT_BYTES.to_vec());
+    fontdb.set_serif_family("Noto Sans");
+    fontdb.set_sans_serif_family("Noto Sans");
+
+    let tree = usvg::Tree::from_str(&with_namespaces(svg), &options).ok()?;
+    let size = tree.size();
+
+    let scale = (COVER_WIDTH as f32 / size.width()).min(COVER_HEIGHT as f32 / size.height());
+    let width = ((size.width() * scale).round() as u32).clamp(1, COVER_WIDTH);
+    let height = ((size.height() * scale).round() as u32).clamp(1, COVER_HEIGHT);
+
+    let mut pixmap = Pixmap::new(width, height)?;
+    pixmap.fill(Color::WHITE);
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // The white fill makes every pixel opaque, so the premultiplied RGBA
+    // data can be read back as plain RGB.
+    let rgb: Vec<u8> = pixmap
+        .data()
+        .chunks_exact(4)
+        .flat_map(|px| [px[0], px[1], px[2]])
+        .collect();
+
+    RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+}
+
+/// SVG fragments lifted out of an XHTML page often rely on namespace
+/// declarations made on the `<html>` root; re-declare them on the `<svg>`.
+fn with_namespaces(svg: &str) -> String {
+    let Some(tag_end) = svg.find("<svg").map(|start| start + "<svg".len()) else {
+        return svg.to_string();
+    };
+    let open_tag = &svg[tag_end..svg[tag_end..].find('>').map_or(svg.len(), |i| tag_end + i)];
+
+    let mut declarations = String::new();
+    if !open_tag.contains("xmlns=") {
+        declarations.push_str(&format!(" xmlns=\"{SVG_NAMESPACE}\""));
+    }
+    if svg.contains("xlink:") && !open_tag.contains("xmlns:xlink=") {
+        declarations.push_str(&format!(" xmlns:xlink=\"{XLINK_NAMESPACE}\""));
+    }
+
+    let mut output = svg.to_string();
+    output.insert_str(tag_end, &declarations);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{image_hrefs, rasterize, with_namespaces};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn image_hrefs_collects_linked_images_and_skips_data_uris() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink">
+            <image xlink:href="../Images/cover.jpg" width="10" height="10"/>
+            <image href="data:image/png;base64,AAAA" width="10" height="10"/>
+            <image href="plain.png" width="10" height="10"/>
+        </svg>"#;
+
+        assert_eq!(image_hrefs(svg), vec!["../Images/cover.jpg", "plain.png"]);
+    }
+
+    #[test]
+    fn with_namespaces_adds_missing_declarations() {
+        let svg = r#"<svg viewBox="0 0 1 1"><image xlink:href="a.png"/></svg>"#;
+        let fixed = with_namespaces(svg);
+
+        assert!(fixed.starts_with(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox"#
+        ));
+    }
+
+    #[test]
+    fn rasterize_fits_svg_into_cover_size() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 300">
+            <rect width="100" height="300" fill="#c81414"/>
+        </svg>"##;
+
+        let image = rasterize(svg, &HashMap::new()).expect("svg should rasterize");
+        assert_eq!(image.width(), 200);
+        assert_eq!(image.height(), 600);
+
+        let pixel = image.to_rgb8().get_pixel(100, 300).0;
+        assert!(pixel[0] > 150 && pixel[1] < 60 && pixel[2] < 60);
+    }
+
+    #[test]
+    fn rasterize_resolves_linked_images_from_map() {
+        let mut png = Vec::new();
+        image::RgbImage::from_fn(8, 8, |_, _| image::Rgb([20, 20, 200]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let images = HashMap::from([("img/cover.png".to_string(), Arc::new(png))]);
+
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"
+            viewBox="0 0 400 600"><image xlink:href="img/cover.png" width="400" height="600"
+            preserveAspectRatio="none"/></svg>"#;
+
+        let image = rasterize(svg, &images).expect("svg should rasterize");
+        let pixel = image.to_rgb8().get_pixel(200, 300).0;
+        assert!(pixel[2] > 150 && pixel[0] < 60);
+    }
+}
@@ -0,0 +1,58 @@
+use super::svg;
+use anyhow::{Context, Result};
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const SIDECAR_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// Find a user-supplied cover next to a book: `<bookname>.jpg` first, then a
+/// directory-wide `cover.jpg` (`.jpeg` and `.png` are accepted too).
+pub fn find_sidecar_cover(book_path: &Path) -> Option<PathBuf> {
+    let dir = book_path.parent()?;
+    let stem = book_path.file_stem()?.to_str()?;
+
+    [stem, "cover"]
+        .iter()
+        .flat_map(|name| {
+            SIDECAR_EXTENSIONS
+                .iter()
+                .map(move |ext| dir.join(format!("{name}.{ext}")))
+        })
+        .find(|candidate| candidate.is_file())
+}
+
+/// Whether `path` could be a sidecar cover for some book in its directory.
+pub fn is_sidecar_cover_name(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| SIDECAR_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Decode a user-provided image (raster or SVG) and write it as the book's cover.
+pub fn import_cover_image(source: &Path, book_id: &str, covers_dir: &Path) -> Result<PathBuf> {
+    let bytes =
+        std::fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+
+    let is_svg = source
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("svg"));
+    let decoded = if is_svg {
+        let text = std::str::from_utf8(&bytes).context("SVG cover is not valid UTF-8")?;
+        svg::rasterize(text, &HashMap::new()).context("Failed to rasterize SVG cover")?
+    } else {
+        image::load_from_memory(&bytes)
+            .with_context(|| format!("Unsupported cover image: {}", source.display()))?
+    };
+
+    std::fs::create_dir_all(covers_dir)?;
+    let cover_path = covers_dir.join(format!("{book_id}.jpg"));
+    decoded
+        .into_rgb8()
+        .save_with_format(&cover_path, ImageFormat::Jpeg)
+        .with_context(|| format!("Failed to write {}", cover_path.display()))?;
+
+    Ok(cover_path)
+}
@@ -0,0 +1,295 @@
+//! The child-process side of [`PdfRenderer`]: a `watcher-rs pdfium-worker`
+//! process owns pdfium and answers jobs on stdin/stdout, so a job that hangs
+//! inside pdfium can be ended by killing the process.
+//!
+//! Every message is a little-endian `u32` length followed by that many bytes
+//! of bincode. Reader sources stay in the parent: the worker asks for the
+//! byte ranges pdfium wants and the parent answers with the data.
+
+use anyhow::{Context, Result, bail};
+use image::DynamicImage;
+use pdfium_render::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::path::PathBuf;
+
+use super::pdf::select_cover_image;
+use super::renderer::{PdfInfo, RenderParams};
+
+#[cfg(doc)]
+use super::renderer::PdfRenderer;
+
+/// The hidden subcommand that runs [`serve`].
+pub const COMMAND: &str = "pdfium-worker";
+
+/// Largest message either side accepts, so garbage on the pipe (a program
+/// that isn't a worker) fails fast instead of allocating gigabytes.
+const MAX_FRAME_LEN: u32 = 1 << 30;
+/// Largest range the worker asks for in one read.
+const MAX_READ_LEN: usize = 4 << 20;
+
+/// What to do with a document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) enum Task {
+    Info,
+    Cover,
+    Page { index: u16, params: RenderParams },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum JobSource {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+    /// Read through [`Reply::Read`] requests.
+    Reader {
+        size: u64,
+    },
+}
+
+/// Parent to worker.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum Request {
+    Job {
+        source: JobSource,
+        task: Task,
+    },
+    /// The bytes asked for by the last [`Reply::Read`]; short at the end of
+    /// the file.
+    Data(Vec<u8>),
+    ReadFailed,
+}
+
+/// Worker to parent.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) enum Reply {
+    /// Sent once pdfium is bound.
+    Ready,
+    Read {
+        offset: u64,
+        len: u32,
+    },
+    Info(PdfInfo),
+    Image {
+        width: u32,
+        height: u32,
+        rgb: Vec<u8>,
+    },
+    NoCover,
+    Failed {
+        message: String,
+        /// Not the document's fault; the job may succeed later.
+        unavailable: bool,
+    },
+}
+
+pub(super) fn write_frame<T: Serialize>(output: &mut impl Write, message: &T) -> Result<()> {
+    let body = bincode::serialize(message)?;
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .context("PDF worker message too large")?;
+    let mut frame = Vec::with_capacity(4 + body.len());
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(&body);
+    output.write_all(&frame)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// The next message, or `None` at a clean end of stream.
+pub(super) fn read_frame<T: DeserializeOwned>(input: &mut impl Read) -> Result<Option<T>> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_LEN {
+        bail!("PDF worker message of {len} bytes is too large");
+    }
+    let mut body = vec![0u8; len as usize];
+    input.read_exact(&mut body)?;
+    Ok(Some(bincode::deserialize(&body)?))
+}
+
+/// Run jobs from stdin until it closes. Nothing else may write to stdout.
+pub fn serve() -> Result<()> {
+    let mut input = io::stdin();
+    let mut output = io::stdout();
+
+    let pdfium = match Pdfium::bind_to_statically_linked_library() {
+        Ok(bindings) => Pdfium::new(bindings),
+        Err(e) => {
+            let reply = Reply::Failed {
+                message: format!("Failed to bind pdfium: {e}"),
+                unavailable: true,
+            };
+            return write_frame(&mut output, &reply);
+        }
+    };
+    write_frame(&mut output, &Reply::Ready)?;
+
+    while let Some(request) = read_frame::<Request>(&mut input)? {
+        let Request::Job { source, task } = request else {
+            bail!("PDF worker expected a job");
+        };
+        // A panicking job must not take the worker down with it.
+        let reply =
+            catch_unwind(AssertUnwindSafe(|| run(&pdfium, source, task))).unwrap_or_else(|_| {
+                Reply::Failed {
+                    message: "PDF job panicked".to_string(),
+                    unavailable: false,
+                }
+            });
+        write_frame(&mut output, &reply)?;
+    }
+    Ok(())
+}
+
+fn run(pdfium: &Pdfium, source: JobSource, task: Task) -> Reply {
+    let document = match source {
+        JobSource::File(path) => pdfium.load_pdf_from_file(&path, None),
+        JobSource::Bytes(bytes) => pdfium.load_pdf_from_byte_vec(bytes, None),
+        JobSource::Reader { size } => {
+            pdfium.load_pdf_from_reader(RemoteReader { size, pos: 0 }, None)
+        }
+    };
+    let document = match document {
+        Ok(document) => document,
+        Err(e) => return failed(format!("Failed to open PDF: {e}")),
+    };
+
+    match task {
+        Task::Info => Reply::Info(info(&document)),
+        Task::Cover => match select_cover_image(&document) {
+            Some(image) => image_reply(image),
+            None => Reply::NoCover,
+        },
+        Task::Page { index, params } => match document.pages().get(index) {
+            Ok(page) => match render_page_image(&page, &params) {
+                Ok(image) => image_reply(image),
+                Err(e) => failed(e.to_string()),
+            },
+            Err(e) => failed(format!("Page {index} not available: {e}")),
+        },
+    }
+}
+
+fn failed(message: String) -> Reply {
+    Reply::Failed {
+        message,
+        unavailable: false,
+    }
+}
+
+fn info(document: &PdfDocument) -> PdfInfo {
+    let tag = |kind| {
+        document
+            .metadata()
+            .get(kind)
+            .map(|tag| tag.value().trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    PdfInfo {
+        title: tag(PdfDocumentMetadataTagType::Title),
+        author: tag(PdfDocumentMetadataTagType::Author),
+        pages: document.pages().len() as u32,
+    }
+}
+
+fn image_reply(image: DynamicImage) -> Reply {
+    let rgb = image.into_rgb8();
+    Reply::Image {
+        width: rgb.width(),
+        height: rgb.height(),
+        rgb: rgb.into_raw(),
+    }
+}
+
+/// Render `page` with the given parameters.
+pub(super) fn render_page_image(page: &PdfPage, params: &RenderParams) -> Result<DynamicImage> {
+    let bitmap = page
+        .render_with_config(&params.config())
+        .map_err(|e| anyhow::anyhow!("Failed to render page: {e}"))?;
+    Ok(bitmap.as_image())
+}
+
+/// A PDF held by the parent, read one [`Reply::Read`] at a time.
+struct RemoteReader {
+    size: u64,
+    pos: u64,
+}
+
+impl Read for RemoteReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.size {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(MAX_READ_LEN)
+            .min((self.size - self.pos) as usize);
+        let request = Reply::Read {
+            offset: self.pos,
+            len: len as u32,
+        };
+        write_frame(&mut io::stdout(), &request).map_err(io::Error::other)?;
+
+        match read_frame::<Request>(&mut io::stdin()).map_err(io::Error::other)? {
+            Some(Request::Data(data)) => {
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                self.pos += n as u64;
+                Ok(n)
+            }
+            Some(Request::ReadFailed) => Err(io::Error::other("PDF read failed")),
+            _ => Err(io::Error::other("PDF worker expected data")),
+        }
+    }
+}
+
+impl Seek for RemoteReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => self.size as i128 + offset as i128,
+            SeekFrom::Current(offset) => self.pos as i128 + offset as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start of file",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reply, Request, read_frame, write_frame};
+    use std::io::Cursor;
+
+    #[test]
+    fn frames_round_trip_and_end_cleanly() {
+        let mut pipe = Vec::new();
+        write_frame(&mut pipe, &Request::Data(vec![1, 2, 3])).unwrap();
+
+        let mut input = Cursor::new(pipe);
+        match read_frame::<Request>(&mut input).unwrap() {
+            Some(Request::Data(data)) => assert_eq!(data, vec![1, 2, 3]),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(read_frame::<Request>(&mut input).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut input = Cursor::new(b"running 0 tests\n".to_vec());
+        assert!(read_frame::<Reply>(&mut input).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+//! JSON bridge used by the Next.js app to run SQL through the watcher binary.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rusqlite::{
+    CachedStatement, Connection, OpenFlags, Row, Transaction, TransactionBehavior,
+    types::{Value as SqlValue, ValueRef},
+};
+use serde::Deserialize;
+use serde_json::{Map as JsonMap, Value as JsonValue, json};
+
+/// Prepared statements kept per connection by long-lived callers.
+const STATEMENT_CACHE_CAPACITY: usize = 128;
+
+/// Type tag marking a JSON object as a typed SQL value, e.g.
+/// `{"$type": "blob", "base64": "..."}`. BLOB columns are returned the same way.
+const TYPE_TAG: &str = "$type";
+
+#[derive(Deserialize)]
+pub struct SqlRequest {
+    pub sql: String,
+    #[serde(default)]
+    pub params: Params,
+}
+
+/// Positional (`?1`) parameters as an array, or named (`:name`, `@name`,
+/// `$name`) parameters as an object. Object keys without a prefix get `:`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Params {
+    Positional(Vec<JsonValue>),
+    Named(JsonMap<String, JsonValue>),
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::Positional(Vec::new())
+    }
+}
+
+enum Bindings {
+    Positional(Vec<SqlValue>),
+    Named(Vec<(String, SqlValue)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    #[serde(alias = "query")]
+    QueryAll,
+    QueryOne,
+    Execute,
+}
+
+/// One statement of a `transaction` request.
+#[derive(Deserialize)]
+pub struct Statement {
+    pub mode: Mode,
+    #[serde(flatten)]
+    pub request: SqlRequest,
+}
+
+#[derive(Deserialize)]
+pub struct TransactionRequest {
+    pub statements: Vec<Statement>,
+}
+
+/// Open a read-write connection, applying pragmas and pending migrations.
+pub fn open_connection(path: &str) -> Result<Connection> {
+    let conn =
+        Connection::open(path).with_context(|| format!("Failed to open database at {path}"))?;
+    conn.execute_batch(
+        "PRAGMA auto_vacuum=INCREMENTAL;
+         PRAGMA journal_mode=WAL;
+         PRAGMA foreign_keys=ON;
+         PRAGMA busy_timeout=5000;",
+    )
+    .context("Failed to initialize SQLite pragmas")?;
+    super::migrations::run(&conn)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(conn)
+}
+
+/// Open a connection that SQLite itself refuses to write through.
+pub fn open_read_only_connection(path: &str) -> Result<Connection> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI,
+    )
+    .with_context(|| format!("Failed to open database read-only at {path}"))?;
+    conn.execute_batch("PRAGMA busy_timeout=5000;")
+        .context("Failed to initialize SQLite pragmas")?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    Ok(conn)
+}
+
+/// Run one request and build the response object for `mode`:
+/// `{"rows": [...]}`, `{"row": {...} | null}` or `{"changes": N}`.
+pub fn run(conn: &Connection, mode: Mode, request: &SqlRequest) -> Result<JsonValue> {
+    let bindings = to_bindings(&request.params)?;
+
+    Ok(match mode {
+        Mode::QueryAll => json!({ "rows": query_all(conn, &request.sql, &bindings)? }),
+        Mode::QueryOne => json!({ "row": query_one(conn, &request.sql, &bindings)? }),
+        Mode::Execute => json!({ "changes": execute(conn, &request.sql, &bindings)? }),
+    })
+}
+
+/// Run every statement inside one immediate transaction and return
+/// `{"results": [...]}` with one response object per statement. The first
+/// failure rolls everything back and is reported with its statement index.
+pub fn run_transaction(conn: &Connection, request: &TransactionRequest) -> Result<JsonValue> {
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+        .context("Failed to begin transaction")?;
+
+    let mut results = Vec::with_capacity(request.statements.len());
+    for (index, statement) in request.statements.iter().enumerate() {
+        let result = run(&tx, statement.mode, &statement.request)
+            .with_context(|| format!("Statement {index} failed; transaction rolled back"))?;
+        results.push(result);
+    }
+
+    tx.commit().context("Failed to commit transaction")?;
+    Ok(json!({ "results": results }))
+}
+
+/// Whether `sql` only reads, according to SQLite's own statement analysis.
+pub fn is_read_only(conn: &Connection, sql: &str) -> Result<bool> {
+    let stmt = conn
+        .prepare_cached(sql)
+        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
+    Ok(stmt.readonly())
+}
+
+fn to_bindings(params: &Params) -> Result<Bindings> {
+    Ok(match params {
+        Params::Positional(values) => {
+            Bindings::Positional(values.iter().map(to_sql_value).collect::<Result<_>>()?)
+        }
+        Params::Named(values) => Bindings::Named(
+            values
+                .iter()
+                .map(|(name, value)| {
+                    let name = if name.starts_with([':', '@', '$']) {
+                        name.clone()
+                    } else {
+                        format!(":{name}")
+                    };
+                    Ok((name, to_sql_value(value)?))
+                })
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+fn to_sql_value(value: &JsonValue) -> Result<SqlValue> {
+    match value {
+        JsonValue::Null => Ok(SqlValue::Null),
+        JsonValue::Bool(flag) => Ok(SqlValue::Integer(if *flag { 1 } else { 0 })),
+        JsonValue::Number(number) => {
+            if let Some(int) = number.as_i64() {
+                Ok(SqlValue::Integer(int))
+            } else if let Some(float) = number.as_f64() {
+                Ok(SqlValue::Real(float))
+            } else {
+                Err(anyhow::anyhow!("Unsupported JSON number parameter"))
+            }
+        }
+        JsonValue::String(text) => Ok(SqlValue::Text(text.clone())),
+        JsonValue::Object(object) if object.get(TYPE_TAG) == Some(&json!("blob")) => {
+            let encoded = object
+                .get("base64")
+                .and_then(JsonValue::as_str)
+                .context("Blob parameter is missing its base64 data")?;
+            let bytes = BASE64
+                .decode(encoded)
+                .context("Blob parameter is not valid base64")?;
+            Ok(SqlValue::Blob(bytes))
+        }
+        JsonValue::Array(_) | JsonValue::Object(_) => {
+            Err(anyhow::anyhow!("Unsupported SQL parameter type"))
+        }
+    }
+}
+
+fn bind(stmt: &mut CachedStatement<'_>, bindings: &Bindings) -> Result<()> {
+    match bindings {
+        Bindings::Positional(values) => {
+            if values.len() != stmt.parameter_count() {
+                bail!(
+                    "Expected {} SQL parameters, got {}",
+                    stmt.parameter_count(),
+                    values.len()
+                );
+            }
+            for (index, value) in values.iter().enumerate() {
+                stmt.raw_bind_parameter(index + 1, value)?;
+            }
+        }
+        Bindings::Named(values) => {
+            let mut bound = vec![false; stmt.parameter_count()];
+            for (name, value) in values {
+                let index = stmt
+                    .parameter_index(name)?
+                    .with_context(|| format!("Unknown SQL parameter {name}"))?;
+                stmt.raw_bind_parameter(index, value)?;
+                bound[index - 1] = true;
+            }
+            // Unbound parameters would silently be NULL.
+            if let Some(index) = bound.iter().position(|bound| !bound) {
+                let name = stmt.parameter_name(index + 1).unwrap_or("?");
+                bail!("Missing SQL parameter {name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn query_all(
+    conn: &Connection,
+    sql: &str,
+    bindings: &Bindings,
+) -> Result<Vec<JsonMap<String, JsonValue>>> {
+    let mut stmt = conn
+        .prepare_cached(sql)
+        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
+    let column_names: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|name| (*name).to_string())
+        .collect();
+    bind(&mut stmt, bindings).with_context(|| format!("Failed to bind SQL parameters: {sql}"))?;
+    let mut rows = stmt.raw_query();
+
+    let mut output = Vec::new();
+    while let Some(row) = rows.next().context("Failed to read SQL row")? {
+        output.push(row_to_json(row, &column_names)?);
+    }
+
+    Ok(output)
+}
+
+fn query_one(
+    conn: &Connection,
+    sql: &str,
+    bindings: &Bindings,
+) -> Result<Option<JsonMap<String, JsonValue>>> {
+    let mut stmt = conn
+        .prepare_cached(sql)
+        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
+    let column_names: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|name| (*name).to_string())
+        .collect();
+    bind(&mut stmt, bindings).with_context(|| format!("Failed to bind SQL parameters: {sql}"))?;
+    let mut rows = stmt.raw_query();
+
+    if let Some(row) = rows.next().context("Failed to read SQL row")? {
+        return Ok(Some(row_to_json(row, &column_names)?));
+    }
+
+    Ok(None)
+}
+
+fn execute(conn: &Connection, sql: &str, bindings: &Bindings) -> Result<usize> {
+    let mut stmt = conn
+        .prepare_cached(sql)
+        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
+    bind(&mut stmt, bindings).with_context(|| format!("Failed to bind SQL parameters: {sql}"))?;
+    stmt.raw_execute()
+        .with_context(|| format!("Failed to execute SQL: {sql}"))
+}
+
+fn row_to_json(row: &Row<'_>, column_names: &[String]) -> Result<JsonMap<String, JsonValue>> {
+    let mut output = JsonMap::new();
+
+    for (index, name) in column_names.iter().enumerate() {
+        let value = match row.get_ref(index).context("Failed to read SQL column")? {
+            ValueRef::Null => JsonValue::Null,
+            ValueRef::Integer(v) => JsonValue::from(v),
+            ValueRef::Real(v) => JsonValue::from(v),
+            ValueRef::Text(v) => JsonValue::String(String::from_utf8_lossy(v).to_string()),
+            ValueRef::Blob(v) => json!({ TYPE_TAG: "blob", "base64": BASE64.encode(v) }),
+        };
+
+        output.insert(name.clone(), value);
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, SqlRequest, TransactionRequest, run, run_transaction};
+    use rusqlite::Connection;
+
+    fn settings_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE settings (key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL)",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn count(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM settings", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn transaction_returns_per_statement_results() {
+        let conn = settings_db();
+        let request: TransactionRequest = serde_json::from_str(
+            r#"{"statements": [
+                {"mode": "execute", "sql": "INSERT INTO settings VALUES (?1, ?2)", "params": ["a", "1"]},
+                {"mode": "execute", "sql": "INSERT INTO settings VALUES (?1, ?2)", "params": ["b", "2"]},
+                {"mode": "query", "sql": "SELECT key FROM settings ORDER BY key"},
+                {"mode": "query-one", "sql": "SELECT value FROM settings WHERE key = 'b'"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let output = run_transaction(&conn, &request).expect("transaction should commit");
+        let results = output["results"].as_array().unwrap();
+        assert_eq!(results[0]["changes"], 1);
+        assert_eq!(results[2]["rows"].as_array().unwrap().len(), 2);
+        assert_eq!(results[3]["row"]["value"], "2");
+        assert_eq!(count(&conn), 2);
+    }
+
+    #[test]
+    fn transaction_rolls_back_on_first_error() {
+        let conn = settings_db();
+        let request: TransactionRequest = serde_json::from_str(
+            r#"{"statements": [
+                {"mode": "execute", "sql": "INSERT INTO settings VALUES ('a', '1')"},
+                {"mode": "execute", "sql": "INSERT INTO settings VALUES ('a', '2')"},
+                {"mode": "execute", "sql": "INSERT INTO settings VALUES ('c', '3')"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let error = run_transaction(&conn, &request).expect_err("duplicate key must fail");
+        assert!(error.to_string().contains("Statement 1 failed"));
+        assert_eq!(count(&conn), 0);
+    }
+
+    #[test]
+    fn named_params_bind_with_or_without_prefix() {
+        let conn = settings_db();
+        let insert: SqlRequest = serde_json::from_str(
+            r#"{"sql": "INSERT INTO settings VALUES (:key, @value)", "params": {"key": "a", "@value": "1"}}"#,
+        )
+        .unwrap();
+        run(&conn, Mode::Execute, &insert).expect("named params should bind");
+
+        let unknown: SqlRequest = serde_json::from_str(
+            r#"{"sql": "SELECT * FROM settings WHERE key = :key", "params": {"nope": "a"}}"#,
+        )
+        .unwrap();
+        let error = run(&conn, Mode::QueryAll, &unknown).expect_err("unknown name must fail");
+        assert!(format!("{error:#}").contains("Unknown SQL parameter :nope"));
+
+        let missing: SqlRequest = serde_json::from_str(
+            r#"{"sql": "INSERT INTO settings VALUES (:key, :value)", "params": {"key": "b"}}"#,
+        )
+        .unwrap();
+        let error = run(&conn, Mode::Execute, &missing).expect_err("missing name must fail");
+        assert!(format!("{error:#}").contains("Missing SQL parameter :value"));
+        assert_eq!(count(&conn), 1);
+    }
+
+    #[test]
+    fn positional_param_count_must_match() {
+        let conn = settings_db();
+        let request: SqlRequest = serde_json::from_str(
+            r#"{"sql": "INSERT INTO settings VALUES (?1, ?2)", "params": ["a"]}"#,
+        )
+        .unwrap();
+        let error = run(&conn, Mode::Execute, &request).expect_err("missing param must fail");
+        assert!(format!("{error:#}").contains("Expected 2 SQL parameters, got 1"));
+    }
+
+    #[test]
+    fn blobs_round_trip_as_tagged_base64() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE files (data BLOB)")
+            .unwrap();
+
+        let insert: SqlRequest = serde_json::from_str(
+            r#"{"sql": "INSERT INTO files VALUES (?1)", "params": [{"$type": "blob", "base64": "AAEC/w=="}]}"#,
+        )
+        .unwrap();
+        run(&conn, Mode::Execute, &insert).unwrap();
+
+        let stored: Vec<u8> = conn
+            .query_row("SELECT data FROM files", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, vec![0, 1, 2, 255]);
+
+        let select: SqlRequest =
+            serde_json::from_str(r#"{"sql": "SELECT data FROM files"}"#).unwrap();
+        let output = run(&conn, Mode::QueryOne, &select).unwrap();
+        assert_eq!(
+            output["row"]["data"],
+            serde_json::json!({"$type": "blob", "base64": "AAEC/w=="})
+        );
+    }
+}
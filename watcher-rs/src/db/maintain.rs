@@ -0,0 +1,214 @@
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::{Database, unix_now};
+
+/// A scan marker not refreshed for this long is left over from a watcher
+/// that crashed mid-scan and no longer blocks maintenance. Running scans
+/// refresh theirs every [`SCAN_MARKER_REFRESH`].
+const SCAN_MARKER_STALE_SECS: i64 = 60 * 60;
+
+/// How often a running scan calls [`Database::begin_scan`] again.
+pub const SCAN_MARKER_REFRESH: Duration = Duration::from_secs(5 * 60);
+
+pub struct MaintainOptions {
+    /// Run a full `ANALYZE` instead of `PRAGMA optimize`.
+    pub analyze: bool,
+    /// Rewrite the whole file with `VACUUM`.
+    pub full_vacuum: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceReport {
+    pub db_bytes_before: u64,
+    pub db_bytes_after: u64,
+    pub wal_bytes_before: u64,
+    pub wal_bytes_after: u64,
+    pub freelist_pages_before: i64,
+    pub freelist_pages_after: i64,
+    /// `none`, `incremental` or `full`.
+    pub vacuum: &'static str,
+    /// `optimize` or `full`.
+    pub analyze: &'static str,
+    /// The checkpoint could not finish because a reader held the WAL open.
+    pub checkpoint_busy: bool,
+    pub reclaimed_bytes: u64,
+    pub duration_ms: u64,
+}
+
+impl Database {
+    /// Optimize query plans, return free pages to the filesystem and truncate
+    /// the WAL. Callers should check [`Database::scan_in_progress`] first:
+    /// this holds the write lock while it vacuums.
+    pub fn maintain(&self, options: &MaintainOptions) -> Result<MaintenanceReport> {
+        let conn = self.conn();
+        let started = Instant::now();
+        let (db_bytes_before, wal_bytes_before) = file_sizes(&conn)?;
+        let freelist_pages_before = pragma_i64(&conn, "freelist_count")?;
+
+        let analyze = if options.analyze {
+            conn.execute_batch("ANALYZE;")?;
+            "full"
+        } else {
+            conn.execute_batch("PRAGMA optimize;")?;
+            "optimize"
+        };
+
+        let vacuum = if options.full_vacuum {
+            conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL; VACUUM;")?;
+            "full"
+        } else if pragma_i64(&conn, "auto_vacuum")? == 2 {
+            // Each step frees one page, so it has to be stepped to completion.
+            let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+            let mut rows = stmt.query([])?;
+            while rows.next()?.is_some() {}
+            "incremental"
+        } else {
+            "none"
+        };
+
+        // Last, so the pages written by the vacuum are checkpointed too.
+        let checkpoint_busy = if pragma_text(&conn, "journal_mode")? == "wal" {
+            let busy: i64 =
+                conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+            busy != 0
+        } else {
+            false
+        };
+
+        let (db_bytes_after, wal_bytes_after) = file_sizes(&conn)?;
+        Ok(MaintenanceReport {
+            db_bytes_before,
+            db_bytes_after,
+            wal_bytes_before,
+            wal_bytes_after,
+            freelist_pages_before,
+            freelist_pages_after: pragma_i64(&conn, "freelist_count")?,
+            vacuum,
+            analyze,
+            checkpoint_busy,
+            reclaimed_bytes: (db_bytes_before + wal_bytes_before)
+                .saturating_sub(db_bytes_after + wal_bytes_after),
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Note that `source` (`local` or `s3`) started a scan, or is still
+    /// scanning. Kept in `settings` so `db maintain` in another process can
+    /// see it.
+    pub fn begin_scan(&self, source: &str) -> Result<()> {
+        let conn = self.conn();
+        let now = unix_now();
+        conn.execute(
+            "INSERT INTO settings (key, value, updated_at)
+             VALUES (?1, ?2, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            params![scan_key(source), now],
+        )?;
+        Ok(())
+    }
+
+    pub fn end_scan(&self, source: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM settings WHERE key = ?1",
+            params![scan_key(source)],
+        )?;
+        Ok(())
+    }
+
+    /// Whether any watcher is in the middle of a scan.
+    pub fn scan_in_progress(&self) -> Result<bool> {
+        let conn = self.conn();
+        let found = conn
+            .query_row(
+                "SELECT 1 FROM settings
+                 WHERE key LIKE 'scan_in_progress:%' AND updated_at > ?1
+                 LIMIT 1",
+                params![unix_now() - SCAN_MARKER_STALE_SECS],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+}
+
+/// Size of the main database (from its page count, so in-memory databases
+/// work too) and of its `-wal` file.
+fn file_sizes(conn: &Connection) -> Result<(u64, u64)> {
+    let pages = pragma_i64(conn, "page_count")?;
+    let page_size = pragma_i64(conn, "page_size")?;
+    let wal = match conn.path() {
+        Some(path) if !path.is_empty() => Path::new(&format!("{path}-wal"))
+            .metadata()
+            .map(|m| m.len())
+            .unwrap_or(0),
+        _ => 0,
+    };
+    Ok(((pages * page_size) as u64, wal))
+}
+
+fn pragma_i64(conn: &Connection, name: &str) -> Result<i64> {
+    Ok(conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))?)
+}
+
+fn pragma_text(conn: &Connection, name: &str) -> Result<String> {
+    Ok(conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))?)
+}
+
+fn scan_key(source: &str) -> String {
+    format!("scan_in_progress:{source}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MaintainOptions;
+    use crate::db::Database;
+
+    #[test]
+    fn maintain_reclaims_freed_pages_and_truncates_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("library.db");
+        let db = Database::open(path.to_str().unwrap()).unwrap();
+
+        let padding = "x".repeat(4000);
+        for i in 0..200 {
+            db.conn()
+                .execute(
+                    "INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, 0)",
+                    rusqlite::params![format!("k{i}"), padding],
+                )
+                .unwrap();
+        }
+        db.conn()
+            .execute("DELETE FROM settings WHERE key LIKE 'k%'", [])
+            .unwrap();
+
+        let report = db
+            .maintain(&MaintainOptions {
+                analyze: false,
+                full_vacuum: false,
+            })
+            .unwrap();
+        assert_eq!(report.vacuum, "incremental");
+        assert!(report.freelist_pages_before > 0);
+        assert_eq!(report.freelist_pages_after, 0);
+        assert_eq!(report.wal_bytes_after, 0);
+        assert!(report.reclaimed_bytes > 0);
+    }
+
+    #[test]
+    fn scan_markers_block_maintenance_until_cleared() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(!db.scan_in_progress().unwrap());
+        db.begin_scan("local").unwrap();
+        db.begin_scan("s3").unwrap();
+        db.end_scan("local").unwrap();
+        assert!(db.scan_in_progress().unwrap());
+        db.end_scan("s3").unwrap();
+        assert!(!db.scan_in_progress().unwrap());
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{Context, Result, bail};
+use rusqlite::Connection;
+
+/// A single step of a migration.
+enum Step {
+    Sql(&'static str),
+    /// `ALTER TABLE ... ADD COLUMN`, skipped when the column already exists.
+    /// Databases bootstrapped by the Node side may already have it.
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+    /// Data fixes plain SQL can't express, run inside the transaction.
+    Run(fn(&Connection) -> Result<()>),
+    /// Work SQLite refuses to do inside a transaction, like `VACUUM`. Runs
+    /// before the pending migrations' transaction, so it must be safe to
+    /// repeat: another process may run it too, or the migrations may fail.
+    OutsideTransaction(fn(&Connection) -> Result<()>),
+}
+
+struct Migration {
+    version: i64,
+    name: &'static str,
+    steps: &'static [Step],
+}
+
+/// Every schema change, oldest first. Versions are recorded in
+/// `PRAGMA user_version`; append new migrations, never edit applied ones.
+///
+/// The early migrations mirror `src/lib/db/migrations` and are written to be
+/// no-ops on databases the Node side already created.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        steps: &[
+            Step::Sql(
+                "CREATE TABLE IF NOT EXISTS books (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    title TEXT NOT NULL,
+                    author TEXT,
+                    description TEXT,
+                    file_type TEXT NOT NULL,
+                    file_path TEXT NOT NULL,
+                    file_size INTEGER NOT NULL,
+                    file_hash TEXT NOT NULL,
+                    cover_path TEXT,
+                    page_count INTEGER,
+                    added_at INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS users (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    email TEXT NOT NULL,
+                    password_hash TEXT NOT NULL,
+                    display_name TEXT NOT NULL,
+                    role TEXT DEFAULT 'user' NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+                CREATE UNIQUE INDEX IF NOT EXISTS users_email_unique ON users (email);
+                CREATE TABLE IF NOT EXISTS collections (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    user_id TEXT NOT NULL,
+                    name TEXT NOT NULL,
+                    description TEXT,
+                    share_token TEXT,
+                    shared_at INTEGER,
+                    created_at INTEGER NOT NULL,
+                    FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE no action ON DELETE no action
+                );
+                CREATE UNIQUE INDEX IF NOT EXISTS collections_share_token_unique
+                    ON collections (share_token);
+                CREATE TABLE IF NOT EXISTS collection_books (
+                    collection_id TEXT NOT NULL,
+                    book_id TEXT NOT NULL,
+                    added_at INTEGER NOT NULL,
+                    PRIMARY KEY (collection_id, book_id),
+                    FOREIGN KEY (collection_id) REFERENCES collections(id) ON UPDATE no action ON DELETE no action,
+                    FOREIGN KEY (book_id) REFERENCES books(id) ON UPDATE no action ON DELETE cascade
+                );
+                CREATE TABLE IF NOT EXISTS reading_progress (
+                    id TEXT PRIMARY KEY NOT NULL,
+                    user_id TEXT NOT NULL,
+                    book_id TEXT NOT NULL,
+                    current_page INTEGER DEFAULT 0 NOT NULL,
+                    total_pages INTEGER,
+                    epub_location TEXT,
+                    percent_complete REAL DEFAULT 0 NOT NULL,
+                    status TEXT DEFAULT 'not_started' NOT NULL,
+                    last_read_at INTEGER,
+                    FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE no action ON DELETE no action,
+                    FOREIGN KEY (book_id) REFERENCES books(id) ON UPDATE no action ON DELETE cascade
+                );
+                CREATE TABLE IF NOT EXISTS settings (
+                    key TEXT PRIMARY KEY NOT NULL,
+                    value TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );",
+            ),
+            // Older databases were created without the unique book indexes and
+            // may hold duplicates, which are merged before the indexes go on.
+            Step::Run(merge_duplicate_books),
+            Step::Sql(
+                "CREATE UNIQUE INDEX IF NOT EXISTS books_file_path_unique ON books (file_path);
+                CREATE UNIQUE INDEX IF NOT EXISTS books_file_hash_unique ON books (file_hash);",
+            ),
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "s3 source columns",
+        steps: &[
+            Step::AddColumn {
+                table: "books",
+                column: "source",
+                definition: "TEXT NOT NULL DEFAULT 'local'",
+            },
+            Step::AddColumn {
+                table: "books",
+                column: "s3_bucket",
+                definition: "TEXT",
+            },
+            Step::AddColumn {
+                table: "books",
+                column: "s3_etag",
+                definition: "TEXT",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "user cover overrides",
+        steps: &[Step::AddColumn {
+            table: "books",
+            column: "cover_source",
+            definition: "TEXT NOT NULL DEFAULT 'generated'",
+        }],
+    },
+    Migration {
+        version: 4,
+        name: "library change feed",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS library_events (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                type TEXT NOT NULL,
+                book_id TEXT NOT NULL,
+                fields TEXT NOT NULL DEFAULT '[]',
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS library_events_created_at ON library_events (created_at);",
+        )],
+    },
+    Migration {
+        version: 5,
+        name: "soft-deleted books",
+        steps: &[
+            Step::AddColumn {
+                table: "books",
+                column: "missing_at",
+                definition: "INTEGER",
+            },
+            Step::Sql("CREATE INDEX IF NOT EXISTS books_missing_at ON books (missing_at);"),
+        ],
+    },
+    Migration {
+        version: 6,
+        name: "book series",
+        steps: &[
+            Step::AddColumn {
+                table: "books",
+                column: "series",
+                definition: "TEXT",
+            },
+            Step::AddColumn {
+                table: "books",
+                column: "series_index",
+                definition: "REAL",
+            },
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "synced bucket copies",
+        steps: &[
+            Step::AddColumn {
+                table: "books",
+                column: "s3_key",
+                definition: "TEXT",
+            },
+            Step::AddColumn {
+                table: "books",
+                column: "synced_hash",
+                definition: "TEXT",
+            },
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "named s3 sources",
+        steps: &[
+            Step::AddColumn {
+                table: "books",
+                column: "s3_source",
+                definition: "TEXT",
+            },
+            // The same key may be a book in two sources; local paths stay
+            // unique among themselves.
+            Step::Sql(
+                "CREATE INDEX IF NOT EXISTS books_s3_source ON books (s3_source);
+                DROP INDEX IF EXISTS books_file_path_unique;
+                CREATE UNIQUE INDEX books_file_path_unique ON books (
+                    coalesce(CASE WHEN source = 's3' THEN s3_source END, ''), file_path
+                );",
+            ),
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "incremental auto-vacuum",
+        steps: &[Step::OutsideTransaction(enable_incremental_vacuum)],
+    },
+    Migration {
+        version: 10,
+        name: "content unique per source",
+        // Scoped like `books_file_path_unique`: each source keeps its own copy
+        // of a file that is in several of them.
+        steps: &[Step::Sql(
+            "DROP INDEX IF EXISTS books_file_hash_unique;
+            CREATE UNIQUE INDEX books_file_hash_unique ON books (
+                coalesce(CASE WHEN source = 's3' THEN s3_source END, ''), file_hash
+            );",
+        )],
+    },
+    Migration {
+        version: 11,
+        name: "skipped s3 duplicates",
+        steps: &[Step::Sql(
+            "CREATE TABLE IF NOT EXISTS s3_skipped_objects (
+                s3_source TEXT NOT NULL,
+                key TEXT NOT NULL,
+                etag TEXT NOT NULL,
+                duplicate_of TEXT NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+                skipped_at INTEGER NOT NULL,
+                PRIMARY KEY (s3_source, key)
+            );
+            CREATE INDEX IF NOT EXISTS s3_skipped_objects_duplicate_of
+                ON s3_skipped_objects (duplicate_of);",
+        )],
+    },
+];
+
+/// Schema version this build migrates databases to.
+pub const SCHEMA_VERSION: i64 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Bring the schema up to [`SCHEMA_VERSION`]. Pending migrations are applied
+/// in one immediate transaction, so concurrent openers wait for each other
+/// instead of applying the same migration twice. Databases written by a newer
+/// build are refused rather than modified.
+pub fn run(conn: &Connection) -> Result<()> {
+    let current = user_version(conn)?;
+    check_not_newer(current)?;
+    if current == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        for step in migration.steps {
+            if let Step::OutsideTransaction(work) = step {
+                work(conn).with_context(|| {
+                    format!(
+                        "Schema migration {} ({}) failed",
+                        migration.version, migration.name
+                    )
+                })?;
+            }
+        }
+    }
+
+    conn.execute_batch("BEGIN IMMEDIATE")?;
+    let result = apply_pending(conn);
+    match result {
+        Ok(()) => conn.execute_batch("COMMIT")?,
+        Err(_) => conn.execute_batch("ROLLBACK")?,
+    }
+    result
+}
+
+fn apply_pending(conn: &Connection) -> Result<()> {
+    // Another process may have migrated while we waited for the lock.
+    let current = user_version(conn)?;
+    check_not_newer(current)?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        for step in migration.steps {
+            apply_step(conn, step).with_context(|| {
+                format!(
+                    "Schema migration {} ({}) failed",
+                    migration.version, migration.name
+                )
+            })?;
+        }
+        conn.pragma_update(None, "user_version", migration.version)?;
+    }
+    Ok(())
+}
+
+fn apply_step(conn: &Connection, step: &Step) -> Result<()> {
+    match step {
+        Step::Sql(sql) => conn.execute_batch(sql)?,
+        Step::AddColumn {
+            table,
+            column,
+            definition,
+        } => {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+                [table, column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+                ))?;
+            }
+        }
+        Step::Run(work) => work(conn)?,
+        Step::OutsideTransaction(_) => {}
+    }
+    Ok(())
+}
+
+/// `auto_vacuum` is only honored when a database is created, so databases
+/// from before it was set need one full `VACUUM` to switch over.
+fn enable_incremental_vacuum(conn: &Connection) -> Result<()> {
+    let mode: i64 = conn.pragma_query_value(None, "auto_vacuum", |row| row.get(0))?;
+    if mode != 2 {
+        conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL; VACUUM;")?;
+    }
+    Ok(())
+}
+
+/// Fold books sharing a file hash, then a path, into the earliest added
+/// one. Collection entries and reading progress move to the kept book unless
+/// it already has them, so merging never loses what a user did.
+fn merge_duplicate_books(conn: &Connection) -> Result<()> {
+    for column in ["file_hash", "file_path"] {
+        let duplicates: Vec<(String, String)> = conn
+            .prepare(&format!(
+                "SELECT id, (SELECT k.id FROM books k WHERE k.{column} = books.{column}
+                             ORDER BY k.added_at, k.id LIMIT 1) AS keeper
+                 FROM books WHERE id != keeper"
+            ))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for (duplicate, keeper) in duplicates {
+            conn.execute(
+                "INSERT OR IGNORE INTO collection_books (collection_id, book_id, added_at)
+                 SELECT collection_id, ?2, added_at FROM collection_books WHERE book_id = ?1",
+                [&duplicate, &keeper],
+            )?;
+            conn.execute(
+                "UPDATE reading_progress SET book_id = ?2
+                 WHERE book_id = ?1
+                   AND user_id NOT IN (SELECT user_id FROM reading_progress WHERE book_id = ?2)",
+                [&duplicate, &keeper],
+            )?;
+            conn.execute(
+                "DELETE FROM collection_books WHERE book_id = ?1",
+                [&duplicate],
+            )?;
+            conn.execute(
+                "DELETE FROM reading_progress WHERE book_id = ?1",
+                [&duplicate],
+            )?;
+            conn.execute("DELETE FROM books WHERE id = ?1", [&duplicate])?;
+        }
+    }
+    Ok(())
+}
+
+pub fn user_version(conn: &Connection) -> Result<i64> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+fn check_not_newer(version: i64) -> Result<()> {
+    if version > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {version} is newer than this watcher supports \
+             ({SCHEMA_VERSION}); upgrade watcher-rs"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MIGRATIONS, SCHEMA_VERSION, run, user_version};
+    use rusqlite::Connection;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .unwrap();
+        stmt.query_map([table], |row| row.get(0))
+            .unwrap()
+            .map(|name| name.unwrap())
+            .collect()
+    }
+
+    fn book_ids(conn: &Connection) -> Vec<String> {
+        conn.prepare("SELECT id FROM books ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|id| id.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn migrations_are_numbered_sequentially() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+        }
+    }
+
+    #[test]
+    fn run_creates_schema_and_is_idempotent() {
+        let conn = Connection::open_in_memory().unwrap();
+        run(&conn).expect("fresh database should migrate");
+        run(&conn).expect("second run should be a no-op");
+
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+        let books = columns(&conn, "books");
+        for column in [
+            "source",
+            "s3_bucket",
+            "s3_etag",
+            "cover_source",
+            "missing_at",
+            "s3_key",
+            "s3_source",
+        ] {
+            assert!(books.contains(&column.to_string()), "missing {column}");
+        }
+    }
+
+    #[test]
+    fn run_adopts_database_bootstrapped_by_node() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE books (
+                id TEXT PRIMARY KEY NOT NULL, title TEXT NOT NULL, author TEXT,
+                description TEXT, file_type TEXT NOT NULL, file_path TEXT NOT NULL,
+                file_size INTEGER NOT NULL, file_hash TEXT NOT NULL, cover_path TEXT,
+                page_count INTEGER, added_at INTEGER NOT NULL, updated_at INTEGER NOT NULL,
+                source TEXT NOT NULL DEFAULT 'local'
+            );
+            INSERT INTO books VALUES ('a', 'A', NULL, NULL, 'pdf', '/a.pdf', 1, 'h1', NULL, NULL, 1, 1, 'local');
+            INSERT INTO books VALUES ('b', 'B', NULL, NULL, 'pdf', '/b.pdf', 1, 'h2', NULL, NULL, 2, 2, 'local');",
+        )
+        .unwrap();
+
+        run(&conn).expect("existing schema should be adopted");
+
+        assert_eq!(book_ids(&conn), vec!["a", "b"]);
+        assert!(columns(&conn, "books").contains(&"s3_etag".to_string()));
+    }
+
+    #[test]
+    fn run_switches_existing_databases_to_incremental_vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = Connection::open(dir.path().join("library.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE settings (key TEXT PRIMARY KEY NOT NULL, value TEXT NOT NULL, updated_at INTEGER NOT NULL);
+            CREATE TABLE books (id TEXT PRIMARY KEY NOT NULL, file_hash TEXT NOT NULL, source TEXT NOT NULL, s3_source TEXT);",
+        )
+        .unwrap();
+        conn.pragma_update(None, "user_version", 8).unwrap();
+        let mode = |conn: &Connection| -> i64 {
+            conn.pragma_query_value(None, "auto_vacuum", |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(mode(&conn), 0);
+
+        run(&conn).expect("migration should succeed");
+        assert_eq!(mode(&conn), 2);
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn run_merges_duplicate_books_keeping_their_user_data() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE books (
+                id TEXT PRIMARY KEY NOT NULL, title TEXT NOT NULL, author TEXT,
+                description TEXT, file_type TEXT NOT NULL, file_path TEXT NOT NULL,
+                file_size INTEGER NOT NULL, file_hash TEXT NOT NULL, cover_path TEXT,
+                page_count INTEGER, added_at INTEGER NOT NULL, updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE collection_books (
+                collection_id TEXT NOT NULL, book_id TEXT NOT NULL, added_at INTEGER NOT NULL,
+                PRIMARY KEY (collection_id, book_id)
+            );
+            CREATE TABLE reading_progress (
+                id TEXT PRIMARY KEY NOT NULL, user_id TEXT NOT NULL, book_id TEXT NOT NULL
+            );
+            INSERT INTO books VALUES ('a', 'A', NULL, NULL, 'pdf', '/a.pdf', 1, 'h', NULL, NULL, 1, 1);
+            INSERT INTO books VALUES ('b', 'B', NULL, NULL, 'pdf', '/b.pdf', 1, 'h', NULL, NULL, 2, 2);
+            INSERT INTO books VALUES ('c', 'C', NULL, NULL, 'pdf', '/a.pdf', 1, 'h3', NULL, NULL, 3, 3);
+            INSERT INTO collection_books VALUES ('shelf', 'a', 1), ('shelf', 'b', 2), ('other', 'c', 3);
+            INSERT INTO reading_progress VALUES ('p1', 'u1', 'a'), ('p2', 'u1', 'b'), ('p3', 'u2', 'c');",
+        )
+        .unwrap();
+
+        run(&conn).expect("duplicates should be merged");
+
+        assert_eq!(book_ids(&conn), vec!["a"]);
+        let rows = |sql: &str| -> Vec<String> {
+            conn.prepare(sql)
+                .unwrap()
+                .query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect()
+        };
+        assert_eq!(
+            rows("SELECT collection_id || ':' || book_id FROM collection_books ORDER BY 1"),
+            vec!["other:a", "shelf:a"]
+        );
+        assert_eq!(
+            rows("SELECT id || ':' || user_id || ':' || book_id FROM reading_progress ORDER BY 1"),
+            vec!["p1:u1:a", "p3:u2:a"]
+        );
+        assert_eq!(user_version(&conn).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn run_refuses_newer_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+
+        let error = run(&conn).expect_err("newer schema must be refused");
+        assert!(
+            error
+                .to_string()
+                .contains("newer than this watcher supports")
+        );
+    }
+}
@@ -0,0 +1,1169 @@
+pub mod bridge;
+pub mod maintain;
+pub mod migrations;
+pub mod serve;
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, Transaction, TransactionBehavior, params};
+use serde::Serialize;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The library database. Every method locks the connection for its
+/// duration, so one handle can be shared by watchers on different threads
+/// and their writes are serialized.
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+pub struct BookRow {
+    pub id: String,
+    pub title: String,
+    pub file_path: String,
+    pub file_hash: String,
+    pub file_type: String,
+    pub cover_path: Option<String>,
+    pub cover_source: String,
+    pub source: String,
+    /// When the book's file disappeared; `None` for books in the library.
+    pub missing_at: Option<i64>,
+}
+
+/// What happened to a book, as recorded in `library_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Added,
+    Updated,
+    Deleted,
+    Moved,
+    Restored,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Added => "added",
+            EventKind::Updated => "updated",
+            EventKind::Deleted => "deleted",
+            EventKind::Moved => "moved",
+            EventKind::Restored => "restored",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryEvent {
+    pub seq: i64,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub book_id: String,
+    pub fields: Vec<String>,
+    pub created_at: i64,
+}
+
+/// How long `library_events` rows are kept before compaction.
+pub const EVENT_RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// `books.cover_source` for covers extracted, rendered or synthesized by the watcher.
+pub const COVER_SOURCE_GENERATED: &str = "generated";
+/// `books.cover_source` for covers pinned by the user; re-ingest must not replace them.
+pub const COVER_SOURCE_USER: &str = "user";
+
+impl BookRow {
+    pub fn has_user_cover(&self) -> bool {
+        self.cover_source == COVER_SOURCE_USER
+    }
+}
+
+pub struct OrphanRow {
+    pub id: String,
+    pub title: String,
+    pub file_path: String,
+    pub cover_path: Option<String>,
+    /// The S3 source the book came from, whose cover store holds its cover.
+    pub s3_source: Option<String>,
+}
+
+/// Row returned when querying S3 books for diff computation.
+#[derive(Clone)]
+pub struct S3BookRow {
+    pub id: String,
+    pub title: String,
+    /// The object key; for synced local books, `s3_key`.
+    pub file_path: String,
+    pub file_type: String,
+    pub cover_path: Option<String>,
+    pub s3_etag: Option<String>,
+    /// A local book whose bucket copy `sync` keeps up to date. The S3
+    /// watcher leaves these alone.
+    pub synced: bool,
+}
+
+/// An S3 object skipped as another copy of a book, which scans leave alone
+/// while that book is in the library.
+pub struct SkippedObject {
+    pub key: String,
+    pub etag: String,
+    /// The book the object is a copy of.
+    pub duplicate_of: String,
+}
+
+/// A local book as seen by `sync`, with its bucket copy if it has one.
+pub struct SyncRow {
+    pub id: String,
+    pub title: String,
+    pub file_path: String,
+    pub file_hash: String,
+    pub s3_source: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_key: Option<String>,
+    pub s3_etag: Option<String>,
+    /// `file_hash` when the local file and the bucket copy last matched.
+    pub synced_hash: Option<String>,
+}
+
+/// Where a book's file lives: a local path, or an object key in an S3 bucket.
+pub struct BookFile {
+    pub id: String,
+    pub file_path: String,
+    pub file_type: String,
+    pub file_hash: String,
+    pub source: String,
+    pub s3_source: Option<String>,
+    pub s3_bucket: Option<String>,
+    /// Key of the bucket copy of a local book kept in sync by `sync`.
+    pub s3_key: Option<String>,
+    pub cover_path: Option<String>,
+}
+
+/// A book in the library with everything `verify` checks.
+pub struct LibraryBook {
+    pub id: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub file_path: String,
+    pub file_type: String,
+    pub file_hash: String,
+    pub cover_path: Option<String>,
+    pub source: String,
+    pub s3_source: Option<String>,
+    pub s3_bucket: Option<String>,
+}
+
+/// A book as written by `export`.
+#[derive(Debug, Serialize)]
+pub struct CatalogBook {
+    pub id: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub file_type: String,
+    pub file_path: String,
+    pub file_size: i64,
+    pub file_hash: String,
+    pub cover_path: Option<String>,
+    pub page_count: Option<i64>,
+    pub source: String,
+    pub s3_bucket: Option<String>,
+    pub added_at: i64,
+    pub updated_at: i64,
+}
+
+/// One user's progress through one book.
+#[derive(Debug, Serialize)]
+pub struct ProgressRow {
+    pub book_id: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub status: String,
+    pub percent_complete: f64,
+    pub current_page: i64,
+    pub total_pages: Option<i64>,
+    pub last_read_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionRow {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub user_id: String,
+    pub user_name: String,
+    /// Books in the library, in the order they were added to the collection.
+    pub book_ids: Vec<String>,
+}
+
+pub struct NewBook<'a> {
+    pub id: &'a str,
+    pub title: &'a str,
+    pub author: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub file_type: &'a str,
+    pub file_path: &'a str,
+    pub file_size: i64,
+    pub file_hash: &'a str,
+    pub cover_path: Option<&'a str>,
+    pub cover_source: &'a str,
+    pub page_count: Option<i64>,
+    pub added_at: i64,
+    pub updated_at: i64,
+    pub source: &'a str,
+    /// Name of the S3 source the book was found in.
+    pub s3_source: Option<&'a str>,
+    pub s3_bucket: Option<&'a str>,
+    pub s3_etag: Option<&'a str>,
+}
+
+pub struct UpdateBook<'a> {
+    pub title: &'a str,
+    pub author: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub file_size: i64,
+    pub file_hash: &'a str,
+    pub cover_path: Option<&'a str>,
+    pub cover_source: &'a str,
+    pub page_count: Option<i64>,
+    pub updated_at: i64,
+    /// The object's new ETag; `None` leaves the recorded one alone, so a
+    /// local change doesn't forget which bucket copy was synced.
+    pub s3_etag: Option<&'a str>,
+}
+
+impl Database {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn =
+            Connection::open(path).with_context(|| format!("Failed to open database at {path}"))?;
+        // auto_vacuum only takes effect on a new database; older ones are
+        // switched over by a migration.
+        conn.execute_batch(
+            "PRAGMA auto_vacuum=INCREMENTAL;
+             PRAGMA journal_mode=WAL;
+             PRAGMA foreign_keys=ON;
+             PRAGMA busy_timeout=5000;",
+        )?;
+        migrations::run(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch("PRAGMA foreign_keys=ON;")?;
+        migrations::run(&conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// A panic while holding the lock leaves nothing half-done that SQLite
+    /// wouldn't have rolled back, so a poisoned lock is still usable.
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Version of the schema this database is at (see [`migrations`]).
+    pub fn schema_version(&self) -> Result<i64> {
+        let conn = self.conn();
+        migrations::user_version(&conn)
+    }
+
+    /// The present S3 book in `source` whose object has this ETag and size:
+    /// another copy of the same upload.
+    pub fn find_s3_copy(&self, source: &str, etag: &str, size: i64) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books
+             WHERE source = 's3' AND s3_source = ?1 AND s3_etag = ?2 AND file_size = ?3
+               AND missing_at IS NULL
+             LIMIT 1",
+        )?;
+        let result = stmt
+            .query_row(params![source, etag, size], book_row)
+            .optional()?;
+        Ok(result)
+    }
+
+    pub fn find_by_path(&self, path: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books WHERE file_path = ?1 AND missing_at IS NULL LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![path], book_row).optional()?;
+        Ok(result)
+    }
+
+    /// The present S3 book stored at `key` in `source`.
+    pub fn find_by_s3_key(&self, source: &str, key: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books
+             WHERE source = 's3' AND s3_source = ?1 AND file_path = ?2 AND missing_at IS NULL
+             LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![source, key], book_row).optional()?;
+        Ok(result)
+    }
+
+    pub fn find_by_id(&self, id: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books WHERE id = ?1 AND missing_at IS NULL LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![id], book_row).optional()?;
+        Ok(result)
+    }
+
+    /// The present local book with this content.
+    pub fn find_book_by_hash(&self, hash: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books WHERE source = 'local' AND file_hash = ?1 AND missing_at IS NULL
+             LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![hash], book_row).optional()?;
+        Ok(result)
+    }
+
+    /// A missing book that a reappearing file in `source` may belong to: one
+    /// with the same content from any source, or else one from `source` last
+    /// seen at the same path. Matching content across sources keeps reading
+    /// progress and collections when a file moves between sources.
+    pub fn find_missing(&self, source: &str, hash: &str, path: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books
+             WHERE missing_at IS NOT NULL
+               AND (file_hash = ?2 OR (source = ?1 AND file_path = ?3))
+             ORDER BY file_hash = ?2 DESC, source = ?1 DESC LIMIT 1",
+        )?;
+        let result = stmt
+            .query_row(params![source, hash, path], book_row)
+            .optional()?;
+        Ok(result)
+    }
+
+    /// A missing S3 book from any source whose object had this ETag and size.
+    pub fn find_missing_s3_copy(&self, etag: &str, size: i64) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books
+             WHERE missing_at IS NOT NULL AND source = 's3' AND s3_etag = ?1 AND file_size = ?2
+             LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![etag, size], book_row).optional()?;
+        Ok(result)
+    }
+
+    /// The missing S3 book from `source` last seen at `key`.
+    pub fn find_missing_s3_key(&self, source: &str, key: &str) -> Result<Option<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books
+             WHERE missing_at IS NOT NULL AND source = 's3' AND s3_source = ?1 AND file_path = ?2
+             LIMIT 1",
+        )?;
+        let result = stmt.query_row(params![source, key], book_row).optional()?;
+        Ok(result)
+    }
+
+    /// Missing books of `size` bytes from any source; a new object is one of
+    /// them if its content hashes to their `file_hash`.
+    pub fn find_missing_by_size(&self, size: i64) -> Result<Vec<BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, file_type, cover_path, cover_source, source,
+                    missing_at
+             FROM books WHERE missing_at IS NOT NULL AND file_size = ?1",
+        )?;
+        let rows = stmt
+            .query_map(params![size], book_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn find_book_file(&self, id: &str) -> Result<Option<BookFile>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, file_path, file_type, file_hash, source, s3_source, s3_bucket, s3_key,
+                    cover_path
+             FROM books WHERE id = ?1 LIMIT 1",
+        )?;
+        let result = stmt
+            .query_row(params![id], |row| {
+                Ok(BookFile {
+                    id: row.get(0)?,
+                    file_path: row.get(1)?,
+                    file_type: row.get(2)?,
+                    file_hash: row.get(3)?,
+                    source: row.get(4)?,
+                    s3_source: row.get(5)?,
+                    s3_bucket: row.get(6)?,
+                    s3_key: row.get(7)?,
+                    cover_path: row.get(8)?,
+                })
+            })
+            .optional()?;
+        Ok(result)
+    }
+
+    pub fn insert_book(&self, book: &NewBook) -> Result<usize> {
+        let conn = self.conn();
+        let changes = conn.execute(
+            "INSERT INTO books (id, title, author, description, file_type, file_path,
+                                file_size, file_hash, cover_path, cover_source, page_count,
+                                added_at, updated_at, source, s3_source, s3_bucket, s3_etag)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                     ?17)
+             ON CONFLICT DO NOTHING",
+            params![
+                book.id,
+                book.title,
+                book.author,
+                book.description,
+                book.file_type,
+                book.file_path,
+                book.file_size,
+                book.file_hash,
+                book.cover_path,
+                book.cover_source,
+                book.page_count,
+                book.added_at,
+                book.updated_at,
+                book.source,
+                book.s3_source,
+                book.s3_bucket,
+                book.s3_etag,
+            ],
+        )?;
+        Ok(changes)
+    }
+
+    /// Update a book and return the names of the columns whose values changed.
+    pub fn update_book(&self, id: &str, book: &UpdateBook) -> Result<Vec<&'static str>> {
+        let conn = self.conn();
+        const FIELDS: [&str; 9] = [
+            "title",
+            "author",
+            "description",
+            "file_size",
+            "file_hash",
+            "cover_path",
+            "cover_source",
+            "page_count",
+            "s3_etag",
+        ];
+        let changed: Vec<bool> = conn
+            .query_row(
+                "SELECT title IS NOT ?1, author IS NOT ?2, description IS NOT ?3,
+                        file_size IS NOT ?4, file_hash IS NOT ?5, cover_path IS NOT ?6,
+                        cover_source IS NOT ?7, page_count IS NOT ?8,
+                        ?9 IS NOT NULL AND s3_etag IS NOT ?9
+                 FROM books WHERE id = ?10",
+                params![
+                    book.title,
+                    book.author,
+                    book.description,
+                    book.file_size,
+                    book.file_hash,
+                    book.cover_path,
+                    book.cover_source,
+                    book.page_count,
+                    book.s3_etag,
+                    id,
+                ],
+                |row| (0..FIELDS.len()).map(|i| row.get(i)).collect(),
+            )
+            .optional()?
+            .unwrap_or_default();
+
+        conn.execute(
+            "UPDATE books SET title = ?1, author = ?2, description = ?3,
+                              file_size = ?4, file_hash = ?5, cover_path = ?6,
+                              cover_source = ?7, page_count = ?8, updated_at = ?9,
+                              s3_etag = COALESCE(?10, s3_etag)
+             WHERE id = ?11",
+            params![
+                book.title,
+                book.author,
+                book.description,
+                book.file_size,
+                book.file_hash,
+                book.cover_path,
+                book.cover_source,
+                book.page_count,
+                book.updated_at,
+                book.s3_etag,
+                id,
+            ],
+        )?;
+        Ok(FIELDS
+            .iter()
+            .zip(changed)
+            .filter_map(|(field, changed)| changed.then_some(*field))
+            .collect())
+    }
+
+    /// Point a book at a new location after its file was moved.
+    pub fn move_book(&self, id: &str, file_path: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET file_path = ?1, updated_at = ?2 WHERE id = ?3",
+            params![file_path, unix_now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Pin a user-provided cover so re-ingest leaves it alone.
+    pub fn set_user_cover(&self, id: &str, cover_path: &str) -> Result<usize> {
+        let conn = self.conn();
+        let changes = conn.execute(
+            "UPDATE books SET cover_path = ?1, cover_source = ?2, updated_at = ?3 WHERE id = ?4",
+            params![cover_path, COVER_SOURCE_USER, unix_now(), id],
+        )?;
+        Ok(changes)
+    }
+
+    /// Record the object an S3 book is stored as: its ETag, and the
+    /// fingerprint of it kept as `file_hash`.
+    pub fn set_s3_object(&self, id: &str, etag: &str, file_hash: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET s3_etag = ?1, file_hash = ?2 WHERE id = ?3",
+            params![etag, file_hash, id],
+        )?;
+        Ok(())
+    }
+
+    /// Record the series a book belongs to. Returns whether anything changed.
+    pub fn set_series(
+        &self,
+        id: &str,
+        series: Option<&str>,
+        series_index: Option<f64>,
+    ) -> Result<bool> {
+        let conn = self.conn();
+        let changes = conn.execute(
+            "UPDATE books SET series = ?1, series_index = ?2
+             WHERE id = ?3 AND (series IS NOT ?1 OR series_index IS NOT ?2)",
+            params![series, series_index, id],
+        )?;
+        Ok(changes > 0)
+    }
+
+    /// Replace a cover with one the watcher generated.
+    pub fn set_generated_cover(&self, id: &str, cover_path: &str) -> Result<usize> {
+        let conn = self.conn();
+        let changes = conn.execute(
+            "UPDATE books SET cover_path = ?1, cover_source = ?2, updated_at = ?3 WHERE id = ?4",
+            params![cover_path, COVER_SOURCE_GENERATED, unix_now(), id],
+        )?;
+        Ok(changes)
+    }
+
+    /// Soft-delete: hide the book but keep its row, progress and collection
+    /// entries so it can be restored if the file comes back.
+    pub fn mark_missing(&self, id: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET missing_at = ?1 WHERE id = ?2 AND missing_at IS NULL",
+            params![unix_now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Bring a missing book back, at `file_path` in `source`. A book that
+    /// moved between local files and S3 drops the old source's S3 details.
+    pub fn restore_book(&self, id: &str, source: &str, file_path: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET missing_at = NULL, file_path = ?2, updated_at = ?3,
+                    s3_source = CASE WHEN ?1 = 'local' THEN NULL ELSE s3_source END,
+                    s3_bucket = CASE WHEN ?1 = 'local' THEN NULL ELSE s3_bucket END,
+                    s3_etag = CASE WHEN ?1 = source THEN s3_etag END,
+                    source = ?1
+             WHERE id = ?4",
+            params![source, file_path, unix_now(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Record which S3 source a book belongs to, e.g. after restoring a
+    /// missing book found again under another source.
+    pub fn set_s3_source(&self, id: &str, source: &str, bucket: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET s3_source = ?1, s3_bucket = ?2 WHERE id = ?3",
+            params![source, bucket, id],
+        )?;
+        Ok(())
+    }
+
+    /// Permanently delete books missing for longer than `max_age_secs` and
+    /// return them so their covers can be removed.
+    pub fn purge_missing(&self, max_age_secs: i64) -> Result<Vec<OrphanRow>> {
+        let conn = self.conn();
+        let cutoff = unix_now() - max_age_secs;
+        let mut stmt = conn.prepare(
+            "DELETE FROM books WHERE missing_at IS NOT NULL AND missing_at < ?1
+             RETURNING id, title, file_path, cover_path, s3_source",
+        )?;
+        let rows = stmt
+            .query_map(params![cutoff], |row| {
+                Ok(OrphanRow {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    file_path: row.get(2)?,
+                    cover_path: row.get(3)?,
+                    s3_source: row.get(4)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    pub fn delete_book(&self, id: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM books WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Return all local books still in the library (for orphan cleanup in local mode).
+    pub fn all_books(&self) -> Result<Vec<OrphanRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, cover_path, s3_source FROM books
+             WHERE source = 'local' AND missing_at IS NULL",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(OrphanRow {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    file_path: row.get(2)?,
+                    cover_path: row.get(3)?,
+                    s3_source: row.get(4)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Every book in the library, local and S3.
+    pub fn library_books(&self) -> Result<Vec<LibraryBook>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, author, file_path, file_type, file_hash, cover_path, source,
+                    s3_source, s3_bucket
+             FROM books WHERE missing_at IS NULL ORDER BY title",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(LibraryBook {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    author: row.get(2)?,
+                    file_path: row.get(3)?,
+                    file_type: row.get(4)?,
+                    file_hash: row.get(5)?,
+                    cover_path: row.get(6)?,
+                    source: row.get(7)?,
+                    s3_source: row.get(8)?,
+                    s3_bucket: row.get(9)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Every book in the library with its full metadata, ordered by title.
+    pub fn catalog_books(&self) -> Result<Vec<CatalogBook>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, author, description, series, series_index, file_type, file_path,
+                    file_size, file_hash, cover_path, page_count, source, s3_bucket,
+                    added_at, updated_at
+             FROM books WHERE missing_at IS NULL ORDER BY title COLLATE NOCASE, id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(CatalogBook {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    author: row.get(2)?,
+                    description: row.get(3)?,
+                    series: row.get(4)?,
+                    series_index: row.get(5)?,
+                    file_type: row.get(6)?,
+                    file_path: row.get(7)?,
+                    file_size: row.get(8)?,
+                    file_hash: row.get(9)?,
+                    cover_path: row.get(10)?,
+                    page_count: row.get(11)?,
+                    source: row.get(12)?,
+                    s3_bucket: row.get(13)?,
+                    added_at: row.get(14)?,
+                    updated_at: row.get(15)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Reading progress of every user for books in the library.
+    pub fn reading_progress(&self) -> Result<Vec<ProgressRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT rp.book_id, rp.user_id, u.display_name, rp.status, rp.percent_complete,
+                    rp.current_page, rp.total_pages, rp.last_read_at
+             FROM reading_progress rp
+             INNER JOIN users u ON u.id = rp.user_id
+             INNER JOIN books b ON b.id = rp.book_id
+             WHERE b.missing_at IS NULL
+             ORDER BY rp.book_id, u.display_name",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(ProgressRow {
+                    book_id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    user_name: row.get(2)?,
+                    status: row.get(3)?,
+                    percent_complete: row.get(4)?,
+                    current_page: row.get(5)?,
+                    total_pages: row.get(6)?,
+                    last_read_at: row.get(7)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Every user's collections with the books in them.
+    pub fn collections(&self) -> Result<Vec<CollectionRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT c.id, c.name, c.description, c.user_id, u.display_name
+             FROM collections c
+             INNER JOIN users u ON u.id = c.user_id
+             ORDER BY c.name COLLATE NOCASE, c.id",
+        )?;
+        let mut collections = stmt
+            .query_map([], |row| {
+                Ok(CollectionRow {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    description: row.get(2)?,
+                    user_id: row.get(3)?,
+                    user_name: row.get(4)?,
+                    book_ids: Vec::new(),
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT cb.book_id FROM collection_books cb
+             INNER JOIN books b ON b.id = cb.book_id
+             WHERE cb.collection_id = ?1 AND b.missing_at IS NULL
+             ORDER BY cb.added_at, cb.book_id",
+        )?;
+        for collection in &mut collections {
+            collection.book_ids = stmt
+                .query_map(params![collection.id], |row| row.get(0))?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+        }
+        Ok(collections)
+    }
+
+    /// Cover paths of all rows, including missing books awaiting purge.
+    pub fn cover_paths(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT cover_path FROM books WHERE cover_path IS NOT NULL")?;
+        let rows = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Return all S3 books in the library from a given source (for diff
+    /// computation), including local books synced to it. Missing books are
+    /// left out, so their keys show up as added if they return.
+    pub fn find_s3_books(&self, source: &str) -> Result<Vec<S3BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, CASE WHEN source = 's3' THEN file_path ELSE s3_key END,
+                    file_type, cover_path, s3_etag, source = 'local'
+             FROM books
+             WHERE s3_source = ?1 AND (source = 's3' OR s3_key IS NOT NULL)
+               AND missing_at IS NULL",
+        )?;
+        let rows = stmt
+            .query_map(params![source], s3_book_row)?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// The present S3 book (or synced local book) for `key` in `source`, if any.
+    pub fn find_s3_book(&self, source: &str, key: &str) -> Result<Option<S3BookRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, CASE WHEN source = 's3' THEN file_path ELSE s3_key END,
+                    file_type, cover_path, s3_etag, source = 'local'
+             FROM books
+             WHERE s3_source = ?1 AND missing_at IS NULL
+               AND ((source = 's3' AND file_path = ?2) OR (source = 'local' AND s3_key = ?2))",
+        )?;
+        Ok(stmt
+            .query_row(params![source, key], s3_book_row)
+            .optional()?)
+    }
+
+    /// Remember that the object at `key` in `source` is a copy of the book
+    /// `duplicate_of`, so it isn't processed again until it changes.
+    pub fn record_skipped_object(
+        &self,
+        source: &str,
+        key: &str,
+        etag: &str,
+        duplicate_of: &str,
+    ) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO s3_skipped_objects (s3_source, key, etag, duplicate_of, skipped_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (s3_source, key) DO UPDATE
+             SET etag = excluded.etag, duplicate_of = excluded.duplicate_of,
+                 skipped_at = excluded.skipped_at",
+            params![source, key, etag, duplicate_of, unix_now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn forget_skipped_object(&self, source: &str, key: &str) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM s3_skipped_objects WHERE s3_source = ?1 AND key = ?2",
+            params![source, key],
+        )?;
+        Ok(())
+    }
+
+    /// Objects skipped in `source` whose book is still in the library. Once
+    /// it goes missing, its copies are processed again and one takes over.
+    pub fn skipped_objects(&self, source: &str) -> Result<Vec<SkippedObject>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT s.key, s.etag, s.duplicate_of
+             FROM s3_skipped_objects s JOIN books b ON b.id = s.duplicate_of
+             WHERE s.s3_source = ?1 AND b.missing_at IS NULL",
+        )?;
+        let rows = stmt
+            .query_map(params![source], |row| {
+                Ok(SkippedObject {
+                    key: row.get(0)?,
+                    etag: row.get(1)?,
+                    duplicate_of: row.get(2)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Keys in `source` skipped as copies of the book `id`.
+    pub fn skipped_copies_of(&self, source: &str, id: &str) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT key FROM s3_skipped_objects WHERE s3_source = ?1 AND duplicate_of = ?2",
+        )?;
+        let keys = stmt
+            .query_map(params![source, id], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(keys)
+    }
+
+    /// Assign books recorded before sources had names to `source`: those in
+    /// `bucket` under one of `prefixes` (any key when there are none).
+    /// Returns how many were claimed.
+    pub fn claim_s3_books(&self, source: &str, bucket: &str, prefixes: &[String]) -> Result<usize> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "UPDATE books SET s3_source = ?1
+             WHERE s3_source IS NULL AND s3_bucket = ?2
+               AND substr(CASE WHEN source = 's3' THEN file_path ELSE s3_key END,
+                          1, length(?3)) = ?3",
+        )?;
+        let mut claimed = 0;
+        if prefixes.is_empty() {
+            claimed += stmt.execute(params![source, bucket, ""])?;
+        }
+        for prefix in prefixes {
+            claimed += stmt.execute(params![source, bucket, prefix])?;
+        }
+        Ok(claimed)
+    }
+
+    /// Local books still in the library, for `sync`.
+    pub fn sync_rows(&self) -> Result<Vec<SyncRow>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, title, file_path, file_hash, s3_source, s3_bucket, s3_key, s3_etag,
+                    synced_hash
+             FROM books WHERE source = 'local' AND missing_at IS NULL",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(SyncRow {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    file_path: row.get(2)?,
+                    file_hash: row.get(3)?,
+                    s3_source: row.get(4)?,
+                    s3_bucket: row.get(5)?,
+                    s3_key: row.get(6)?,
+                    s3_etag: row.get(7)?,
+                    synced_hash: row.get(8)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Record the bucket copy of a local book. `synced_hash` is `None` while
+    /// an upload is in flight, so an interrupted one is retried.
+    pub fn set_s3_copy(
+        &self,
+        id: &str,
+        source: &str,
+        bucket: &str,
+        key: &str,
+        etag: Option<&str>,
+        synced_hash: Option<&str>,
+    ) -> Result<()> {
+        let conn = self.conn();
+        conn.execute(
+            "UPDATE books SET s3_source = ?1, s3_bucket = ?2, s3_key = ?3, s3_etag = ?4,
+                              synced_hash = ?5
+             WHERE id = ?6 AND source = 'local'",
+            params![source, bucket, key, etag, synced_hash, id],
+        )?;
+        Ok(())
+    }
+
+    /// Turn an S3 book into a local one after its object was downloaded to
+    /// `file_path`; the object stays recorded as the book's bucket copy.
+    pub fn adopt_local_copy(&self, id: &str, file_path: &str, file_hash: &str) -> Result<usize> {
+        let conn = self.conn();
+        let changes = conn.execute(
+            "UPDATE books SET source = 'local', s3_key = file_path, file_path = ?1,
+                              file_hash = ?2, synced_hash = ?2, updated_at = ?3
+             WHERE id = ?4 AND source = 's3'",
+            params![file_path, file_hash, unix_now(), id],
+        )?;
+        Ok(changes)
+    }
+
+    /// The schema is created by [`Database::open`]; kept so tests can be
+    /// explicit about needing it.
+    pub fn create_test_schema(&self) {
+        let conn = self.conn();
+        migrations::run(&conn).expect("Failed to create test schema");
+    }
+
+    pub fn get_library_version(&self) -> Result<Option<i64>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT value FROM settings WHERE key = 'library_version' LIMIT 1")?;
+        let result = stmt
+            .query_row([], |row| {
+                let val: String = row.get(0)?;
+                Ok(val.parse::<i64>().unwrap_or(0))
+            })
+            .optional()?;
+        Ok(result)
+    }
+
+    /// Bump `library_version`; see [`bump_library_version`].
+    pub fn increment_library_version(&self) -> Result<()> {
+        bump_library_version(&self.conn())
+    }
+
+    /// Append to the `library_events` change feed and bump `library_version`.
+    /// Returns the event's sequence number.
+    ///
+    /// Both happen in one transaction, so a reader that sees the new version
+    /// also sees the event, and watchers sharing this handle (or the file)
+    /// never interleave their bumps.
+    pub fn record_event(&self, kind: EventKind, book_id: &str, fields: &[&str]) -> Result<i64> {
+        let fields = serde_json::to_string(fields)?;
+        let conn = self.conn();
+        let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO library_events (type, book_id, fields, created_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![kind.as_str(), book_id, fields, unix_now()],
+        )?;
+        let seq = tx.last_insert_rowid();
+        bump_library_version(&tx)?;
+        tx.commit()?;
+        Ok(seq)
+    }
+
+    /// Events with a sequence number greater than `since`, oldest first.
+    pub fn events_since(&self, since: i64, limit: u32) -> Result<Vec<LibraryEvent>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT seq, type, book_id, fields, created_at FROM library_events
+             WHERE seq > ?1 ORDER BY seq LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![since, limit], |row| {
+            let fields: String = row.get(3)?;
+            Ok(LibraryEvent {
+                seq: row.get(0)?,
+                kind: row.get(1)?,
+                book_id: row.get(2)?,
+                fields: serde_json::from_str(&fields).unwrap_or_default(),
+                created_at: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Highest sequence number ever assigned (0 if no event was recorded).
+    pub fn latest_event_seq(&self) -> Result<i64> {
+        let conn = self.conn();
+        let seq = conn
+            .query_row(
+                "SELECT seq FROM sqlite_sequence WHERE name = 'library_events'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(seq.unwrap_or(0))
+    }
+
+    /// Highest sequence number removed by compaction. Clients that last saw
+    /// an older sequence have missed events and must resync in full.
+    pub fn compacted_event_seq(&self) -> Result<i64> {
+        let conn = self.conn();
+        let seq = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = 'library_events_compacted_seq'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        Ok(seq.and_then(|value| value.parse().ok()).unwrap_or(0))
+    }
+
+    /// Drop events older than `max_age_secs`. Returns how many were removed.
+    pub fn compact_events(&self, max_age_secs: i64) -> Result<usize> {
+        let conn = self.conn();
+        let now = unix_now();
+        let cutoff = now - max_age_secs;
+        let Some(through): Option<i64> = conn.query_row(
+            "SELECT MAX(seq) FROM library_events WHERE created_at < ?1",
+            params![cutoff],
+            |row| row.get(0),
+        )?
+        else {
+            return Ok(0);
+        };
+
+        let removed = conn.execute(
+            "DELETE FROM library_events WHERE seq <= ?1",
+            params![through],
+        )?;
+        conn.execute(
+            "INSERT INTO settings (key, value, updated_at)
+             VALUES ('library_events_compacted_seq', ?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+            params![through.to_string(), now],
+        )?;
+        Ok(removed)
+    }
+}
+
+fn book_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<BookRow> {
+    Ok(BookRow {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        file_path: row.get(2)?,
+        file_hash: row.get(3)?,
+        file_type: row.get(4)?,
+        cover_path: row.get(5)?,
+        cover_source: row.get(6)?,
+        source: row.get(7)?,
+        missing_at: row.get(8)?,
+    })
+}
+
+fn s3_book_row(row: &rusqlite::Row) -> rusqlite::Result<S3BookRow> {
+    Ok(S3BookRow {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        file_path: row.get(2)?,
+        file_type: row.get(3)?,
+        cover_path: row.get(4)?,
+        s3_etag: row.get(5)?,
+        synced: row.get(6)?,
+    })
+}
+
+/// The value is the current unix second, but always moves forward so two
+/// changes within one second stay distinct.
+fn bump_library_version(conn: &Connection) -> Result<()> {
+    let now = unix_now();
+    conn.execute(
+        "INSERT INTO settings (key, value, updated_at)
+         VALUES ('library_version', ?1, ?2)
+         ON CONFLICT (key) DO UPDATE SET
+             value = CAST(MAX(?2, CAST(value AS INTEGER) + 1) AS TEXT),
+             updated_at = excluded.updated_at",
+        params![now.to_string(), now],
+    )?;
+    Ok(())
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock before epoch")
+        .as_secs() as i64
+}
+
+trait OptionalRow {
+    fn optional(self) -> rusqlite::Result<Option<Self::Inner>>
+    where
+        Self: Sized;
+    type Inner;
+}
+
+impl<T> OptionalRow for rusqlite::Result<T> {
+    type Inner = T;
+    fn optional(self) -> rusqlite::Result<Option<T>> {
+        match self {
+            Ok(v) => Ok(Some(v)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde_json::{Value as JsonValue, json};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use super::bridge::{self, Mode, SqlRequest, TransactionRequest};
+
+/// A request line: a caller-chosen id plus the same body the one-shot
+/// commands read from stdin, with the action given as `mode`. `read_only`
+/// requests always run on a read-only connection, like `db --read-only`.
+#[derive(Deserialize)]
+struct ServeRequest {
+    #[serde(default)]
+    id: JsonValue,
+    #[serde(default)]
+    read_only: bool,
+    #[serde(flatten)]
+    call: Call,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "mode", rename_all = "kebab-case")]
+enum Call {
+    QueryAll(SqlRequest),
+    QueryOne(SqlRequest),
+    Execute(SqlRequest),
+    Transaction(TransactionRequest),
+}
+
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+struct Job {
+    line: String,
+    output: Output,
+    order: Arc<StreamOrder>,
+    seq: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    /// Not classified yet; treated as a write.
+    Unknown,
+    Read,
+    Write,
+}
+
+/// Request ordering within one stream. Reads run concurrently, but a request
+/// waits for every earlier write on its stream to finish, and a write for
+/// every earlier request, so a caller that pipelines a write and then a read
+/// sees its own write. Separate streams are not ordered against each other.
+#[derive(Default)]
+struct StreamOrder {
+    pending: Mutex<BTreeMap<u64, Access>>,
+    changed: Condvar,
+}
+
+impl StreamOrder {
+    fn queue(&self, seq: u64) {
+        self.lock().insert(seq, Access::Unknown);
+    }
+
+    /// Record how `seq` accesses the database and wait for its turn.
+    fn wait_turn(&self, seq: u64, access: Access) {
+        let mut pending = self.lock();
+        pending.insert(seq, access);
+        self.changed.notify_all();
+        while pending
+            .range(..seq)
+            .any(|(_, earlier)| access == Access::Write || *earlier != Access::Read)
+        {
+            pending = self
+                .changed
+                .wait(pending)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    fn finish(&self, seq: u64) {
+        self.lock().remove(&seq);
+        self.changed.notify_all();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, Access>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Shared state for one `db serve` process: a single writer connection and a
+/// queue drained by reader workers, each holding its own read-only connection.
+pub struct Server {
+    jobs: mpsc::Sender<Job>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Server {
+    /// Open the writer (running migrations) and `readers` read-only connections.
+    pub fn start(db_path: &str, readers: usize) -> Result<Self> {
+        let writer = Arc::new(Mutex::new(bridge::open_connection(db_path)?));
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        let mut workers = Vec::new();
+        for _ in 0..readers.max(1) {
+            let reader = bridge::open_read_only_connection(db_path)?;
+            let writer = Arc::clone(&writer);
+            let queue = Arc::clone(&queue);
+
+            workers.push(thread::spawn(move || {
+                loop {
+                    let job = match queue.lock().expect("job queue poisoned").recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    let response = handle_job(&job, &reader, &writer);
+                    job.order.finish(job.seq);
+                    let mut output = job.output.lock().expect("output poisoned");
+                    let _ = writeln!(output, "{response}").and_then(|_| output.flush());
+                }
+            }));
+        }
+
+        Ok(Self { jobs, workers })
+    }
+
+    /// Serve NDJSON requests from `input`, writing responses to `output`.
+    /// Responses arrive in completion order, but each request observes every
+    /// write sent before it on the same stream (see [`StreamOrder`]).
+    /// Returns once `input` is exhausted; responses may still be in flight.
+    pub fn serve<R: BufRead>(&self, input: R, output: Box<dyn Write + Send>) -> Result<()> {
+        let output: Output = Arc::new(Mutex::new(output));
+        let order = Arc::new(StreamOrder::default());
+
+        for (seq, line) in (0u64..).zip(input.lines()) {
+            let line = line.context("Failed to read request line")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            order.queue(seq);
+            self.jobs
+                .send(Job {
+                    line,
+                    output: Arc::clone(&output),
+                    order: Arc::clone(&order),
+                    seq,
+                })
+                .context("DB workers have stopped")?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop accepting jobs and wait for queued ones to finish.
+    pub fn shutdown(self) {
+        drop(self.jobs);
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+
+    /// Accept connections on a Unix socket; each one is an independent
+    /// NDJSON stream sharing this server's connections. Runs until killed.
+    #[cfg(unix)]
+    pub fn listen(self, socket_path: &std::path::Path) -> Result<()> {
+        use std::io::BufReader;
+        use std::os::unix::net::UnixListener;
+
+        if socket_path.exists() {
+            std::fs::remove_file(socket_path).with_context(|| {
+                format!("Failed to remove stale socket {}", socket_path.display())
+            })?;
+        }
+        let listener = UnixListener::bind(socket_path)
+            .with_context(|| format!("Failed to bind {}", socket_path.display()))?;
+        let server = Arc::new(self);
+
+        for stream in listener.incoming() {
+            let stream = stream.context("Failed to accept DB client")?;
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                let Ok(write_half) = stream.try_clone() else {
+                    return;
+                };
+                let _ = server.serve(BufReader::new(stream), Box::new(write_half));
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Run a single request line once its turn comes and build its response.
+/// Responses echo the request `id` and carry either the one-shot output keys
+/// or `error`.
+fn handle_job(job: &Job, reader: &Connection, writer: &Mutex<Connection>) -> JsonValue {
+    let request: ServeRequest = match serde_json::from_str(&job.line) {
+        Ok(request) => request,
+        Err(e) => {
+            let id = serde_json::from_str::<JsonValue>(&job.line)
+                .ok()
+                .and_then(|value| value.get("id").cloned())
+                .unwrap_or(JsonValue::Null);
+            return json!({ "id": id, "error": format!("Invalid SQL request JSON: {e}") });
+        }
+    };
+
+    let access = classify(&request, reader);
+    job.order.wait_turn(job.seq, access);
+    match dispatch(&request, access, reader, writer) {
+        Ok(JsonValue::Object(mut output)) => {
+            output.insert("id".to_string(), request.id);
+            JsonValue::Object(output)
+        }
+        Ok(other) => json!({ "id": request.id, "result": other }),
+        Err(e) => json!({ "id": request.id, "error": format!("{e:#}") }),
+    }
+}
+
+/// Reads run on the worker's read-only connection; anything SQLite reports as
+/// writing (including `INSERT ... RETURNING` sent as a query) goes through
+/// the single writer, as do transactions. SQL that fails to prepare counts as
+/// a read, since it fails the same way on either connection.
+fn classify(request: &ServeRequest, reader: &Connection) -> Access {
+    if request.read_only {
+        return Access::Read;
+    }
+    match &request.call {
+        Call::QueryAll(sql_request) | Call::QueryOne(sql_request) => {
+            match bridge::is_read_only(reader, &sql_request.sql) {
+                Ok(false) => Access::Write,
+                Ok(true) | Err(_) => Access::Read,
+            }
+        }
+        Call::Execute(_) | Call::Transaction(_) => Access::Write,
+    }
+}
+
+fn dispatch(
+    request: &ServeRequest,
+    access: Access,
+    reader: &Connection,
+    writer: &Mutex<Connection>,
+) -> Result<JsonValue> {
+    let (mode, sql_request) = match &request.call {
+        Call::QueryAll(sql_request) => (Mode::QueryAll, sql_request),
+        Call::QueryOne(sql_request) => (Mode::QueryOne, sql_request),
+        Call::Execute(sql_request) => (Mode::Execute, sql_request),
+        Call::Transaction(transaction) => {
+            if access == Access::Read {
+                return bridge::run_transaction(reader, transaction);
+            }
+            let writer = writer.lock().expect("writer connection poisoned");
+            return bridge::run_transaction(&writer, transaction);
+        }
+    };
+
+    if access == Access::Read {
+        bridge::run(reader, mode, sql_request)
+    } else {
+        let writer = writer.lock().expect("writer connection poisoned");
+        bridge::run(&writer, mode, sql_request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Server;
+    use serde_json::Value as JsonValue;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn serve(db_path: &str, input: &str) -> Vec<JsonValue> {
+        let server = Server::start(db_path, 2).expect("server should start");
+        let buffer = SharedBuffer::default();
+        server
+            .serve(input.as_bytes(), Box::new(buffer.clone()))
+            .expect("serve should succeed");
+        server.shutdown();
+
+        let bytes = buffer.0.lock().unwrap().clone();
+        let mut responses: Vec<JsonValue> = String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        responses.sort_by_key(|response| response["id"].as_i64());
+        responses
+    }
+
+    #[test]
+    fn serve_answers_each_request_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("library.db");
+        let db_path = db_path.to_str().unwrap();
+
+        let responses = serve(
+            db_path,
+            r#"{"id": 1, "mode": "execute", "sql": "INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, 0)", "params": ["theme", "dark"]}"#,
+        );
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["changes"], 1);
+
+        let responses = serve(
+            db_path,
+            concat!(
+                r#"{"id": 2, "mode": "query-one", "sql": "SELECT value FROM settings WHERE key = ?1", "params": ["theme"]}"#,
+                "\n",
+                r#"{"id": 3, "mode": "query-all", "sql": "SELECT key FROM settings"}"#,
+                "\n",
+                r#"{"id": 4, "mode": "query-all", "sql": "SELECT * FROM missing_table"}"#,
+                "\n",
+                r#"{"id": 5, "mode": "query-all", "sql": "UPDATE settings SET value = 'light' RETURNING value"}"#,
+                "\n",
+                r#"{"id": 6, "mode": "transaction", "statements": [{"mode": "execute", "sql": "DELETE FROM settings"}, {"mode": "query-one", "sql": "SELECT COUNT(*) AS n FROM settings"}]}"#,
+                "\n",
+                "not json\n",
+            ),
+        );
+
+        assert_eq!(responses.len(), 6);
+        assert!(responses[0]["id"].is_null());
+        assert!(responses[0]["error"].is_string());
+        assert_eq!(responses[1]["row"]["value"], "dark");
+        assert_eq!(responses[2]["rows"].as_array().unwrap().len(), 1);
+        assert!(
+            responses[3]["error"]
+                .as_str()
+                .unwrap()
+                .contains("missing_table")
+        );
+        assert_eq!(responses[4]["rows"][0]["value"], "light");
+        assert_eq!(responses[5]["results"][1]["row"]["n"], 0);
+    }
+
+    #[test]
+    fn serve_reads_see_earlier_writes_on_the_same_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("library.db");
+
+        let mut input = String::new();
+        for n in 0..50 {
+            input.push_str(&format!(
+                r#"{{"id": {}, "mode": "execute", "sql": "INSERT INTO settings (key, value, updated_at) VALUES ('k{n}', 'v', 0)"}}"#,
+                n * 2
+            ));
+            input.push('\n');
+            input.push_str(&format!(
+                r#"{{"id": {}, "mode": "query-one", "sql": "SELECT value FROM settings WHERE key = 'k{n}'"}}"#,
+                n * 2 + 1
+            ));
+            input.push('\n');
+        }
+
+        let responses = serve(db_path.to_str().unwrap(), &input);
+        assert_eq!(responses.len(), 100);
+        for response in responses.iter().skip(1).step_by(2) {
+            assert_eq!(response["row"]["value"], "v", "{response}");
+        }
+    }
+
+    #[test]
+    fn read_only_requests_never_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("library.db");
+
+        let responses = serve(
+            db_path.to_str().unwrap(),
+            concat!(
+                r#"{"id": 1, "read_only": true, "mode": "query-all", "sql": "INSERT INTO settings (key, value, updated_at) VALUES ('a', 'b', 0) RETURNING key"}"#,
+                "\n",
+                r#"{"id": 2, "mode": "query-one", "sql": "SELECT COUNT(*) AS n FROM settings"}"#,
+                "\n",
+            ),
+        );
+        assert!(
+            responses[0]["error"]
+                .as_str()
+                .unwrap()
+                .contains("readonly database")
+        );
+        assert_eq!(responses[1]["row"]["n"], 0);
+    }
+}
@@ -0,0 +1,273 @@
+pub mod opds;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::db::{CatalogBook, CollectionRow, Database, ProgressRow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Jsonl,
+    Csv,
+    Opds,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "jsonl" | "json" => Ok(ExportFormat::Jsonl),
+            "csv" => Ok(ExportFormat::Csv),
+            "opds" => Ok(ExportFormat::Opds),
+            other => Err(format!("Unsupported export format: {other}")),
+        }
+    }
+}
+
+pub struct ExportOptions<'a> {
+    pub formats: &'a [ExportFormat],
+    pub include_progress: bool,
+    pub include_collections: bool,
+    /// Roots that OPDS links are made relative to.
+    pub links: opds::LinkRoots<'a>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSummary {
+    pub books: usize,
+    pub progress: usize,
+    pub collections: usize,
+    pub files: Vec<PathBuf>,
+}
+
+/// Write the catalog to `out_dir` in each requested format:
+///   - jsonl: `books.jsonl`, one book per line, with `progress` and
+///     `collections` arrays when requested
+///   - csv: `books.csv`, plus `progress.csv` and `collections.csv` (one row
+///     per collection entry) when requested
+///   - opds: a static OPDS 1.2 feed tree under `opds/` (see [`opds::write`])
+pub fn run(db: &Database, out_dir: &Path, options: &ExportOptions) -> Result<ExportSummary> {
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let books = db.catalog_books()?;
+    let progress = if options.include_progress {
+        db.reading_progress()?
+    } else {
+        Vec::new()
+    };
+    let wants_opds = options.formats.contains(&ExportFormat::Opds);
+    let collections = if options.include_collections || wants_opds {
+        db.collections()?
+    } else {
+        Vec::new()
+    };
+
+    let mut files = Vec::new();
+    if options.formats.contains(&ExportFormat::Jsonl) {
+        let path = out_dir.join("books.jsonl");
+        write_jsonl(&path, &books, &progress, &collections, options)?;
+        files.push(path);
+    }
+    if options.formats.contains(&ExportFormat::Csv) {
+        let path = out_dir.join("books.csv");
+        write_csv(&path, &books)?;
+        files.push(path);
+        if options.include_progress {
+            let path = out_dir.join("progress.csv");
+            write_csv(&path, &progress)?;
+            files.push(path);
+        }
+        if options.include_collections {
+            let path = out_dir.join("collections.csv");
+            write_collections_csv(&path, &collections)?;
+            files.push(path);
+        }
+    }
+    if wants_opds {
+        files.extend(opds::write(
+            &out_dir.join("opds"),
+            &books,
+            &collections,
+            &options.links,
+        )?);
+    }
+
+    Ok(ExportSummary {
+        books: books.len(),
+        progress: progress.len(),
+        collections: if options.include_collections {
+            collections.len()
+        } else {
+            0
+        },
+        files,
+    })
+}
+
+fn write_jsonl(
+    path: &Path,
+    books: &[CatalogBook],
+    progress: &[ProgressRow],
+    collections: &[CollectionRow],
+    options: &ExportOptions,
+) -> Result<()> {
+    let mut progress_by_book: HashMap<&str, Vec<&ProgressRow>> = HashMap::new();
+    for row in progress {
+        progress_by_book
+            .entry(row.book_id.as_str())
+            .or_default()
+            .push(row);
+    }
+    let mut collections_by_book: HashMap<&str, Vec<serde_json::Value>> = HashMap::new();
+    for collection in collections {
+        for book_id in &collection.book_ids {
+            collections_by_book
+                .entry(book_id.as_str())
+                .or_default()
+                .push(json!({ "id": collection.id, "name": collection.name }));
+        }
+    }
+
+    let mut out = BufWriter::new(create(path)?);
+    for book in books {
+        let mut value = serde_json::to_value(book)?;
+        if options.include_progress {
+            value["progress"] = json!(progress_by_book.get(book.id.as_str()));
+        }
+        if options.include_collections {
+            value["collections"] = json!(collections_by_book.get(book.id.as_str()));
+        }
+        // Books without rows still get an empty array rather than null.
+        for key in ["progress", "collections"] {
+            if value.get(key).is_some_and(|v| v.is_null()) {
+                value[key] = json!([]);
+            }
+        }
+        serde_json::to_writer(&mut out, &value)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv<T: Serialize>(path: &Path, rows: &[T]) -> Result<()> {
+    let mut out = csv::Writer::from_writer(create(path)?);
+    for row in rows {
+        out.serialize(row)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_collections_csv(path: &Path, collections: &[CollectionRow]) -> Result<()> {
+    let mut out = csv::Writer::from_writer(create(path)?);
+    out.write_record([
+        "collection_id",
+        "collection_name",
+        "user_id",
+        "user_name",
+        "book_id",
+    ])?;
+    for collection in collections {
+        for book_id in &collection.book_ids {
+            out.write_record([
+                &collection.id,
+                &collection.name,
+                &collection.user_id,
+                &collection.user_name,
+                book_id,
+            ])?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<File> {
+    File::create(path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportFormat, ExportOptions, opds, run};
+    use crate::db::{COVER_SOURCE_GENERATED, Database, NewBook};
+    use std::fs;
+
+    fn insert(db: &Database, id: &str, title: &str, author: &str) {
+        db.insert_book(&NewBook {
+            id,
+            title,
+            author: Some(author),
+            description: None,
+            file_type: "epub",
+            file_path: &format!("/library/{title}.epub"),
+            file_size: 1,
+            file_hash: id,
+            cover_path: Some(&format!("/covers/{id}.jpg")),
+            cover_source: COVER_SOURCE_GENERATED,
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "local",
+            s3_source: None,
+            s3_bucket: None,
+            s3_etag: None,
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn export_writes_every_requested_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open_in_memory().unwrap();
+        insert(&db, "b1", "Dune", "Frank Herbert");
+        insert(&db, "b2", "Dune Messiah", "Frank Herbert");
+        db.set_series("b1", Some("Dune"), Some(1.0)).unwrap();
+        db.set_series("b2", Some("Dune"), Some(2.0)).unwrap();
+
+        let summary = run(
+            &db,
+            dir.path(),
+            &ExportOptions {
+                formats: &[ExportFormat::Jsonl, ExportFormat::Csv, ExportFormat::Opds],
+                include_progress: true,
+                include_collections: true,
+                links: opds::LinkRoots {
+                    library_root: Some("/library".as_ref()),
+                    covers_dir: Some("/covers".as_ref()),
+                },
+            },
+        )
+        .unwrap();
+        assert_eq!(summary.books, 2);
+
+        let jsonl = fs::read_to_string(dir.path().join("books.jsonl")).unwrap();
+        let first: serde_json::Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+        assert_eq!(first["title"], "Dune");
+        assert_eq!(first["series_index"], 1.0);
+        assert_eq!(first["progress"], serde_json::json!([]));
+
+        let csv = fs::read_to_string(dir.path().join("books.csv")).unwrap();
+        assert!(csv.starts_with("id,title,author,"));
+        assert_eq!(csv.lines().count(), 3);
+
+        let series = fs::read_dir(dir.path().join("opds/series"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let feed = fs::read_to_string(series).unwrap();
+        assert!(feed.find("Dune Messiah").unwrap() > feed.find("<title>Dune</title>").unwrap());
+        assert!(feed.contains(r#"href="../../library/Dune%20Messiah.epub""#));
+        assert!(feed.contains(r#"href="../../covers/b2.jpg""#));
+    }
+}
@@ -0,0 +1,385 @@
+use anyhow::{Context, Result};
+use quick_xml::escape::escape;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::{CatalogBook, CollectionRow};
+
+const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// Where book files and covers live when links are written. Book links become
+/// `library/<path under library_root>` (S3 books use their key) and cover links
+/// `covers/<file name>`, both relative to the directory holding `opds/`.
+pub struct LinkRoots<'a> {
+    pub library_root: Option<&'a Path>,
+    pub covers_dir: Option<&'a Path>,
+}
+
+/// A feed file and the books or sub-feeds it lists.
+struct Feed<'a> {
+    /// Path below the OPDS root, e.g. `authors/frank-herbert-1a2b3c4d.xml`.
+    path: String,
+    title: String,
+    books: Vec<&'a CatalogBook>,
+}
+
+/// Write a static OPDS 1.2 catalog to `dir`:
+///
+/// ```text
+/// index.xml                  navigation: all books, by author, by series, by collection
+/// all.xml                    acquisition feed of every book
+/// authors.xml, authors/*.xml one acquisition feed per author
+/// series.xml, series/*.xml   one per series, in reading order
+/// collections.xml, collections/*.xml
+/// ```
+///
+/// Feed timestamps come from the books, so re-exporting an unchanged library
+/// produces identical files.
+pub fn write(
+    dir: &Path,
+    books: &[CatalogBook],
+    collections: &[CollectionRow],
+    links: &LinkRoots,
+) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+
+    let mut by_author: BTreeMap<String, Vec<&CatalogBook>> = BTreeMap::new();
+    let mut by_series: BTreeMap<String, Vec<&CatalogBook>> = BTreeMap::new();
+    for book in books {
+        let author = book.author.as_deref().unwrap_or("Unknown author");
+        by_author.entry(author.to_string()).or_default().push(book);
+        if let Some(series) = &book.series {
+            by_series.entry(series.clone()).or_default().push(book);
+        }
+    }
+    for series in by_series.values_mut() {
+        series.sort_by(|a, b| {
+            a.series_index
+                .unwrap_or(f64::MAX)
+                .total_cmp(&b.series_index.unwrap_or(f64::MAX))
+        });
+    }
+
+    let by_id: HashMap<&str, &CatalogBook> = books.iter().map(|b| (b.id.as_str(), b)).collect();
+    let collection_feeds: Vec<Feed> = collections
+        .iter()
+        .map(|collection| Feed {
+            path: format!("collections/{}.xml", collection.id),
+            title: collection.name.clone(),
+            books: collection
+                .book_ids
+                .iter()
+                .filter_map(|id| by_id.get(id.as_str()).copied())
+                .collect(),
+        })
+        .collect();
+    let author_feeds = group_feeds("authors", by_author);
+    let series_feeds = group_feeds("series", by_series);
+
+    let all = Feed {
+        path: "all.xml".to_string(),
+        title: "All books".to_string(),
+        books: books.iter().collect(),
+    };
+    let sections = [
+        ("authors.xml", "By author", &author_feeds),
+        ("series.xml", "By series", &series_feeds),
+        ("collections.xml", "By collection", &collection_feeds),
+    ];
+
+    // Root navigation feed.
+    let mut entries = vec![navigation_entry(
+        &all.path,
+        &all.title,
+        all.books.len(),
+        updated(&all.books),
+    )];
+    for (path, title, feeds) in &sections {
+        let books: Vec<&CatalogBook> = feeds.iter().flat_map(|f| f.books.clone()).collect();
+        entries.push(navigation_entry(path, title, feeds.len(), updated(&books)));
+    }
+    written.push(write_feed(
+        dir,
+        "index.xml",
+        "Library",
+        NAVIGATION_TYPE,
+        updated(&all.books),
+        &entries.concat(),
+    )?);
+
+    written.push(write_acquisition_feed(dir, &all, links)?);
+    for (path, title, feeds) in &sections {
+        let entries: Vec<String> = feeds
+            .iter()
+            .map(|feed| {
+                navigation_entry(
+                    &feed.path,
+                    &feed.title,
+                    feed.books.len(),
+                    updated(&feed.books),
+                )
+            })
+            .collect();
+        let books: Vec<&CatalogBook> = feeds.iter().flat_map(|f| f.books.clone()).collect();
+        written.push(write_feed(
+            dir,
+            path,
+            title,
+            NAVIGATION_TYPE,
+            updated(&books),
+            &entries.concat(),
+        )?);
+        for feed in feeds.iter() {
+            written.push(write_acquisition_feed(dir, feed, links)?);
+        }
+    }
+
+    Ok(written)
+}
+
+fn group_feeds<'a>(group: &str, entries: BTreeMap<String, Vec<&'a CatalogBook>>) -> Vec<Feed<'a>> {
+    entries
+        .into_iter()
+        .map(|(name, books)| Feed {
+            path: format!("{group}/{}.xml", slug(&name)),
+            title: name,
+            books,
+        })
+        .collect()
+}
+
+fn write_acquisition_feed(dir: &Path, feed: &Feed, links: &LinkRoots) -> Result<PathBuf> {
+    let up = up_to_export_root(&feed.path);
+    let entries: String = feed
+        .books
+        .iter()
+        .map(|book| acquisition_entry(book, &up, links))
+        .collect();
+    write_feed(
+        dir,
+        &feed.path,
+        &feed.title,
+        ACQUISITION_TYPE,
+        updated(&feed.books),
+        &entries,
+    )
+}
+
+fn write_feed(
+    dir: &Path,
+    path: &str,
+    title: &str,
+    kind: &str,
+    updated: i64,
+    entries: &str,
+) -> Result<PathBuf> {
+    let depth = path.matches('/').count();
+    let to_root = "../".repeat(depth);
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" \
+         xmlns:dc=\"http://purl.org/dc/terms/\" \
+         xmlns:opds=\"http://opds-spec.org/2010/catalog\">\n",
+    );
+    let _ = writeln!(xml, "  <id>urn:watcher-rs:opds:{}</id>", escape(path));
+    let _ = writeln!(xml, "  <title>{}</title>", escape(title));
+    let _ = writeln!(xml, "  <updated>{}</updated>", timestamp(updated));
+    let _ = writeln!(
+        xml,
+        "  <link rel=\"self\" href=\"{}\" type=\"{kind}\"/>",
+        href(path.rsplit('/').next().unwrap_or(path))
+    );
+    let _ = writeln!(
+        xml,
+        "  <link rel=\"start\" href=\"{to_root}index.xml\" type=\"{NAVIGATION_TYPE}\"/>"
+    );
+    if path != "index.xml" {
+        let up = if depth == 0 {
+            "index.xml".to_string()
+        } else {
+            format!("../{}.xml", path.split('/').next().unwrap_or_default())
+        };
+        let _ = writeln!(
+            xml,
+            "  <link rel=\"up\" href=\"{up}\" type=\"{NAVIGATION_TYPE}\"/>"
+        );
+    }
+    xml.push_str(entries);
+    xml.push_str("</feed>\n");
+
+    let file = dir.join(path);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&file, xml).with_context(|| format!("Failed to write {}", file.display()))?;
+    Ok(file)
+}
+
+fn navigation_entry(path: &str, title: &str, count: usize, updated: i64) -> String {
+    // Navigation entries only appear in feeds at the OPDS root, so `path`
+    // is already the right relative link.
+    let mut xml = String::new();
+    xml.push_str("  <entry>\n");
+    let _ = writeln!(xml, "    <title>{}</title>", escape(title));
+    let _ = writeln!(xml, "    <id>urn:watcher-rs:opds:{}</id>", escape(path));
+    let _ = writeln!(xml, "    <updated>{}</updated>", timestamp(updated));
+    let _ = writeln!(xml, "    <content type=\"text\">{count} entries</content>");
+    let kind = if path.contains('/') || path == "all.xml" {
+        ACQUISITION_TYPE
+    } else {
+        NAVIGATION_TYPE
+    };
+    let _ = writeln!(
+        xml,
+        "    <link rel=\"subsection\" href=\"{}\" type=\"{kind}\"/>",
+        href(path)
+    );
+    xml.push_str("  </entry>\n");
+    xml
+}
+
+fn acquisition_entry(book: &CatalogBook, up: &str, links: &LinkRoots) -> String {
+    let mut xml = String::new();
+    xml.push_str("  <entry>\n");
+    let _ = writeln!(xml, "    <title>{}</title>", escape(&book.title));
+    let _ = writeln!(xml, "    <id>urn:uuid:{}</id>", escape(&book.id));
+    let _ = writeln!(xml, "    <updated>{}</updated>", timestamp(book.updated_at));
+    if let Some(author) = &book.author {
+        let _ = writeln!(xml, "    <author><name>{}</name></author>", escape(author));
+    }
+    let _ = writeln!(
+        xml,
+        "    <dc:issued>{}</dc:issued>",
+        timestamp(book.added_at)
+    );
+    if let Some(series) = &book.series {
+        let label = match book.series_index {
+            Some(index) => format!("{series} #{index}"),
+            None => series.clone(),
+        };
+        let _ = writeln!(
+            xml,
+            "    <category scheme=\"series\" term=\"{}\" label=\"{}\"/>",
+            escape(series),
+            escape(&label)
+        );
+    }
+    if let Some(description) = &book.description {
+        let _ = writeln!(
+            xml,
+            "    <summary type=\"text\">{}</summary>",
+            escape(description)
+        );
+    }
+
+    let mime = if book.file_type == "pdf" {
+        "application/pdf"
+    } else {
+        "application/epub+zip"
+    };
+    let _ = writeln!(
+        xml,
+        "    <link rel=\"http://opds-spec.org/acquisition\" href=\"{up}library/{}\" type=\"{mime}\"/>",
+        href(&book_link(book, links.library_root))
+    );
+    if let Some(cover) = &book.cover_path {
+        let cover = cover_link(Path::new(cover), links.covers_dir);
+        let mime = if cover.ends_with(".png") {
+            "image/png"
+        } else {
+            "image/jpeg"
+        };
+        for rel in [
+            "http://opds-spec.org/image",
+            "http://opds-spec.org/image/thumbnail",
+        ] {
+            let _ = writeln!(
+                xml,
+                "    <link rel=\"{rel}\" href=\"{up}covers/{}\" type=\"{mime}\"/>",
+                href(&cover)
+            );
+        }
+    }
+    xml.push_str("  </entry>\n");
+    xml
+}
+
+/// Path of a book file below the exported `library/` directory.
+fn book_link(book: &CatalogBook, library_root: Option<&Path>) -> String {
+    if book.source != "local" {
+        return book.file_path.trim_start_matches('/').to_string();
+    }
+    let path = Path::new(&book.file_path);
+    match library_root.and_then(|root| path.strip_prefix(root).ok()) {
+        Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+        None => file_name(path),
+    }
+}
+
+fn cover_link(cover: &Path, covers_dir: Option<&Path>) -> String {
+    match covers_dir.and_then(|dir| cover.strip_prefix(dir).ok()) {
+        Some(relative) => relative.to_string_lossy().replace('\\', "/"),
+        None => file_name(cover),
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// `../` steps from a feed file to the directory that holds `opds/`.
+fn up_to_export_root(feed_path: &str) -> String {
+    "../".repeat(feed_path.matches('/').count() + 1)
+}
+
+/// Readable, collision-free file name for an author or series.
+fn slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug: String = slug.trim_matches('-').chars().take(40).collect();
+    let hash = format!("{:x}", Sha256::digest(name.as_bytes()));
+    if slug.is_empty() {
+        hash[..8].to_string()
+    } else {
+        format!("{}-{}", slug.trim_end_matches('-'), &hash[..8])
+    }
+}
+
+/// Percent-encode a relative URL path, keeping `/` separators.
+fn href(path: &str) -> String {
+    let mut out = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => {
+                let _ = write!(out, "%{byte:02X}");
+            }
+        }
+    }
+    out
+}
+
+fn updated(books: &[&CatalogBook]) -> i64 {
+    books.iter().map(|b| b.updated_at).max().unwrap_or(0)
+}
+
+fn timestamp(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
@@ -19,6 +19,8 @@ pub fn extract_epub_metadata(file_path: &Path) -> BookMetadata {
             description: None,
             page_count: None,
             cover_path: None,
+            series: None,
+            series_index: None,
         },
     }
 }
@@ -32,10 +34,30 @@ pub fn extract_epub_metadata_from_bytes(bytes: &[u8], fallback_title: &str) -> B
             description: None,
             page_count: None,
             cover_path: None,
+            series: None,
+            series_index: None,
         },
     }
 }
 
+pub fn extract_epub_metadata_from_reader<R: Read + std::io::Seek>(
+    reader: R,
+    fallback_title: &str,
+) -> BookMetadata {
+    zip::ZipArchive::new(reader)
+        .map_err(anyhow::Error::from)
+        .and_then(|mut archive| try_extract_from_archive(&mut archive, fallback_title))
+        .unwrap_or_else(|_| BookMetadata {
+            title: fallback_title.to_string(),
+            author: None,
+            description: None,
+            page_count: None,
+            cover_path: None,
+            series: None,
+            series_index: None,
+        })
+}
+
 fn try_extract(file_path: &Path, fallback_title: &str) -> anyhow::Result<BookMetadata> {
     let file = std::fs::File::open(file_path)?;
     let mut archive = zip::ZipArchive::new(file)?;
@@ -53,18 +75,21 @@ fn try_extract_from_archive<R: Read + std::io::Seek>(
     fallback_title: &str,
 ) -> anyhow::Result<BookMetadata> {
     let opf_path = parse_container_xml(archive)?;
-    let (title, author, description) = parse_opf(archive, &opf_path)?;
+    let opf = parse_opf(archive, &opf_path)?;
 
-    let title = title
+    let title = opf
+        .title
         .filter(|t| !t.trim().is_empty())
         .unwrap_or_else(|| fallback_title.to_string());
 
     Ok(BookMetadata {
         title,
-        author,
-        description,
+        author: opf.author,
+        description: opf.description,
         page_count: None,
         cover_path: None,
+        series: opf.series,
+        series_index: opf.series_index,
     })
 }
 
@@ -100,10 +125,20 @@ fn parse_container_xml<R: Read + std::io::Seek>(
     anyhow::bail!("No rootfile found in container.xml")
 }
 
+/// Metadata read from the OPF package document.
+#[derive(Default)]
+struct OpfMetadata {
+    title: Option<String>,
+    author: Option<String>,
+    description: Option<String>,
+    series: Option<String>,
+    series_index: Option<f64>,
+}
+
 fn parse_opf<R: Read + std::io::Seek>(
     archive: &mut zip::ZipArchive<R>,
     opf_path: &str,
-) -> anyhow::Result<(Option<String>, Option<String>, Option<String>)> {
+) -> anyhow::Result<OpfMetadata> {
     let mut opf_file = archive.by_name(opf_path)?;
     let mut xml = String::new();
     opf_file.read_to_string(&mut xml)?;
@@ -111,21 +146,36 @@ fn parse_opf<R: Read + std::io::Seek>(
     let mut reader = Reader::from_str(&xml);
     let mut buf = Vec::new();
 
-    let mut title: Option<String> = None;
-    let mut author: Option<String> = None;
-    let mut description: Option<String> = None;
-
+    let mut opf = OpfMetadata::default();
     let mut current_element: Option<&'static str> = None;
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 let local = e.local_name();
-                match local.as_ref() {
-                    b"title" => current_element = Some("title"),
-                    b"creator" => current_element = Some("creator"),
-                    b"description" => current_element = Some("description"),
-                    _ => current_element = None,
+                current_element = match local.as_ref() {
+                    b"title" => Some("title"),
+                    b"creator" => Some("creator"),
+                    b"description" => Some("description"),
+                    // EPUB 3: <meta property="belongs-to-collection">Series</meta>
+                    // refined by <meta property="group-position">2</meta>.
+                    b"meta" => match attribute(e, b"property").as_deref() {
+                        Some("belongs-to-collection") => Some("series"),
+                        Some("group-position") => Some("series_index"),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+            }
+            Ok(Event::Empty(ref e)) if e.local_name().as_ref() == b"meta" => {
+                // Calibre: <meta name="calibre:series" content="Series"/>
+                let content = attribute(e, b"content").filter(|c| !c.trim().is_empty());
+                match attribute(e, b"name").as_deref() {
+                    Some("calibre:series") if opf.series.is_none() => opf.series = content,
+                    Some("calibre:series_index") if opf.series_index.is_none() => {
+                        opf.series_index = content.and_then(|c| c.trim().parse().ok());
+                    }
+                    _ => {}
                 }
             }
             Ok(Event::Text(ref e)) => {
@@ -133,9 +183,15 @@ fn parse_opf<R: Read + std::io::Seek>(
                     let text = e.unescape().unwrap_or_default().trim().to_string();
                     if !text.is_empty() {
                         match el {
-                            "title" if title.is_none() => title = Some(text),
-                            "creator" if author.is_none() => author = Some(text),
-                            "description" if description.is_none() => description = Some(text),
+                            "title" if opf.title.is_none() => opf.title = Some(text),
+                            "creator" if opf.author.is_none() => opf.author = Some(text),
+                            "description" if opf.description.is_none() => {
+                                opf.description = Some(text)
+                            }
+                            "series" if opf.series.is_none() => opf.series = Some(text),
+                            "series_index" if opf.series_index.is_none() => {
+                                opf.series_index = text.parse().ok()
+                            }
                             _ => {}
                         }
                     }
@@ -151,5 +207,13 @@ fn parse_opf<R: Read + std::io::Seek>(
         buf.clear();
     }
 
-    Ok((title, author, description))
+    Ok(opf)
+}
+
+fn attribute(element: &quick_xml::events::BytesStart<'_>, name: &[u8]) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.local_name().as_ref() == name)
+        .map(|attr| String::from_utf8_lossy(&attr.value).to_string())
 }
@@ -7,4 +7,7 @@ pub struct BookMetadata {
     pub description: Option<String>,
     pub page_count: Option<u32>,
     pub cover_path: Option<String>,
+    pub series: Option<String>,
+    /// Position within `series`; fractional for novellas between volumes.
+    pub series_index: Option<f64>,
 }
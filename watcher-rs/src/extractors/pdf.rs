@@ -1,4 +1,5 @@
 use super::BookMetadata;
+use crate::covers::renderer::{PdfRenderer, PdfSource, ReadSeek, RenderUnavailable};
 use std::path::Path;
 
 pub fn extract_pdf_metadata(file_path: &Path) -> BookMetadata {
@@ -16,6 +17,8 @@ pub fn extract_pdf_metadata(file_path: &Path) -> BookMetadata {
             description: None,
             page_count: None,
             cover_path: None,
+            series: None,
+            series_index: None,
         },
     }
 }
@@ -29,10 +32,47 @@ pub fn extract_pdf_metadata_from_bytes(bytes: &[u8], fallback_title: &str) -> Bo
             description: None,
             page_count: None,
             cover_path: None,
+            series: None,
+            series_index: None,
         },
     }
 }
 
+/// Read the Info dictionary and page count through pdfium, which loads only
+/// the trailer, xref and the objects it is asked for. Used for remote files
+/// where reading all of it (as lopdf does) would mean downloading all of it.
+///
+/// A document pdfium can't open gets the fallback metadata; a failed read or
+/// an unavailable renderer is an error, since trying again may work.
+pub fn extract_pdf_metadata_from_reader(
+    reader: Box<dyn ReadSeek>,
+    fallback_title: &str,
+) -> anyhow::Result<BookMetadata> {
+    Ok(
+        match PdfRenderer::shared().info(PdfSource::Reader(reader)) {
+            Ok(info) => BookMetadata {
+                title: info.title.unwrap_or_else(|| fallback_title.to_string()),
+                author: info.author,
+                description: None,
+                page_count: (info.pages > 0).then_some(info.pages),
+                cover_path: None,
+                series: None,
+                series_index: None,
+            },
+            Err(e) if e.is::<RenderUnavailable>() => return Err(e),
+            Err(_) => BookMetadata {
+                title: fallback_title.to_string(),
+                author: None,
+                description: None,
+                page_count: None,
+                cover_path: None,
+                series: None,
+                series_index: None,
+            },
+        },
+    )
+}
+
 fn try_extract(file_path: &Path, fallback_title: &str) -> anyhow::Result<BookMetadata> {
     let doc = lopdf::Document::load(file_path)?;
     extract_from_doc(&doc, fallback_title)
@@ -53,6 +93,8 @@ fn extract_from_doc(doc: &lopdf::Document, fallback_title: &str) -> anyhow::Resu
         description: None,
         page_count: if pages > 0 { Some(pages) } else { None },
         cover_path: None,
+        series: None,
+        series_index: None,
     })
 }
 
@@ -73,14 +115,14 @@ fn extract_info_dict(doc: &lopdf::Document, fallback_title: &str) -> (String, Op
     let title = dict
         .get(b"Title")
         .ok()
-        .and_then(|obj| pdf_object_to_string(obj))
+        .and_then(pdf_object_to_string)
         .filter(|s| !s.is_empty())
         .unwrap_or_else(|| fallback_title.to_string());
 
     let author = dict
         .get(b"Author")
         .ok()
-        .and_then(|obj| pdf_object_to_string(obj))
+        .and_then(pdf_object_to_string)
         .filter(|s| !s.is_empty());
 
     (title, author)
@@ -1,7 +1,11 @@
 use crate::covers::{default_covers_dir, generate_epub_cover, generate_pdf_cover};
-use crate::db::{Database, NewBook, unix_now};
+use crate::db::{
+    COVER_SOURCE_GENERATED, COVER_SOURCE_USER, Database, EventKind, NewBook, unix_now,
+};
 use crate::extractors::epub::extract_epub_metadata;
 use crate::extractors::pdf::extract_pdf_metadata;
+use crate::handlers::change::handle_change_with_covers_dir;
+use crate::handlers::cover::import_sidecar_cover;
 use crate::log::log;
 use anyhow::Result;
 use sha2::{Digest, Sha256};
@@ -43,13 +47,49 @@ pub fn handle_add_with_covers_dir(
     }
 
     let file_hash = compute_sha256(file_path)?;
+    let file_path_str = file_path.to_string_lossy();
+
+    if let Some(existing) = db.find_book_by_hash(&file_hash)? {
+        // Same content whose old file is gone: the book was moved, not copied.
+        if !Path::new(&existing.file_path).exists() {
+            db.move_book(&existing.id, &file_path_str)?;
+            log(&format!(
+                "[MOVE] \"{}\" -> {}",
+                existing.title,
+                file_path.display()
+            ));
+            db.record_event(EventKind::Moved, &existing.id, &["file_path"])?;
+            return Ok(());
+        }
 
-    if let Some(existing_title) = db.find_by_hash(&file_hash)? {
         log(&format!(
             "[SKIP] Duplicate (matches \"{}\"): {}",
-            existing_title,
+            existing.title,
+            file_path.display()
+        ));
+        return Ok(());
+    }
+
+    if let Some(missing) = db.find_missing("local", &file_hash, &file_path_str)? {
+        // The book came back within the retention window; keep its user data.
+        db.restore_book(&missing.id, "local", &file_path_str)?;
+        log(&format!(
+            "[RESTORE] \"{}\" -> {}",
+            missing.title,
             file_path.display()
         ));
+        let mut fields = Vec::new();
+        if missing.file_path != file_path_str {
+            fields.push("file_path");
+        }
+        if missing.source != "local" {
+            fields.push("source");
+        }
+        db.record_event(EventKind::Restored, &missing.id, &fields)?;
+
+        if missing.file_hash != file_hash {
+            return handle_change_with_covers_dir(db, file_path, covers_dir);
+        }
         return Ok(());
     }
 
@@ -60,7 +100,15 @@ pub fn handle_add_with_covers_dir(
     } else {
         extract_epub_metadata(file_path)
     };
-    let cover_path = if file_type == "pdf" {
+    let sidecar_cover = import_sidecar_cover(file_path, &book_id, covers_dir);
+    let cover_source = if sidecar_cover.is_some() {
+        COVER_SOURCE_USER
+    } else {
+        COVER_SOURCE_GENERATED
+    };
+    let cover_path = if sidecar_cover.is_some() {
+        sidecar_cover
+    } else if file_type == "pdf" {
         generate_pdf_cover(
             file_path,
             &book_id,
@@ -80,7 +128,6 @@ pub fn handle_add_with_covers_dir(
     let cover_path_str = cover_path.as_ref().and_then(|p| p.to_str());
 
     let now = unix_now();
-    let file_path_str = file_path.to_string_lossy();
 
     let changes = db.insert_book(&NewBook {
         id: &book_id,
@@ -92,10 +139,12 @@ pub fn handle_add_with_covers_dir(
         file_size: meta.len() as i64,
         file_hash: &file_hash,
         cover_path: cover_path_str,
+        cover_source,
         page_count: metadata.page_count.map(|p| p as i64),
         added_at: now,
         updated_at: now,
         source: "local",
+        s3_source: None,
         s3_bucket: None,
         s3_etag: None,
     })?;
@@ -109,6 +158,7 @@ pub fn handle_add_with_covers_dir(
         "[OK] Added \"{}\" ({})",
         metadata.title, file_type
     ));
-    db.increment_library_version()?;
+    db.set_series(&book_id, metadata.series.as_deref(), metadata.series_index)?;
+    db.record_event(EventKind::Added, &book_id, &[])?;
     Ok(())
 }
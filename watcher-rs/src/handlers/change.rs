@@ -1,11 +1,14 @@
 use crate::covers::{default_covers_dir, generate_epub_cover, generate_pdf_cover};
-use crate::db::{Database, UpdateBook, unix_now};
+use crate::db::{
+    COVER_SOURCE_GENERATED, COVER_SOURCE_USER, Database, EventKind, UpdateBook, unix_now,
+};
 use crate::extractors::epub::extract_epub_metadata;
 use crate::extractors::pdf::extract_pdf_metadata;
 use crate::handlers::add::{compute_sha256, handle_add_with_covers_dir};
+use crate::handlers::cover::import_sidecar_cover;
 use crate::log::log;
 use anyhow::Result;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub fn handle_change(db: &Database, file_path: &Path) -> Result<()> {
     let covers_dir = default_covers_dir();
@@ -41,6 +44,23 @@ pub fn handle_change_with_covers_dir(
 
     let new_hash = compute_sha256(file_path)?;
     if new_hash == book.file_hash {
+        // A sidecar cover may have appeared while the watcher was not running.
+        if !book.has_user_cover()
+            && let Some(cover_path) = import_sidecar_cover(file_path, &book.id, covers_dir)
+        {
+            db.set_user_cover(&book.id, &cover_path.to_string_lossy())?;
+            log(&format!(
+                "[COVER] Applied sidecar cover to \"{}\"",
+                book.title
+            ));
+            db.record_event(
+                EventKind::Updated,
+                &book.id,
+                &["cover_path", "cover_source"],
+            )?;
+            return Ok(());
+        }
+
         log(&format!("[SKIP] Hash unchanged for \"{}\"", book.title));
         return Ok(());
     }
@@ -50,7 +70,18 @@ pub fn handle_change_with_covers_dir(
     } else {
         extract_epub_metadata(file_path)
     };
-    let cover_path = if book.file_type == "pdf" {
+    let sidecar_cover = import_sidecar_cover(file_path, &book.id, covers_dir);
+    let cover_source = if sidecar_cover.is_some() || book.has_user_cover() {
+        COVER_SOURCE_USER
+    } else {
+        COVER_SOURCE_GENERATED
+    };
+    let cover_path = if sidecar_cover.is_some() {
+        sidecar_cover
+    } else if book.has_user_cover() {
+        // Pinned covers survive content changes.
+        book.cover_path.as_ref().map(PathBuf::from)
+    } else if book.file_type == "pdf" {
         generate_pdf_cover(
             file_path,
             &book.id,
@@ -82,7 +113,7 @@ pub fn handle_change_with_covers_dir(
 
     let now = unix_now();
 
-    db.update_book(
+    let mut changed = db.update_book(
         &book.id,
         &UpdateBook {
             title: &metadata.title,
@@ -91,16 +122,20 @@ pub fn handle_change_with_covers_dir(
             file_size: meta.len() as i64,
             file_hash: &new_hash,
             cover_path: cover_path_str,
+            cover_source,
             page_count: metadata.page_count.map(|p| p as i64),
             updated_at: now,
             s3_etag: None,
         },
     )?;
+    if db.set_series(&book.id, metadata.series.as_deref(), metadata.series_index)? {
+        changed.push("series");
+    }
 
     log(&format!(
         "[UPDATE] \"{}\" -> \"{}\" ({})",
         book.title, metadata.title, book.file_type
     ));
-    db.increment_library_version()?;
+    db.record_event(EventKind::Updated, &book.id, &changed)?;
     Ok(())
 }
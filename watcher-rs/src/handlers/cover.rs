@@ -0,0 +1,90 @@
+use crate::covers::{find_sidecar_cover, import_cover_image};
+use crate::db::{Database, EventKind};
+use crate::log::log;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Pin `image_path` as the cover for `book_id` (used by `cover set`).
+pub fn set_user_cover(
+    db: &Database,
+    book_id: &str,
+    image_path: &Path,
+    covers_dir: &Path,
+) -> Result<PathBuf> {
+    let book = db
+        .find_by_id(book_id)?
+        .with_context(|| format!("No book with id {book_id}"))?;
+
+    let cover_path = import_cover_image(image_path, &book.id, covers_dir)?;
+    db.set_user_cover(&book.id, &cover_path.to_string_lossy())?;
+
+    log(&format!(
+        "[COVER] Pinned user cover for \"{}\" from {}",
+        book.title,
+        image_path.display()
+    ));
+    db.record_event(
+        EventKind::Updated,
+        &book.id,
+        &["cover_path", "cover_source"],
+    )?;
+    Ok(cover_path)
+}
+
+/// Import the sidecar cover next to `book_path`, if there is one.
+/// Failures are logged and treated as "no sidecar" so ingestion carries on.
+pub fn import_sidecar_cover(book_path: &Path, book_id: &str, covers_dir: &Path) -> Option<PathBuf> {
+    let sidecar = find_sidecar_cover(book_path)?;
+    match import_cover_image(&sidecar, book_id, covers_dir) {
+        Ok(cover_path) => Some(cover_path),
+        Err(e) => {
+            log(&format!(
+                "[WARN] Ignoring sidecar cover {}: {}",
+                sidecar.display(),
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// A sidecar image was created or modified: re-pin it on every tracked book
+/// in the same directory that resolves to it.
+pub fn handle_sidecar_cover(db: &Database, image_path: &Path, covers_dir: &Path) -> Result<()> {
+    let Some(dir) = image_path.parent() else {
+        return Ok(());
+    };
+
+    for entry in std::fs::read_dir(dir)? {
+        let book_path = entry?.path();
+        let is_book = book_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| {
+                let lower = e.to_lowercase();
+                lower == "pdf" || lower == "epub"
+            })
+            .unwrap_or(false);
+        if !is_book || find_sidecar_cover(&book_path).as_deref() != Some(image_path) {
+            continue;
+        }
+
+        let Some(book) = db.find_by_path(&book_path.to_string_lossy())? else {
+            continue;
+        };
+
+        let cover_path = import_cover_image(image_path, &book.id, covers_dir)?;
+        db.set_user_cover(&book.id, &cover_path.to_string_lossy())?;
+        log(&format!(
+            "[COVER] Applied sidecar cover to \"{}\"",
+            book.title
+        ));
+        db.record_event(
+            EventKind::Updated,
+            &book.id,
+            &["cover_path", "cover_source"],
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::db::Database;
+use crate::db::{Database, EventKind};
 use crate::log::log;
 use anyhow::Result;
 use std::path::Path;
@@ -17,13 +17,10 @@ pub fn handle_delete(db: &Database, file_path: &Path) -> Result<()> {
         }
     };
 
-    if let Some(ref cover_path) = book.cover_path {
-        let _ = std::fs::remove_file(cover_path);
-    }
+    // The cover stays with the row until the book is purged.
+    db.mark_missing(&book.id)?;
 
-    db.delete_book(&book.id)?;
-
-    log(&format!("[DELETE] Removed \"{}\" from library", book.title));
-    db.increment_library_version()?;
+    log(&format!("[DELETE] Marked \"{}\" as missing", book.title));
+    db.record_event(EventKind::Deleted, &book.id, &[])?;
     Ok(())
 }
@@ -1,5 +1,6 @@
 pub mod add;
 pub mod change;
+pub mod cover;
 pub mod delete;
 pub mod orphan_cleanup;
 
@@ -7,5 +8,6 @@ pub use add::handle_add;
 pub use add::handle_add_with_covers_dir;
 pub use change::handle_change;
 pub use change::handle_change_with_covers_dir;
+pub use cover::{handle_sidecar_cover, set_user_cover};
 pub use delete::handle_delete;
 pub use orphan_cleanup::remove_orphaned_books;
@@ -1,8 +1,13 @@
+pub mod backup;
+pub mod combined;
 pub mod covers;
 pub mod db;
+pub mod export;
 pub mod extractors;
 pub mod handlers;
 pub mod log;
+pub mod pages;
 pub mod s3;
 pub mod tunnel;
+pub mod verify;
 pub mod watcher;
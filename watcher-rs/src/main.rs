@@ -1,16 +1,28 @@
 use anyhow::{Context, Result};
 use clap::{Args, Parser, Subcommand};
-use rusqlite::{
-    Connection, Row, params_from_iter,
-    types::{Value as SqlValue, ValueRef},
-};
-use serde::Deserialize;
-use serde_json::{Map as JsonMap, Value as JsonValue, json};
+use serde_json::json;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use watcher_rs::backup::{self, RestoreOptions};
 use watcher_rs::db::Database;
-use watcher_rs::s3::S3Config;
+use watcher_rs::db::bridge::{self, Mode, SqlRequest, TransactionRequest};
+use watcher_rs::db::maintain::MaintainOptions;
+use watcher_rs::db::serve::Server;
+use watcher_rs::export::{self, ExportFormat, ExportOptions, opds};
+use watcher_rs::pages::{PageFormat, PageRequest};
+use watcher_rs::s3::client::PresignOptions;
+use watcher_rs::s3::covers::CoverStoreConfig;
+use watcher_rs::s3::credentials::S3Credentials;
+use watcher_rs::s3::presign;
+use watcher_rs::s3::stream::StreamRequest;
+use watcher_rs::s3::sync::{self, SyncOptions};
+use watcher_rs::s3::webhook::WebhookConfig;
+use watcher_rs::s3::{S3Config, source_for, sources};
+use watcher_rs::verify::{self, VerifyOptions};
+use watcher_rs::watcher::{Housekeeping, WatchMode};
 
 #[derive(Parser)]
 #[command(
@@ -30,7 +42,17 @@ struct Cli {
     #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
     covers_path: String,
 
-    // S3 configuration (optional — if S3_BUCKET is set, S3 mode is used instead of local)
+    /// Which watchers to run: local, s3 or combined (both in one process,
+    /// sharing the database). Defaults to s3 when S3_BUCKET or S3_SOURCES is
+    /// set, local otherwise.
+    #[arg(long, env = "WATCH_MODE")]
+    watch_mode: Option<WatchMode>,
+
+    // S3 configuration (optional — if S3_BUCKET or S3_SOURCES is set and
+    // WATCH_MODE isn't, S3 mode is used instead of local)
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
     #[arg(long, env = "S3_ENDPOINT")]
     s3_endpoint: Option<String>,
 
@@ -40,17 +62,47 @@ struct Cli {
     #[arg(long, env = "S3_BUCKET")]
     s3_bucket: Option<String>,
 
-    #[arg(long, env = "S3_ACCESS_KEY_ID")]
-    s3_access_key: Option<String>,
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
 
-    #[arg(long, env = "S3_SECRET_ACCESS_KEY")]
-    s3_secret_key: Option<String>,
+    #[command(flatten)]
+    s3_keys: S3KeyArgs,
 
-    #[arg(long, env = "S3_PREFIX")]
-    s3_prefix: Option<String>,
+    #[command(flatten)]
+    s3_covers: S3CoverArgs,
 
     #[arg(long, env = "S3_POLL_INTERVAL", default_value = "60")]
     s3_poll_interval: u64,
+
+    /// Objects read and processed in parallel during an S3 scan.
+    #[arg(long, env = "S3_CONCURRENCY", default_value = "8")]
+    s3_concurrency: usize,
+
+    /// Seconds between full S3 listing diffs (which also catch deletions).
+    /// Polls in between still list everything but only process objects
+    /// modified since the last scan. 0 diffs the full listing every poll.
+    /// With the webhook, sources are only listed at startup and this often.
+    #[arg(long, env = "S3_RECONCILE_INTERVAL", default_value = "3600")]
+    s3_reconcile_interval: u64,
+
+    /// Listen here for S3 event notifications (e.g. 0.0.0.0:9090) and apply
+    /// them immediately; polling then stops between reconciles.
+    #[arg(long, env = "S3_WEBHOOK_ADDR")]
+    s3_webhook_addr: Option<std::net::SocketAddr>,
+
+    /// Token the notification sender must pass in the Authorization header.
+    #[arg(long, env = "S3_WEBHOOK_TOKEN")]
+    s3_webhook_token: Option<String>,
+
+    /// Days a book whose file disappeared is kept (with its reading progress
+    /// and collection entries) in case the file comes back.
+    #[arg(long, env = "TRASH_RETENTION_DAYS", default_value = "7")]
+    trash_retention_days: u64,
+
+    /// Hours between database maintenance runs (WAL checkpoint, optimize,
+    /// incremental vacuum). 0 disables it.
+    #[arg(long, env = "DB_MAINTENANCE_INTERVAL_HOURS", default_value = "24")]
+    db_maintenance_interval_hours: u64,
 }
 
 #[derive(Subcommand)]
@@ -58,8 +110,29 @@ enum Command {
     Db(DbCommand),
     /// Stream an S3 object to stdout (used by the Next.js file-serving route).
     S3Stream(S3StreamCommand),
+    /// Print presigned GET URLs for an S3 object or for a book and its cover.
+    S3Presign(S3PresignCommand),
+    /// Upload local books to the bucket and optionally download bucket-only ones.
+    Sync(SyncCommand),
     /// Run the reverse tunnel client to expose the local server publicly.
     Tunnel(TunnelCommand),
+    /// Manage book covers.
+    Cover(CoverCommand),
+    /// Render a PDF page to an image on stdout (server-side fallback for the reader).
+    RenderPage(RenderPageCommand),
+    /// Print library change events recorded after a sequence number.
+    Changes(ChangesCommand),
+    /// Write a consistent snapshot of the database and covers to a .tar.zst archive.
+    Backup(BackupCommand),
+    /// Restore a database and covers from an archive written by `backup`.
+    Restore(RestoreCommand),
+    /// Check the database against files, covers and the bucket; print a JSON report.
+    Verify(VerifyCommand),
+    /// Export the catalog as JSON Lines, CSV and/or a static OPDS feed.
+    Export(ExportCommand),
+    /// Run PDF jobs for the parent process over stdin/stdout.
+    #[command(hide = true)]
+    PdfiumWorker,
 }
 
 #[derive(Args)]
@@ -67,6 +140,10 @@ struct DbCommand {
     #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
     db_path: String,
 
+    /// Open the database with SQLITE_OPEN_READ_ONLY (query-all/query-one only).
+    #[arg(long, global = true)]
+    read_only: bool,
+
     #[command(subcommand)]
     action: DbAction,
 }
@@ -76,18 +153,89 @@ enum DbAction {
     QueryAll,
     QueryOne,
     Execute,
+    /// Run `{"statements": [{"mode", "sql", "params"}, ...]}` atomically.
+    Transaction,
+    /// Checkpoint the WAL, refresh statistics and vacuum; print what was reclaimed.
+    Maintain {
+        /// Run a full ANALYZE instead of PRAGMA optimize.
+        #[arg(long)]
+        analyze: bool,
+
+        /// Rewrite the whole database with VACUUM.
+        #[arg(long)]
+        full_vacuum: bool,
+
+        /// Run even if a watcher reports a scan in progress.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Keep the database open and answer NDJSON requests
+    /// (`{"id", "mode", "sql", "params"}` per line) until stdin closes.
+    Serve {
+        /// Number of read-only connections serving queries concurrently.
+        #[arg(long, default_value = "4")]
+        readers: usize,
+
+        /// Listen on a Unix socket instead of stdin/stdout.
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct CoverCommand {
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
+    covers_path: String,
+
+    #[command(subcommand)]
+    action: CoverAction,
+}
+
+#[derive(Subcommand)]
+enum CoverAction {
+    /// Pin an image as a book's cover so re-ingest leaves it alone.
+    Set {
+        /// ID of the book to update.
+        book_id: String,
+        /// Image file (JPEG, PNG, GIF or SVG).
+        image: PathBuf,
+    },
 }
 
 #[derive(Args)]
 struct S3StreamCommand {
-    /// S3 object key to stream.
+    /// S3 object key to stream, or an `s3://bucket/key` URI (as recorded
+    /// for covers kept in a bucket).
     #[arg(long)]
     key: String,
 
-    /// Optional HTTP Range header value (e.g. "bytes=0-1023").
+    /// Optional HTTP Range header value (e.g. "bytes=0-1023", "bytes=-1024").
     #[arg(long)]
     range: Option<String>,
 
+    /// Only write the header line (for HEAD requests).
+    #[arg(long)]
+    head: bool,
+
+    /// If-None-Match header value; a match is answered with status 304.
+    #[arg(long)]
+    if_none_match: Option<String>,
+
+    /// If-Modified-Since header value; an unmodified object is answered
+    /// with status 304.
+    #[arg(long)]
+    if_modified_since: Option<String>,
+
+    /// Named source (from S3_SOURCES) the key belongs to.
+    #[arg(long)]
+    source: Option<String>,
+
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
     // S3 credentials (inherited from env vars by default)
     #[arg(long, env = "S3_ENDPOINT")]
     s3_endpoint: Option<String>,
@@ -96,13 +244,351 @@ struct S3StreamCommand {
     s3_region: String,
 
     #[arg(long, env = "S3_BUCKET")]
-    s3_bucket: String,
+    s3_bucket: Option<String>,
+
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
+}
+
+#[derive(Args)]
+#[command(group(clap::ArgGroup::new("target").required(true).args(["key", "book_id"])))]
+struct S3PresignCommand {
+    /// S3 object key to presign, or an `s3://bucket/key` URI.
+    #[arg(long)]
+    key: Option<String>,
+
+    /// Presign this S3 book's file, and its cover when that is in a bucket.
+    #[arg(long)]
+    book_id: Option<String>,
+
+    /// Seconds the URLs stay valid (at most 604800).
+    #[arg(long, default_value = "300")]
+    expires_in: u32,
+
+    /// Content-Disposition the bucket should answer with
+    /// (e.g. 'attachment; filename="book.pdf"').
+    #[arg(long)]
+    content_disposition: Option<String>,
+
+    /// Content-Type the bucket should answer with.
+    #[arg(long)]
+    content_type: Option<String>,
+
+    /// Named source (from S3_SOURCES) the --key belongs to.
+    #[arg(long)]
+    source: Option<String>,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
+    #[arg(long, env = "S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    #[arg(long, env = "S3_REGION", default_value = "auto")]
+    s3_region: String,
+
+    #[arg(long, env = "S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
+}
+
+#[derive(Args)]
+struct SyncCommand {
+    /// Also download books that are only in the bucket, and bucket-side
+    /// changes to books synced before.
+    #[arg(long, env = "SYNC_DOWNLOAD")]
+    download: bool,
+
+    /// Keep running and sync every --interval seconds.
+    #[arg(long)]
+    watch: bool,
+
+    /// Seconds between syncs with --watch.
+    #[arg(long, env = "SYNC_INTERVAL", default_value = "300")]
+    interval: u64,
+
+    #[arg(long, env = "LIBRARY_PATH", default_value = "./data/library")]
+    library_path: String,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    /// Named source (from S3_SOURCES) to sync with; required when there
+    /// are several.
+    #[arg(long)]
+    source: Option<String>,
+
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
+    #[arg(long, env = "S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    #[arg(long, env = "S3_REGION", default_value = "auto")]
+    s3_region: String,
+
+    #[arg(long, env = "S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
+
+    /// Keys mirror library paths under the first prefix.
+    #[command(flatten)]
+    s3_keys: S3KeyArgs,
+}
 
+/// A JSON file listing named S3 sources, used instead of the single
+/// S3_BUCKET. Options left out of an entry fall back to the S3_* ones.
+#[derive(Args)]
+struct S3SourcesArg {
+    #[arg(long, env = "S3_SOURCES")]
+    s3_sources: Option<PathBuf>,
+}
+
+/// Which keys of the bucket are books.
+#[derive(Args)]
+struct S3KeyArgs {
+    /// Only keys under these prefixes (comma-separated).
+    #[arg(long, env = "S3_PREFIX", value_delimiter = ',')]
+    s3_prefix: Vec<String>,
+
+    /// Leave out keys matching these patterns (comma-separated; `*` matches
+    /// any run of characters, `?` one).
+    #[arg(long, env = "S3_EXCLUDE", value_delimiter = ',')]
+    s3_exclude: Vec<String>,
+}
+
+/// S3 credentials. Without keys, the AWS chain is used: `AWS_*` environment
+/// variables, `~/.aws/credentials`, web identity, container and instance
+/// metadata.
+#[derive(Args)]
+struct S3CredentialArgs {
     #[arg(long, env = "S3_ACCESS_KEY_ID")]
-    s3_access_key: String,
+    s3_access_key: Option<String>,
 
     #[arg(long, env = "S3_SECRET_ACCESS_KEY")]
-    s3_secret_key: String,
+    s3_secret_key: Option<String>,
+
+    /// Session token for temporary keys.
+    #[arg(long, env = "S3_SESSION_TOKEN")]
+    s3_session_token: Option<String>,
+
+    /// Profile in ~/.aws/credentials.
+    #[arg(long, env = "S3_PROFILE")]
+    s3_profile: Option<String>,
+}
+
+/// Where generated covers of S3 books go. Without either option they are
+/// kept in COVERS_PATH.
+#[derive(Args)]
+struct S3CoverArgs {
+    /// Upload generated covers under this prefix (e.g. "covers/").
+    #[arg(long, env = "S3_COVERS_PREFIX")]
+    s3_covers_prefix: Option<String>,
+
+    /// Bucket for uploaded covers; defaults to S3_BUCKET.
+    #[arg(long, env = "S3_COVERS_BUCKET")]
+    s3_covers_bucket: Option<String>,
+}
+
+impl S3CoverArgs {
+    fn to_config(&self) -> Option<CoverStoreConfig> {
+        if self.s3_covers_prefix.is_none() && self.s3_covers_bucket.is_none() {
+            return None;
+        }
+        Some(CoverStoreConfig {
+            bucket: self.s3_covers_bucket.clone(),
+            prefix: self.s3_covers_prefix.clone().unwrap_or_default(),
+        })
+    }
+}
+
+impl S3CredentialArgs {
+    fn into_credentials(self) -> S3Credentials {
+        S3Credentials {
+            access_key: self.s3_access_key,
+            secret_key: self.s3_secret_key,
+            session_token: self.s3_session_token,
+            profile: self.s3_profile,
+        }
+    }
+}
+
+#[derive(Args)]
+struct RenderPageCommand {
+    /// ID of the book to render.
+    #[arg(long)]
+    book_id: String,
+
+    /// Page number, starting at 1.
+    #[arg(long)]
+    page: u16,
+
+    /// Output width in pixels.
+    #[arg(long, default_value = "1200")]
+    width: u32,
+
+    /// Image format: jpeg or png.
+    #[arg(long, default_value = "jpeg")]
+    format: PageFormat,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    /// Directory rendered pages are cached in.
+    #[arg(long, env = "PAGE_CACHE_PATH", default_value = "./data/page-cache")]
+    cache_path: String,
+
+    /// Megabytes the page cache may use before the least recently served
+    /// pages are evicted. 0 disables eviction.
+    #[arg(long, env = "PAGE_CACHE_MAX_MB", default_value = "1024")]
+    cache_max_mb: u64,
+
+    // S3 credentials, needed only for books stored in S3
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
+    #[arg(long, env = "S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    #[arg(long, env = "S3_REGION", default_value = "auto")]
+    s3_region: String,
+
+    #[arg(long, env = "S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
+}
+
+#[derive(Args)]
+struct ChangesCommand {
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    /// Return events with a sequence number greater than this.
+    #[arg(long, default_value = "0")]
+    since: i64,
+
+    /// Maximum number of events to return.
+    #[arg(long, default_value = "1000")]
+    limit: u32,
+}
+
+#[derive(Args)]
+struct BackupCommand {
+    /// Archive to write (e.g. backup.tar.zst).
+    output: PathBuf,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: PathBuf,
+
+    #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
+    covers_path: PathBuf,
+
+    /// Recorded in the manifest so `restore --library-root` can re-link files.
+    #[arg(long, env = "LIBRARY_PATH", default_value = "./data/library")]
+    library_path: PathBuf,
+}
+
+#[derive(Args)]
+struct RestoreCommand {
+    /// Archive written by `backup`.
+    archive: PathBuf,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: PathBuf,
+
+    #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
+    covers_path: PathBuf,
+
+    /// Re-link local book files from the backed-up library path to this root.
+    #[arg(long)]
+    library_root: Option<PathBuf>,
+
+    /// Replace an existing database.
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct VerifyCommand {
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
+    covers_path: PathBuf,
+
+    /// Re-hash local files to find content that changed behind the watcher's back.
+    #[arg(long)]
+    rehash: bool,
+
+    /// Regenerate missing covers, delete orphan covers and re-process
+    /// books whose hash no longer matches.
+    #[arg(long)]
+    repair: bool,
+
+    /// Write the report here instead of stdout.
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    // S3 credentials, needed only to check S3 books
+    #[command(flatten)]
+    s3_sources: S3SourcesArg,
+
+    #[arg(long, env = "S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    #[arg(long, env = "S3_REGION", default_value = "auto")]
+    s3_region: String,
+
+    #[arg(long, env = "S3_BUCKET")]
+    s3_bucket: Option<String>,
+
+    #[command(flatten)]
+    s3_credentials: S3CredentialArgs,
+
+    #[command(flatten)]
+    s3_keys: S3KeyArgs,
+
+    #[command(flatten)]
+    s3_covers: S3CoverArgs,
+}
+
+#[derive(Args)]
+struct ExportCommand {
+    /// Directory to write the export files into.
+    #[arg(long)]
+    out: PathBuf,
+
+    #[arg(long, env = "DATABASE_PATH", default_value = "./data/library.db")]
+    db_path: String,
+
+    /// Comma-separated list of formats: jsonl, csv, opds.
+    #[arg(long, value_delimiter = ',', default_value = "jsonl,csv")]
+    format: Vec<ExportFormat>,
+
+    /// Include each user's reading progress.
+    #[arg(long)]
+    include_progress: bool,
+
+    /// Include collections and their books.
+    #[arg(long)]
+    include_collections: bool,
+
+    /// OPDS links to book files are made relative to this directory.
+    #[arg(long, env = "LIBRARY_PATH", default_value = "./data/library")]
+    library_path: PathBuf,
+
+    /// OPDS links to covers are made relative to this directory.
+    #[arg(long, env = "COVERS_PATH", default_value = "./data/covers")]
+    covers_path: PathBuf,
 }
 
 #[derive(Args)]
@@ -120,123 +606,330 @@ struct TunnelCommand {
     local_addr: String,
 }
 
-#[derive(Deserialize)]
-struct SqlRequest {
-    sql: String,
-    #[serde(default)]
-    params: Vec<JsonValue>,
-}
-
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
         Some(Command::Db(cmd)) => run_db_command(cmd),
         Some(Command::S3Stream(cmd)) => run_s3_stream(cmd),
+        Some(Command::S3Presign(cmd)) => run_s3_presign(cmd),
+        Some(Command::Sync(cmd)) => run_sync(cmd),
         Some(Command::Tunnel(cmd)) => run_tunnel(cmd),
+        Some(Command::Cover(cmd)) => run_cover_command(cmd),
+        Some(Command::RenderPage(cmd)) => run_render_page(cmd),
+        Some(Command::Changes(cmd)) => run_changes(cmd),
+        Some(Command::Backup(cmd)) => run_backup(cmd),
+        Some(Command::Restore(cmd)) => run_restore(cmd),
+        Some(Command::Verify(cmd)) => run_verify(cmd),
+        Some(Command::Export(cmd)) => run_export(cmd),
+        Some(Command::PdfiumWorker) => watcher_rs::covers::worker::serve(),
         None => {
-            // Auto-detect: if S3_BUCKET is set, run S3 watcher; otherwise local.
-            if cli.s3_bucket.is_some() {
-                run_s3_watcher(cli)
-            } else {
-                run_watcher(cli)
+            // Without WATCH_MODE, auto-detect: if S3_BUCKET or S3_SOURCES is
+            // set, run S3 watcher; otherwise local.
+            let mode = cli.watch_mode.unwrap_or(
+                if cli.s3_bucket.is_some() || cli.s3_sources.s3_sources.is_some() {
+                    WatchMode::S3
+                } else {
+                    WatchMode::Local
+                },
+            );
+            match mode {
+                WatchMode::Local => run_watcher(cli),
+                WatchMode::S3 => run_s3_watcher(cli),
+                WatchMode::Combined => run_combined_watcher(cli),
             }
         }
     }
 }
 
 fn run_watcher(args: Cli) -> Result<()> {
-    // Ensure library directory exists
-    std::fs::create_dir_all(&args.library_path)?;
-    std::fs::create_dir_all(&args.covers_path)?;
+    let library_path = library_dir(&args)?;
+    let covers_path = covers_dir(&args)?;
+    let db = Database::open(&args.db_path)?;
+    let shutdown = shutdown_signal()?;
 
-    // Resolve to absolute path
-    let library_path = std::fs::canonicalize(&args.library_path)?;
-    let covers_path = std::fs::canonicalize(&args.covers_path)?;
+    // Run the watcher (blocks until shutdown)
+    watcher_rs::watcher::run(
+        library_path,
+        covers_path,
+        &db,
+        Some(housekeeping(&args)),
+        shutdown,
+    )?;
 
-    // Open database
-    let db = Database::open(&args.db_path)?;
+    Ok(())
+}
 
-    // Set up shutdown signal
+fn housekeeping(args: &Cli) -> Housekeeping {
+    let maintenance_interval = (args.db_maintenance_interval_hours > 0)
+        .then(|| Duration::from_secs(args.db_maintenance_interval_hours * 60 * 60));
+    Housekeeping {
+        trash_retention_secs: (args.trash_retention_days as i64).saturating_mul(24 * 60 * 60),
+        maintenance_interval,
+    }
+}
+
+/// Create the library directory if needed and resolve it to an absolute path.
+fn library_dir(args: &Cli) -> Result<PathBuf> {
+    std::fs::create_dir_all(&args.library_path)?;
+    Ok(std::fs::canonicalize(&args.library_path)?)
+}
+
+fn covers_dir(args: &Cli) -> Result<PathBuf> {
+    std::fs::create_dir_all(&args.covers_path)?;
+    Ok(std::fs::canonicalize(&args.covers_path)?)
+}
+
+/// A flag set on Ctrl-C / SIGTERM.
+fn shutdown_signal() -> Result<Arc<AtomicBool>> {
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown_flag = Arc::clone(&shutdown);
     ctrlc::set_handler(move || {
         shutdown_flag.store(true, Ordering::Relaxed);
     })?;
-
-    // Run the watcher (blocks until shutdown)
-    watcher_rs::watcher::run(library_path, covers_path, db, shutdown)?;
-
-    Ok(())
+    Ok(shutdown)
 }
 
-fn run_s3_watcher(args: Cli) -> Result<()> {
-    let bucket = args
-        .s3_bucket
-        .as_ref()
-        .context("S3_BUCKET is required for S3 mode")?;
-    let access_key = args
-        .s3_access_key
-        .as_ref()
-        .context("S3_ACCESS_KEY_ID is required for S3 mode")?;
-    let secret_key = args
-        .s3_secret_key
-        .as_ref()
-        .context("S3_SECRET_ACCESS_KEY is required for S3 mode")?;
-
-    let config = S3Config {
+/// The S3 sources to watch, from `S3_SOURCES` or the single `S3_BUCKET`.
+fn watched_sources(args: &Cli) -> Result<Vec<S3Config>> {
+    let file = args.s3_sources.s3_sources.as_deref();
+    if file.is_none() && args.s3_bucket.is_none() {
+        anyhow::bail!("S3_BUCKET or S3_SOURCES is required to watch S3");
+    }
+    let template = S3Config {
+        name: String::new(),
         endpoint: args.s3_endpoint.clone(),
         region: args.s3_region.clone(),
-        bucket: bucket.clone(),
-        access_key: access_key.clone(),
-        secret_key: secret_key.clone(),
-        prefix: args.s3_prefix.clone(),
+        bucket: args.s3_bucket.clone().unwrap_or_default(),
+        credentials: S3Credentials {
+            access_key: args.s3_credentials.s3_access_key.clone(),
+            secret_key: args.s3_credentials.s3_secret_key.clone(),
+            session_token: args.s3_credentials.s3_session_token.clone(),
+            profile: args.s3_credentials.s3_profile.clone(),
+        },
+        prefixes: args.s3_keys.s3_prefix.clone(),
+        exclude: args.s3_keys.s3_exclude.clone(),
         poll_interval: args.s3_poll_interval,
+        concurrency: args.s3_concurrency,
+        reconcile_interval: args.s3_reconcile_interval,
+        covers: args.s3_covers.to_config(),
     };
+    sources::resolve(file, template)
+}
 
-    std::fs::create_dir_all(&args.covers_path)?;
-    let covers_path = std::fs::canonicalize(&args.covers_path)?;
-    let db = Database::open(&args.db_path)?;
+fn webhook(args: &Cli) -> Option<WebhookConfig> {
+    args.s3_webhook_addr.map(|addr| WebhookConfig {
+        addr,
+        token: args.s3_webhook_token.clone(),
+    })
+}
 
-    let shutdown = Arc::new(AtomicBool::new(false));
-    let shutdown_flag = Arc::clone(&shutdown);
-    ctrlc::set_handler(move || {
-        shutdown_flag.store(true, Ordering::Relaxed);
-    })?;
+fn run_s3_watcher(args: Cli) -> Result<()> {
+    let sources = watched_sources(&args)?;
+    let covers_path = covers_dir(&args)?;
+    let db = Database::open(&args.db_path)?;
+    let shutdown = shutdown_signal()?;
 
     // Create a tokio runtime for async S3 operations
     let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
     rt.block_on(watcher_rs::s3::watcher::run(
-        config,
+        sources,
         &covers_path,
-        db,
+        &db,
+        housekeeping(&args),
+        webhook(&args),
         shutdown,
     ))?;
 
     Ok(())
 }
 
+fn run_combined_watcher(args: Cli) -> Result<()> {
+    let sources = watched_sources(&args)?;
+    let library_path = library_dir(&args)?;
+    let covers_path = covers_dir(&args)?;
+    let db = Database::open(&args.db_path)?;
+    let shutdown = shutdown_signal()?;
+
+    let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
+    rt.block_on(watcher_rs::combined::run(
+        library_path,
+        covers_path,
+        sources,
+        db,
+        housekeeping(&args),
+        webhook(&args),
+        shutdown,
+    ))
+}
+
 fn run_s3_stream(cmd: S3StreamCommand) -> Result<()> {
-    let config = S3Config {
+    let template = S3Config {
         endpoint: cmd.s3_endpoint,
         region: cmd.s3_region,
-        bucket: cmd.s3_bucket,
-        access_key: cmd.s3_access_key,
-        secret_key: cmd.s3_secret_key,
-        prefix: None,
-        poll_interval: 0,
+        bucket: cmd.s3_bucket.unwrap_or_default(),
+        credentials: cmd.s3_credentials.into_credentials(),
+        ..command_config()
     };
+    let sources = sources::resolve(cmd.s3_sources.s3_sources.as_deref(), template)?;
+    let (config, key) = pick_source(&sources, cmd.source.as_deref(), &cmd.key)?;
 
     let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
     rt.block_on(watcher_rs::s3::stream::run(
         config,
-        &cmd.key,
-        cmd.range.as_deref(),
+        &StreamRequest {
+            key,
+            range: cmd.range.as_deref(),
+            head_only: cmd.head,
+            if_none_match: cmd.if_none_match.as_deref(),
+            if_modified_since: cmd.if_modified_since.as_deref(),
+        },
     ))?;
 
     Ok(())
 }
 
+fn run_s3_presign(cmd: S3PresignCommand) -> Result<()> {
+    let template = S3Config {
+        endpoint: cmd.s3_endpoint,
+        region: cmd.s3_region,
+        bucket: cmd.s3_bucket.unwrap_or_default(),
+        credentials: cmd.s3_credentials.into_credentials(),
+        ..command_config()
+    };
+    let sources = sources::resolve(cmd.s3_sources.s3_sources.as_deref(), template)?;
+    let options = PresignOptions {
+        expires_in_secs: cmd.expires_in,
+        content_disposition: cmd.content_disposition,
+        content_type: cmd.content_type,
+    };
+
+    let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
+    let output = match (cmd.key, cmd.book_id) {
+        (Some(key), _) => {
+            let (config, key) = pick_source(&sources, cmd.source.as_deref(), &key)?;
+            if config.bucket.is_empty() {
+                anyhow::bail!("S3_BUCKET is required to presign a key");
+            }
+            serde_json::to_value(rt.block_on(presign::presign_key(&config, key, &options))?)?
+        }
+        (None, Some(book_id)) => {
+            let db = Database::open(&cmd.db_path)?;
+            serde_json::to_value(
+                rt.block_on(presign::presign_book(&db, &sources, &book_id, &options))?,
+            )?
+        }
+        (None, None) => unreachable!("clap requires --key or --book-id"),
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn run_sync(cmd: SyncCommand) -> Result<()> {
+    std::fs::create_dir_all(&cmd.library_path)?;
+    // Book paths are stored absolute, so keys are derived from the same form.
+    let library_path = std::fs::canonicalize(&cmd.library_path)?;
+    let template = S3Config {
+        endpoint: cmd.s3_endpoint,
+        region: cmd.s3_region,
+        bucket: cmd.s3_bucket.unwrap_or_default(),
+        credentials: cmd.s3_credentials.into_credentials(),
+        prefixes: cmd.s3_keys.s3_prefix,
+        exclude: cmd.s3_keys.s3_exclude,
+        poll_interval: cmd.interval,
+        ..command_config()
+    };
+    let sources = sources::resolve(cmd.s3_sources.s3_sources.as_deref(), template)?;
+    let config = match &cmd.source {
+        Some(name) => sources.iter().find(|s| s.name == *name),
+        None => source_for(&sources, None, None),
+    }
+    .context("Pass --source to pick one of the S3 sources")?;
+    if config.bucket.is_empty() {
+        anyhow::bail!("S3_BUCKET or S3_SOURCES is required to sync");
+    }
+    let options = SyncOptions {
+        library_path: &library_path,
+        download: cmd.download,
+    };
+    let db = Database::open(&cmd.db_path)?;
+    let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
+
+    if cmd.watch {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let shutdown_flag = Arc::clone(&shutdown);
+        ctrlc::set_handler(move || {
+            shutdown_flag.store(true, Ordering::Relaxed);
+        })?;
+        return rt.block_on(sync::watch(
+            &db,
+            config,
+            &options,
+            Duration::from_secs(cmd.interval),
+            &shutdown,
+        ));
+    }
+
+    let report = rt.block_on(async {
+        let bucket = watcher_rs::s3::client::create_bucket(config)?;
+        sync::run(&db, &bucket, config, &options).await
+    })?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// S3 settings for one-shot commands, before the bucket and credentials are
+/// filled in.
+fn command_config() -> S3Config {
+    S3Config {
+        name: String::new(),
+        endpoint: None,
+        region: String::new(),
+        bucket: String::new(),
+        credentials: S3Credentials::default(),
+        prefixes: Vec::new(),
+        exclude: Vec::new(),
+        poll_interval: 0,
+        concurrency: 1,
+        reconcile_interval: 0,
+        covers: None,
+    }
+}
+
+/// The source and key `key` refers to: the named source, or the one for the
+/// bucket of an `s3://bucket/key` URI (any configured source's credentials
+/// reach other buckets on the same endpoint).
+fn pick_source<'a>(
+    sources: &[S3Config],
+    name: Option<&str>,
+    key: &'a str,
+) -> Result<(S3Config, &'a str)> {
+    if let Some(name) = name {
+        let config = sources
+            .iter()
+            .find(|s| s.name == name)
+            .with_context(|| format!("Unknown S3 source: {}", name))?;
+        return Ok((config.clone(), key));
+    }
+    match presign::parse_s3_uri(key) {
+        Some((bucket, key)) => {
+            let config = source_for(sources, None, Some(bucket))
+                .or(sources.first())
+                .context("No S3 source configured")?;
+            let config = S3Config {
+                bucket: bucket.to_string(),
+                ..config.clone()
+            };
+            Ok((config, key))
+        }
+        None => {
+            let config =
+                source_for(sources, None, None).context("Pass --source to pick an S3 source")?;
+            Ok((config.clone(), key))
+        }
+    }
+}
+
 fn run_tunnel(cmd: TunnelCommand) -> Result<()> {
     let config = watcher_rs::tunnel::client::TunnelConfig {
         subdomain: cmd.subdomain,
@@ -254,30 +947,216 @@ fn run_tunnel(cmd: TunnelCommand) -> Result<()> {
     Ok(())
 }
 
+fn run_cover_command(cmd: CoverCommand) -> Result<()> {
+    std::fs::create_dir_all(&cmd.covers_path)?;
+    let covers_path = std::fs::canonicalize(&cmd.covers_path)?;
+    let db = Database::open(&cmd.db_path)?;
+
+    match cmd.action {
+        CoverAction::Set { book_id, image } => {
+            let cover_path =
+                watcher_rs::handlers::set_user_cover(&db, &book_id, &image, &covers_path)?;
+            println!(
+                "{}",
+                json!({ "book_id": book_id, "cover_path": cover_path.to_string_lossy() })
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn run_render_page(cmd: RenderPageCommand) -> Result<()> {
+    let db = Database::open(&cmd.db_path)?;
+    // The bucket comes from the book row; credentials are only resolved
+    // when an S3 book is actually rendered.
+    let template = S3Config {
+        endpoint: cmd.s3_endpoint,
+        region: cmd.s3_region,
+        bucket: cmd.s3_bucket.unwrap_or_default(),
+        credentials: cmd.s3_credentials.into_credentials(),
+        ..command_config()
+    };
+    let s3 = sources::resolve(cmd.s3_sources.s3_sources.as_deref(), template)?;
+    let request = PageRequest {
+        book_id: cmd.book_id,
+        page: cmd.page,
+        width: cmd.width,
+        format: cmd.format,
+    };
+
+    let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
+    rt.block_on(watcher_rs::pages::run(
+        &db,
+        &s3,
+        std::path::Path::new(&cmd.cache_path),
+        cmd.cache_max_mb.saturating_mul(1024 * 1024),
+        &request,
+    ))?;
+
+    Ok(())
+}
+
+fn run_backup(cmd: BackupCommand) -> Result<()> {
+    // Paths are stored absolute in the DB, so record them the same way.
+    let covers_path = std::fs::canonicalize(&cmd.covers_path).unwrap_or(cmd.covers_path);
+    let library_path = std::fs::canonicalize(&cmd.library_path).unwrap_or(cmd.library_path);
+    let manifest = backup::backup(&cmd.db_path, &covers_path, Some(&library_path), &cmd.output)?;
+    println!("{}", serde_json::to_string_pretty(&manifest)?);
+    Ok(())
+}
+
+fn run_restore(cmd: RestoreCommand) -> Result<()> {
+    std::fs::create_dir_all(&cmd.covers_path)?;
+    let covers_path = std::fs::canonicalize(&cmd.covers_path)?;
+    let manifest = backup::restore(
+        &cmd.archive,
+        &RestoreOptions {
+            db_path: &cmd.db_path,
+            covers_dir: &covers_path,
+            library_root: cmd.library_root.as_deref(),
+            force: cmd.force,
+        },
+    )?;
+    println!("{}", serde_json::to_string_pretty(&manifest)?);
+    Ok(())
+}
+
+fn run_verify(cmd: VerifyCommand) -> Result<()> {
+    let db = Database::open(&cmd.db_path)?;
+    let covers_path = std::fs::canonicalize(&cmd.covers_path).unwrap_or(cmd.covers_path);
+    let template = S3Config {
+        endpoint: cmd.s3_endpoint,
+        region: cmd.s3_region,
+        bucket: cmd.s3_bucket.unwrap_or_default(),
+        credentials: cmd.s3_credentials.into_credentials(),
+        prefixes: cmd.s3_keys.s3_prefix,
+        exclude: cmd.s3_keys.s3_exclude,
+        covers: cmd.s3_covers.to_config(),
+        ..command_config()
+    };
+    let mut s3 = sources::resolve(cmd.s3_sources.s3_sources.as_deref(), template)?;
+    // Without a bucket there is nothing to check S3 books against.
+    s3.retain(|source| !source.bucket.is_empty());
+    let options = VerifyOptions {
+        rehash: cmd.rehash,
+        repair: cmd.repair,
+    };
+
+    let rt = tokio::runtime::Runtime::new().context("Failed to create tokio runtime")?;
+    let report = rt.block_on(verify::run(&db, &covers_path, &s3, &options))?;
+
+    let json = serde_json::to_string_pretty(&report)?;
+    match cmd.output {
+        Some(path) => std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write {}", path.display()))?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+fn run_export(cmd: ExportCommand) -> Result<()> {
+    let db = Database::open(&cmd.db_path)?;
+    let library_path = std::fs::canonicalize(&cmd.library_path).unwrap_or(cmd.library_path);
+    let covers_path = std::fs::canonicalize(&cmd.covers_path).unwrap_or(cmd.covers_path);
+    let summary = export::run(
+        &db,
+        &cmd.out,
+        &ExportOptions {
+            formats: &cmd.format,
+            include_progress: cmd.include_progress,
+            include_collections: cmd.include_collections,
+            links: opds::LinkRoots {
+                library_root: Some(&library_path),
+                covers_dir: Some(&covers_path),
+            },
+        },
+    )?;
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+fn run_db_maintain(db_path: &str, analyze: bool, full_vacuum: bool, force: bool) -> Result<()> {
+    let db = Database::open(db_path)?;
+    if !force && db.scan_in_progress()? {
+        anyhow::bail!("A library scan is in progress; try again later or pass --force");
+    }
+    let report = db.maintain(&MaintainOptions {
+        analyze,
+        full_vacuum,
+    })?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn run_changes(cmd: ChangesCommand) -> Result<()> {
+    let db = Database::open(&cmd.db_path)?;
+    let events = db.events_since(cmd.since, cmd.limit)?;
+
+    // Events after `since` were compacted away; the caller must resync fully.
+    let reset = cmd.since < db.compacted_event_seq()?;
+    println!(
+        "{}",
+        json!({
+            "events": events,
+            "latest_seq": db.latest_event_seq()?,
+            "reset": reset,
+        })
+    );
+    Ok(())
+}
+
 fn run_db_command(cmd: DbCommand) -> Result<()> {
+    let mode = match cmd.action {
+        DbAction::QueryAll => Some(Mode::QueryAll),
+        DbAction::QueryOne => Some(Mode::QueryOne),
+        DbAction::Execute => Some(Mode::Execute),
+        DbAction::Transaction => None,
+        DbAction::Maintain {
+            analyze,
+            full_vacuum,
+            force,
+        } => {
+            if cmd.read_only {
+                anyhow::bail!("--read-only only applies to query-all and query-one");
+            }
+            return run_db_maintain(&cmd.db_path, analyze, full_vacuum, force);
+        }
+        DbAction::Serve { readers, socket } => {
+            if cmd.read_only {
+                anyhow::bail!("--read-only only applies to query-all and query-one");
+            }
+            return run_db_serve(&cmd.db_path, readers, socket);
+        }
+    };
+
     let mut stdin = String::new();
     std::io::stdin()
         .read_to_string(&mut stdin)
         .context("Failed to read SQL request from stdin")?;
 
-    let request: SqlRequest = serde_json::from_str(&stdin).context("Invalid SQL request JSON")?;
-    let bindings = to_sql_values(request.params)?;
-    let conn = open_connection(&cmd.db_path)?;
-
-    let output = match cmd.action {
-        DbAction::QueryAll => {
-            let rows = query_all(&conn, &request.sql, &bindings)?;
-            json!({ "rows": rows })
+    let conn = if cmd.read_only {
+        if !matches!(mode, Some(Mode::QueryAll | Mode::QueryOne)) {
+            anyhow::bail!("--read-only only applies to query-all and query-one");
+        }
+        // A fresh install has nothing to open read-only yet.
+        if !Path::new(&cmd.db_path).exists() {
+            drop(bridge::open_connection(&cmd.db_path)?);
         }
-        DbAction::QueryOne => {
-            let row = query_one(&conn, &request.sql, &bindings)?;
-            json!({ "row": row })
+        bridge::open_read_only_connection(&cmd.db_path)?
+    } else {
+        bridge::open_connection(&cmd.db_path)?
+    };
+    let output = match mode {
+        Some(mode) => {
+            let request: SqlRequest =
+                serde_json::from_str(&stdin).context("Invalid SQL request JSON")?;
+            bridge::run(&conn, mode, &request)?
         }
-        DbAction::Execute => {
-            let changes = conn
-                .execute(&request.sql, params_from_iter(bindings.iter()))
-                .with_context(|| format!("Failed to execute SQL: {}", request.sql))?;
-            json!({ "changes": changes })
+        None => {
+            let request: TransactionRequest =
+                serde_json::from_str(&stdin).context("Invalid transaction request JSON")?;
+            bridge::run_transaction(&conn, &request)?
         }
     };
 
@@ -288,106 +1167,18 @@ fn run_db_command(cmd: DbCommand) -> Result<()> {
     Ok(())
 }
 
-fn open_connection(path: &str) -> Result<Connection> {
-    let conn =
-        Connection::open(path).with_context(|| format!("Failed to open database at {path}"))?;
-    conn.execute_batch(
-        "PRAGMA journal_mode=WAL;
-         PRAGMA foreign_keys=ON;
-         PRAGMA busy_timeout=5000;",
-    )
-    .context("Failed to initialize SQLite pragmas")?;
-    Ok(conn)
-}
-
-fn to_sql_values(params: Vec<JsonValue>) -> Result<Vec<SqlValue>> {
-    params
-        .into_iter()
-        .map(|value| match value {
-            JsonValue::Null => Ok(SqlValue::Null),
-            JsonValue::Bool(flag) => Ok(SqlValue::Integer(if flag { 1 } else { 0 })),
-            JsonValue::Number(number) => {
-                if let Some(int) = number.as_i64() {
-                    Ok(SqlValue::Integer(int))
-                } else if let Some(float) = number.as_f64() {
-                    Ok(SqlValue::Real(float))
-                } else {
-                    Err(anyhow::anyhow!("Unsupported JSON number parameter"))
-                }
-            }
-            JsonValue::String(text) => Ok(SqlValue::Text(text)),
-            JsonValue::Array(_) | JsonValue::Object(_) => {
-                Err(anyhow::anyhow!("Unsupported SQL parameter type"))
-            }
-        })
-        .collect()
-}
-
-fn query_all(
-    conn: &Connection,
-    sql: &str,
-    bindings: &[SqlValue],
-) -> Result<Vec<JsonMap<String, JsonValue>>> {
-    let mut stmt = conn
-        .prepare(sql)
-        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
-    let column_names: Vec<String> = stmt
-        .column_names()
-        .iter()
-        .map(|name| (*name).to_string())
-        .collect();
-    let mut rows = stmt
-        .query(params_from_iter(bindings.iter()))
-        .with_context(|| format!("Failed to run SQL query: {sql}"))?;
-
-    let mut output = Vec::new();
-    while let Some(row) = rows.next().context("Failed to read SQL row")? {
-        output.push(row_to_json(row, &column_names)?);
-    }
-
-    Ok(output)
-}
-
-fn query_one(
-    conn: &Connection,
-    sql: &str,
-    bindings: &[SqlValue],
-) -> Result<Option<JsonMap<String, JsonValue>>> {
-    let mut stmt = conn
-        .prepare(sql)
-        .with_context(|| format!("Failed to prepare SQL: {sql}"))?;
-    let column_names: Vec<String> = stmt
-        .column_names()
-        .iter()
-        .map(|name| (*name).to_string())
-        .collect();
-    let mut rows = stmt
-        .query(params_from_iter(bindings.iter()))
-        .with_context(|| format!("Failed to run SQL query: {sql}"))?;
-
-    if let Some(row) = rows.next().context("Failed to read SQL row")? {
-        return Ok(Some(row_to_json(row, &column_names)?));
-    }
-
-    Ok(None)
-}
-
-fn row_to_json(row: &Row<'_>, column_names: &[String]) -> Result<JsonMap<String, JsonValue>> {
-    let mut output = JsonMap::new();
+fn run_db_serve(db_path: &str, readers: usize, socket: Option<PathBuf>) -> Result<()> {
+    let server = Server::start(db_path, readers)?;
 
-    for (index, name) in column_names.iter().enumerate() {
-        let value = match row.get_ref(index).context("Failed to read SQL column")? {
-            ValueRef::Null => JsonValue::Null,
-            ValueRef::Integer(v) => JsonValue::from(v),
-            ValueRef::Real(v) => JsonValue::from(v),
-            ValueRef::Text(v) => JsonValue::String(String::from_utf8_lossy(v).to_string()),
-            ValueRef::Blob(v) => {
-                JsonValue::Array(v.iter().map(|byte| JsonValue::from(*byte)).collect())
-            }
-        };
-
-        output.insert(name.clone(), value);
+    match socket {
+        #[cfg(unix)]
+        Some(path) => server.listen(&path),
+        #[cfg(not(unix))]
+        Some(_) => anyhow::bail!("--socket is only supported on Unix"),
+        None => {
+            server.serve(std::io::stdin().lock(), Box::new(std::io::stdout()))?;
+            server.shutdown();
+            Ok(())
+        }
     }
-
-    Ok(output)
 }
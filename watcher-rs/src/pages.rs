@@ -0,0 +1,339 @@
+use anyhow::{Context, Result, bail};
+use image::{ImageFormat, ImageReader};
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::covers::renderer::{PdfRenderer, PdfSource, RenderParams};
+use crate::db::{BookFile, Database};
+use crate::s3::client::create_bucket;
+use crate::s3::range::{BucketRange, RangedObject};
+use crate::s3::scanner::head_object;
+use crate::s3::{S3Config, source_for};
+
+/// Widest page image a caller may ask for.
+pub const MAX_PAGE_WIDTH: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFormat {
+    Jpeg,
+    Png,
+}
+
+impl PageFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PageFormat::Jpeg => "image/jpeg",
+            PageFormat::Png => "image/png",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            PageFormat::Jpeg => "jpg",
+            PageFormat::Png => "png",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            PageFormat::Jpeg => ImageFormat::Jpeg,
+            PageFormat::Png => ImageFormat::Png,
+        }
+    }
+}
+
+impl FromStr for PageFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(PageFormat::Jpeg),
+            "png" => Ok(PageFormat::Png),
+            other => Err(format!("Unsupported page format: {other}")),
+        }
+    }
+}
+
+/// Temp files left by a render that died are removed once this old.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A page of a book to render. `page` is 1-based, like the reader's page numbers.
+pub struct PageRequest {
+    pub book_id: String,
+    pub page: u16,
+    pub width: u32,
+    pub format: PageFormat,
+}
+
+/// Render a PDF page and stream it to stdout, serving from the disk cache when
+/// the same page was rendered before.
+///
+/// Protocol (same framing as `s3-stream`):
+///   1. First line: JSON header `{"content_length": N, "content_type": "...", "status": 200, ...}\n`
+///   2. Remaining bytes: the encoded image
+///
+/// `s3` is only needed for books stored in S3; the bucket recorded on the
+/// book takes precedence over the configured one. After a new page is
+/// rendered, the least recently served pages are evicted until the cache
+/// fits in `cache_max_bytes` (0 for no limit).
+pub async fn run(
+    db: &Database,
+    s3: &[S3Config],
+    cache_dir: &Path,
+    cache_max_bytes: u64,
+    request: &PageRequest,
+) -> Result<()> {
+    if request.page == 0 {
+        bail!("Page numbers start at 1");
+    }
+    if request.width == 0 || request.width > MAX_PAGE_WIDTH {
+        bail!("Width must be between 1 and {MAX_PAGE_WIDTH}");
+    }
+
+    let book = db
+        .find_book_file(&request.book_id)?
+        .with_context(|| format!("No book with id {}", request.book_id))?;
+    if book.file_type != "pdf" {
+        bail!("Book {} is not a PDF", book.id);
+    }
+
+    let path = cache_path(cache_dir, &book, request);
+    let (bytes, cached) = match read_cached(&path) {
+        Some(bytes) => (bytes, true),
+        None => {
+            let source = pdf_source(&book, s3).await?;
+            let format = request.format;
+            let params = RenderParams {
+                width: Some(request.width),
+                ..RenderParams::dpi(72.0)
+            };
+            let page_index = request.page - 1;
+            let bytes = tokio::task::spawn_blocking(move || {
+                let bytes = render_page(source, page_index, params, format)?;
+                write_to_cache(&path, &bytes)?;
+                Ok::<_, anyhow::Error>(bytes)
+            })
+            .await
+            .context("Page render task failed")??;
+            if cache_max_bytes > 0 {
+                // Stdout carries the image, so failures here go unreported.
+                let _ = prune_cache(cache_dir, cache_max_bytes);
+            }
+            (bytes, false)
+        }
+    };
+    let (width, height) = ImageReader::new(Cursor::new(&bytes))
+        .with_guessed_format()?
+        .into_dimensions()?;
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let header = serde_json::json!({
+        "content_length": bytes.len(),
+        "content_type": request.format.content_type(),
+        "status": 200,
+        "width": width,
+        "height": height,
+        "cached": cached,
+    });
+    writeln!(out, "{}", header)?;
+    out.write_all(&bytes)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Cache location for a rendered page. The content hash is part of the name,
+/// so a replaced file never serves stale pages.
+pub fn cache_path(cache_dir: &Path, book: &BookFile, request: &PageRequest) -> PathBuf {
+    let hash = &book.file_hash[..book.file_hash.len().min(16)];
+    cache_dir.join(&book.id).join(format!(
+        "{}-p{}-w{}.{}",
+        hash,
+        request.page,
+        request.width,
+        request.format.extension()
+    ))
+}
+
+async fn pdf_source(book: &BookFile, s3: &[S3Config]) -> Result<PdfSource> {
+    if book.source != "s3" {
+        return Ok(PdfSource::File(PathBuf::from(&book.file_path)));
+    }
+
+    let mut config = source_for(s3, book.s3_source.as_deref(), book.s3_bucket.as_deref())
+        .context("S3 credentials are required to render S3 books")?
+        .clone();
+    if let Some(bucket) = &book.s3_bucket {
+        config.bucket = bucket.clone();
+    }
+    let bucket = create_bucket(&config)?;
+    let object = head_object(&bucket, &book.file_path)
+        .await?
+        .with_context(|| format!("s3://{}/{} no longer exists", config.bucket, book.file_path))?;
+    // pdfium reads only the parts of the file the page needs.
+    let range = BucketRange::new(&bucket, &object.key, object.size);
+    let ranged = RangedObject::new(Arc::new(range), object.size);
+    Ok(PdfSource::Reader(Box::new(ranged.reader())))
+}
+
+fn render_page(
+    source: PdfSource,
+    page_index: u16,
+    params: RenderParams,
+    format: PageFormat,
+) -> Result<Vec<u8>> {
+    let image = PdfRenderer::shared().render_page(source, page_index, params)?;
+    let mut bytes = Vec::new();
+    image
+        .into_rgb8()
+        .write_to(&mut Cursor::new(&mut bytes), format.image_format())?;
+    Ok(bytes)
+}
+
+/// The cached page at `path`, marking it as recently served.
+fn read_cached(path: &Path) -> Option<Vec<u8>> {
+    let bytes = fs::read(path).ok()?;
+    if let Ok(file) = File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    Some(bytes)
+}
+
+/// Write through a uniquely named temp file and rename it into place, so a
+/// concurrent reader never sees a half-written image and two renders of the
+/// same page don't trip over each other.
+fn write_to_cache(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = path.parent().context("Invalid cache path")?;
+    fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(bytes)?;
+    file.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Delete the least recently served pages until the cache takes at most
+/// `max_bytes`, along with book directories left empty.
+pub fn prune_cache(cache_dir: &Path, max_bytes: u64) -> Result<()> {
+    let now = SystemTime::now();
+    let mut pages = Vec::new();
+    let mut total = 0;
+    for book_dir in fs::read_dir(cache_dir)? {
+        let book_dir = book_dir?.path();
+        if !book_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&book_dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let modified = metadata.modified().unwrap_or(now);
+            let in_progress = entry.file_name().to_string_lossy().starts_with(".tmp")
+                && now.duration_since(modified).unwrap_or_default() < STALE_TEMP_AGE;
+            if in_progress {
+                continue;
+            }
+            total += metadata.len();
+            pages.push((modified, metadata.len(), entry.path()));
+        }
+    }
+    if total <= max_bytes {
+        return Ok(());
+    }
+
+    pages.sort();
+    for (_, len, path) in pages {
+        if total <= max_bytes {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+            if let Some(dir) = path.parent() {
+                // Only succeeds once the directory is empty.
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageFormat, PageRequest, cache_path, prune_cache};
+    use crate::db::BookFile;
+    use std::fs::File;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    fn book() -> BookFile {
+        BookFile {
+            id: "book-1".to_string(),
+            file_path: "/library/scan.pdf".to_string(),
+            file_type: "pdf".to_string(),
+            file_hash: "0123456789abcdef0123456789abcdef".to_string(),
+            source: "local".to_string(),
+            s3_source: None,
+            s3_bucket: None,
+            s3_key: None,
+            cover_path: None,
+        }
+    }
+
+    #[test]
+    fn cache_path_includes_hash_page_width_and_format() {
+        let request = PageRequest {
+            book_id: "book-1".to_string(),
+            page: 12,
+            width: 800,
+            format: PageFormat::Png,
+        };
+
+        assert_eq!(
+            cache_path(Path::new("/cache"), &book(), &request),
+            Path::new("/cache/book-1/0123456789abcdef-p12-w800.png")
+        );
+    }
+
+    #[test]
+    fn prune_cache_evicts_least_recently_served_pages() {
+        let cache = tempfile::tempdir().unwrap();
+        let old_dir = cache.path().join("book-1");
+        let new_dir = cache.path().join("book-2");
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::create_dir_all(&new_dir).unwrap();
+
+        let old = old_dir.join("a-p1-w800.jpg");
+        let new = new_dir.join("b-p1-w800.jpg");
+        std::fs::write(&old, vec![0u8; 600]).unwrap();
+        std::fs::write(&new, vec![0u8; 600]).unwrap();
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+
+        prune_cache(cache.path(), 1000).unwrap();
+        assert!(!old.exists());
+        assert!(!old_dir.exists());
+        assert!(new.exists());
+
+        prune_cache(cache.path(), 1000).unwrap();
+        assert!(new.exists());
+    }
+
+    #[test]
+    fn page_format_parses_common_names() {
+        assert_eq!("jpg".parse::<PageFormat>(), Ok(PageFormat::Jpeg));
+        assert_eq!("JPEG".parse::<PageFormat>(), Ok(PageFormat::Jpeg));
+        assert_eq!("png".parse::<PageFormat>(), Ok(PageFormat::Png));
+        assert!("webp".parse::<PageFormat>().is_err());
+    }
+}
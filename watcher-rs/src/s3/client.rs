@@ -1,8 +1,12 @@
 use super::S3Config;
+use super::credentials::resolve;
 use anyhow::{Context, Result};
 use s3::Bucket;
-use s3::creds::Credentials;
 use s3::region::Region;
+use std::collections::HashMap;
+
+/// The longest expiry S3 accepts for a presigned URL (7 days).
+pub const MAX_PRESIGN_EXPIRY_SECS: u32 = 7 * 24 * 60 * 60;
 
 /// Create an S3 Bucket handle from config.
 pub fn create_bucket(config: &S3Config) -> Result<Box<Bucket>> {
@@ -15,14 +19,7 @@ pub fn create_bucket(config: &S3Config) -> Result<Box<Bucket>> {
         config.region.parse().context("Invalid S3 region")?
     };
 
-    let credentials = Credentials::new(
-        Some(&config.access_key),
-        Some(&config.secret_key),
-        None,
-        None,
-        None,
-    )
-    .context("Failed to create S3 credentials")?;
+    let (credentials, _) = resolve(&config.credentials).context("Failed to load S3 credentials")?;
 
     let bucket = Bucket::new(&config.bucket, region, credentials)
         .context("Failed to create S3 bucket handle")?
@@ -30,3 +27,91 @@ pub fn create_bucket(config: &S3Config) -> Result<Box<Bucket>> {
 
     Ok(bucket)
 }
+
+/// Overrides applied to a presigned GET.
+#[derive(Debug, Clone)]
+pub struct PresignOptions {
+    pub expires_in_secs: u32,
+    /// Sent back as `Content-Disposition` (e.g. `attachment; filename="book.pdf"`).
+    pub content_disposition: Option<String>,
+    /// Sent back as `Content-Type`, for objects uploaded without one.
+    pub content_type: Option<String>,
+}
+
+/// A presigned GET URL for `key`, usable without credentials until it expires.
+pub async fn presign_get(bucket: &Bucket, key: &str, options: &PresignOptions) -> Result<String> {
+    if options.expires_in_secs == 0 || options.expires_in_secs > MAX_PRESIGN_EXPIRY_SECS {
+        anyhow::bail!(
+            "Presigned URL expiry must be between 1 and {} seconds",
+            MAX_PRESIGN_EXPIRY_SECS
+        );
+    }
+
+    let mut queries = HashMap::new();
+    if let Some(disposition) = &options.content_disposition {
+        queries.insert(
+            "response-content-disposition".to_string(),
+            disposition.clone(),
+        );
+    }
+    if let Some(content_type) = &options.content_type {
+        queries.insert("response-content-type".to_string(), content_type.clone());
+    }
+
+    bucket
+        .presign_get(
+            key,
+            options.expires_in_secs,
+            Some(queries).filter(|q| !q.is_empty()),
+        )
+        .await
+        .with_context(|| format!("Failed to presign s3://{}", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PresignOptions, create_bucket, presign_get};
+    use crate::s3::S3Config;
+    use crate::s3::credentials::S3Credentials;
+
+    fn config() -> S3Config {
+        S3Config {
+            name: "default".to_string(),
+            endpoint: Some("http://127.0.0.1:9000".to_string()),
+            region: "auto".to_string(),
+            bucket: "books".to_string(),
+            credentials: S3Credentials::from_keys("key", "secret"),
+            prefixes: Vec::new(),
+            exclude: Vec::new(),
+            poll_interval: 0,
+            concurrency: 1,
+            reconcile_interval: 0,
+            covers: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn presign_get_adds_response_overrides() {
+        let bucket = create_bucket(&config()).unwrap();
+        let options = PresignOptions {
+            expires_in_secs: 300,
+            content_disposition: Some("attachment; filename=\"My Book.pdf\"".to_string()),
+            content_type: Some("application/pdf".to_string()),
+        };
+
+        let url = presign_get(&bucket, "shelf/My Book.pdf", &options)
+            .await
+            .unwrap();
+        assert!(url.starts_with("http://127.0.0.1:9000/books/shelf/My%20Book.pdf?"));
+        assert!(url.contains("X-Amz-Expires=300"));
+        assert!(url.contains("X-Amz-Signature="));
+        assert!(url.contains("response-content-disposition=attachment"));
+        assert!(url.contains("response-content-type=application"));
+
+        let too_long = PresignOptions {
+            expires_in_secs: 8 * 24 * 60 * 60,
+            ..options
+        };
+        assert!(presign_get(&bucket, "a.pdf", &too_long).await.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use s3::Bucket;
+use std::path::{Path, PathBuf};
+
+use super::S3Config;
+use super::client::create_bucket;
+use super::credentials::RefreshingBucket;
+use super::presign::parse_s3_uri;
+use super::stream::content_type;
+use crate::log::log;
+
+/// Where generated covers are uploaded in S3 mode, so they survive redeploys
+/// of containers without a persistent `COVERS_PATH`.
+#[derive(Debug, Clone)]
+pub struct CoverStoreConfig {
+    /// Defaults to the books bucket.
+    pub bucket: Option<String>,
+    /// Prepended to the cover's file name, e.g. `covers/`.
+    pub prefix: String,
+}
+
+/// Uploads and deletes cover objects. Covers are recorded in `cover_path` as
+/// `s3://bucket/key`. Credentials are refreshed before each request.
+pub struct CoverStore {
+    bucket: RefreshingBucket,
+    prefix: String,
+}
+
+impl CoverStore {
+    /// A store using `config`'s endpoint and credentials and `covers`' bucket.
+    pub fn new(config: &S3Config, covers: &CoverStoreConfig) -> Result<Self> {
+        let config = S3Config {
+            bucket: covers
+                .bucket
+                .clone()
+                .unwrap_or_else(|| config.bucket.clone()),
+            ..config.clone()
+        };
+        Ok(Self {
+            bucket: RefreshingBucket::new(*create_bucket(&config)?, config.credentials.clone()),
+            prefix: covers.prefix.clone(),
+        })
+    }
+
+    /// Upload the cover rendered at `local` and remove the local file.
+    /// Returns the `s3://` URI to record in `cover_path`.
+    pub async fn upload(&self, local: &Path) -> Result<String> {
+        let file_name = local
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("Invalid cover file name")?;
+        let key = format!("{}{}", self.prefix, file_name);
+        let bucket = self.bucket.current().await;
+        self.upload_to(local, &bucket, &key).await?;
+        Ok(format!("s3://{}/{}", bucket.name, key))
+    }
+
+    /// Upload the cover rendered at `local` to the object `uri` already
+    /// points at, and remove the local file.
+    pub async fn replace(&self, local: &Path, uri: &str) -> Result<()> {
+        let (bucket_name, key) = parse_s3_uri(uri).context("Not an S3 cover")?;
+        let bucket = self.bucket_named(bucket_name).await;
+        self.upload_to(local, &bucket, key).await
+    }
+
+    async fn upload_to(&self, local: &Path, bucket: &Bucket, key: &str) -> Result<()> {
+        let bytes =
+            std::fs::read(local).with_context(|| format!("Failed to read {}", local.display()))?;
+        let response = bucket
+            .put_object_with_content_type(key, &bytes, content_type(key))
+            .await
+            .with_context(|| format!("Failed to upload s3://{}/{}", bucket.name, key))?;
+        if response.status_code() != 200 {
+            anyhow::bail!(
+                "S3 PutObject returned status {} for key: {}",
+                response.status_code(),
+                key
+            );
+        }
+        let _ = std::fs::remove_file(local);
+        Ok(())
+    }
+
+    /// Delete the cover object `uri` points at. A missing object is fine.
+    pub async fn delete(&self, uri: &str) -> Result<()> {
+        let (bucket_name, key) = parse_s3_uri(uri).context("Not an S3 cover")?;
+        let bucket = self.bucket_named(bucket_name).await;
+        let response = bucket
+            .delete_object(key)
+            .await
+            .with_context(|| format!("Failed to delete {}", uri))?;
+        match response.status_code() {
+            200 | 204 | 404 => Ok(()),
+            status => anyhow::bail!("S3 DeleteObject returned status {} for {}", status, uri),
+        }
+    }
+
+    /// Covers recorded under another bucket (the store was reconfigured)
+    /// are reached with the same endpoint and credentials.
+    async fn bucket_named(&self, name: &str) -> Bucket {
+        let mut bucket = self.bucket.current().await;
+        bucket.name = name.to_string();
+        bucket
+    }
+}
+
+/// Where generated covers end up: rendered into `dir`, then moved to `store`
+/// when one is configured.
+#[derive(Clone, Copy)]
+pub struct CoverTarget<'a> {
+    pub dir: &'a Path,
+    pub store: Option<&'a CoverStore>,
+}
+
+impl<'a> CoverTarget<'a> {
+    pub fn local(dir: &'a Path) -> Self {
+        Self { dir, store: None }
+    }
+
+    /// The `cover_path` to record for a freshly rendered cover.
+    pub async fn publish(&self, rendered: Option<PathBuf>) -> Result<Option<String>> {
+        let Some(rendered) = rendered else {
+            return Ok(None);
+        };
+        match self.store {
+            Some(store) => store.upload(&rendered).await.map(Some),
+            None => Ok(Some(rendered.to_string_lossy().into_owned())),
+        }
+    }
+
+    /// Remove a cover that is no longer referenced, local or in a bucket.
+    pub async fn remove(&self, cover_path: &str) {
+        if parse_s3_uri(cover_path).is_none() {
+            let _ = std::fs::remove_file(cover_path);
+            return;
+        }
+        match self.store {
+            Some(store) => {
+                if let Err(e) = store.delete(cover_path).await {
+                    log(&format!("[S3] [ERROR] Failed to remove cover: {:#}", e));
+                }
+            }
+            None => log(&format!(
+                "[S3] [WARN] No cover store configured; left {} in place",
+                cover_path
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CoverTarget;
+
+    #[tokio::test]
+    async fn local_target_keeps_rendered_covers_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let rendered = dir.path().join("book-1.jpg");
+        std::fs::write(&rendered, b"jpeg").unwrap();
+        let covers = CoverTarget::local(dir.path());
+
+        let cover_path = covers.publish(Some(rendered.clone())).await.unwrap();
+        assert_eq!(cover_path.as_deref(), rendered.to_str());
+        assert_eq!(covers.publish(None).await.unwrap(), None);
+
+        // Bucket covers can't be removed without a store; local ones can.
+        covers.remove("s3://books/covers/book-1.jpg").await;
+        covers.remove(&cover_path.unwrap()).await;
+        assert!(!rendered.exists());
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result, bail};
+use s3::Bucket;
+use s3::creds::{Credentials, Rfc3339OffsetDateTime};
+use serde::Deserialize;
+use std::env;
+use std::time::Duration;
+
+use crate::log::log;
+
+/// Temporary credentials are replaced once they are this close to expiring,
+/// so a request never goes out with credentials that lapse mid-flight.
+const REFRESH_BEFORE_EXPIRY_SECS: i64 = 5 * 60;
+
+/// Where to get S3 credentials. Explicit keys win; without them the usual
+/// AWS chain is tried in order: environment, shared profile, web identity,
+/// container endpoint, instance metadata.
+#[derive(Debug, Clone, Default)]
+pub struct S3Credentials {
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// Session token for temporary (STS) keys.
+    pub session_token: Option<String>,
+    /// Profile in `~/.aws/credentials`. Falls back to `AWS_PROFILE`, then
+    /// `default`.
+    pub profile: Option<String>,
+}
+
+impl S3Credentials {
+    pub fn from_keys(access_key: &str, secret_key: &str) -> Self {
+        Self {
+            access_key: Some(access_key.to_string()),
+            secret_key: Some(secret_key.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+/// Resolve credentials through the chain. Also returns which source they
+/// came from, for logging.
+///
+/// A source that is configured but fails (a missing profile that was asked
+/// for by name, an unreachable container endpoint) is an error rather than
+/// a reason to fall through, so a typo doesn't silently pick up other keys.
+pub fn resolve(source: &S3Credentials) -> Result<(Credentials, &'static str)> {
+    match (&source.access_key, &source.secret_key) {
+        (Some(access_key), Some(secret_key)) => {
+            return Ok((
+                Credentials {
+                    access_key: Some(access_key.clone()),
+                    secret_key: Some(secret_key.clone()),
+                    security_token: None,
+                    session_token: source.session_token.clone(),
+                    expiration: None,
+                },
+                "explicit keys",
+            ));
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            bail!("S3 access key and secret key must be set together")
+        }
+        (None, None) => {}
+    }
+
+    if env::var_os("AWS_ACCESS_KEY_ID").is_some() {
+        let credentials =
+            Credentials::from_env().context("Incomplete AWS_* credentials in environment")?;
+        return Ok((credentials, "environment"));
+    }
+
+    let profile = source
+        .profile
+        .clone()
+        .or_else(|| env::var("AWS_PROFILE").ok());
+    match Credentials::from_profile(profile.as_deref()) {
+        Ok(credentials) => return Ok((credentials, "profile")),
+        Err(e) if profile.is_some() => {
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to load profile \"{}\" from ~/.aws/credentials",
+                    profile.unwrap_or_default()
+                )
+            });
+        }
+        Err(_) => {}
+    }
+
+    if env::var_os("AWS_ROLE_ARN").is_some() && env::var_os("AWS_WEB_IDENTITY_TOKEN_FILE").is_some()
+    {
+        let session_name =
+            env::var("AWS_ROLE_SESSION_NAME").unwrap_or_else(|_| "watcher-rs".to_string());
+        let credentials = Credentials::from_sts_env(&session_name)
+            .context("Failed to assume role with web identity")?;
+        return Ok((credentials, "web identity"));
+    }
+
+    if let Some(url) = container_credentials_url() {
+        let credentials = from_container(&url)
+            .with_context(|| format!("Failed to fetch credentials from {url}"))?;
+        return Ok((credentials, "container"));
+    }
+
+    if let Ok(credentials) =
+        Credentials::from_instance_metadata_v2().or_else(|_| Credentials::from_instance_metadata())
+    {
+        return Ok((credentials, "instance metadata"));
+    }
+
+    bail!(
+        "No S3 credentials found: set S3_ACCESS_KEY_ID and S3_SECRET_ACCESS_KEY, \
+         the AWS_* environment variables, or a profile in ~/.aws/credentials"
+    )
+}
+
+/// Seconds until `credentials` expire, if they do.
+pub fn expires_in(credentials: &Credentials) -> Option<i64> {
+    credentials
+        .expiration
+        .map(|expiration| expiration.unix_timestamp() - crate::db::unix_now())
+}
+
+/// Re-resolve the bucket's credentials when they are about to expire.
+///
+/// rust-s3 only refreshes after expiry, and then through the default chain
+/// rather than `source`, so long-running work calls this before each
+/// request it starts. Static keys never expire and are left alone.
+pub async fn refresh_if_expiring(bucket: &mut Bucket, source: &S3Credentials) -> Result<()> {
+    let current = bucket.credentials().await?;
+    if expires_in(&current).is_none_or(|secs| secs > REFRESH_BEFORE_EXPIRY_SECS) {
+        return Ok(());
+    }
+
+    let source = source.clone();
+    let (credentials, from) = tokio::task::spawn_blocking(move || resolve(&source)).await??;
+    let valid_for = expires_in(&credentials)
+        .map(|secs| format!(", valid for {}m", secs / 60))
+        .unwrap_or_default();
+    log(&format!(
+        "[S3] Refreshed credentials from {}{}",
+        from, valid_for
+    ));
+    bucket.set_credentials(credentials);
+    Ok(())
+}
+
+/// A bucket shared by work that can outlast its credentials, such as a scan
+/// of thousands of objects: every [`current`](Self::current) call refreshes
+/// them first when they are about to expire.
+pub struct RefreshingBucket {
+    bucket: tokio::sync::Mutex<Bucket>,
+    source: S3Credentials,
+}
+
+impl RefreshingBucket {
+    pub fn new(bucket: Bucket, source: S3Credentials) -> Self {
+        Self {
+            bucket: tokio::sync::Mutex::new(bucket),
+            source,
+        }
+    }
+
+    /// The bucket with credentials valid for a while yet. A failed refresh
+    /// is logged and the current credentials are used until they lapse.
+    pub async fn current(&self) -> Bucket {
+        let mut bucket = self.bucket.lock().await;
+        if let Err(e) = refresh_if_expiring(&mut bucket, &self.source).await {
+            log(&format!(
+                "[S3] [ERROR] Failed to refresh credentials for {}: {:#}",
+                bucket.name, e
+            ));
+        }
+        bucket.clone()
+    }
+}
+
+/// ECS task roles set the relative URI; EKS Pod Identity and other agents
+/// set the full one.
+fn container_credentials_url() -> Option<String> {
+    if let Ok(path) = env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+        return Some(format!("http://169.254.170.2{path}"));
+    }
+    env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI").ok()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+    expiration: Option<Rfc3339OffsetDateTime>,
+}
+
+fn from_container(url: &str) -> Result<Credentials> {
+    let mut request = attohttpc::get(url).timeout(Duration::from_secs(5));
+    let token = match env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE") {
+        Ok(path) => Some(
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {path}"))?
+                .trim()
+                .to_string(),
+        ),
+        Err(_) => env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN").ok(),
+    };
+    if let Some(token) = token {
+        request = request.header("Authorization", token);
+    }
+
+    let response = request.send()?;
+    if !response.is_success() {
+        bail!("status {}", response.status());
+    }
+    parse_container_credentials(&response.bytes()?)
+}
+
+fn parse_container_credentials(body: &[u8]) -> Result<Credentials> {
+    let parsed: ContainerCredentials =
+        serde_json::from_slice(body).context("Invalid credentials response")?;
+    Ok(Credentials {
+        access_key: Some(parsed.access_key_id),
+        secret_key: Some(parsed.secret_access_key),
+        security_token: None,
+        session_token: parsed.token,
+        expiration: parsed.expiration,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        RefreshingBucket, S3Credentials, expires_in, parse_container_credentials, resolve,
+    };
+    use s3::{Bucket, Region};
+
+    #[test]
+    fn explicit_keys_carry_the_session_token() {
+        let source = S3Credentials {
+            session_token: Some("token".to_string()),
+            ..S3Credentials::from_keys("key", "secret")
+        };
+        let (credentials, from) = resolve(&source).unwrap();
+        assert_eq!(from, "explicit keys");
+        assert_eq!(credentials.access_key.as_deref(), Some("key"));
+        assert_eq!(credentials.session_token.as_deref(), Some("token"));
+        assert_eq!(expires_in(&credentials), None);
+
+        let half = S3Credentials {
+            secret_key: None,
+            ..source
+        };
+        assert!(resolve(&half).is_err());
+    }
+
+    #[test]
+    fn container_credentials_include_expiry() {
+        let body = br#"{
+            "AccessKeyId": "ASIAEXAMPLE",
+            "SecretAccessKey": "secret",
+            "Token": "session",
+            "Expiration": "2099-01-01T00:00:00Z"
+        }"#;
+        let credentials = parse_container_credentials(body).unwrap();
+        assert_eq!(credentials.access_key.as_deref(), Some("ASIAEXAMPLE"));
+        assert_eq!(credentials.session_token.as_deref(), Some("session"));
+        assert!(expires_in(&credentials).unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn refreshing_bucket_replaces_lapsing_credentials() {
+        let body = br#"{
+            "AccessKeyId": "ASIAOLD",
+            "SecretAccessKey": "secret",
+            "Token": "session",
+            "Expiration": "2000-01-01T00:00:00Z"
+        }"#;
+        let lapsed = parse_container_credentials(body).unwrap();
+        let region = Region::Custom {
+            region: "us-east-1".to_string(),
+            endpoint: "http://127.0.0.1:9".to_string(),
+        };
+        let bucket = *Bucket::new("books", region, lapsed).unwrap();
+
+        let refreshing = RefreshingBucket::new(bucket, S3Credentials::from_keys("key", "new"));
+        let credentials = refreshing.current().await.credentials().await.unwrap();
+        assert_eq!(credentials.access_key.as_deref(), Some("key"));
+        assert_eq!(expires_in(&credentials), None);
+    }
+}
@@ -1,19 +1,25 @@
 use anyhow::{Context, Result};
+use futures_util::future::BoxFuture;
 use s3::Bucket;
 use sha2::{Digest, Sha256};
-use std::future::Future;
-use std::path::Path;
-use std::pin::Pin;
-
-use super::scanner::{S3Object, title_from_key};
-use crate::covers::{generate_epub_cover_from_bytes, generate_pdf_cover_from_bytes};
-use crate::db::{Database, NewBook, UpdateBook, unix_now};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::covers::CoverTarget;
+use super::range::{BucketRange, RangedObject};
+use super::scanner::{S3Object, head_object, title_from_key};
+use crate::covers::{generate_epub_cover_from_reader, generate_pdf_cover_from_reader};
+use crate::db::{
+    BookRow, COVER_SOURCE_GENERATED, COVER_SOURCE_USER, Database, EventKind, NewBook, UpdateBook,
+    unix_now,
+};
+use crate::extractors::BookMetadata;
+use crate::extractors::epub::extract_epub_metadata_from_reader;
+use crate::extractors::pdf::extract_pdf_metadata_from_reader;
 use crate::log::log;
 
-type FetchFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send + 'a>>;
-
 /// Download bytes for an S3 object (fully buffered).
-async fn fetch_object_bytes(bucket: &Bucket, key: &str) -> Result<Vec<u8>> {
+pub(crate) async fn fetch_object_bytes(bucket: &Bucket, key: &str) -> Result<Vec<u8>> {
     let response = bucket
         .get_object(key)
         .await
@@ -30,27 +36,30 @@ async fn fetch_object_bytes(bucket: &Bucket, key: &str) -> Result<Vec<u8>> {
     Ok(response.to_vec())
 }
 
-trait ObjectBytesFetcher {
-    fn fetch_object_bytes<'a>(&'a self, key: &'a str) -> FetchFuture<'a>;
+trait ObjectSource {
+    /// Open `object` for ranged reads; nothing is fetched until it is read.
+    fn open(&self, object: &S3Object) -> RangedObject;
+
+    /// Whether an object is still stored at `key`.
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>>;
 }
 
-struct BucketObjectFetcher<'a> {
+struct BucketObjectSource<'a> {
     bucket: &'a Bucket,
 }
 
-impl ObjectBytesFetcher for BucketObjectFetcher<'_> {
-    fn fetch_object_bytes<'a>(&'a self, key: &'a str) -> FetchFuture<'a> {
-        Box::pin(fetch_object_bytes(self.bucket, key))
+impl ObjectSource for BucketObjectSource<'_> {
+    fn open(&self, object: &S3Object) -> RangedObject {
+        let range = BucketRange::new(self.bucket, &object.key, object.size);
+        RangedObject::new(Arc::new(range), object.size)
     }
-}
 
-fn compute_sha256(bytes: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(bytes);
-    format!("{:x}", hasher.finalize())
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move { Ok(head_object(self.bucket, key).await?.is_some()) })
+    }
 }
 
-fn file_type_from_key(key: &str) -> &str {
+fn file_type_from_key(key: &str) -> &'static str {
     if key.to_lowercase().ends_with(".pdf") {
         "pdf"
     } else {
@@ -58,70 +67,215 @@ fn file_type_from_key(key: &str) -> &str {
     }
 }
 
-/// Process a newly discovered S3 object: download, extract metadata, generate cover, insert into DB.
+/// Run `f` on the blocking pool: reads through a [`RangedObject`] block on
+/// ranged GETs, and extraction and cover rendering are CPU-bound anyway.
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .context("S3 extraction task failed")
+}
+
+async fn sha256(object: &RangedObject) -> Result<String> {
+    let object = object.clone();
+    blocking(move || object.sha256()).await?
+}
+
+/// What an S3 book stores as `file_hash`: a digest of the object's ETag and
+/// size rather than of its content, so it is known without downloading the
+/// object. It changes whenever the object is replaced.
+fn fingerprint(object: &S3Object) -> String {
+    let digest = Sha256::digest(format!("{}:{}", object.size, object.etag));
+    format!("{digest:x}")
+}
+
+/// A missing book from any source whose content hashes the same as the
+/// object's. The object is only hashed when a missing book has its size.
+async fn find_missing_by_content(db: &Database, ranged: &RangedObject) -> Result<Option<BookRow>> {
+    let candidates = db.find_missing_by_size(ranged.size() as i64)?;
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let hash = sha256(ranged).await?;
+    Ok(candidates.into_iter().find(|book| book.file_hash == hash))
+}
+
+/// Bytes at each end of a PDF fetched before pdfium opens it: the trailer and
+/// xref at the end, and for linearized files the first pages at the start.
+const PDF_PREFETCH_BYTES: u64 = 512 * 1024;
+
+/// Extract metadata and, when `covers_dir` is given, generate a cover. EPUBs
+/// cost their central directory plus the OPF and cover entries; PDFs their
+/// trailer, xref, Info dictionary and first pages.
+///
+/// PDF reads that pdfium makes beyond the prefetched ends happen while the
+/// shared PDF worker waits, and a read that fails fails the object, so it
+/// is retried instead of being added without metadata or cover.
+async fn extract(
+    object: &RangedObject,
+    file_type: &'static str,
+    fallback_title: String,
+    book_id: &str,
+    covers_dir: Option<&Path>,
+) -> Result<(BookMetadata, Option<PathBuf>)> {
+    let object = object.clone();
+    let book_id = book_id.to_string();
+    let covers_dir = covers_dir.map(Path::to_path_buf);
+    blocking(move || {
+        if file_type != "pdf" {
+            let metadata = extract_epub_metadata_from_reader(object.reader(), &fallback_title);
+            let title = metadata.title.as_str();
+            let author = metadata.author.as_deref();
+            let cover = covers_dir.and_then(|dir| {
+                generate_epub_cover_from_reader(object.reader(), &book_id, title, author, &dir)
+            });
+            return Ok((metadata, cover));
+        }
+
+        let size = object.size();
+        object.prefetch(0, PDF_PREFETCH_BYTES)?;
+        object.prefetch(size.saturating_sub(PDF_PREFETCH_BYTES), size)?;
+        let metadata =
+            extract_pdf_metadata_from_reader(Box::new(object.reader()), &fallback_title)?;
+        let cover = match covers_dir {
+            Some(dir) => generate_pdf_cover_from_reader(
+                Box::new(object.reader()),
+                &book_id,
+                &metadata.title,
+                metadata.author.as_deref(),
+                &dir,
+            )?,
+            None => None,
+        };
+        Ok((metadata, cover))
+    })
+    .await?
+}
+
+/// How much of the object was fetched, for the log line.
+fn read_summary(object: &RangedObject) -> String {
+    let (requests, bytes) = object.fetched();
+    format!(
+        "read {} KiB of {} KiB in {} request(s)",
+        bytes.div_ceil(1024),
+        object.size().div_ceil(1024),
+        requests
+    )
+}
+
+/// Where an object was found: the configured source and its bucket.
+#[derive(Clone, Copy)]
+pub struct S3Origin<'a> {
+    pub source: &'a str,
+    pub bucket: &'a str,
+}
+
+/// Process a newly discovered S3 object: read its metadata through ranged
+/// GETs, generate a cover and insert it into the DB. The object is known by
+/// its ETag and size; its content is only hashed to match a missing book.
 pub async fn handle_s3_add(
     bucket: &Bucket,
     object: &S3Object,
     db: &Database,
-    bucket_name: &str,
-    covers_dir: &Path,
+    origin: S3Origin<'_>,
+    covers: CoverTarget<'_>,
 ) -> Result<()> {
-    let fetcher = BucketObjectFetcher { bucket };
-    handle_s3_add_with_fetcher(&fetcher, object, db, bucket_name, covers_dir).await
+    let source = BucketObjectSource { bucket };
+    handle_s3_add_from_source(&source, object, db, origin, covers).await
 }
 
-async fn handle_s3_add_with_fetcher(
-    fetcher: &dyn ObjectBytesFetcher,
+async fn handle_s3_add_from_source(
+    source: &dyn ObjectSource,
     object: &S3Object,
     db: &Database,
-    bucket_name: &str,
-    covers_dir: &Path,
+    origin: S3Origin<'_>,
+    covers: CoverTarget<'_>,
 ) -> Result<()> {
     let file_type = file_type_from_key(&object.key);
     let fallback_title = title_from_key(&object.key);
 
-    let bytes = fetcher.fetch_object_bytes(&object.key).await?;
-    if bytes.is_empty() {
+    if object.size == 0 {
         log(&format!("[S3] [SKIP] Zero-byte object: {}", object.key));
         return Ok(());
     }
 
-    let file_hash = compute_sha256(&bytes);
+    let file_hash = fingerprint(object);
+
+    if let Some(existing) = db.find_s3_copy(origin.source, &object.etag, object.size as i64)? {
+        // Same upload whose old key is gone: the object was moved, not copied.
+        if !source.exists(&existing.file_path).await? {
+            db.move_book(&existing.id, &object.key)?;
+            log(&format!(
+                "[S3] [MOVE] \"{}\" -> {}",
+                existing.title, object.key
+            ));
+            db.record_event(EventKind::Moved, &existing.id, &["file_path"])?;
+            return Ok(());
+        }
 
-    if let Some(existing_title) = db.find_by_hash(&file_hash)? {
+        db.record_skipped_object(origin.source, &object.key, &object.etag, &existing.id)?;
         log(&format!(
             "[S3] [SKIP] Duplicate (matches \"{}\"): {}",
-            existing_title, object.key
+            existing.title, object.key
         ));
         return Ok(());
     }
+    db.forget_skipped_object(origin.source, &object.key)?;
 
-    let book_id = uuid::Uuid::new_v4().to_string();
+    let ranged = source.open(object);
 
-    let metadata = if file_type == "pdf" {
-        crate::extractors::pdf::extract_pdf_metadata_from_bytes(&bytes, &fallback_title)
+    // The same upload, then whatever was at the key (re-extracted, as the
+    // object may have been replaced), then the same content elsewhere.
+    let restore = if let Some(book) = db.find_missing_s3_copy(&object.etag, object.size as i64)? {
+        Some((book, true))
+    } else if let Some(book) = db.find_missing_s3_key(origin.source, &object.key)? {
+        Some((book, false))
     } else {
-        crate::extractors::epub::extract_epub_metadata_from_bytes(&bytes, &fallback_title)
+        find_missing_by_content(db, &ranged)
+            .await?
+            .map(|book| (book, true))
     };
+    if let Some((missing, same_content)) = restore {
+        // The object came back within the retention window; keep its user data.
+        db.restore_book(&missing.id, "s3", &object.key)?;
+        db.set_s3_source(&missing.id, origin.source, origin.bucket)?;
+        log(&format!(
+            "[S3] [RESTORE] \"{}\" -> {}",
+            missing.title, object.key
+        ));
+        let mut fields = Vec::new();
+        if missing.file_path != object.key {
+            fields.push("file_path");
+        }
+        if missing.source != "s3" {
+            fields.push("source");
+        }
+        db.record_event(EventKind::Restored, &missing.id, &fields)?;
 
-    let cover_path = if file_type == "pdf" {
-        generate_pdf_cover_from_bytes(
-            &bytes,
-            &book_id,
-            &metadata.title,
-            metadata.author.as_deref(),
-            covers_dir,
-        )
-    } else {
-        generate_epub_cover_from_bytes(
-            &bytes,
-            &book_id,
-            &metadata.title,
-            metadata.author.as_deref(),
-            covers_dir,
-        )
-    };
-    let cover_path_str = cover_path.as_ref().and_then(|p| p.to_str());
+        if !same_content {
+            return Box::pin(handle_s3_change_from_source(
+                source, object, db, origin, covers,
+            ))
+            .await;
+        }
+        db.set_s3_object(&missing.id, &object.etag, &file_hash)?;
+        return Ok(());
+    }
+
+    let book_id = uuid::Uuid::new_v4().to_string();
+
+    let (metadata, cover_path) = extract(
+        &ranged,
+        file_type,
+        fallback_title,
+        &book_id,
+        Some(covers.dir),
+    )
+    .await?;
+    let cover_path = covers.publish(cover_path).await?;
 
     let now = unix_now();
 
@@ -132,158 +286,159 @@ async fn handle_s3_add_with_fetcher(
         description: metadata.description.as_deref(),
         file_type,
         file_path: &object.key,
-        file_size: bytes.len() as i64,
+        file_size: object.size as i64,
         file_hash: &file_hash,
-        cover_path: cover_path_str,
+        cover_path: cover_path.as_deref(),
+        cover_source: COVER_SOURCE_GENERATED,
         page_count: metadata.page_count.map(|p| p as i64),
         added_at: now,
         updated_at: now,
         source: "s3",
-        s3_bucket: Some(bucket_name),
+        s3_source: Some(origin.source),
+        s3_bucket: Some(origin.bucket),
         s3_etag: Some(&object.etag),
     })?;
 
     if changes == 0 {
+        // Another copy of the same upload was added concurrently.
+        if let Some(cover) = &cover_path {
+            covers.remove(cover).await;
+        }
+        if let Some(existing) = db.find_s3_copy(origin.source, &object.etag, object.size as i64)? {
+            db.record_skipped_object(origin.source, &object.key, &object.etag, &existing.id)?;
+        }
         log(&format!("[S3] [SKIP] Already exists: {}", object.key));
         return Ok(());
     }
 
     log(&format!(
-        "[S3] [OK] Added \"{}\" ({})",
-        metadata.title, file_type
+        "[S3] [OK] Added \"{}\" ({}, {})",
+        metadata.title,
+        file_type,
+        read_summary(&ranged)
     ));
-    db.increment_library_version()?;
+    db.set_series(&book_id, metadata.series.as_deref(), metadata.series_index)?;
+    db.record_event(EventKind::Added, &book_id, &[])?;
     Ok(())
 }
 
-/// Re-process an S3 object whose ETag changed: re-extract metadata and update DB.
+/// Re-process an S3 object whose ETag changed: re-extract metadata and update
+/// DB. A new ETag is taken as new content, since telling otherwise would mean
+/// downloading the whole object.
 pub async fn handle_s3_change(
     bucket: &Bucket,
     object: &S3Object,
     db: &Database,
-    bucket_name: &str,
-    covers_dir: &Path,
+    origin: S3Origin<'_>,
+    covers: CoverTarget<'_>,
 ) -> Result<()> {
-    let fetcher = BucketObjectFetcher { bucket };
-    handle_s3_change_with_fetcher(&fetcher, object, db, bucket_name, covers_dir).await
+    let source = BucketObjectSource { bucket };
+    handle_s3_change_from_source(&source, object, db, origin, covers).await
 }
 
-async fn handle_s3_change_with_fetcher(
-    fetcher: &dyn ObjectBytesFetcher,
+async fn handle_s3_change_from_source(
+    source: &dyn ObjectSource,
     object: &S3Object,
     db: &Database,
-    bucket_name: &str,
-    covers_dir: &Path,
+    origin: S3Origin<'_>,
+    covers: CoverTarget<'_>,
 ) -> Result<()> {
-    let book = match db.find_by_path(&object.key)? {
+    let book = match db.find_by_s3_key(origin.source, &object.key)? {
         Some(b) => b,
         None => {
             log(&format!(
                 "[S3] [INFO] Change for untracked key; adding: {}",
                 object.key
             ));
-            return handle_s3_add_with_fetcher(fetcher, object, db, bucket_name, covers_dir).await;
+            return handle_s3_add_from_source(source, object, db, origin, covers).await;
         }
     };
 
-    let bytes = fetcher.fetch_object_bytes(&object.key).await?;
-    let new_hash = compute_sha256(&bytes);
-
-    if new_hash == book.file_hash {
-        // ETag changed but content didn't (can happen with re-uploads of same content).
-        // Just update the ETag.
-        db.update_s3_etag(&book.id, &object.etag)?;
-        log(&format!(
-            "[S3] [SKIP] Hash unchanged for \"{}\", updated ETag",
-            book.title
-        ));
-        return Ok(());
-    }
+    let ranged = source.open(object);
+    let new_hash = fingerprint(object);
 
     let file_type = file_type_from_key(&object.key);
     let fallback_title = title_from_key(&object.key);
 
-    let metadata = if file_type == "pdf" {
-        crate::extractors::pdf::extract_pdf_metadata_from_bytes(&bytes, &fallback_title)
+    // Pinned covers survive content changes.
+    let (metadata, generated_cover) = extract(
+        &ranged,
+        file_type,
+        fallback_title,
+        &book.id,
+        (!book.has_user_cover()).then_some(covers.dir),
+    )
+    .await?;
+
+    let cover_source = if book.has_user_cover() {
+        COVER_SOURCE_USER
     } else {
-        crate::extractors::epub::extract_epub_metadata_from_bytes(&bytes, &fallback_title)
+        COVER_SOURCE_GENERATED
     };
-
-    let cover_path = if file_type == "pdf" {
-        generate_pdf_cover_from_bytes(
-            &bytes,
-            &book.id,
-            &metadata.title,
-            metadata.author.as_deref(),
-            covers_dir,
-        )
+    let cover_path = if book.has_user_cover() {
+        book.cover_path.clone()
     } else {
-        generate_epub_cover_from_bytes(
-            &bytes,
-            &book.id,
-            &metadata.title,
-            metadata.author.as_deref(),
-            covers_dir,
-        )
+        covers.publish(generated_cover).await?
     };
-    let cover_path_str = cover_path.as_ref().and_then(|p| p.to_str());
 
     // Clean up old cover if replaced
-    if let Some(ref old_cover) = book.cover_path {
-        let should_delete = match cover_path.as_ref() {
-            Some(new_cover) => new_cover.to_string_lossy() != old_cover.as_str(),
-            None => true,
-        };
-        if should_delete {
-            let _ = std::fs::remove_file(old_cover);
-        }
+    if let Some(ref old_cover) = book.cover_path
+        && cover_path.as_ref() != Some(old_cover)
+    {
+        covers.remove(old_cover).await;
     }
 
     let now = unix_now();
 
-    db.update_book(
+    let mut changed = db.update_book(
         &book.id,
         &UpdateBook {
             title: &metadata.title,
             author: metadata.author.as_deref(),
             description: metadata.description.as_deref(),
-            file_size: bytes.len() as i64,
+            file_size: object.size as i64,
             file_hash: &new_hash,
-            cover_path: cover_path_str,
+            cover_path: cover_path.as_deref(),
+            cover_source,
             page_count: metadata.page_count.map(|p| p as i64),
             updated_at: now,
             s3_etag: Some(&object.etag),
         },
     )?;
+    if db.set_series(&book.id, metadata.series.as_deref(), metadata.series_index)? {
+        changed.push("series");
+    }
 
     log(&format!(
-        "[S3] [UPDATE] \"{}\" -> \"{}\" ({})",
-        book.title, metadata.title, book.file_type
+        "[S3] [UPDATE] \"{}\" -> \"{}\" ({}, {})",
+        book.title,
+        metadata.title,
+        book.file_type,
+        read_summary(&ranged)
     ));
-    db.increment_library_version()?;
+    db.record_event(EventKind::Updated, &book.id, &changed)?;
     Ok(())
 }
 
-/// Remove a book from the DB whose S3 object no longer exists.
+/// Mark a book missing whose S3 object no longer exists.
 pub fn handle_s3_delete(db: &Database, book: &crate::db::S3BookRow) -> Result<()> {
-    if let Some(ref cover_path) = book.cover_path {
-        let _ = std::fs::remove_file(cover_path);
-    }
-
-    db.delete_book(&book.id)?;
+    // The cover stays with the row until the book is purged.
+    db.mark_missing(&book.id)?;
 
     log(&format!(
-        "[S3] [DELETE] Removed \"{}\" from library",
+        "[S3] [DELETE] Marked \"{}\" as missing",
         book.title
     ));
-    db.increment_library_version()?;
+    db.record_event(EventKind::Deleted, &book.id, &[])?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::s3::range::RangeSource;
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use tempfile::tempdir;
 
     #[derive(Clone)]
@@ -295,6 +450,8 @@ mod tests {
     #[derive(Default)]
     struct MockFetcher {
         responses: HashMap<String, MockFetchResponse>,
+        /// Bytes read from every object opened through the fetcher.
+        bytes_read: Arc<AtomicU64>,
     }
 
     impl MockFetcher {
@@ -313,24 +470,48 @@ mod tests {
         }
     }
 
-    impl ObjectBytesFetcher for MockFetcher {
-        fn fetch_object_bytes<'a>(&'a self, key: &'a str) -> FetchFuture<'a> {
-            let response = self.responses.get(key).cloned();
-            Box::pin(async move {
-                match response {
-                    Some(MockFetchResponse::Bytes(bytes)) => Ok(bytes),
-                    Some(MockFetchResponse::Error(message)) => anyhow::bail!(message),
-                    None => anyhow::bail!("No mock response configured for key: {key}"),
+    struct MockRange(MockFetchResponse, Arc<AtomicU64>);
+
+    impl RangeSource for MockRange {
+        fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+            match &self.0 {
+                MockFetchResponse::Bytes(bytes) => {
+                    self.1.fetch_add(end - start, Ordering::Relaxed);
+                    Ok(bytes[start as usize..end as usize].to_vec())
                 }
-            })
+                MockFetchResponse::Error(message) => anyhow::bail!(message.clone()),
+            }
         }
     }
 
+    impl ObjectSource for MockFetcher {
+        fn open(&self, object: &S3Object) -> RangedObject {
+            let response = self.responses.get(&object.key).cloned().unwrap_or_else(|| {
+                MockFetchResponse::Error(format!(
+                    "No mock response configured for key: {}",
+                    object.key
+                ))
+            });
+            let range = MockRange(response, Arc::clone(&self.bytes_read));
+            RangedObject::new(Arc::new(range), object.size)
+        }
+
+        fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool>> {
+            Box::pin(std::future::ready(Ok(self.responses.contains_key(key))))
+        }
+    }
+
+    const ORIGIN: S3Origin<'static> = S3Origin {
+        source: "source-a",
+        bucket: "bucket-a",
+    };
+
     fn s3_object(key: &str, etag: &str, size: u64) -> S3Object {
         S3Object {
             key: key.to_string(),
             size,
             etag: etag.to_string(),
+            last_modified: None,
         }
     }
 
@@ -342,12 +523,12 @@ mod tests {
         let bytes = b"fake-pdf-bytes";
         let fetcher = MockFetcher::default().with_bytes(key, bytes);
 
-        handle_s3_add_with_fetcher(
+        handle_s3_add_from_source(
             &fetcher,
             &s3_object(key, "etag-1", bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("add should succeed");
@@ -359,7 +540,7 @@ mod tests {
         assert_eq!(book.file_type, "pdf");
         assert_eq!(book.title, "new-book");
 
-        let s3_books = db.find_s3_books("bucket-a").expect("query s3 books");
+        let s3_books = db.find_s3_books("source-a").expect("query s3 books");
         assert_eq!(s3_books.len(), 1);
         assert_eq!(s3_books[0].file_path, key);
         assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-1"));
@@ -372,19 +553,19 @@ mod tests {
         let key = "library/empty.pdf";
         let fetcher = MockFetcher::default().with_bytes(key, &[]);
 
-        handle_s3_add_with_fetcher(
+        handle_s3_add_from_source(
             &fetcher,
             &s3_object(key, "etag-empty", 0),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("zero-byte add should not error");
 
         assert!(db.find_by_path(key).expect("query by path").is_none());
         assert!(
-            db.find_s3_books("bucket-a")
+            db.find_s3_books("source-a")
                 .expect("query s3 books")
                 .is_empty()
         );
@@ -397,12 +578,12 @@ mod tests {
         let key = "library/broken.pdf";
         let fetcher = MockFetcher::default().with_error(key, "simulated download failure");
 
-        let result = handle_s3_add_with_fetcher(
+        let result = handle_s3_add_from_source(
             &fetcher,
             &s3_object(key, "etag-broken", 5),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await;
 
@@ -412,14 +593,14 @@ mod tests {
         assert!(error_text.contains("simulated download failure"));
         assert!(db.find_by_path(key).expect("query by path").is_none());
         assert!(
-            db.find_s3_books("bucket-a")
+            db.find_s3_books("source-a")
                 .expect("query s3 books")
                 .is_empty()
         );
     }
 
     #[tokio::test]
-    async fn handle_s3_add_skips_duplicate_hash() {
+    async fn handle_s3_add_skips_copies_of_the_same_upload() {
         let db = Database::open_in_memory().expect("in-memory db");
         let covers_dir = tempdir().expect("covers tempdir");
         let key_a = "library/book-a.pdf";
@@ -429,31 +610,203 @@ mod tests {
             .with_bytes(key_a, same_bytes)
             .with_bytes(key_b, same_bytes);
 
-        handle_s3_add_with_fetcher(
+        handle_s3_add_from_source(
             &fetcher,
             &s3_object(key_a, "etag-a", same_bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("first add should succeed");
-        handle_s3_add_with_fetcher(
+        handle_s3_add_from_source(
             &fetcher,
-            &s3_object(key_b, "etag-b", same_bytes.len() as u64),
+            &s3_object(key_b, "etag-a", same_bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("duplicate add should not error");
 
         assert!(db.find_by_path(key_a).expect("query key_a").is_some());
         assert!(db.find_by_path(key_b).expect("query key_b").is_none());
-        assert_eq!(
-            db.find_s3_books("bucket-a").expect("query s3 books").len(),
-            1
+        let books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(books.len(), 1);
+        let skipped = db.skipped_objects("source-a").expect("query skipped");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].key, key_b);
+        assert_eq!(skipped[0].duplicate_of, books[0].id);
+
+        // Once the book is gone, its copy is processed again.
+        handle_s3_delete(&db, &books[0]).expect("delete should succeed");
+        assert!(db.skipped_objects("source-a").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn handle_s3_add_moves_a_book_whose_old_key_is_gone() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let bytes = b"moved-content";
+        let before = MockFetcher::default().with_bytes("inbox/book.pdf", bytes);
+        let after = MockFetcher::default().with_bytes("shelf/book.pdf", bytes);
+
+        handle_s3_add_from_source(
+            &before,
+            &s3_object("inbox/book.pdf", "etag-1", bytes.len() as u64),
+            &db,
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("first add should succeed");
+        let book = db.find_by_path("inbox/book.pdf").unwrap().expect("book");
+
+        handle_s3_add_from_source(
+            &after,
+            &s3_object("shelf/book.pdf", "etag-1", bytes.len() as u64),
+            &db,
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("moved add should succeed");
+
+        let books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].id, book.id);
+        assert_eq!(books[0].file_path, "shelf/book.pdf");
+        let event = db.events_since(0, 10).unwrap().pop().expect("event");
+        assert_eq!(event.kind, "moved");
+        assert!(db.skipped_objects("source-a").unwrap().is_empty());
+        // The new key needs no further reads: the book was already extracted.
+        assert_eq!(after.bytes_read.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn handle_s3_add_restores_missing_local_book_with_same_content() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let key = "library/uploaded.pdf";
+        let bytes = b"book-content";
+        let file_hash = format!("{:x}", Sha256::digest(bytes));
+        db.insert_book(&NewBook {
+            id: "local-book",
+            title: "Local Book",
+            author: None,
+            description: None,
+            file_type: "pdf",
+            file_path: "/library/uploaded.pdf",
+            file_size: bytes.len() as i64,
+            file_hash: &file_hash,
+            cover_path: None,
+            cover_source: "generated",
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "local",
+            s3_source: None,
+            s3_bucket: None,
+            s3_etag: None,
+        })
+        .expect("insert local book");
+        db.mark_missing("local-book").expect("mark missing");
+
+        let fetcher = MockFetcher::default().with_bytes(key, bytes);
+        handle_s3_add_from_source(
+            &fetcher,
+            &s3_object(key, "etag-1", bytes.len() as u64),
+            &db,
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("add should succeed");
+
+        let s3_books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(s3_books.len(), 1);
+        assert_eq!(s3_books[0].id, "local-book");
+        assert_eq!(s3_books[0].file_path, key);
+        assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-1"));
+        let event = db
+            .events_since(0, 10)
+            .expect("events")
+            .pop()
+            .expect("event");
+        assert_eq!(event.kind, "restored");
+        assert_eq!(event.fields, vec!["file_path", "source"]);
+    }
+
+    /// A one-page PDF with a title in its Info dictionary.
+    fn minimal_pdf(title: &str) -> Vec<u8> {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 200 300] >>".to_string(),
+            format!("<< /Title ({title}) >>"),
+        ];
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (index, body) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{body}\nendobj\n", index + 1).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{offset:010} 00000 n \n").as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R /Info 4 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+                objects.len() + 1
+            )
+            .as_bytes(),
         );
+        pdf
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn handle_s3_add_processes_pdfs_concurrently() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let objects: Vec<(S3Object, Vec<u8>)> = (0..6)
+            .map(|index| {
+                let bytes = minimal_pdf(&format!("Book {index}"));
+                let key = format!("library/book-{index}.pdf");
+                let etag = format!("etag-{index}");
+                (s3_object(&key, &etag, bytes.len() as u64), bytes)
+            })
+            .collect();
+        let fetcher = objects
+            .iter()
+            .fold(MockFetcher::default(), |fetcher, (object, bytes)| {
+                fetcher.with_bytes(&object.key, bytes)
+            });
+
+        let results = futures_util::future::join_all(objects.iter().map(|(object, _)| {
+            handle_s3_add_from_source(
+                &fetcher,
+                object,
+                &db,
+                ORIGIN,
+                CoverTarget::local(covers_dir.path()),
+            )
+        }))
+        .await;
+        for result in results {
+            result.expect("every PDF should be added");
+        }
+
+        let books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(books.len(), objects.len());
+        for book in &books {
+            assert_eq!(book.file_type, "pdf");
+            let cover = book.cover_path.as_deref().expect("PDF books get a cover");
+            assert!(Path::new(cover).is_file(), "{cover}");
+        }
     }
 
     #[tokio::test]
@@ -464,64 +817,168 @@ mod tests {
         let bytes = b"fake-epub-bytes";
         let fetcher = MockFetcher::default().with_bytes(key, bytes);
 
-        handle_s3_change_with_fetcher(
+        handle_s3_change_from_source(
             &fetcher,
             &s3_object(key, "etag-1", bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("change should route to add");
 
         let inserted = db.find_by_path(key).expect("query by path");
         assert!(inserted.is_some());
-        let s3_books = db.find_s3_books("bucket-a").expect("query s3 books");
+        let s3_books = db.find_s3_books("source-a").expect("query s3 books");
         assert_eq!(s3_books.len(), 1);
         assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-1"));
     }
 
     #[tokio::test]
-    async fn handle_s3_change_unchanged_hash_only_updates_etag() {
+    async fn same_key_in_another_source_is_another_book() {
         let db = Database::open_in_memory().expect("in-memory db");
         let covers_dir = tempdir().expect("covers tempdir");
-        let key = "library/unchanged.pdf";
-        let bytes = b"same-bytes";
-        let fetcher = MockFetcher::default().with_bytes(key, bytes);
+        let key = "library/shared.pdf";
+        let other = S3Origin {
+            source: "source-b",
+            bucket: "bucket-b",
+        };
 
-        handle_s3_add_with_fetcher(
+        let fetcher = MockFetcher::default().with_bytes(key, b"first-bytes");
+        handle_s3_add_from_source(
             &fetcher,
-            &s3_object(key, "etag-1", bytes.len() as u64),
+            &s3_object(key, "etag-a", 11),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
-        .expect("initial add should succeed");
-        let before = db
-            .find_by_path(key)
-            .expect("query before")
-            .expect("book before");
+        .expect("add to source-a");
+        let fetcher = MockFetcher::default().with_bytes(key, b"second-bytes");
+        handle_s3_change_from_source(
+            &fetcher,
+            &s3_object(key, "etag-b", 12),
+            &db,
+            other,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("change in source-b");
+
+        let a = db.find_s3_books("source-a").expect("query source-a");
+        let b = db.find_s3_books("source-b").expect("query source-b");
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].s3_etag.as_deref(), Some("etag-a"));
+        assert_eq!(b.len(), 1);
+        assert_eq!(b[0].s3_etag.as_deref(), Some("etag-b"));
+    }
+
+    #[tokio::test]
+    async fn same_content_in_another_source_is_another_book() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let bytes = b"shared-content";
+        let file_hash = format!("{:x}", Sha256::digest(bytes));
+        db.insert_book(&NewBook {
+            id: "local-book",
+            title: "Local Book",
+            author: None,
+            description: None,
+            file_type: "pdf",
+            file_path: "/library/shared.pdf",
+            file_size: bytes.len() as i64,
+            file_hash: &file_hash,
+            cover_path: None,
+            cover_source: "generated",
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "local",
+            s3_source: None,
+            s3_bucket: None,
+            s3_etag: None,
+        })
+        .expect("insert local book");
+        let other = S3Origin {
+            source: "source-b",
+            bucket: "bucket-b",
+        };
+
+        let fetcher = MockFetcher::default()
+            .with_bytes("a/shared.pdf", bytes)
+            .with_bytes("b/shared.pdf", bytes);
+        for (key, origin) in [("a/shared.pdf", ORIGIN), ("b/shared.pdf", other)] {
+            handle_s3_add_from_source(
+                &fetcher,
+                &s3_object(key, "etag", bytes.len() as u64),
+                &db,
+                origin,
+                CoverTarget::local(covers_dir.path()),
+            )
+            .await
+            .expect("add should succeed");
+        }
 
-        handle_s3_change_with_fetcher(
+        let a = db.find_s3_books("source-a").expect("query source-a");
+        let b = db.find_s3_books("source-b").expect("query source-b");
+        assert_eq!(a.len(), 1);
+        assert_eq!(b.len(), 1);
+        assert_ne!(a[0].id, b[0].id);
+        assert!(db.find_by_path("/library/shared.pdf").unwrap().is_some());
+    }
+
+    /// An EPUB with a title and `padding` bytes of stored images.
+    fn large_epub(title: &str, padding: usize) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file("META-INF/container.xml", stored).unwrap();
+        zip.write_all(
+            br#"<container><rootfiles><rootfile full-path="content.opf"/></rootfiles></container>"#,
+        )
+        .unwrap();
+        zip.start_file("images/plates.bin", stored).unwrap();
+        zip.write_all(&vec![7u8; padding]).unwrap();
+        zip.start_file("content.opf", stored).unwrap();
+        zip.write_all(
+            format!(
+                r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>{title}</dc:title></metadata></package>"#
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn handle_s3_add_reads_only_what_extraction_needs() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let key = "library/large.epub";
+        let bytes = large_epub("Large Book", 8 * 1024 * 1024);
+        let fetcher = MockFetcher::default().with_bytes(key, &bytes);
+
+        handle_s3_add_from_source(
             &fetcher,
-            &s3_object(key, "etag-2", bytes.len() as u64),
+            &s3_object(key, "etag-1", bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
-        .expect("change should succeed");
+        .expect("add should succeed");
 
-        let after = db
+        let book = db
             .find_by_path(key)
-            .expect("query after")
-            .expect("book after");
-        assert_eq!(after.file_hash, before.file_hash);
-        assert_eq!(after.title, before.title);
-
-        let s3_books = db.find_s3_books("bucket-a").expect("query s3 books");
-        assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-2"));
+            .expect("query by path")
+            .expect("book should exist");
+        assert_eq!(book.title, "Large Book");
+        let read = fetcher.bytes_read.load(Ordering::Relaxed);
+        assert!(read < bytes.len() as u64 / 4, "read {read} bytes");
     }
 
     #[tokio::test]
@@ -534,12 +991,12 @@ mod tests {
         let fetcher_initial = MockFetcher::default().with_bytes(key, original_bytes);
         let fetcher_updated = MockFetcher::default().with_bytes(key, updated_bytes);
 
-        handle_s3_add_with_fetcher(
+        handle_s3_add_from_source(
             &fetcher_initial,
             &s3_object(key, "etag-1", original_bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("initial add should succeed");
@@ -548,12 +1005,12 @@ mod tests {
             .expect("query before")
             .expect("book before");
 
-        handle_s3_change_with_fetcher(
+        handle_s3_change_from_source(
             &fetcher_updated,
             &s3_object(key, "etag-2", updated_bytes.len() as u64),
             &db,
-            "bucket-a",
-            covers_dir.path(),
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
         )
         .await
         .expect("changed content update should succeed");
@@ -564,12 +1021,57 @@ mod tests {
             .expect("book after");
         assert_ne!(after.file_hash, before.file_hash);
 
-        let s3_books = db.find_s3_books("bucket-a").expect("query s3 books");
+        let s3_books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-2"));
+    }
+
+    #[tokio::test]
+    async fn handle_s3_add_restores_missing_book() {
+        let db = Database::open_in_memory().expect("in-memory db");
+        let covers_dir = tempdir().expect("covers tempdir");
+        let key = "library/flaky.pdf";
+        let bytes = b"flaky-content";
+        let fetcher = MockFetcher::default().with_bytes(key, bytes);
+
+        handle_s3_add_from_source(
+            &fetcher,
+            &s3_object(key, "etag-1", bytes.len() as u64),
+            &db,
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("initial add should succeed");
+        let book = db
+            .find_s3_books("source-a")
+            .expect("query s3 books")
+            .remove(0);
+
+        handle_s3_delete(&db, &book).expect("delete should succeed");
+        assert!(
+            db.find_s3_books("source-a")
+                .expect("query s3 books")
+                .is_empty()
+        );
+
+        handle_s3_add_from_source(
+            &fetcher,
+            &s3_object(key, "etag-2", bytes.len() as u64),
+            &db,
+            ORIGIN,
+            CoverTarget::local(covers_dir.path()),
+        )
+        .await
+        .expect("re-add should restore");
+
+        let s3_books = db.find_s3_books("source-a").expect("query s3 books");
+        assert_eq!(s3_books.len(), 1);
+        assert_eq!(s3_books[0].id, book.id);
         assert_eq!(s3_books[0].s3_etag.as_deref(), Some("etag-2"));
     }
 
     #[test]
-    fn handle_s3_delete_removes_row_and_increments_library_version() {
+    fn handle_s3_delete_marks_missing_and_increments_library_version() {
         let db = Database::open_in_memory().expect("in-memory db");
         let now = unix_now();
         let key = "library/delete-me.pdf";
@@ -584,10 +1086,12 @@ mod tests {
             file_size: 11,
             file_hash: "hash-delete-me",
             cover_path: None,
+            cover_source: COVER_SOURCE_GENERATED,
             page_count: None,
             added_at: now,
             updated_at: now,
             source: "s3",
+            s3_source: Some("source-a"),
             s3_bucket: Some("bucket-a"),
             s3_etag: Some("etag-delete"),
         })
@@ -599,7 +1103,7 @@ mod tests {
         );
 
         let book = db
-            .find_s3_books("bucket-a")
+            .find_s3_books("source-a")
             .expect("query s3 books")
             .into_iter()
             .find(|row| row.file_path == key)
@@ -1,17 +1,68 @@
 pub mod client;
+pub mod covers;
+pub mod credentials;
 pub mod handlers;
+pub mod presign;
+pub mod range;
 pub mod scanner;
+pub mod sources;
 pub mod stream;
+pub mod sync;
 pub mod watcher;
+pub mod webhook;
+
+/// Name of the source configured with `S3_BUCKET` rather than `S3_SOURCES`.
+pub const DEFAULT_SOURCE: &str = "default";
 
 /// Configuration for connecting to an S3-compatible bucket.
 #[derive(Debug, Clone)]
 pub struct S3Config {
+    /// Recorded on each book as `s3_source`; diffs are scoped to it.
+    pub name: String,
     pub endpoint: Option<String>,
     pub region: String,
     pub bucket: String,
-    pub access_key: String,
-    pub secret_key: String,
-    pub prefix: Option<String>,
+    pub credentials: credentials::S3Credentials,
+    /// Key prefixes to watch; empty watches the whole bucket.
+    pub prefixes: Vec<String>,
+    /// Keys matching any of these patterns (`*` and `?` wildcards) are left out.
+    pub exclude: Vec<String>,
     pub poll_interval: u64,
+    /// Objects processed at once during a scan.
+    pub concurrency: usize,
+    /// Seconds between full listing diffs; polls in between still list
+    /// everything but only process objects modified since the last one. 0
+    /// diffs the full listing every poll.
+    pub reconcile_interval: u64,
+    /// Upload generated covers here instead of keeping them in `COVERS_PATH`.
+    pub covers: Option<covers::CoverStoreConfig>,
+}
+
+impl S3Config {
+    /// Whether `key` is under one of the prefixes and not excluded.
+    pub fn accepts(&self, key: &str) -> bool {
+        let in_prefix =
+            self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p.as_str()));
+        in_prefix
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| scanner::matches_pattern(pattern, key))
+    }
+}
+
+/// The configured source a book came from: by the name recorded on it or,
+/// for books recorded before sources had names, by bucket. A lone source is
+/// used for everything, as single-bucket setups always were.
+pub fn source_for<'a>(
+    sources: &'a [S3Config],
+    name: Option<&str>,
+    bucket: Option<&str>,
+) -> Option<&'a S3Config> {
+    name.and_then(|name| sources.iter().find(|s| s.name == name))
+        .or_else(|| bucket.and_then(|bucket| sources.iter().find(|s| s.bucket == bucket)))
+        .or(match sources {
+            [only] => Some(only),
+            _ => None,
+        })
 }
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use super::client::{PresignOptions, create_bucket, presign_get};
+use super::{S3Config, source_for};
+use crate::db::{BookFile, Database, unix_now};
+
+/// A presigned URL and when it stops working.
+#[derive(Debug, Serialize)]
+pub struct PresignedUrl {
+    pub bucket: String,
+    pub key: String,
+    pub url: String,
+    /// Unix seconds.
+    pub expires_at: i64,
+}
+
+/// URLs for a book's file and, when it is kept in a bucket, its cover.
+#[derive(Debug, Serialize)]
+pub struct BookUrls {
+    pub book_id: String,
+    pub file: PresignedUrl,
+    pub cover: Option<PresignedUrl>,
+}
+
+/// Presign `key` in the configured bucket.
+pub async fn presign_key(
+    config: &S3Config,
+    key: &str,
+    options: &PresignOptions,
+) -> Result<PresignedUrl> {
+    let bucket = create_bucket(config)?;
+    let url = presign_get(&bucket, key, options).await?;
+    Ok(PresignedUrl {
+        bucket: config.bucket.clone(),
+        key: key.to_string(),
+        url,
+        expires_at: unix_now() + i64::from(options.expires_in_secs),
+    })
+}
+
+/// Presign the file of an S3 book (or the bucket copy of a synced local
+/// book), with `options` applied, and its cover with only the expiry. The
+/// book's source supplies the endpoint and credentials; its own bucket is
+/// used, whatever the source's says.
+pub async fn presign_book(
+    db: &Database,
+    sources: &[S3Config],
+    book_id: &str,
+    options: &PresignOptions,
+) -> Result<BookUrls> {
+    let book = db
+        .find_book_file(book_id)?
+        .with_context(|| format!("Book not found: {}", book_id))?;
+    // Local books synced by `sync` are presigned through their bucket copy.
+    let key = match (book.source.as_str(), &book.s3_key) {
+        ("s3", _) => &book.file_path,
+        (_, Some(key)) => key,
+        _ => anyhow::bail!("Book {} is not stored in S3", book_id),
+    };
+
+    let config = source_for(
+        sources,
+        book.s3_source.as_deref(),
+        book.s3_bucket.as_deref(),
+    )
+    .with_context(|| format!("No S3 source configured for book {}", book_id))?;
+    let mut book_config = config.clone();
+    if let Some(bucket) = &book.s3_bucket {
+        book_config.bucket = bucket.clone();
+    }
+    let file = presign_key(&book_config, key, options).await?;
+
+    let cover = match cover_object(&book) {
+        Some((bucket, key)) => {
+            let cover_config = S3Config {
+                bucket: bucket.to_string(),
+                ..config.clone()
+            };
+            let cover_options = PresignOptions {
+                content_disposition: None,
+                content_type: None,
+                ..options.clone()
+            };
+            Some(presign_key(&cover_config, key, &cover_options).await?)
+        }
+        None => None,
+    };
+
+    Ok(BookUrls {
+        book_id: book.id,
+        file,
+        cover,
+    })
+}
+
+/// Covers kept in a bucket are recorded in `cover_path` as `s3://bucket/key`;
+/// anything else is a local file and can't be presigned.
+pub fn parse_s3_uri(path: &str) -> Option<(&str, &str)> {
+    path.strip_prefix("s3://")?
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+}
+
+fn cover_object(book: &BookFile) -> Option<(&str, &str)> {
+    book.cover_path.as_deref().and_then(parse_s3_uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_s3_uri;
+
+    #[test]
+    fn parse_s3_uri_splits_bucket_and_key() {
+        assert_eq!(
+            parse_s3_uri("s3://books/covers/abc.jpg"),
+            Some(("books", "covers/abc.jpg"))
+        );
+        assert_eq!(parse_s3_uri("/data/covers/abc.jpg"), None);
+        assert_eq!(parse_s3_uri("s3://books/"), None);
+        assert_eq!(parse_s3_uri("s3://books"), None);
+    }
+}
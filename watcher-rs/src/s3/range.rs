@@ -0,0 +1,422 @@
+use anyhow::{Context, Result, bail};
+use s3::Bucket;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+
+/// Smallest unit fetched and cached.
+pub const BLOCK_SIZE: u64 = 64 * 1024;
+/// Blocks kept per object (4 MiB).
+const CACHE_BLOCKS: usize = 64;
+/// Sequential reads double the fetch size up to this many blocks (1 MiB).
+const MAX_READAHEAD_BLOCKS: u64 = 16;
+/// Request size when hashing a whole object (8 MiB).
+const HASH_CHUNK: u64 = 128 * BLOCK_SIZE;
+/// Blocks at each end of the object that hashing leaves in the cache, since
+/// metadata and cover extraction read there first (512 KiB each).
+const KEPT_END_BLOCKS: u64 = 8;
+
+/// Random access to the bytes of one object.
+pub trait RangeSource: Send + Sync {
+    /// Bytes `start..end` of the object; `end` is at most its size.
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>>;
+}
+
+/// Ranged GETs against a bucket. Blocks the calling thread on the runtime
+/// it was created in, so reads must happen off the async workers (e.g. in
+/// `spawn_blocking` or on the pdfium thread).
+pub struct BucketRange {
+    bucket: Box<Bucket>,
+    key: String,
+    size: u64,
+    runtime: Handle,
+}
+
+impl BucketRange {
+    /// Must be called from within a tokio runtime.
+    pub fn new(bucket: &Bucket, key: &str, size: u64) -> Self {
+        Self {
+            bucket: Box::new(bucket.clone()),
+            key: key.to_string(),
+            size,
+            runtime: Handle::current(),
+        }
+    }
+}
+
+impl RangeSource for BucketRange {
+    fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+        // Open-ended when reading to EOF. rust-s3 asserts start < end, so a
+        // one-byte range in the middle is widened by a byte and trimmed.
+        let last = if end >= self.size {
+            None
+        } else if end - 1 == start {
+            Some(end)
+        } else {
+            Some(end - 1)
+        };
+        let response = self
+            .runtime
+            .block_on(self.bucket.get_object_range(&self.key, start, last))
+            .with_context(|| format!("Failed to read s3://{} at {}-{}", self.key, start, end))?;
+
+        let bytes = match response.status_code() {
+            206 => response.to_vec(),
+            // Servers that ignore Range send the whole object.
+            200 => response
+                .to_vec()
+                .get(start as usize..)
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default(),
+            status => bail!(
+                "S3 GetObject returned status {} for key: {}",
+                status,
+                self.key
+            ),
+        };
+        let wanted = (end - start) as usize;
+        if bytes.len() < wanted {
+            bail!(
+                "Short read from s3://{}: wanted {} bytes at {}, got {}",
+                self.key,
+                wanted,
+                start,
+                bytes.len()
+            );
+        }
+        Ok(bytes[..wanted].to_vec())
+    }
+}
+
+/// An object read in cached blocks. Clones share the cache, so metadata
+/// extraction and cover generation only fetch each block once.
+#[derive(Clone)]
+pub struct RangedObject {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    source: Arc<dyn RangeSource>,
+    size: u64,
+    cache: Mutex<BlockCache>,
+    requests: AtomicU64,
+    bytes_fetched: AtomicU64,
+}
+
+#[derive(Default)]
+struct BlockCache {
+    blocks: HashMap<u64, Arc<[u8]>>,
+    /// Least recently inserted first.
+    order: VecDeque<u64>,
+}
+
+impl RangedObject {
+    pub fn new(source: Arc<dyn RangeSource>, size: u64) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                source,
+                size,
+                cache: Mutex::new(BlockCache::default()),
+                requests: AtomicU64::new(0),
+                bytes_fetched: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.shared.size
+    }
+
+    /// A new `Read + Seek` cursor at offset 0.
+    pub fn reader(&self) -> RangeReader {
+        RangeReader {
+            object: self.clone(),
+            pos: 0,
+            next_block: u64::MAX,
+            readahead: 1,
+        }
+    }
+
+    /// `(requests, bytes)` fetched so far.
+    pub fn fetched(&self) -> (u64, u64) {
+        (
+            self.shared.requests.load(Ordering::Relaxed),
+            self.shared.bytes_fetched.load(Ordering::Relaxed),
+        )
+    }
+
+    /// SHA-256 of the whole object, to compare it with a local file's
+    /// `file_hash`. Streamed in large ranged GETs; the blocks at either end
+    /// stay cached for any extraction that follows.
+    pub fn sha256(&self) -> Result<String> {
+        let size = self.size();
+        let block_count = size.div_ceil(BLOCK_SIZE);
+        let mut hasher = Sha256::new();
+        let mut start = 0;
+        while start < size {
+            let end = (start + HASH_CHUNK).min(size);
+            let bytes = self.fetch(start, end)?;
+            hasher.update(&bytes);
+
+            let mut cache = self.shared.cache.lock().unwrap_or_else(|e| e.into_inner());
+            for (i, chunk) in bytes.chunks(BLOCK_SIZE as usize).enumerate() {
+                let index = start / BLOCK_SIZE + i as u64;
+                if index < KEPT_END_BLOCKS || index + KEPT_END_BLOCKS >= block_count {
+                    cache.insert(index, Arc::from(chunk));
+                }
+            }
+            start = end;
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Make sure bytes `start..end` are cached, in as few requests as the
+    /// cache allows. Lets a caller do the network reads on a thread of its
+    /// choosing before handing the object to a reader that must not wait.
+    pub fn prefetch(&self, start: u64, end: u64) -> Result<()> {
+        let end = end.min(self.size());
+        let mut index = start / BLOCK_SIZE;
+        while index * BLOCK_SIZE < end {
+            let span = (end - index * BLOCK_SIZE).div_ceil(BLOCK_SIZE);
+            self.block(index, span.min(CACHE_BLOCKS as u64 / 2))?;
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Bytes `start..end` straight from the source, counted in [`fetched`].
+    ///
+    /// [`fetched`]: RangedObject::fetched
+    fn fetch(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+        let bytes = self.shared.source.read_range(start, end)?;
+        self.shared.requests.fetch_add(1, Ordering::Relaxed);
+        self.shared
+            .bytes_fetched
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        Ok(bytes)
+    }
+
+    /// Block `index`, fetching it (and up to `span - 1` following uncached
+    /// blocks in the same request) if needed.
+    fn block(&self, index: u64, span: u64) -> Result<Arc<[u8]>> {
+        if let Some(block) = self.cached(index) {
+            return Ok(block);
+        }
+
+        let block_count = self.size().div_ceil(BLOCK_SIZE);
+        let mut last = index;
+        while last + 1 < block_count && last + 1 < index + span && self.cached(last + 1).is_none() {
+            last += 1;
+        }
+        let start = index * BLOCK_SIZE;
+        let end = ((last + 1) * BLOCK_SIZE).min(self.size());
+        let bytes = self.fetch(start, end)?;
+
+        let mut cache = self.shared.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let mut first = None;
+        for (i, chunk) in bytes.chunks(BLOCK_SIZE as usize).enumerate() {
+            let block: Arc<[u8]> = Arc::from(chunk);
+            first.get_or_insert_with(|| Arc::clone(&block));
+            cache.insert(index + i as u64, block);
+        }
+        first.context("Empty range read")
+    }
+
+    fn cached(&self, index: u64) -> Option<Arc<[u8]>> {
+        let cache = self.shared.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.blocks.get(&index).cloned()
+    }
+}
+
+impl BlockCache {
+    fn insert(&mut self, index: u64, block: Arc<[u8]>) {
+        if self.blocks.insert(index, block).is_none() {
+            self.order.push_back(index);
+        }
+        while self.order.len() > CACHE_BLOCKS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.blocks.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// `Read + Seek` over a [`RangedObject`], for `zip` and pdfium.
+pub struct RangeReader {
+    object: RangedObject,
+    pos: u64,
+    /// Block after the last one this reader fetched, to spot sequential reads.
+    next_block: u64,
+    readahead: u64,
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.object.size();
+        if buf.is_empty() || self.pos >= size {
+            return Ok(0);
+        }
+
+        let index = self.pos / BLOCK_SIZE;
+        let block = match self.object.cached(index) {
+            Some(block) => block,
+            None => {
+                self.readahead = if index == self.next_block {
+                    (self.readahead * 2).min(MAX_READAHEAD_BLOCKS)
+                } else {
+                    1
+                };
+                // Fetch at least as many blocks as the caller asked for.
+                let wanted = (buf.len() as u64).div_ceil(BLOCK_SIZE);
+                let span = self.readahead.max(wanted).min(CACHE_BLOCKS as u64 / 2);
+                self.next_block = index + span;
+                self.object.block(index, span).map_err(io::Error::other)?
+            }
+        };
+
+        let offset = (self.pos - index * BLOCK_SIZE) as usize;
+        let n = buf.len().min(block.len() - offset);
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for RangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.object.size() as i128;
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i128,
+            SeekFrom::End(offset) => size + offset as i128,
+            SeekFrom::Current(offset) => self.pos as i128 + offset as i128,
+        };
+        if target < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start of object",
+            ));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BLOCK_SIZE, RangeSource, RangedObject};
+    use anyhow::Result;
+    use sha2::{Digest, Sha256};
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorded {
+        bytes: Vec<u8>,
+        ranges: Mutex<Vec<(u64, u64)>>,
+    }
+
+    impl RangeSource for Recorded {
+        fn read_range(&self, start: u64, end: u64) -> Result<Vec<u8>> {
+            self.ranges.lock().unwrap().push((start, end));
+            Ok(self.bytes[start as usize..end as usize].to_vec())
+        }
+    }
+
+    fn object(bytes: Vec<u8>) -> (RangedObject, Arc<Recorded>) {
+        let size = bytes.len() as u64;
+        let source = Arc::new(Recorded {
+            bytes,
+            ..Recorded::default()
+        });
+        (RangedObject::new(source.clone(), size), source)
+    }
+
+    #[test]
+    fn reads_match_the_object_and_hit_the_cache() {
+        let bytes: Vec<u8> = (0..(3 * BLOCK_SIZE + 10)).map(|i| i as u8).collect();
+        let (object, source) = object(bytes.clone());
+
+        let mut reader = object.reader();
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, bytes[bytes.len() - 10..]);
+
+        let mut again = object.reader();
+        again.seek(SeekFrom::End(-5)).unwrap();
+        let mut byte = [0u8; 1];
+        again.read_exact(&mut byte).unwrap();
+        assert_eq!(byte[0], bytes[bytes.len() - 5]);
+
+        let ranges = source.ranges.lock().unwrap();
+        assert_eq!(*ranges, vec![(3 * BLOCK_SIZE, 3 * BLOCK_SIZE + 10)]);
+    }
+
+    #[test]
+    fn sha256_hashes_the_whole_object_and_keeps_its_ends() {
+        let bytes: Vec<u8> = (0..(300 * BLOCK_SIZE + 7))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let (object, source) = object(bytes.clone());
+        let expected = format!("{:x}", Sha256::digest(&bytes));
+        assert_eq!(object.sha256().unwrap(), expected);
+        assert_eq!(source.ranges.lock().unwrap().len(), 3);
+
+        // Extraction reads at both ends are served from the cache.
+        let mut reader = object.reader();
+        let mut head = [0u8; 16];
+        reader.read_exact(&mut head).unwrap();
+        reader.seek(SeekFrom::End(-16)).unwrap();
+        let mut tail = [0u8; 16];
+        reader.read_exact(&mut tail).unwrap();
+        assert_eq!(head, bytes[..16]);
+        assert_eq!(tail, bytes[bytes.len() - 16..]);
+        assert_eq!(source.ranges.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn prefetch_fills_the_cache_in_one_request() {
+        let bytes: Vec<u8> = (0..(10 * BLOCK_SIZE)).map(|i| i as u8).collect();
+        let (object, source) = object(bytes);
+        object.prefetch(0, 4 * BLOCK_SIZE).unwrap();
+        object.prefetch(BLOCK_SIZE, 3 * BLOCK_SIZE).unwrap();
+
+        let mut reader = object.reader();
+        let mut buf = vec![0u8; 4 * BLOCK_SIZE as usize];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(*source.ranges.lock().unwrap(), vec![(0, 4 * BLOCK_SIZE)]);
+    }
+
+    #[test]
+    fn zip_reads_only_the_directory_and_the_entry_it_needs() {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        zip.start_file("big.bin", stored).unwrap();
+        zip.write_all(&vec![7u8; 40 * BLOCK_SIZE as usize]).unwrap();
+        zip.start_file("small.txt", stored).unwrap();
+        zip.write_all(b"hello").unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        let (object, _) = object(bytes.clone());
+
+        let mut archive = zip::ZipArchive::new(object.reader()).unwrap();
+        let mut text = String::new();
+        archive
+            .by_name("small.txt")
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "hello");
+
+        // The end of the archive, the entry itself and the first local header.
+        let (_, fetched) = object.fetched();
+        assert!(
+            fetched <= 3 * BLOCK_SIZE,
+            "fetched {fetched} of {}",
+            bytes.len()
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use anyhow::{Context, Result};
 use s3::Bucket;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::db::S3BookRow;
+use super::S3Config;
+use crate::db::{S3BookRow, SkippedObject};
 
 /// An object found in S3 during a scan.
 #[derive(Debug, Clone)]
@@ -10,6 +11,8 @@ pub struct S3Object {
     pub key: String,
     pub size: u64,
     pub etag: String,
+    /// `LastModified` as unix seconds, when the server reported it.
+    pub last_modified: Option<i64>,
 }
 
 /// The diff between what's in S3 and what's in the database.
@@ -29,12 +32,14 @@ pub async fn list_objects(bucket: &Bucket, prefix: Option<&str>) -> Result<Vec<S
     let mut objects = Vec::new();
     for result in &results {
         for item in &result.contents {
-            let key_lower = item.key.to_lowercase();
-            if key_lower.ends_with(".pdf") || key_lower.ends_with(".epub") {
+            if is_book_key(&item.key) {
                 objects.push(S3Object {
                     key: item.key.clone(),
                     size: item.size,
                     etag: item.e_tag.clone().unwrap_or_default(),
+                    last_modified: chrono::DateTime::parse_from_rfc3339(&item.last_modified)
+                        .ok()
+                        .map(|t| t.timestamp()),
                 });
             }
         }
@@ -43,15 +48,117 @@ pub async fn list_objects(bucket: &Bucket, prefix: Option<&str>) -> Result<Vec<S
     Ok(objects)
 }
 
-/// Compare S3 listing against DB records to find what changed.
-pub fn compute_diff(s3_objects: &[S3Object], db_books: &[S3BookRow]) -> ScanDiff {
+/// List the books of a source: objects under any of its prefixes, minus
+/// excluded keys. Overlapping prefixes don't list an object twice.
+pub async fn list_source_objects(bucket: &Bucket, config: &S3Config) -> Result<Vec<S3Object>> {
+    if config.prefixes.is_empty() {
+        let objects = list_objects(bucket, None).await?;
+        return Ok(objects
+            .into_iter()
+            .filter(|o| config.accepts(&o.key))
+            .collect());
+    }
+
+    let mut by_key = BTreeMap::new();
+    for prefix in &config.prefixes {
+        for object in list_objects(bucket, Some(prefix)).await? {
+            if config.accepts(&object.key) {
+                by_key.insert(object.key.clone(), object);
+            }
+        }
+    }
+    Ok(by_key.into_values().collect())
+}
+
+/// Look up a single object. `None` if it doesn't exist (or isn't a book).
+pub async fn head_object(bucket: &Bucket, key: &str) -> Result<Option<S3Object>> {
+    if !is_book_key(key) {
+        return Ok(None);
+    }
+    let (head, status) = bucket
+        .head_object(key)
+        .await
+        .with_context(|| format!("Failed to look up s3://{}", key))?;
+    match status {
+        200 => Ok(Some(S3Object {
+            key: key.to_string(),
+            size: head.content_length.unwrap_or(0).max(0) as u64,
+            etag: head.e_tag.unwrap_or_default(),
+            last_modified: head
+                .last_modified
+                .and_then(|t| chrono::DateTime::parse_from_rfc2822(&t).ok())
+                .map(|t| t.timestamp()),
+        })),
+        404 => Ok(None),
+        status => anyhow::bail!("S3 HeadObject returned status {} for key: {}", status, key),
+    }
+}
+
+pub fn is_book_key(key: &str) -> bool {
+    let key_lower = key.to_lowercase();
+    key_lower.ends_with(".pdf") || key_lower.ends_with(".epub")
+}
+
+/// Compare S3 listing against DB records to find what changed. Objects
+/// holding synced copies of local books are owned by `sync`: they are never
+/// added, and their changes and deletions are left to it. Unchanged objects
+/// in `skipped` aren't added again unless the book they copy is removed.
+pub fn compute_diff(
+    s3_objects: &[S3Object],
+    db_books: &[S3BookRow],
+    skipped: &[SkippedObject],
+) -> ScanDiff {
+    diff(s3_objects, db_books, skipped, true)
+}
+
+/// Like [`compute_diff`], but only for objects modified at or after `since`.
+/// A partial listing can't show deletions, so `removed` is always empty;
+/// those wait for the next full reconcile.
+pub fn compute_diff_since(
+    s3_objects: &[S3Object],
+    db_books: &[S3BookRow],
+    skipped: &[SkippedObject],
+    since: i64,
+) -> ScanDiff {
+    let recent: Vec<S3Object> = s3_objects
+        .iter()
+        .filter(|o| o.last_modified.is_none_or(|t| t >= since))
+        .cloned()
+        .collect();
+    diff(&recent, db_books, skipped, false)
+}
+
+fn diff(
+    s3_objects: &[S3Object],
+    db_books: &[S3BookRow],
+    skipped: &[SkippedObject],
+    complete: bool,
+) -> ScanDiff {
     let s3_map: HashMap<&str, &S3Object> = s3_objects.iter().map(|o| (o.key.as_str(), o)).collect();
     let db_map: HashMap<&str, &S3BookRow> =
         db_books.iter().map(|b| (b.file_path.as_str(), b)).collect();
 
+    let removed: Vec<S3BookRow> = if complete {
+        db_books
+            .iter()
+            .filter(|b| !b.synced && !s3_map.contains_key(b.file_path.as_str()))
+            .cloned()
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let removed_ids: HashSet<&str> = removed.iter().map(|b| b.id.as_str()).collect();
+    let skipped: HashMap<&str, &SkippedObject> =
+        skipped.iter().map(|s| (s.key.as_str(), s)).collect();
     let added: Vec<S3Object> = s3_objects
         .iter()
         .filter(|o| !db_map.contains_key(o.key.as_str()))
+        .filter(|o| {
+            !skipped
+                .get(o.key.as_str())
+                .is_some_and(|s| s.etag == o.etag && !removed_ids.contains(s.duplicate_of.as_str()))
+        })
         .cloned()
         .collect();
 
@@ -60,17 +167,11 @@ pub fn compute_diff(s3_objects: &[S3Object], db_books: &[S3BookRow]) -> ScanDiff
         .filter(|o| {
             db_map
                 .get(o.key.as_str())
-                .map_or(false, |b| b.s3_etag.as_deref() != Some(&o.etag))
+                .is_some_and(|b| !b.synced && b.s3_etag.as_deref() != Some(&o.etag))
         })
         .cloned()
         .collect();
 
-    let removed: Vec<S3BookRow> = db_books
-        .iter()
-        .filter(|b| !s3_map.contains_key(b.file_path.as_str()))
-        .cloned()
-        .collect();
-
     ScanDiff {
         added,
         changed,
@@ -78,6 +179,37 @@ pub fn compute_diff(s3_objects: &[S3Object], db_books: &[S3BookRow]) -> ScanDiff
     }
 }
 
+/// Match `key` against an exclusion pattern, where `*` matches any run of
+/// characters (slashes included) and `?` any single character.
+pub fn matches_pattern(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+    // Where the last `*` was seen, and how much of the key it had taken.
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                Some((star_p, star_k)) => {
+                    p = star_p + 1;
+                    k = star_k + 1;
+                    star = Some((star_p, star_k + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 /// Derive a human-readable title from an S3 object key.
 /// e.g. "books/My Great Book.pdf" → "My Great Book"
 pub fn title_from_key(key: &str) -> String {
@@ -91,8 +223,8 @@ pub fn title_from_key(key: &str) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::{S3Object, compute_diff, title_from_key};
-    use crate::db::S3BookRow;
+    use super::{S3Object, compute_diff, compute_diff_since, matches_pattern, title_from_key};
+    use crate::db::{S3BookRow, SkippedObject};
 
     fn s3_book_row(path: &str, etag: Option<&str>) -> S3BookRow {
         S3BookRow {
@@ -106,6 +238,7 @@ mod tests {
             },
             cover_path: None,
             s3_etag: etag.map(|value| value.to_string()),
+            synced: false,
         }
     }
 
@@ -114,6 +247,7 @@ mod tests {
             key: path.to_string(),
             size: 123,
             etag: etag.to_string(),
+            last_modified: Some(1_000),
         }
     }
 
@@ -130,7 +264,7 @@ mod tests {
             s3_book_row("removed-book.pdf", Some("etag-removed")),
         ];
 
-        let diff = compute_diff(&s3_objects, &db_books);
+        let diff = compute_diff(&s3_objects, &db_books, &[]);
 
         assert_eq!(diff.added.len(), 1);
         assert_eq!(diff.changed.len(), 1);
@@ -146,7 +280,7 @@ mod tests {
         let s3_objects = vec![s3_object("book.pdf", "etag-1")];
         let db_books = vec![s3_book_row("book.pdf", None)];
 
-        let diff = compute_diff(&s3_objects, &db_books);
+        let diff = compute_diff(&s3_objects, &db_books, &[]);
 
         assert!(diff.added.is_empty());
         assert_eq!(diff.changed.len(), 1);
@@ -154,14 +288,87 @@ mod tests {
         assert_eq!(diff.changed[0].key, "book.pdf");
     }
 
+    #[test]
+    fn compute_diff_since_skips_old_objects_and_removals() {
+        let mut recent = s3_object("recent.pdf", "etag-2");
+        recent.last_modified = Some(2_000);
+        let s3_objects = vec![s3_object("old-unseen.pdf", "etag-1"), recent];
+        let db_books = vec![s3_book_row("gone.pdf", Some("etag-gone"))];
+
+        let diff = compute_diff_since(&s3_objects, &db_books, &[], 1_500);
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].key, "recent.pdf");
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn compute_diff_leaves_synced_copies_to_sync() {
+        let s3_objects = vec![s3_object("synced.pdf", "etag-2")];
+        let mut synced = s3_book_row("synced.pdf", Some("etag-1"));
+        synced.synced = true;
+        let mut gone = s3_book_row("synced-gone.pdf", Some("etag-1"));
+        gone.synced = true;
+
+        let diff = compute_diff(&s3_objects, &[synced, gone], &[]);
+
+        assert!(diff.added.is_empty());
+        assert!(diff.changed.is_empty());
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn compute_diff_leaves_skipped_copies_alone_while_their_book_is_kept() {
+        let skipped = |key: &str, duplicate_of: &str| SkippedObject {
+            key: key.to_string(),
+            etag: "etag-copy".to_string(),
+            duplicate_of: format!("id-{duplicate_of}"),
+        };
+        let s3_objects = vec![
+            s3_object("original.pdf", "etag-copy"),
+            s3_object("copy.pdf", "etag-copy"),
+            s3_object("replaced-copy.pdf", "etag-new"),
+            s3_object("orphaned-copy.pdf", "etag-copy"),
+        ];
+        let db_books = vec![
+            s3_book_row("original.pdf", Some("etag-copy")),
+            s3_book_row("gone.pdf", Some("etag-copy")),
+        ];
+        let skipped = vec![
+            skipped("copy.pdf", "original.pdf"),
+            skipped("replaced-copy.pdf", "original.pdf"),
+            skipped("orphaned-copy.pdf", "gone.pdf"),
+        ];
+
+        let diff = compute_diff(&s3_objects, &db_books, &skipped);
+        let added: Vec<&str> = diff.added.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(added, vec!["replaced-copy.pdf", "orphaned-copy.pdf"]);
+
+        // An incremental poll can't tell that gone.pdf was removed.
+        let diff = compute_diff_since(&s3_objects, &db_books, &skipped, 0);
+        let added: Vec<&str> = diff.added.iter().map(|o| o.key.as_str()).collect();
+        assert_eq!(added, vec!["replaced-copy.pdf"]);
+    }
+
     #[test]
     fn compute_diff_empty_inputs_are_stable() {
-        let diff = compute_diff(&[], &[]);
+        let diff = compute_diff(&[], &[], &[]);
         assert!(diff.added.is_empty());
         assert!(diff.changed.is_empty());
         assert!(diff.removed.is_empty());
     }
 
+    #[test]
+    fn matches_pattern_supports_star_and_question_mark() {
+        assert!(matches_pattern("*/drafts/*", "fiction/drafts/Dune.epub"));
+        assert!(matches_pattern("*.pdf", "deep/nested/scan.pdf"));
+        assert!(matches_pattern("vol-?.epub", "vol-3.epub"));
+        assert!(matches_pattern("exact.pdf", "exact.pdf"));
+        assert!(!matches_pattern("*.pdf", "book.epub"));
+        assert!(!matches_pattern("vol-?.epub", "vol-10.epub"));
+        assert!(!matches_pattern("*/drafts/*", "drafts.pdf"));
+    }
+
     #[test]
     fn title_from_key_uses_filename_without_extension() {
         assert_eq!(
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::covers::CoverStoreConfig;
+use super::credentials::S3Credentials;
+use super::{DEFAULT_SOURCE, S3Config};
+
+/// One entry of the `S3_SOURCES` file. Fields left out fall back to the
+/// corresponding `S3_*` options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceEntry {
+    name: String,
+    bucket: String,
+    endpoint: Option<String>,
+    region: Option<String>,
+    #[serde(default)]
+    prefixes: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    poll_interval: Option<u64>,
+    reconcile_interval: Option<u64>,
+    concurrency: Option<usize>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    profile: Option<String>,
+    covers_prefix: Option<String>,
+    covers_bucket: Option<String>,
+}
+
+/// The sources to work with: those listed in `file` when given, otherwise
+/// `single` as the [`DEFAULT_SOURCE`].
+pub fn resolve(file: Option<&Path>, single: S3Config) -> Result<Vec<S3Config>> {
+    match file {
+        Some(path) => load(path, &single),
+        None => Ok(vec![S3Config {
+            name: DEFAULT_SOURCE.to_string(),
+            ..single
+        }]),
+    }
+}
+
+/// Read named sources from the JSON array in `path`, filling gaps from
+/// `defaults`.
+pub fn load(path: &Path, defaults: &S3Config) -> Result<Vec<S3Config>> {
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read S3 sources from {}", path.display()))?;
+    parse(&json, defaults).with_context(|| format!("Invalid S3 sources in {}", path.display()))
+}
+
+fn parse(json: &str, defaults: &S3Config) -> Result<Vec<S3Config>> {
+    let entries: Vec<SourceEntry> = serde_json::from_str(json)?;
+    if entries.is_empty() {
+        bail!("No sources listed");
+    }
+
+    let mut names = HashSet::new();
+    let mut sources = Vec::with_capacity(entries.len());
+    for entry in entries {
+        // Names are stored on every book, so keep them plain.
+        let valid_name = !entry.name.is_empty()
+            && entry
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            bail!(
+                "Source name \"{}\" must be letters, digits, '-' or '_'",
+                entry.name
+            );
+        }
+        if !names.insert(entry.name.clone()) {
+            bail!("Source \"{}\" is listed twice", entry.name);
+        }
+        if entry.bucket.is_empty() {
+            bail!("Source \"{}\" has no bucket", entry.name);
+        }
+        sources.push(source(entry, defaults));
+    }
+    Ok(sources)
+}
+
+fn source(entry: SourceEntry, defaults: &S3Config) -> S3Config {
+    let has_credentials = entry.access_key_id.is_some()
+        || entry.secret_access_key.is_some()
+        || entry.profile.is_some();
+    let credentials = if has_credentials {
+        S3Credentials {
+            access_key: entry.access_key_id,
+            secret_key: entry.secret_access_key,
+            session_token: entry.session_token,
+            profile: entry.profile,
+        }
+    } else {
+        defaults.credentials.clone()
+    };
+    let covers = if entry.covers_prefix.is_some() || entry.covers_bucket.is_some() {
+        Some(CoverStoreConfig {
+            bucket: entry.covers_bucket,
+            prefix: entry.covers_prefix.unwrap_or_default(),
+        })
+    } else {
+        defaults.covers.clone()
+    };
+
+    S3Config {
+        name: entry.name,
+        endpoint: entry.endpoint.or_else(|| defaults.endpoint.clone()),
+        region: entry.region.unwrap_or_else(|| defaults.region.clone()),
+        bucket: entry.bucket,
+        credentials,
+        prefixes: entry.prefixes,
+        exclude: entry.exclude,
+        poll_interval: entry.poll_interval.unwrap_or(defaults.poll_interval),
+        concurrency: entry.concurrency.unwrap_or(defaults.concurrency),
+        reconcile_interval: entry
+            .reconcile_interval
+            .unwrap_or(defaults.reconcile_interval),
+        covers,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::s3::S3Config;
+    use crate::s3::credentials::S3Credentials;
+
+    fn defaults() -> S3Config {
+        S3Config {
+            name: String::new(),
+            endpoint: Some("https://r2.example.com".to_string()),
+            region: "auto".to_string(),
+            bucket: String::new(),
+            credentials: S3Credentials::from_keys("key", "secret"),
+            prefixes: Vec::new(),
+            exclude: Vec::new(),
+            poll_interval: 60,
+            concurrency: 8,
+            reconcile_interval: 3600,
+            covers: None,
+        }
+    }
+
+    #[test]
+    fn sources_fill_gaps_from_defaults() {
+        let sources = parse(
+            r#"[
+                {"name": "fiction", "bucket": "books", "prefixes": ["fiction/"],
+                 "exclude": ["*/drafts/*"]},
+                {"name": "archive", "bucket": "archive", "endpoint": "http://minio:9000",
+                 "profile": "minio", "poll_interval": 900}
+            ]"#,
+            &defaults(),
+        )
+        .unwrap();
+
+        assert_eq!(sources[0].name, "fiction");
+        assert_eq!(
+            sources[0].endpoint.as_deref(),
+            Some("https://r2.example.com")
+        );
+        assert_eq!(sources[0].credentials.access_key.as_deref(), Some("key"));
+        assert_eq!(sources[0].poll_interval, 60);
+        assert!(sources[0].accepts("fiction/Dune.epub"));
+        assert!(!sources[0].accepts("fiction/drafts/Dune.epub"));
+        assert!(!sources[0].accepts("poetry/Odes.epub"));
+
+        assert_eq!(sources[1].endpoint.as_deref(), Some("http://minio:9000"));
+        assert_eq!(sources[1].credentials.access_key, None);
+        assert_eq!(sources[1].credentials.profile.as_deref(), Some("minio"));
+        assert_eq!(sources[1].poll_interval, 900);
+        assert!(sources[1].accepts("anything.pdf"));
+    }
+
+    #[test]
+    fn sources_need_unique_plain_names() {
+        let twice = r#"[{"name": "a", "bucket": "x"}, {"name": "a", "bucket": "y"}]"#;
+        assert!(parse(twice, &defaults()).is_err());
+        assert!(parse(r#"[{"name": "a b", "bucket": "x"}]"#, &defaults()).is_err());
+        assert!(parse(r#"[{"name": "a", "bucket": "x", "typo": 1}]"#, &defaults()).is_err());
+        assert!(parse("[]", &defaults()).is_err());
+    }
+}
@@ -1,114 +1,309 @@
 use anyhow::{Context, Result};
-use std::io::Write;
+use s3::Bucket;
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use super::S3Config;
 use super::client::create_bucket;
 
+/// What the caller asked for, taken from the incoming HTTP request.
+#[derive(Debug, Default)]
+pub struct StreamRequest<'a> {
+    pub key: &'a str,
+    /// `Range` header value (e.g. "bytes=0-1023", "bytes=512-", "bytes=-1024").
+    pub range: Option<&'a str>,
+    /// Write the header line only, as for a `HEAD` request.
+    pub head_only: bool,
+    pub if_none_match: Option<&'a str>,
+    pub if_modified_since: Option<&'a str>,
+}
+
+/// The JSON header line. `content_length` is the number of body bytes that
+/// follow; `total_size` is the size of the whole object.
+#[derive(Debug, Serialize)]
+struct Header<'a> {
+    status: u16,
+    content_type: &'a str,
+    content_length: u64,
+    total_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range_start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range_end: Option<u64>,
+    /// Ready to use as the `Content-Range` header on 206 and 416 responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_range: Option<String>,
+}
+
 /// Stream an S3 object to stdout.
 ///
 /// Protocol:
-///   1. First line: JSON header `{"content_length": N, "content_type": "..."}\n`
-///   2. Remaining bytes: raw object body
+///   1. First line: JSON header `{"status": N, "content_length": N, "total_size": N, ...}\n`
+///   2. Remaining bytes: raw object body (none for HEAD, 304, 404 and 416)
 ///
-/// If `range` is provided (e.g. "bytes=0-1023"), a range request is made.
-pub async fn run(config: S3Config, key: &str, range: Option<&str>) -> Result<()> {
+/// The body is copied from S3 as it arrives rather than buffered. A
+/// syntactically invalid `Range` is ignored and the whole object is sent,
+/// as RFC 7233 allows.
+pub async fn run(config: S3Config, request: &StreamRequest<'_>) -> Result<()> {
     let bucket = create_bucket(&config)?;
-    let stdout = std::io::stdout();
-    let mut out = stdout.lock();
+    let mut out = tokio::io::stdout();
+    stream(&bucket, request, &mut out).await?;
+    out.flush().await?;
+    Ok(())
+}
 
-    let content_type = if key.to_lowercase().ends_with(".epub") {
-        "application/epub+zip"
-    } else {
-        "application/pdf"
+async fn stream<W>(bucket: &Bucket, request: &StreamRequest<'_>, out: &mut W) -> Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    let key = request.key;
+    let content_type = content_type(key);
+
+    let (head, status) = bucket
+        .head_object(key)
+        .await
+        .with_context(|| format!("Failed to look up s3://{}", key))?;
+    let mut header = Header {
+        status,
+        content_type,
+        content_length: 0,
+        total_size: 0,
+        etag: None,
+        last_modified: None,
+        range_start: None,
+        range_end: None,
+        content_range: None,
     };
+    match status {
+        200 => {}
+        404 => return write_header(out, &header).await,
+        status => anyhow::bail!("S3 HeadObject returned status {} for key: {}", status, key),
+    }
 
-    if let Some(range_value) = range {
-        // Parse "bytes=START-END"
-        let (start, end) = parse_range(range_value)?;
-        let response = bucket
-            .get_object_range(key, start, end)
-            .await
-            .with_context(|| format!("Failed to get range from s3://{}", key))?;
-
-        let bytes = response.to_vec();
-        let header = serde_json::json!({
-            "content_length": bytes.len(),
-            "content_type": content_type,
-            "status": 206,
-            "range_start": start,
-            "range_end": start + bytes.len() as u64 - 1,
-        });
-        writeln!(out, "{}", header)?;
-        out.write_all(&bytes)?;
-    } else {
-        let response = bucket
-            .get_object(key)
-            .await
-            .with_context(|| format!("Failed to get s3://{}", key))?;
-
-        let bytes = response.to_vec();
-        let header = serde_json::json!({
-            "content_length": bytes.len(),
-            "content_type": content_type,
-            "status": 200,
-        });
-        writeln!(out, "{}", header)?;
-        out.write_all(&bytes)?;
+    let size = head.content_length.unwrap_or(0).max(0) as u64;
+    header.total_size = size;
+    header.etag = head.e_tag.as_deref();
+    header.last_modified = head.last_modified.as_deref();
+
+    if not_modified(
+        header.etag,
+        header.last_modified,
+        request.if_none_match,
+        request.if_modified_since,
+    ) {
+        header.status = 304;
+        return write_header(out, &header).await;
+    }
+
+    let range = request.range.and_then(|value| parse_range(value).ok());
+    let span = match range {
+        Some(range) => match range.resolve(size) {
+            Some((start, end)) => {
+                header.status = 206;
+                header.range_start = Some(start);
+                header.range_end = Some(end);
+                header.content_range = Some(format!("bytes {start}-{end}/{size}"));
+                Some((start, end))
+            }
+            None => {
+                header.status = 416;
+                header.content_range = Some(format!("bytes */{size}"));
+                return write_header(out, &header).await;
+            }
+        },
+        None => {
+            header.status = 200;
+            None
+        }
+    };
+    header.content_length = span.map_or(size, |(start, end)| end - start + 1);
+    write_header(out, &header).await?;
+    if request.head_only {
+        return Ok(());
+    }
+
+    let status = match span {
+        None => bucket.get_object_to_writer(key, out).await,
+        // rust-s3 requires start < end, so the last byte is asked for as an
+        // open range and any other single byte is fetched on its own.
+        Some((start, end)) if end + 1 == size => {
+            bucket
+                .get_object_range_to_writer(key, start, None, out)
+                .await
+        }
+        Some((start, end)) if start < end => {
+            bucket
+                .get_object_range_to_writer(key, start, Some(end), out)
+                .await
+        }
+        Some((start, _)) => match bucket.get_object_range(key, start, Some(start + 1)).await {
+            Ok(response) => {
+                let status = response.status_code();
+                if (200..300).contains(&status) {
+                    let byte = response
+                        .bytes()
+                        .first()
+                        .with_context(|| format!("S3 returned no data for s3://{}", key))?;
+                    out.write_all(&[*byte]).await?;
+                }
+                Ok(status)
+            }
+            Err(e) => Err(e),
+        },
+    }
+    .with_context(|| format!("Failed to get s3://{}", key))?;
+    if !(200..300).contains(&status) {
+        anyhow::bail!("S3 GetObject returned status {} for key: {}", status, key);
+    }
+    Ok(())
+}
+
+/// Books, and the covers uploaded next to them.
+pub(crate) fn content_type(key: &str) -> &'static str {
+    let key = key.to_lowercase();
+    match key.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("epub") => "application/epub+zip",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => "application/pdf",
     }
+}
 
-    out.flush()?;
+async fn write_header<W: AsyncWrite + Unpin>(out: &mut W, header: &Header<'_>) -> Result<()> {
+    let mut line = serde_json::to_vec(header)?;
+    line.push(b'\n');
+    out.write_all(&line).await?;
     Ok(())
 }
 
-/// Parse a Range header value like "bytes=0-1023" into (start, Option<end>).
-fn parse_range(range: &str) -> Result<(u64, Option<u64>)> {
+/// A single byte range from a `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteRange {
+    /// `bytes=START-END` or `bytes=START-`.
+    From { start: u64, end: Option<u64> },
+    /// `bytes=-N`: the last N bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// The inclusive span to send for an object of `size` bytes, or `None`
+    /// if the range can't be satisfied. An end past the object is clamped.
+    fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        if size == 0 {
+            return None;
+        }
+        match self {
+            ByteRange::From { start, .. } if start >= size => None,
+            ByteRange::From { start, end } => Some((start, end.unwrap_or(u64::MAX).min(size - 1))),
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(length) => Some((size.saturating_sub(length), size - 1)),
+        }
+    }
+}
+
+/// Parse a Range header value like "bytes=0-1023", "bytes=512-" or
+/// "bytes=-1024". Multiple ranges are not supported.
+fn parse_range(range: &str) -> Result<ByteRange> {
     let range = range
         .strip_prefix("bytes=")
         .context("Invalid range header: must start with 'bytes='")?;
+    if range.contains(',') {
+        anyhow::bail!("Multiple ranges are not supported: {}", range);
+    }
 
     let parts: Vec<&str> = range.splitn(2, '-').collect();
     if parts.len() != 2 {
         anyhow::bail!("Invalid range format: {}", range);
     }
 
-    let start = if parts[0].is_empty() {
-        0
-    } else {
-        parts[0].parse::<u64>().context("Invalid range start")?
-    };
+    if parts[0].is_empty() {
+        let length = parts[1].parse::<u64>().context("Invalid range end")?;
+        return Ok(ByteRange::Suffix(length));
+    }
 
+    let start = parts[0].parse::<u64>().context("Invalid range start")?;
     let end = if parts[1].is_empty() {
         None
     } else {
         Some(parts[1].parse::<u64>().context("Invalid range end")?)
     };
+    if end.is_some_and(|end| end < start) {
+        anyhow::bail!("Invalid range: end before start: {}", range);
+    }
+
+    Ok(ByteRange::From { start, end })
+}
+
+/// Whether a conditional request can be answered with 304. As in RFC 7232,
+/// `If-Modified-Since` is only considered when `If-None-Match` is absent.
+fn not_modified(
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+) -> bool {
+    if let Some(if_none_match) = if_none_match {
+        return etag.is_some_and(|etag| {
+            if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|candidate| candidate == "*" || weak(candidate) == weak(etag))
+        });
+    }
+
+    let since =
+        if_modified_since.and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+    let modified = last_modified.and_then(|value| chrono::DateTime::parse_from_rfc2822(value).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
 
-    Ok((start, end))
+/// Weak comparison: `W/"x"` and `"x"` are the same entity tag.
+fn weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse_range;
+    use super::{ByteRange, not_modified, parse_range};
 
     #[test]
     fn parse_range_handles_start_and_end() {
-        let (start, end) = parse_range("bytes=10-20").expect("range should parse");
-        assert_eq!(start, 10);
-        assert_eq!(end, Some(20));
+        let range = parse_range("bytes=10-20").expect("range should parse");
+        assert_eq!(
+            range,
+            ByteRange::From {
+                start: 10,
+                end: Some(20)
+            }
+        );
     }
 
     #[test]
     fn parse_range_handles_open_ended_range() {
-        let (start, end) = parse_range("bytes=512-").expect("range should parse");
-        assert_eq!(start, 512);
-        assert_eq!(end, None);
+        let range = parse_range("bytes=512-").expect("range should parse");
+        assert_eq!(
+            range,
+            ByteRange::From {
+                start: 512,
+                end: None
+            }
+        );
     }
 
     #[test]
-    fn parse_range_handles_missing_start_as_zero() {
-        let (start, end) = parse_range("bytes=-1024").expect("range should parse");
-        assert_eq!(start, 0);
-        assert_eq!(end, Some(1024));
+    fn parse_range_handles_missing_start_as_suffix() {
+        let range = parse_range("bytes=-1024").expect("range should parse");
+        assert_eq!(range, ByteRange::Suffix(1024));
     }
 
     #[test]
@@ -138,4 +333,54 @@ mod tests {
         let error = parse_range("bytes=100").expect_err("missing separator must fail");
         assert!(error.to_string().contains("Invalid range format"));
     }
+
+    #[test]
+    fn parse_range_rejects_reversed_and_multiple_ranges() {
+        assert!(parse_range("bytes=20-10").is_err());
+        assert!(parse_range("bytes=0-1,5-9").is_err());
+    }
+
+    #[test]
+    fn ranges_resolve_against_the_object_size() {
+        let from = |start, end| ByteRange::From { start, end };
+        assert_eq!(from(10, Some(20)).resolve(100), Some((10, 20)));
+        assert_eq!(from(90, Some(200)).resolve(100), Some((90, 99)));
+        assert_eq!(from(40, None).resolve(100), Some((40, 99)));
+        assert_eq!(from(100, None).resolve(100), None);
+        assert_eq!(ByteRange::Suffix(30).resolve(100), Some((70, 99)));
+        assert_eq!(ByteRange::Suffix(500).resolve(100), Some((0, 99)));
+        assert_eq!(ByteRange::Suffix(0).resolve(100), None);
+        assert_eq!(from(0, None).resolve(0), None);
+    }
+
+    #[test]
+    fn conditional_requests_compare_etags_then_dates() {
+        let etag = Some("\"abc\"");
+        let modified = Some("Tue, 15 Nov 1994 12:45:26 GMT");
+
+        assert!(not_modified(etag, modified, Some("\"x\", W/\"abc\""), None));
+        assert!(not_modified(etag, modified, Some("*"), None));
+        assert!(!not_modified(etag, modified, Some("\"x\""), None));
+        // If-None-Match wins over If-Modified-Since.
+        assert!(!not_modified(
+            etag,
+            modified,
+            Some("\"x\""),
+            Some("Wed, 16 Nov 1994 00:00:00 GMT")
+        ));
+
+        assert!(not_modified(
+            etag,
+            modified,
+            None,
+            Some("Tue, 15 Nov 1994 12:45:26 GMT")
+        ));
+        assert!(!not_modified(
+            etag,
+            modified,
+            None,
+            Some("Mon, 14 Nov 1994 00:00:00 GMT")
+        ));
+        assert!(!not_modified(etag, modified, None, Some("not a date")));
+    }
 }
@@ -0,0 +1,676 @@
+use anyhow::{Context, Result};
+use s3::Bucket;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::S3Config;
+use super::client::create_bucket;
+use super::credentials::refresh_if_expiring;
+use super::handlers::S3Origin;
+use super::range::{BucketRange, RangedObject};
+use super::scanner::{S3Object, head_object, list_source_objects};
+use super::stream::content_type;
+use super::watcher::shutdown_requested;
+use crate::db::{Database, EventKind, S3BookRow, SyncRow};
+use crate::handlers::add::compute_sha256;
+use crate::log::log;
+use crate::watcher::set_scan_marker;
+
+/// Files at least this large are uploaded in parts of this size, so only one
+/// part is held in memory at a time. S3 requires parts of at least 5 MiB.
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+pub struct SyncOptions<'a> {
+    /// The local library; keys mirror paths relative to it.
+    pub library_path: &'a Path,
+    /// Also download books that are only in the bucket, and bucket-side
+    /// changes to synced books.
+    pub download: bool,
+}
+
+/// What a sync did.
+#[derive(Debug, Default, Serialize)]
+pub struct SyncReport {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    /// Books whose local file and bucket copy already matched.
+    pub unchanged: usize,
+    pub skipped: Vec<SyncIssue>,
+    pub failed: Vec<SyncIssue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncIssue {
+    pub book_id: String,
+    pub key: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} uploaded, {} downloaded, {} unchanged, {} skipped",
+            self.uploaded,
+            self.downloaded,
+            self.unchanged,
+            self.skipped.len()
+        )?;
+        if !self.failed.is_empty() {
+            write!(f, ", {} failed", self.failed.len())?;
+        }
+        Ok(())
+    }
+}
+
+enum Outcome {
+    Uploaded,
+    Downloaded,
+    Unchanged,
+    Skipped(String),
+}
+
+impl SyncReport {
+    fn record(&mut self, book_id: &str, key: &str, outcome: Result<Outcome>) {
+        let issue = |reason: String| SyncIssue {
+            book_id: book_id.to_string(),
+            key: key.to_string(),
+            reason,
+        };
+        match outcome {
+            Ok(Outcome::Uploaded) => self.uploaded += 1,
+            Ok(Outcome::Downloaded) => self.downloaded += 1,
+            Ok(Outcome::Unchanged) => self.unchanged += 1,
+            Ok(Outcome::Skipped(reason)) => {
+                log(&format!("[SYNC] [SKIP] {}: {}", key, reason));
+                self.skipped.push(issue(reason));
+            }
+            Err(e) => {
+                log(&format!("[SYNC] [ERROR] {}: {:#}", key, e));
+                self.failed.push(issue(format!("{:#}", e)));
+            }
+        }
+    }
+
+    fn is_quiet(&self) -> bool {
+        self.uploaded + self.downloaded + self.skipped.len() + self.failed.len() == 0
+    }
+}
+
+/// Sync the local library with `bucket` once.
+///
+/// Local books are uploaded to their path relative to the library, under
+/// the source's first prefix, and the key is recorded on their row, so the S3 watcher sees
+/// a synced copy rather than a new book. The local file wins when both
+/// sides changed. With `options.download`, books only in the bucket are
+/// downloaded and become local books that keep their object as bucket copy.
+pub async fn run(
+    db: &Database,
+    bucket: &Bucket,
+    config: &S3Config,
+    options: &SyncOptions<'_>,
+) -> Result<SyncReport> {
+    set_scan_marker(db, "sync", true);
+    let result = sync_once(db, bucket, config, options).await;
+    set_scan_marker(db, "sync", false);
+    result
+}
+
+async fn sync_once(
+    db: &Database,
+    bucket: &Bucket,
+    config: &S3Config,
+    options: &SyncOptions<'_>,
+) -> Result<SyncReport> {
+    let origin = S3Origin {
+        source: &config.name,
+        bucket: &config.bucket,
+    };
+    let upload_prefix = config
+        .prefixes
+        .first()
+        .map(String::as_str)
+        .unwrap_or_default();
+    db.claim_s3_books(&config.name, &config.bucket, &config.prefixes)?;
+    let objects = list_source_objects(bucket, config).await?;
+    let listing: HashMap<&str, &S3Object> = objects.iter().map(|o| (o.key.as_str(), o)).collect();
+    let mut report = SyncReport::default();
+
+    for book in db.sync_rows()? {
+        // Vanished files are the local watcher's business.
+        let path = Path::new(&book.file_path);
+        if !path.is_file() {
+            continue;
+        }
+        let key = match (&book.s3_source, &book.s3_key) {
+            (Some(source), Some(key)) if *source == config.name => key.clone(),
+            (_, Some(key)) => {
+                let reason = format!(
+                    "synced to s3://{}/{}",
+                    book.s3_bucket.as_deref().unwrap_or_default(),
+                    key
+                );
+                report.record(&book.id, &book.file_path, Ok(Outcome::Skipped(reason)));
+                continue;
+            }
+            _ => match key_for(options.library_path, path, upload_prefix) {
+                Some(key) if config.accepts(&key) => key,
+                // Books outside the library (or excluded from the source)
+                // have no place in the bucket.
+                _ => continue,
+            },
+        };
+        let object = listing.get(key.as_str()).copied();
+        let outcome = sync_local_book(db, bucket, origin, &book, &key, object, options).await;
+        report.record(&book.id, &key, outcome);
+    }
+
+    if options.download {
+        for book in db.find_s3_books(&config.name)? {
+            if book.synced || !listing.contains_key(book.file_path.as_str()) {
+                continue;
+            }
+            let prefix = prefix_of(&config.prefixes, &book.file_path);
+            let outcome = download_book(db, bucket, &book, options.library_path, prefix).await;
+            report.record(&book.id, &book.file_path, outcome);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Sync every `interval` until `shutdown` is set. Results are logged.
+pub async fn watch(
+    db: &Database,
+    config: &S3Config,
+    options: &SyncOptions<'_>,
+    interval: Duration,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let mut bucket = create_bucket(config)?;
+    log(&format!(
+        "[SYNC] Syncing {} with s3://{}/{} every {}s{}",
+        options.library_path.display(),
+        config.bucket,
+        config
+            .prefixes
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default(),
+        interval.as_secs(),
+        if options.download {
+            " (with downloads)"
+        } else {
+            ""
+        }
+    ));
+
+    while !shutdown.load(Ordering::Relaxed) {
+        if let Err(e) = refresh_if_expiring(&mut bucket, &config.credentials).await {
+            log(&format!(
+                "[SYNC] [ERROR] Failed to refresh credentials: {:#}",
+                e
+            ));
+        }
+        match run(db, &bucket, config, options).await {
+            Ok(report) if report.is_quiet() => {}
+            Ok(report) => log(&format!("[SYNC] {}", report)),
+            Err(e) => log(&format!("[SYNC] [ERROR] Sync failed: {:#}", e)),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown_requested(shutdown) => {}
+        }
+    }
+
+    log("[SYNC] Shutting down...");
+    Ok(())
+}
+
+async fn sync_local_book(
+    db: &Database,
+    bucket: &Bucket,
+    origin: S3Origin<'_>,
+    book: &SyncRow,
+    key: &str,
+    object: Option<&S3Object>,
+    options: &SyncOptions<'_>,
+) -> Result<Outcome> {
+    if book.s3_key.is_none() {
+        let Some(object) = object else {
+            return upload_book(db, bucket, origin, book, key).await;
+        };
+        // The key is taken. An untracked object with the same content is
+        // most likely our own upload whose bookkeeping was lost.
+        if let Some(other) = db.find_s3_book(origin.source, key)? {
+            return Ok(Outcome::Skipped(format!(
+                "key already holds \"{}\"",
+                other.title
+            )));
+        }
+        let size = std::fs::metadata(&book.file_path)?.len();
+        if object.size != size || object_sha256(bucket, object).await? != book.file_hash {
+            return Ok(Outcome::Skipped(
+                "key already holds a different object".to_string(),
+            ));
+        }
+        db.set_s3_copy(
+            &book.id,
+            origin.source,
+            origin.bucket,
+            key,
+            Some(&object.etag),
+            Some(&book.file_hash),
+        )?;
+        db.record_event(EventKind::Updated, &book.id, &["s3_key", "s3_etag"])?;
+        log(&format!(
+            "[SYNC] [OK] Linked \"{}\" to existing s3://{}/{}",
+            book.title, origin.bucket, key
+        ));
+        return Ok(Outcome::Unchanged);
+    }
+
+    // A deleted bucket copy is put back; the local file is authoritative.
+    let Some(object) = object else {
+        return upload_book(db, bucket, origin, book, key).await;
+    };
+    let bucket_changed = book.s3_etag.as_deref() != Some(object.etag.as_str());
+    if local_changed(book).await? {
+        if bucket_changed {
+            log(&format!(
+                "[SYNC] [WARN] \"{}\" changed locally and in the bucket; keeping the local file",
+                book.title
+            ));
+        }
+        return upload_book(db, bucket, origin, book, key).await;
+    }
+    if !bucket_changed {
+        return Ok(Outcome::Unchanged);
+    }
+    if !options.download {
+        return Ok(Outcome::Skipped(
+            "bucket copy changed; sync with --download to fetch it".to_string(),
+        ));
+    }
+    refresh_local_copy(db, bucket, origin, book, key, object).await
+}
+
+/// SHA-256 of the object's content, streamed in ranged reads.
+async fn object_sha256(bucket: &Bucket, object: &S3Object) -> Result<String> {
+    let range = BucketRange::new(bucket, &object.key, object.size);
+    let object = RangedObject::new(Arc::new(range), object.size);
+    tokio::task::spawn_blocking(move || object.sha256()).await?
+}
+
+/// Whether the local file differs from the bucket copy. The file is only
+/// re-hashed when the recorded hash differs, since the local watcher may not
+/// have caught up with a download yet.
+async fn local_changed(book: &SyncRow) -> Result<bool> {
+    let Some(synced_hash) = &book.synced_hash else {
+        return Ok(true);
+    };
+    if *synced_hash == book.file_hash {
+        return Ok(false);
+    }
+    let path = PathBuf::from(&book.file_path);
+    let actual = tokio::task::spawn_blocking(move || compute_sha256(&path)).await??;
+    Ok(actual != *synced_hash)
+}
+
+async fn upload_book(
+    db: &Database,
+    bucket: &Bucket,
+    origin: S3Origin<'_>,
+    book: &SyncRow,
+    key: &str,
+) -> Result<Outcome> {
+    // Claim the key first, so an S3 watcher listing the object mid-upload
+    // sees a synced copy instead of a new book. Without a synced hash an
+    // interrupted upload is retried by the next sync.
+    db.set_s3_copy(&book.id, origin.source, origin.bucket, key, None, None)?;
+    let hash = upload_file(bucket, Path::new(&book.file_path), key).await?;
+    let etag = head_object(bucket, key).await?.map(|object| object.etag);
+    db.set_s3_copy(
+        &book.id,
+        origin.source,
+        origin.bucket,
+        key,
+        etag.as_deref(),
+        Some(&hash),
+    )?;
+    db.record_event(EventKind::Updated, &book.id, &["s3_key", "s3_etag"])?;
+    log(&format!(
+        "[SYNC] [OK] Uploaded \"{}\" -> s3://{}/{}",
+        book.title, origin.bucket, key
+    ));
+    Ok(Outcome::Uploaded)
+}
+
+/// Download a bucket-only book into the library and make it a local book
+/// whose bucket copy is the object it came from.
+async fn download_book(
+    db: &Database,
+    bucket: &Bucket,
+    book: &S3BookRow,
+    library_path: &Path,
+    prefix: &str,
+) -> Result<Outcome> {
+    let Some(path) = path_for(library_path, &book.file_path, prefix) else {
+        return Ok(Outcome::Skipped(
+            "key doesn't map to a path in the library".to_string(),
+        ));
+    };
+    if path.exists() {
+        return Ok(Outcome::Skipped(format!(
+            "{} already exists",
+            path.display()
+        )));
+    }
+
+    let partial = partial_path(&path);
+    let hash = download_file(bucket, &book.file_path, &partial).await?;
+    if let Some(other) = db.find_book_by_hash(&hash)?
+        && other.id != book.id
+    {
+        let _ = std::fs::remove_file(&partial);
+        return Ok(Outcome::Skipped(format!(
+            "same content as \"{}\"",
+            other.title
+        )));
+    }
+
+    // The local watcher waits for new files to settle before adding them,
+    // so the row is updated long before it looks at the path.
+    std::fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to move download to {}", path.display()))?;
+    if let Err(e) = db.adopt_local_copy(&book.id, &path.to_string_lossy(), &hash) {
+        let _ = std::fs::remove_file(&path);
+        return Err(e);
+    }
+    db.record_event(EventKind::Moved, &book.id, &["file_path", "source"])?;
+    log(&format!(
+        "[SYNC] [OK] Downloaded \"{}\" -> {}",
+        book.title,
+        path.display()
+    ));
+    Ok(Outcome::Downloaded)
+}
+
+/// Replace a synced book's local file with the bucket copy that changed.
+/// The local watcher picks up the new content and re-extracts metadata.
+async fn refresh_local_copy(
+    db: &Database,
+    bucket: &Bucket,
+    origin: S3Origin<'_>,
+    book: &SyncRow,
+    key: &str,
+    object: &S3Object,
+) -> Result<Outcome> {
+    let path = Path::new(&book.file_path);
+    let partial = partial_path(path);
+    let hash = download_file(bucket, key, &partial).await?;
+    std::fs::rename(&partial, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    db.set_s3_copy(
+        &book.id,
+        origin.source,
+        origin.bucket,
+        key,
+        Some(&object.etag),
+        Some(&hash),
+    )?;
+    log(&format!(
+        "[SYNC] [OK] Downloaded changes to \"{}\" from s3://{}/{}",
+        book.title, origin.bucket, key
+    ));
+    Ok(Outcome::Downloaded)
+}
+
+/// Upload `path` to `key` and return the SHA-256 of what was uploaded.
+///
+/// Files of at least [`PART_SIZE`] go up as a multipart upload, one part in
+/// memory at a time. A failed multipart upload is aborted so its parts don't
+/// linger (and get billed) in the bucket.
+pub async fn upload_file(bucket: &Bucket, path: &Path, key: &str) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let first = read_part(&mut file).await?;
+    hasher.update(&first);
+
+    if first.len() < PART_SIZE {
+        let response = bucket
+            .put_object_with_content_type(key, &first, content_type(key))
+            .await
+            .with_context(|| format!("Failed to upload s3://{}/{}", bucket.name, key))?;
+        if response.status_code() != 200 {
+            anyhow::bail!(
+                "S3 PutObject returned status {} for key: {}",
+                response.status_code(),
+                key
+            );
+        }
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+
+    let upload = bucket
+        .initiate_multipart_upload(key, content_type(key))
+        .await
+        .with_context(|| format!("Failed to start upload of s3://{}/{}", bucket.name, key))?;
+    let result = upload_parts(
+        bucket,
+        &mut file,
+        &mut hasher,
+        key,
+        &upload.upload_id,
+        first,
+    )
+    .await;
+    if result.is_err() {
+        let _ = bucket.abort_upload(key, &upload.upload_id).await;
+    }
+    result?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+async fn upload_parts(
+    bucket: &Bucket,
+    file: &mut tokio::fs::File,
+    hasher: &mut Sha256,
+    key: &str,
+    upload_id: &str,
+    first: Vec<u8>,
+) -> Result<()> {
+    let mut parts = Vec::new();
+    let mut chunk = first;
+    loop {
+        let part_number = parts.len() as u32 + 1;
+        let part = bucket
+            .put_multipart_chunk(chunk, key, part_number, upload_id, content_type(key))
+            .await
+            .with_context(|| format!("Failed to upload part {} of {}", part_number, key))?;
+        parts.push(part);
+
+        chunk = read_part(file).await?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(&chunk);
+    }
+
+    let response = bucket
+        .complete_multipart_upload(key, upload_id, parts)
+        .await
+        .with_context(|| format!("Failed to complete upload of {}", key))?;
+    if response.status_code() != 200 {
+        anyhow::bail!(
+            "S3 CompleteMultipartUpload returned status {} for key: {}",
+            response.status_code(),
+            key
+        );
+    }
+    Ok(())
+}
+
+/// Up to [`PART_SIZE`] bytes; shorter only at the end of the file.
+async fn read_part(file: &mut tokio::fs::File) -> Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    (&mut *file)
+        .take(PART_SIZE as u64)
+        .read_to_end(&mut part)
+        .await?;
+    Ok(part)
+}
+
+/// Download `key` to `dest` and return the SHA-256 of the file. A failed
+/// download leaves nothing behind.
+async fn download_file(bucket: &Bucket, key: &str, dest: &Path) -> Result<String> {
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let result = async {
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .with_context(|| format!("Failed to create {}", dest.display()))?;
+        let status = bucket
+            .get_object_to_writer(key, &mut file)
+            .await
+            .with_context(|| format!("Failed to download s3://{}/{}", bucket.name, key))?;
+        if status != 200 {
+            anyhow::bail!("S3 GetObject returned status {} for key: {}", status, key);
+        }
+        file.flush().await?;
+        let dest = dest.to_path_buf();
+        tokio::task::spawn_blocking(move || compute_sha256(&dest)).await?
+    }
+    .await;
+    if result.is_err() {
+        let _ = std::fs::remove_file(dest);
+    }
+    result
+}
+
+/// Downloads are written next to their destination under a name the local
+/// watcher ignores, then renamed into place.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// The key a local file is synced to: its path relative to the library,
+/// under `prefix`. `None` for files outside the library.
+fn key_for(library_path: &Path, path: &Path, prefix: &str) -> Option<String> {
+    let relative = path.strip_prefix(library_path).ok()?;
+    let segments = relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(segment) => segment.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if segments.is_empty() {
+        return None;
+    }
+    let separator = if prefix.is_empty() || prefix.ends_with('/') {
+        ""
+    } else {
+        "/"
+    };
+    Some(format!("{}{}{}", prefix, separator, segments.join("/")))
+}
+
+/// The longest of `prefixes` that `key` is under; keys are downloaded to
+/// their path below it.
+fn prefix_of<'a>(prefixes: &'a [String], key: &str) -> &'a str {
+    prefixes
+        .iter()
+        .filter(|prefix| key.starts_with(prefix.as_str()))
+        .max_by_key(|prefix| prefix.len())
+        .map(String::as_str)
+        .unwrap_or_default()
+}
+
+/// Where a bucket-only book is downloaded to. Keys that would land outside
+/// the library (`..`, empty or backslashed segments) are refused.
+fn path_for(library_path: &Path, key: &str, prefix: &str) -> Option<PathBuf> {
+    let relative = key.strip_prefix(prefix)?.trim_start_matches('/');
+    let mut path = library_path.to_path_buf();
+    for segment in relative.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." || segment.contains('\\') {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key_for, partial_path, path_for, prefix_of};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn keys_mirror_library_paths_under_the_prefix() {
+        let library = Path::new("/data/library");
+        let path = Path::new("/data/library/Author/Book.epub");
+
+        assert_eq!(
+            key_for(library, path, "").as_deref(),
+            Some("Author/Book.epub")
+        );
+        assert_eq!(
+            key_for(library, path, "books/").as_deref(),
+            Some("books/Author/Book.epub")
+        );
+        assert_eq!(
+            key_for(library, path, "books").as_deref(),
+            Some("books/Author/Book.epub")
+        );
+        assert_eq!(key_for(library, Path::new("/elsewhere/Book.pdf"), ""), None);
+
+        let key = key_for(library, path, "books").unwrap();
+        assert_eq!(
+            path_for(library, &key, "books"),
+            Some(PathBuf::from("/data/library/Author/Book.epub"))
+        );
+    }
+
+    #[test]
+    fn keys_that_escape_the_library_are_refused() {
+        let library = Path::new("/data/library");
+        assert_eq!(
+            path_for(library, "books/../../etc/passwd.pdf", "books/"),
+            None
+        );
+        assert_eq!(path_for(library, "books/Author//Book.pdf", "books/"), None);
+        assert_eq!(path_for(library, "books/a\\..\\b.pdf", "books/"), None);
+        assert_eq!(path_for(library, "other/Book.pdf", "books/"), None);
+        assert_eq!(path_for(library, "books/", "books/"), None);
+    }
+
+    #[test]
+    fn downloads_strip_the_longest_matching_prefix() {
+        let prefixes = ["books/".to_string(), "books/fiction/".to_string()];
+        assert_eq!(
+            prefix_of(&prefixes, "books/fiction/Dune.epub"),
+            "books/fiction/"
+        );
+        assert_eq!(prefix_of(&prefixes, "books/Odes.epub"), "books/");
+        assert_eq!(prefix_of(&[], "Odes.epub"), "");
+    }
+
+    #[test]
+    fn partial_downloads_are_not_book_files() {
+        let partial = partial_path(Path::new("/data/library/Book.pdf"));
+        assert_eq!(partial, PathBuf::from("/data/library/Book.pdf.part"));
+    }
+}
@@ -1,44 +1,236 @@
 use anyhow::Result;
+use futures_util::stream::{self, StreamExt};
+use std::collections::{BTreeSet, HashSet};
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 use super::S3Config;
 use super::client::create_bucket;
-use super::handlers::{handle_s3_add, handle_s3_change, handle_s3_delete};
-use super::scanner::{compute_diff, list_objects};
+use super::covers::{CoverStore, CoverTarget};
+use super::credentials::RefreshingBucket;
+use super::handlers::{S3Origin, handle_s3_add, handle_s3_change, handle_s3_delete};
+use super::scanner::{
+    S3Object, ScanDiff, compute_diff, compute_diff_since, head_object, is_book_key,
+    list_source_objects,
+};
+use super::webhook::{self, Notification, WebhookConfig};
 use crate::db::Database;
+use crate::db::maintain::SCAN_MARKER_REFRESH;
 use crate::log::log;
+use crate::watcher::{
+    Housekeeping, compact_events, maintain_database, purge_missing_books, set_scan_marker,
+};
 
-/// Run the S3 polling watcher. Blocks until shutdown signal.
-pub async fn run(
+/// Changes written just before a scan can be listed with a slightly older
+/// `LastModified` (clock skew, slow multipart completions), so incremental
+/// polls look back this far past the watermark.
+const WATERMARK_SLACK_SECS: i64 = 15 * 60;
+
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A configured source with its clients and polling state.
+struct Source {
     config: S3Config,
+    bucket: RefreshingBucket,
+    cover_store: Option<CoverStore>,
+    /// Newest `LastModified` seen by the last successful scan.
+    watermark: Option<i64>,
+    /// Changes arrive as event notifications, so only full reconciles poll.
+    notified: bool,
+    last_reconcile: Instant,
+    next_poll: Instant,
+}
+
+impl Source {
+    fn new(config: S3Config) -> Result<Self> {
+        let bucket = RefreshingBucket::new(*create_bucket(&config)?, config.credentials.clone());
+        let cover_store = match &config.covers {
+            Some(covers) => Some(CoverStore::new(&config, covers)?),
+            None => None,
+        };
+        let now = Instant::now();
+        Ok(Self {
+            config,
+            bucket,
+            cover_store,
+            watermark: None,
+            notified: false,
+            last_reconcile: now,
+            next_poll: now,
+        })
+    }
+
+    fn covers<'a>(&'a self, dir: &'a Path) -> CoverTarget<'a> {
+        CoverTarget {
+            dir,
+            store: self.cover_store.as_ref(),
+        }
+    }
+
+    /// The kind of scan due now: a full reconcile when one is due (or no
+    /// watermark is known yet), otherwise an incremental poll.
+    fn due_scan(&self) -> ScanKind {
+        let reconcile_due = self.notified
+            || self.config.reconcile_interval == 0
+            || self.last_reconcile.elapsed() >= Duration::from_secs(self.config.reconcile_interval);
+        match self.watermark {
+            Some(watermark) if !reconcile_due => ScanKind::Since(watermark - WATERMARK_SLACK_SECS),
+            _ => ScanKind::Full,
+        }
+    }
+
+    /// Run one scan cycle and schedule the next poll.
+    async fn scan(
+        &mut self,
+        kind: ScanKind,
+        covers_path: &Path,
+        db: &Database,
+        shutdown: &AtomicBool,
+    ) -> Result<CycleCounts> {
+        let covers = self.covers(covers_path);
+        set_scan_marker(db, "s3", true);
+        let scan_result = {
+            let cycle = run_scan_cycle(&self.bucket, &self.config, covers, db, kind, shutdown);
+            tokio::pin!(cycle);
+            // Keep the marker fresh so long scans keep holding off maintenance.
+            loop {
+                tokio::select! {
+                    result = &mut cycle => break result,
+                    _ = tokio::time::sleep(SCAN_MARKER_REFRESH) => set_scan_marker(db, "s3", true),
+                }
+            }
+        };
+        set_scan_marker(db, "s3", false);
+        self.next_poll = Instant::now() + Duration::from_secs(self.next_scan_delay());
+
+        let (counts, new_watermark) = scan_result?;
+        if kind == ScanKind::Full {
+            self.last_reconcile = Instant::now();
+        }
+        // Failed objects are retried by the next poll only if the watermark
+        // stays where it was.
+        if counts.failed == 0 {
+            self.watermark = new_watermark.or(self.watermark);
+        }
+        Ok(counts)
+    }
+
+    /// Seconds until the next scan. Notified sources only reconcile; the
+    /// rest poll, and every poll lists the whole source.
+    fn next_scan_delay(&self) -> u64 {
+        if self.notified && self.config.reconcile_interval > 0 {
+            self.config.reconcile_interval
+        } else {
+            self.config.poll_interval
+        }
+    }
+
+    fn describe(&self) -> String {
+        let bucket = &self.config.bucket;
+        if self.config.prefixes.is_empty() {
+            return format!("s3://{}/", bucket);
+        }
+        self.config
+            .prefixes
+            .iter()
+            .map(|prefix| format!("s3://{}/{}", bucket, prefix))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Run the S3 polling watcher over every source. Blocks until shutdown
+/// signal.
+///
+/// Each source is polled on its own interval. Every poll lists the whole
+/// source; between full reconciles (every `reconcile_interval` seconds) it
+/// only reads and processes objects modified since the previous scan. With
+/// `webhook`, event notifications are applied to the sources watching the
+/// notified keys as they arrive, and sources are only listed at startup and
+/// on `reconcile_interval`.
+pub async fn run(
+    configs: Vec<S3Config>,
     covers_path: &Path,
-    db: Database,
+    db: &Database,
+    housekeeping: Housekeeping,
+    webhook: Option<WebhookConfig>,
     shutdown: Arc<AtomicBool>,
 ) -> Result<()> {
-    let bucket = create_bucket(&config)?;
-    let prefix = config.prefix.as_deref();
+    let mut sources = configs
+        .into_iter()
+        .map(Source::new)
+        .collect::<Result<Vec<_>>>()?;
+    for source in &mut sources {
+        source.notified = webhook.is_some();
+    }
+    if sources.is_empty() {
+        anyhow::bail!("No S3 sources configured");
+    }
 
-    log(&format!(
-        "[S3] Watching s3://{}/{}  (poll every {}s)",
-        config.bucket,
-        prefix.unwrap_or(""),
-        config.poll_interval
-    ));
+    for source in &sources {
+        let config = &source.config;
+        log(&format!(
+            "[S3] Watching {} at {}  ({} every {}s)",
+            config.name,
+            source.describe(),
+            if source.notified { "reconcile" } else { "poll" },
+            source.next_scan_delay()
+        ));
+        if let Some(covers) = &config.covers {
+            log(&format!(
+                "[S3] Uploading covers of {} to s3://{}/{}",
+                config.name,
+                covers.bucket.as_deref().unwrap_or(&config.bucket),
+                covers.prefix
+            ));
+        }
+        match db.claim_s3_books(&config.name, &config.bucket, &config.prefixes) {
+            Ok(0) => {}
+            Ok(claimed) => log(&format!(
+                "[S3] Assigned {} existing book(s) to {}",
+                claimed, config.name
+            )),
+            Err(e) => log(&format!(
+                "[S3] [ERROR] Failed to assign existing books to {}: {}",
+                config.name, e
+            )),
+        }
+    }
+
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+    if let Some(webhook) = &webhook {
+        let buckets: BTreeSet<String> = sources.iter().map(|s| s.config.bucket.clone()).collect();
+        let addr = webhook::spawn(webhook, buckets.into_iter().collect(), events_tx)?;
+        log(&format!(
+            "[S3] Listening for event notifications on {}",
+            addr
+        ));
+    }
+
+    let mut last_maintenance = Instant::now();
 
     // Initial scan
     log("[S3] Starting initial scan...");
-    let scan_result = run_scan_cycle(&bucket, &config.bucket, prefix, covers_path, &db).await;
-    match scan_result {
-        Ok((added, changed, removed)) => {
-            log(&format!(
-                "[S3] Initial scan complete — {} added, {} updated, {} removed",
-                added, changed, removed
-            ));
+    for source in &mut sources {
+        if shutdown.load(Ordering::Relaxed) {
+            break;
         }
-        Err(e) => {
-            log(&format!("[S3] [ERROR] Initial scan failed: {}", e));
+        match source
+            .scan(ScanKind::Full, covers_path, db, &shutdown)
+            .await
+        {
+            Ok(counts) => log(&format!(
+                "[S3] Initial scan of {} complete — {}",
+                source.config.name, counts
+            )),
+            Err(e) => log(&format!(
+                "[S3] [ERROR] Initial scan of {} failed: {}",
+                source.config.name, e
+            )),
         }
     }
 
@@ -47,71 +239,402 @@ pub async fn run(
             break;
         }
 
-        tokio::time::sleep(std::time::Duration::from_secs(config.poll_interval)).await;
+        let next_poll = sources
+            .iter()
+            .map(|s| s.next_poll)
+            .min()
+            .unwrap_or_else(Instant::now);
+        let notification = tokio::select! {
+            _ = tokio::time::sleep_until(next_poll.into()) => None,
+            Some(notification) = events.recv(), if webhook.is_some() => Some(notification),
+            _ = shutdown_requested(&shutdown) => None,
+        };
 
         if shutdown.load(Ordering::Relaxed) {
             break;
         }
 
-        match run_scan_cycle(&bucket, &config.bucket, prefix, covers_path, &db).await {
-            Ok((added, changed, removed)) => {
-                if added + changed + removed > 0 {
+        if let Some(first) = notification {
+            let mut batch = vec![first];
+            while let Ok(notification) = events.try_recv() {
+                batch.push(notification);
+            }
+            for source in &mut sources {
+                let ours: Vec<&Notification> = batch
+                    .iter()
+                    .filter(|n| {
+                        n.bucket
+                            .as_deref()
+                            .is_none_or(|bucket| bucket == source.config.bucket)
+                    })
+                    .collect();
+                if ours.is_empty() {
+                    continue;
+                }
+                let covers = source.covers(covers_path);
+                match apply_notifications(
+                    &source.bucket,
+                    &source.config,
+                    covers,
+                    db,
+                    &ours,
+                    &shutdown,
+                )
+                .await
+                {
+                    Ok(counts) if counts.is_empty() => {}
+                    Ok(counts) => log(&format!(
+                        "[S3] Notification for {}: {}",
+                        source.config.name, counts
+                    )),
+                    Err(e) => log(&format!(
+                        "[S3] [ERROR] Failed to apply notifications for {}: {}",
+                        source.config.name, e
+                    )),
+                }
+            }
+            continue;
+        }
+
+        let now = Instant::now();
+        for source in sources.iter_mut().filter(|s| s.next_poll <= now) {
+            if shutdown.load(Ordering::Relaxed) {
+                break;
+            }
+            let kind = source.due_scan();
+            match source.scan(kind, covers_path, db, &shutdown).await {
+                Ok(counts) if counts.is_empty() => {}
+                Ok(counts) => {
+                    let label = if kind == ScanKind::Full {
+                        "Reconcile"
+                    } else {
+                        "Poll"
+                    };
                     log(&format!(
-                        "[S3] Poll: {} added, {} updated, {} removed",
-                        added, changed, removed
+                        "[S3] {} of {}: {}",
+                        label, source.config.name, counts
                     ));
                 }
+                Err(e) => log(&format!(
+                    "[S3] [ERROR] Poll cycle of {} failed: {}",
+                    source.config.name, e
+                )),
             }
-            Err(e) => {
-                log(&format!("[S3] [ERROR] Poll cycle failed: {}", e));
+        }
+
+        for book in purge_missing_books(db, housekeeping.trash_retention_secs) {
+            if let Some(cover) = &book.cover_path {
+                // Covers of books from a source no longer configured can
+                // still be removed if they are local.
+                let source = sources
+                    .iter()
+                    .find(|s| book.s3_source.as_deref() == Some(s.config.name.as_str()))
+                    .or(match sources.as_slice() {
+                        [only] => Some(only),
+                        _ => None,
+                    });
+                let covers = match source {
+                    Some(source) => source.covers(covers_path),
+                    None => CoverTarget::local(covers_path),
+                };
+                covers.remove(cover).await;
             }
         }
+        compact_events(db);
+
+        if let Some(interval) = housekeeping.maintenance_interval
+            && last_maintenance.elapsed() >= interval
+            && maintain_database(db)
+        {
+            last_maintenance = Instant::now();
+        }
     }
 
     log("[S3] Shutting down...");
     Ok(())
 }
 
+/// Resolves once `shutdown` is set, so a long sleep until the next poll
+/// doesn't hold up the exit.
+pub(super) async fn shutdown_requested(shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::Relaxed) {
+        tokio::time::sleep(SHUTDOWN_CHECK_INTERVAL).await;
+    }
+}
+
+/// How much of the listing a scan cycle diffs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScanKind {
+    /// Everything, including deletions.
+    Full,
+    /// Only objects modified at or after this unix time.
+    Since(i64),
+}
+
+/// What a scan cycle did. Failed objects are not counted as added or changed.
+#[derive(Debug, Default)]
+struct CycleCounts {
+    added: usize,
+    changed: usize,
+    removed: usize,
+    failed: usize,
+}
+
+impl CycleCounts {
+    fn is_empty(&self) -> bool {
+        self.added + self.changed + self.removed + self.failed == 0
+    }
+}
+
+impl std::fmt::Display for CycleCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed",
+            self.added, self.changed, self.removed
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
 /// Run a single scan cycle: list S3 → diff against DB → process changes.
-/// Returns (added_count, changed_count, removed_count).
+/// Incremental cycles still list every object; they only skip reading the
+/// ones unchanged since the watermark. Also returns the newest
+/// `LastModified` in the listing, the watermark for the next incremental
+/// cycle.
 async fn run_scan_cycle(
-    bucket: &s3::Bucket,
-    bucket_name: &str,
-    prefix: Option<&str>,
-    covers_dir: &Path,
+    bucket: &RefreshingBucket,
+    config: &S3Config,
+    covers: CoverTarget<'_>,
     db: &Database,
-) -> Result<(usize, usize, usize)> {
-    let s3_objects = list_objects(bucket, prefix).await?;
-    let db_books = db.find_s3_books(bucket_name)?;
-    let diff = compute_diff(&s3_objects, &db_books);
+    kind: ScanKind,
+    shutdown: &AtomicBool,
+) -> Result<(CycleCounts, Option<i64>)> {
+    let s3_objects = list_source_objects(&bucket.current().await, config).await?;
+    let db_books = db.find_s3_books(&config.name)?;
+    let skipped = db.skipped_objects(&config.name)?;
+    let diff = match kind {
+        ScanKind::Full => {
+            // Forget skipped copies that were deleted since.
+            let listed: HashSet<&str> = s3_objects.iter().map(|o| o.key.as_str()).collect();
+            for object in skipped.iter().filter(|s| !listed.contains(s.key.as_str())) {
+                db.forget_skipped_object(&config.name, &object.key)?;
+            }
+            compute_diff(&s3_objects, &db_books, &skipped)
+        }
+        ScanKind::Since(since) => compute_diff_since(&s3_objects, &db_books, &skipped, since),
+    };
+    let watermark = s3_objects.iter().filter_map(|o| o.last_modified).max();
 
-    let added = diff.added.len();
-    let changed = diff.changed.len();
-    let removed = diff.removed.len();
+    let counts = apply_diff(bucket, config, covers, db, &diff, shutdown).await;
+    Ok((counts, watermark))
+}
 
-    for object in &diff.added {
-        if let Err(e) = handle_s3_add(bucket, object, db, bucket_name, covers_dir).await {
-            log(&format!("[S3] [ERROR] Failed to add {}: {}", object.key, e));
+/// Look up each notified key and apply whatever changed. Notifications only
+/// say which keys to look at; the object itself is the source of truth, so
+/// out-of-order or duplicate events are harmless.
+async fn apply_notifications(
+    bucket: &RefreshingBucket,
+    config: &S3Config,
+    covers: CoverTarget<'_>,
+    db: &Database,
+    notifications: &[&Notification],
+    shutdown: &AtomicBool,
+) -> Result<CycleCounts> {
+    let keys: BTreeSet<&str> = notifications
+        .iter()
+        .map(|n| n.key.as_str())
+        .filter(|key| config.accepts(key) && is_book_key(key))
+        .collect();
+
+    let mut s3_objects = Vec::new();
+    let mut db_books = Vec::new();
+    let mut copies = Vec::new();
+    for &key in &keys {
+        let object = head_object(&bucket.current().await, key).await?;
+        let book = db.find_s3_book(&config.name, key)?;
+        // Copies skipped while the book was here may take its place.
+        if object.is_none()
+            && let Some(book) = &book
+        {
+            copies.extend(db.skipped_copies_of(&config.name, &book.id)?);
         }
+        s3_objects.extend(object);
+        db_books.extend(book);
+    }
+    for key in copies.iter().filter(|key| !keys.contains(key.as_str())) {
+        s3_objects.extend(head_object(&bucket.current().await, key).await?);
     }
 
-    for object in &diff.changed {
-        if let Err(e) = handle_s3_change(bucket, object, db, bucket_name, covers_dir).await {
-            log(&format!(
-                "[S3] [ERROR] Failed to update {}: {}",
-                object.key, e
-            ));
+    let skipped = db.skipped_objects(&config.name)?;
+    let diff = compute_diff(&s3_objects, &db_books, &skipped);
+    Ok(apply_diff(bucket, config, covers, db, &diff, shutdown).await)
+}
+
+/// Credentials are checked before each object is started, so a diff that
+/// takes longer than they last doesn't fail halfway. Removals go first, so
+/// an object that moved to a new key restores its book there.
+async fn apply_diff(
+    bucket: &RefreshingBucket,
+    config: &S3Config,
+    covers: CoverTarget<'_>,
+    db: &Database,
+    diff: &ScanDiff,
+    shutdown: &AtomicBool,
+) -> CycleCounts {
+    let origin = S3Origin {
+        source: &config.name,
+        bucket: &config.bucket,
+    };
+    let mut counts = CycleCounts::default();
+
+    for book in &diff.removed {
+        match handle_s3_delete(db, book) {
+            Ok(()) => counts.removed += 1,
+            Err(e) => {
+                counts.failed += 1;
+                log(&format!(
+                    "[S3] [ERROR] Failed to remove \"{}\": {}",
+                    book.title, e
+                ));
+            }
         }
     }
 
-    for book in &diff.removed {
-        if let Err(e) = handle_s3_delete(db, book) {
-            log(&format!(
-                "[S3] [ERROR] Failed to remove \"{}\": {}",
-                book.title, e
-            ));
+    let (added, failed) = process_concurrently(
+        &diff.added,
+        "add",
+        config.concurrency,
+        shutdown,
+        |object| async move {
+            let bucket = bucket.current().await;
+            handle_s3_add(&bucket, object, db, origin, covers).await
+        },
+    )
+    .await;
+    counts.added = added;
+    counts.failed += failed;
+
+    let (changed, failed) = process_concurrently(
+        &diff.changed,
+        "update",
+        config.concurrency,
+        shutdown,
+        |object| async move {
+            let bucket = bucket.current().await;
+            handle_s3_change(&bucket, object, db, origin, covers).await
+        },
+    )
+    .await;
+    counts.changed = changed;
+    counts.failed += failed;
+
+    counts
+}
+
+/// Run `process` over `objects`, at most `concurrency` at a time, and return
+/// how many succeeded and failed. A failure is logged and the rest carry on;
+/// no new objects are started once `shutdown` is set.
+///
+/// Every future is polled on the calling task, so while reads and
+/// extraction (on the blocking pool) overlap, the DB calls made between
+/// awaits never do.
+async fn process_concurrently<'a, F, Fut>(
+    objects: &'a [S3Object],
+    action: &str,
+    concurrency: usize,
+    shutdown: &AtomicBool,
+    process: F,
+) -> (usize, usize)
+where
+    F: Fn(&'a S3Object) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let total = objects.len();
+    let mut results = stream::iter(objects)
+        .take_while(|_| std::future::ready(!shutdown.load(Ordering::Relaxed)))
+        .map(|object| {
+            let work = process(object);
+            async move { (object, work.await) }
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let (mut succeeded, mut failed) = (0, 0);
+    while let Some((object, result)) = results.next().await {
+        match result {
+            Ok(()) => succeeded += 1,
+            Err(e) => {
+                failed += 1;
+                log(&format!(
+                    "[S3] [ERROR] Failed to {} {}: {:#}",
+                    action, object.key, e
+                ));
+            }
         }
+        let done = succeeded + failed;
+        if done % 100 == 0 && done < total {
+            log(&format!("[S3] Processed {}/{} objects...", done, total));
+        }
+    }
+    (succeeded, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::process_concurrently;
+    use crate::s3::scanner::S3Object;
+    use std::cell::Cell;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    fn objects(count: usize) -> Vec<S3Object> {
+        (0..count)
+            .map(|i| S3Object {
+                key: format!("book-{i}.epub"),
+                size: 1,
+                etag: format!("etag-{i}"),
+                last_modified: None,
+            })
+            .collect()
     }
 
-    Ok((added, changed, removed))
+    #[tokio::test]
+    async fn processing_is_bounded_and_failures_are_isolated() {
+        let objects = objects(20);
+        let in_flight = Cell::new(0);
+        let peak = Cell::new(0);
+
+        let (succeeded, failed) =
+            process_concurrently(&objects, "add", 4, &AtomicBool::new(false), |object| {
+                let (in_flight, peak) = (&in_flight, &peak);
+                async move {
+                    in_flight.set(in_flight.get() + 1);
+                    peak.set(peak.get().max(in_flight.get()));
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    in_flight.set(in_flight.get() - 1);
+                    if object.key.ends_with("3.epub") {
+                        anyhow::bail!("simulated failure");
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert_eq!((succeeded, failed), (18, 2));
+        assert_eq!(peak.get(), 4);
+    }
+
+    #[tokio::test]
+    async fn processing_stops_taking_objects_after_shutdown() {
+        let objects = objects(10);
+        let (succeeded, failed) =
+            process_concurrently(&objects, "add", 2, &AtomicBool::new(true), |_| async {
+                Ok(())
+            })
+            .await;
+        assert_eq!((succeeded, failed), (0, 0));
+    }
 }
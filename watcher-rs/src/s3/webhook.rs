@@ -0,0 +1,284 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::log::log;
+
+/// Requests larger than this are rejected; S3 batches a handful of records.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Where to listen for S3 event notifications.
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub addr: SocketAddr,
+    /// Required in the `Authorization` header (bare or as `Bearer <token>`).
+    pub token: Option<String>,
+}
+
+/// An object that was created or removed, according to a notification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// The bucket named in the record, if the sender included it.
+    pub bucket: Option<String>,
+    pub key: String,
+    pub removed: bool,
+}
+
+#[derive(Deserialize)]
+struct EventBody {
+    #[serde(rename = "Records", default)]
+    records: Vec<Record>,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    #[serde(rename = "eventName", default)]
+    event_name: String,
+    s3: RecordS3,
+}
+
+#[derive(Deserialize)]
+struct RecordS3 {
+    bucket: Option<RecordBucket>,
+    object: RecordObject,
+}
+
+#[derive(Deserialize)]
+struct RecordBucket {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct RecordObject {
+    key: String,
+}
+
+/// Parse an S3-style event notification (as sent by AWS, MinIO and R2
+/// forwarders). Records for buckets not in `buckets` are skipped; keys
+/// arrive URL-encoded and are decoded here.
+pub fn parse_notification(body: &[u8], buckets: &[String]) -> Result<Vec<Notification>> {
+    let body: EventBody = serde_json::from_slice(body).context("Invalid event notification")?;
+    Ok(body
+        .records
+        .into_iter()
+        .filter(|record| {
+            record
+                .s3
+                .bucket
+                .as_ref()
+                .is_none_or(|b| buckets.contains(&b.name))
+        })
+        .map(|record| Notification {
+            bucket: record.s3.bucket.map(|b| b.name),
+            key: decode_key(&record.s3.object.key),
+            removed: record.event_name.contains("ObjectRemoved"),
+        })
+        .collect())
+}
+
+/// Listen on `config.addr` and forward notifications for `buckets` to `events`.
+/// Returns the bound address (useful with port 0). The listener thread exits
+/// on the first request after the receiving side is dropped.
+pub fn spawn(
+    config: &WebhookConfig,
+    buckets: Vec<String>,
+    events: UnboundedSender<Notification>,
+) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(config.addr)
+        .with_context(|| format!("Failed to bind S3 webhook on {}", config.addr))?;
+    let addr = listener.local_addr()?;
+    let token = config.token.clone();
+
+    thread::Builder::new()
+        .name("s3-webhook".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                if let Err(e) = handle_connection(stream, token.as_deref(), &buckets, &events) {
+                    log(&format!("[S3] [WEBHOOK] [ERROR] {}", e));
+                }
+                if events.is_closed() {
+                    break;
+                }
+            }
+        })
+        .context("Failed to spawn S3 webhook thread")?;
+
+    Ok(addr)
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    token: Option<&str>,
+    buckets: &[String],
+    events: &UnboundedSender<Notification>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let method = request_line.split_whitespace().next().unwrap_or_default();
+
+    let mut content_length = 0usize;
+    let mut authorization = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().unwrap_or(0),
+                "authorization" => authorization = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    if method != "POST" {
+        return respond(&mut stream, "405 Method Not Allowed");
+    }
+    if let Some(token) = token {
+        let accepted = authorization
+            .as_deref()
+            .is_some_and(|value| value == token || value.strip_prefix("Bearer ") == Some(token));
+        if !accepted {
+            return respond(&mut stream, "401 Unauthorized");
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return respond(&mut stream, "413 Payload Too Large");
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let notifications = match parse_notification(&body, buckets) {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            respond(&mut stream, "400 Bad Request")?;
+            return Err(e);
+        }
+    };
+    for notification in notifications {
+        let _ = events.send(notification);
+    }
+    respond(&mut stream, "200 OK")
+}
+
+fn respond(stream: &mut TcpStream, status: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Undo the form-style URL encoding S3 applies to keys in notifications.
+fn decode_key(key: &str) -> String {
+    let bytes = key.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|h| std::str::from_utf8(h).ok());
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notification, WebhookConfig, decode_key, parse_notification, spawn};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    const EVENT: &str = r#"{"Records": [
+        {"eventName": "s3:ObjectCreated:Put",
+         "s3": {"bucket": {"name": "books"}, "object": {"key": "new/My+Book%21.epub", "size": 10}}},
+        {"eventName": "s3:ObjectRemoved:Delete",
+         "s3": {"bucket": {"name": "books"}, "object": {"key": "old.pdf"}}},
+        {"eventName": "s3:ObjectCreated:Put",
+         "s3": {"bucket": {"name": "other"}, "object": {"key": "elsewhere.pdf"}}}
+    ]}"#;
+
+    #[test]
+    fn parse_notification_decodes_keys_and_skips_other_buckets() {
+        let notifications = parse_notification(EVENT.as_bytes(), &["books".to_string()]).unwrap();
+        assert_eq!(
+            notifications,
+            vec![
+                Notification {
+                    bucket: Some("books".to_string()),
+                    key: "new/My Book!.epub".to_string(),
+                    removed: false,
+                },
+                Notification {
+                    bucket: Some("books".to_string()),
+                    key: "old.pdf".to_string(),
+                    removed: true,
+                },
+            ]
+        );
+        assert_eq!(decode_key("100%"), "100%");
+
+        let watched = ["books".to_string(), "other".to_string()];
+        let notifications = parse_notification(EVENT.as_bytes(), &watched).unwrap();
+        assert_eq!(notifications.len(), 3);
+        assert_eq!(notifications[2].bucket.as_deref(), Some("other"));
+    }
+
+    fn post(addr: std::net::SocketAddr, auth: Option<&str>) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let auth = auth
+            .map(|value| format!("Authorization: {value}\r\n"))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "POST /events HTTP/1.1\r\nHost: test\r\n{auth}Content-Length: {}\r\n\r\n{EVENT}",
+            EVENT.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn webhook_requires_the_token_and_forwards_events() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let config = WebhookConfig {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            token: Some("secret".to_string()),
+        };
+        let addr = spawn(&config, vec!["books".to_string()], tx).unwrap();
+
+        assert!(post(addr, None).starts_with("HTTP/1.1 401"));
+        assert!(rx.try_recv().is_err());
+
+        assert!(post(addr, Some("Bearer secret")).starts_with("HTTP/1.1 200"));
+        assert_eq!(rx.try_recv().unwrap().key, "new/My Book!.epub");
+        assert!(rx.try_recv().unwrap().removed);
+    }
+}
@@ -1,7 +1,6 @@
 use crate::tunnel::protocol::Frame;
 use anyhow::{Context, Result};
 use http_body_util::BodyExt;
-use hyper::body::Incoming;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use tokio::sync::mpsc;
@@ -43,13 +42,18 @@ pub async fn forward_request(
 
     // Tell the app the original protocol and host so NextAuth and redirect
     // logic work correctly behind the relay.
-    if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("x-forwarded-proto")) {
+    if !headers
+        .iter()
+        .any(|(k, _)| k.eq_ignore_ascii_case("x-forwarded-proto"))
+    {
         builder = builder.header("x-forwarded-proto", "https");
     }
-    if let Some(fwd_host) = &original_host {
-        if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case("x-forwarded-host")) {
-            builder = builder.header("x-forwarded-host", fwd_host.as_str());
-        }
+    if let Some(fwd_host) = &original_host
+        && !headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("x-forwarded-host"))
+    {
+        builder = builder.header("x-forwarded-host", fwd_host.as_str());
     }
 
     let req = builder
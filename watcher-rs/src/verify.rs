@@ -0,0 +1,487 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::covers::{
+    generate_epub_cover, generate_epub_cover_from_bytes, generate_pdf_cover,
+    generate_pdf_cover_from_bytes,
+};
+use crate::db::{Database, EventKind, LibraryBook};
+use crate::handlers::add::compute_sha256;
+use crate::handlers::handle_change_with_covers_dir;
+use crate::log::log;
+use crate::s3::client::create_bucket;
+use crate::s3::covers::{CoverStore, CoverStoreConfig};
+use crate::s3::handlers::fetch_object_bytes;
+use crate::s3::presign::parse_s3_uri;
+use crate::s3::scanner::list_source_objects;
+use crate::s3::{S3Config, source_for};
+
+const COVER_EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "png", "gif", "webp", "svg"];
+
+/// Orphan covers newer than this are left alone by repair: a running watcher
+/// writes a book's cover before it records the book.
+const ORPHAN_COVER_GRACE: Duration = Duration::from_secs(10 * 60);
+
+pub struct VerifyOptions {
+    /// Re-hash local files and compare with `file_hash` (reads every book).
+    pub rehash: bool,
+    pub repair: bool,
+}
+
+/// Problems found by [`run`], grouped by kind.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub books_checked: usize,
+    pub cover_files_checked: usize,
+    /// Local books whose file is gone (orphan cleanup will mark them missing).
+    pub missing_files: Vec<BookIssue>,
+    /// Books whose `cover_path` points at a file that does not exist.
+    pub missing_covers: Vec<BookIssue>,
+    /// Images in the covers directory that no book refers to.
+    pub orphan_covers: Vec<String>,
+    /// Local books whose content no longer matches `file_hash` (only with rehash).
+    pub hash_mismatches: Vec<HashMismatch>,
+    /// Local books whose file exists but could not be read to re-hash it.
+    pub unreadable_files: Vec<UnreadableFile>,
+    /// S3 books whose key is no longer in the bucket.
+    pub missing_s3_keys: Vec<BookIssue>,
+    /// S3 books that could not be checked (no credentials, or another bucket).
+    pub s3_books_unchecked: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repaired: Option<Repaired>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookIssue {
+    pub book_id: String,
+    pub title: String,
+    pub path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HashMismatch {
+    pub book_id: String,
+    pub title: String,
+    pub file_path: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadableFile {
+    pub book_id: String,
+    pub title: String,
+    pub file_path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Repaired {
+    pub covers_regenerated: usize,
+    pub orphan_covers_removed: usize,
+    /// Orphan covers too recent to remove; see [`ORPHAN_COVER_GRACE`].
+    pub orphan_covers_kept: usize,
+    pub books_reprocessed: usize,
+    pub failures: Vec<String>,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.missing_covers.is_empty()
+            && self.orphan_covers.is_empty()
+            && self.hash_mismatches.is_empty()
+            && self.unreadable_files.is_empty()
+            && self.missing_s3_keys.is_empty()
+    }
+}
+
+/// Check the database against the filesystem (and the buckets of the `s3`
+/// sources), then fix what can be fixed if `options.repair` is set: missing
+/// covers are regenerated, orphan covers deleted and books whose hash no
+/// longer matches re-processed through `handle_change`.
+pub async fn run(
+    db: &Database,
+    covers_dir: &Path,
+    s3: &[S3Config],
+    options: &VerifyOptions,
+) -> Result<Report> {
+    let books = db.library_books()?;
+    let mut report = check_local(db, &books, covers_dir, options.rehash)?;
+    check_s3(&books, s3, &mut report).await?;
+
+    if options.repair {
+        report.repaired = Some(repair(db, &books, covers_dir, s3, &report).await);
+    }
+    Ok(report)
+}
+
+fn check_local(
+    db: &Database,
+    books: &[LibraryBook],
+    covers_dir: &Path,
+    rehash: bool,
+) -> Result<Report> {
+    let mut report = Report {
+        books_checked: books.len(),
+        ..Report::default()
+    };
+
+    for book in books {
+        // Covers kept in a bucket are checked by `check_s3`.
+        if let Some(cover_path) = &book.cover_path
+            && parse_s3_uri(cover_path).is_none()
+            && !Path::new(cover_path).is_file()
+        {
+            report.missing_covers.push(issue(book, cover_path));
+        }
+
+        if book.source != "local" {
+            continue;
+        }
+        let path = Path::new(&book.file_path);
+        if !path.is_file() {
+            report.missing_files.push(issue(book, &book.file_path));
+            continue;
+        }
+        if rehash {
+            let actual = match compute_sha256(path) {
+                Ok(actual) => actual,
+                Err(e) => {
+                    report.unreadable_files.push(UnreadableFile {
+                        book_id: book.id.clone(),
+                        title: book.title.clone(),
+                        file_path: book.file_path.clone(),
+                        error: format!("{e:#}"),
+                    });
+                    continue;
+                }
+            };
+            if actual != book.file_hash {
+                report.hash_mismatches.push(HashMismatch {
+                    book_id: book.id.clone(),
+                    title: book.title.clone(),
+                    file_path: book.file_path.clone(),
+                    expected: book.file_hash.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+
+    // Missing books keep their covers until they are purged.
+    let referenced: HashSet<PathBuf> = db
+        .cover_paths()?
+        .iter()
+        .map(|path| normalize(Path::new(path)))
+        .collect();
+    for cover in cover_files(covers_dir)? {
+        report.cover_files_checked += 1;
+        if !referenced.contains(&normalize(&cover)) {
+            report
+                .orphan_covers
+                .push(cover.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(report)
+}
+
+async fn check_s3(books: &[LibraryBook], s3: &[S3Config], report: &mut Report) -> Result<()> {
+    // Each source is listed once, the first time one of its books comes up.
+    let mut listings: HashMap<&str, (Box<s3::Bucket>, HashSet<String>)> = HashMap::new();
+
+    for book in books.iter().filter(|b| b.source == "s3") {
+        let Some(config) = source_for(s3, book.s3_source.as_deref(), book.s3_bucket.as_deref())
+        else {
+            report.s3_books_unchecked += 1;
+            continue;
+        };
+        if book.s3_bucket.as_deref() != Some(config.bucket.as_str()) {
+            report.s3_books_unchecked += 1;
+            continue;
+        }
+        if !listings.contains_key(config.name.as_str()) {
+            let bucket = create_bucket(config)?;
+            let keys = list_source_objects(&bucket, config)
+                .await?
+                .into_iter()
+                .map(|object| object.key)
+                .collect();
+            listings.insert(&config.name, (bucket, keys));
+        }
+        let (bucket, keys) = &listings[config.name.as_str()];
+        if !keys.contains(&book.file_path) {
+            report.missing_s3_keys.push(issue(book, &book.file_path));
+        }
+
+        if let Some(cover_path) = &book.cover_path
+            && let Some((cover_bucket, key)) = parse_s3_uri(cover_path)
+        {
+            let mut cover_bucket_handle = (**bucket).clone();
+            cover_bucket_handle.name = cover_bucket.to_string();
+            if head_object_status(&cover_bucket_handle, key).await? == 404 {
+                report.missing_covers.push(issue(book, cover_path));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn head_object_status(bucket: &s3::Bucket, key: &str) -> Result<u16> {
+    let (_, status) = bucket.head_object(key).await?;
+    Ok(status)
+}
+
+async fn repair(
+    db: &Database,
+    books: &[LibraryBook],
+    covers_dir: &Path,
+    s3: &[S3Config],
+    report: &Report,
+) -> Repaired {
+    let mut repaired = Repaired::default();
+    let by_id: HashMap<&str, &LibraryBook> = books.iter().map(|b| (b.id.as_str(), b)).collect();
+
+    for missing in &report.missing_covers {
+        let Some(book) = by_id.get(missing.book_id.as_str()) else {
+            continue;
+        };
+        match regenerate_cover(db, book, covers_dir, s3).await {
+            Ok(true) => repaired.covers_regenerated += 1,
+            Ok(false) => repaired.failures.push(format!(
+                "No cover could be generated for \"{}\"",
+                book.title
+            )),
+            Err(e) => repaired
+                .failures
+                .push(format!("Cover for \"{}\": {:#}", book.title, e)),
+        }
+    }
+
+    for cover in &report.orphan_covers {
+        if recently_modified(Path::new(cover), ORPHAN_COVER_GRACE) {
+            repaired.orphan_covers_kept += 1;
+            continue;
+        }
+        match std::fs::remove_file(cover) {
+            Ok(()) => {
+                log(&format!("[VERIFY] Removed orphan cover {}", cover));
+                repaired.orphan_covers_removed += 1;
+            }
+            Err(e) => repaired.failures.push(format!("Remove {}: {}", cover, e)),
+        }
+    }
+
+    for mismatch in &report.hash_mismatches {
+        match handle_change_with_covers_dir(db, Path::new(&mismatch.file_path), covers_dir) {
+            Ok(()) => repaired.books_reprocessed += 1,
+            Err(e) => repaired
+                .failures
+                .push(format!("Reprocess {}: {:#}", mismatch.file_path, e)),
+        }
+    }
+
+    repaired
+}
+
+/// Generate a cover for a book whose cover file is gone. A lost user cover
+/// can't be recovered, so it is replaced with a generated one.
+async fn regenerate_cover(
+    db: &Database,
+    book: &LibraryBook,
+    covers_dir: &Path,
+    s3: &[S3Config],
+) -> Result<bool> {
+    let author = book.author.as_deref();
+    let source = source_for(s3, book.s3_source.as_deref(), book.s3_bucket.as_deref());
+    let cover = if book.source == "local" {
+        let path = Path::new(&book.file_path);
+        if !path.is_file() {
+            return Ok(false);
+        }
+        if book.file_type == "pdf" {
+            generate_pdf_cover(path, &book.id, &book.title, author, covers_dir)
+        } else {
+            generate_epub_cover(path, &book.id, &book.title, author, covers_dir)
+        }
+    } else {
+        let Some(config) = source else {
+            return Ok(false);
+        };
+        let mut config = config.clone();
+        if let Some(bucket) = &book.s3_bucket {
+            config.bucket = bucket.clone();
+        }
+        let bucket = create_bucket(&config)?;
+        let bytes = fetch_object_bytes(&bucket, &book.file_path).await?;
+        if book.file_type == "pdf" {
+            generate_pdf_cover_from_bytes(&bytes, &book.id, &book.title, author, covers_dir)
+        } else {
+            generate_epub_cover_from_bytes(&bytes, &book.id, &book.title, author, covers_dir)
+        }
+    };
+
+    let Some(cover) = cover else {
+        return Ok(false);
+    };
+    // Covers of S3 books go back to the bucket: to the object the book
+    // already points at, or to the cover store when one is configured.
+    let cover_path = match source.filter(|_| book.source == "s3") {
+        Some(config) if book.cover_path.as_deref().and_then(parse_s3_uri).is_some() => {
+            let store = CoverStore::new(config, &default_store(config))?;
+            let uri = book.cover_path.clone().unwrap_or_default();
+            store.replace(&cover, &uri).await?;
+            uri
+        }
+        Some(config) if config.covers.is_some() => {
+            let store = CoverStore::new(config, &default_store(config))?;
+            store.upload(&cover).await?
+        }
+        _ => cover.to_string_lossy().into_owned(),
+    };
+    db.set_generated_cover(&book.id, &cover_path)?;
+    db.record_event(
+        EventKind::Updated,
+        &book.id,
+        &["cover_path", "cover_source"],
+    )?;
+    log(&format!(
+        "[VERIFY] Regenerated cover for \"{}\"",
+        book.title
+    ));
+    Ok(true)
+}
+
+fn default_store(config: &S3Config) -> CoverStoreConfig {
+    config.covers.clone().unwrap_or(CoverStoreConfig {
+        bucket: None,
+        prefix: String::new(),
+    })
+}
+
+fn issue(book: &LibraryBook, path: &str) -> BookIssue {
+    BookIssue {
+        book_id: book.id.clone(),
+        title: book.title.clone(),
+        path: path.to_string(),
+    }
+}
+
+fn cover_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_image = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| COVER_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+        if entry.file_type()?.is_file() && is_image {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn recently_modified(path: &Path, within: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < within)
+        })
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VerifyOptions, run};
+    use crate::db::{COVER_SOURCE_GENERATED, Database, NewBook};
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    #[tokio::test]
+    async fn verify_reports_and_repairs_cover_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let covers = dir.path().join("covers");
+        fs::create_dir_all(&covers).unwrap();
+        fs::write(covers.join("stray.jpg"), b"jpeg").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(covers.join("stray.jpg"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        // Just written by a watcher that hasn't recorded its book yet.
+        fs::write(covers.join("fresh.jpg"), b"jpeg").unwrap();
+        let book_path = dir.path().join("book.epub");
+        fs::write(&book_path, b"not really an epub").unwrap();
+
+        let db = Database::open_in_memory().unwrap();
+        let lost_cover = covers.join("book-1.jpg");
+        db.insert_book(&NewBook {
+            id: "book-1",
+            title: "Lost Cover",
+            author: None,
+            description: None,
+            file_type: "epub",
+            file_path: book_path.to_str().unwrap(),
+            file_size: 18,
+            file_hash: "stale-hash",
+            cover_path: lost_cover.to_str(),
+            cover_source: COVER_SOURCE_GENERATED,
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "local",
+            s3_source: None,
+            s3_bucket: None,
+            s3_etag: None,
+        })
+        .unwrap();
+
+        let check = VerifyOptions {
+            rehash: true,
+            repair: false,
+        };
+        let report = run(&db, &covers, &[], &check).await.unwrap();
+        assert_eq!(report.missing_covers.len(), 1);
+        assert_eq!(report.orphan_covers.len(), 2);
+        assert_eq!(report.hash_mismatches.len(), 1);
+        assert!(report.repaired.is_none());
+
+        let report = run(
+            &db,
+            &covers,
+            &[],
+            &VerifyOptions {
+                repair: true,
+                ..check
+            },
+        )
+        .await
+        .unwrap();
+        let repaired = report.repaired.unwrap();
+        assert_eq!(repaired.orphan_covers_removed, 1);
+        assert_eq!(repaired.orphan_covers_kept, 1);
+        assert_eq!(repaired.books_reprocessed, 1);
+        assert!(!covers.join("stray.jpg").exists());
+        assert!(covers.join("fresh.jpg").exists());
+
+        fs::remove_file(covers.join("fresh.jpg")).unwrap();
+        let report = run(&db, &covers, &[], &check).await.unwrap();
+        assert!(report.is_clean(), "{report:?}");
+    }
+}
@@ -1,17 +1,56 @@
-use crate::db::Database;
+use crate::covers::is_sidecar_cover_name;
+use crate::db::maintain::{MaintainOptions, SCAN_MARKER_REFRESH};
+use crate::db::{Database, EVENT_RETENTION_SECS, OrphanRow};
 use crate::handlers::{
-    handle_add_with_covers_dir, handle_change_with_covers_dir, handle_delete, remove_orphaned_books,
+    handle_add_with_covers_dir, handle_change_with_covers_dir, handle_delete, handle_sidecar_cover,
+    remove_orphaned_books,
 };
 use crate::log::log;
+use crate::s3::presign::parse_s3_uri;
 use notify::{EventKind, RecursiveMode, Watcher};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodic cleanup settings shared by the local and S3 watchers.
+#[derive(Debug, Clone)]
+pub struct Housekeeping {
+    /// Seconds a missing book is kept before it is purged.
+    pub trash_retention_secs: i64,
+    /// How often to run [`maintain_database`]; `None` disables it.
+    pub maintenance_interval: Option<Duration>,
+}
+
+/// Which watchers the process runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    Local,
+    S3,
+    /// Local and S3 together, sharing one database handle; see
+    /// [`crate::combined`].
+    Combined,
+}
+
+impl FromStr for WatchMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(WatchMode::Local),
+            "s3" => Ok(WatchMode::S3),
+            "combined" | "both" => Ok(WatchMode::Combined),
+            other => Err(format!("Unsupported watch mode: {other}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 enum PendingKind {
     AddOrModify,
@@ -57,10 +96,94 @@ fn collect_target_files(root: &Path, out: &mut Vec<PathBuf>) -> anyhow::Result<(
     Ok(())
 }
 
+/// Permanently remove books that have been missing longer than `retention_secs`,
+/// along with their local covers. Returns the purged books so covers kept in
+/// a bucket can be deleted by the caller.
+pub fn purge_missing_books(db: &Database, retention_secs: i64) -> Vec<OrphanRow> {
+    let purged = match db.purge_missing(retention_secs) {
+        Ok(purged) => purged,
+        Err(e) => {
+            log(&format!("[ERROR] Purging missing books failed: {}", e));
+            return Vec::new();
+        }
+    };
+
+    for book in &purged {
+        if let Some(ref cover_path) = book.cover_path
+            && parse_s3_uri(cover_path).is_none()
+        {
+            let _ = std::fs::remove_file(cover_path);
+        }
+        log(&format!("[PURGE] Removed \"{}\" from library", book.title));
+    }
+    purged
+}
+
+/// Drop old change-feed events; failures only cost disk space.
+pub fn compact_events(db: &Database) {
+    match db.compact_events(EVENT_RETENTION_SECS) {
+        Ok(0) => {}
+        Ok(removed) => log(&format!("[EVENTS] Compacted {} old event(s)", removed)),
+        Err(e) => log(&format!("[ERROR] Event compaction failed: {}", e)),
+    }
+}
+
+/// Checkpoint the WAL, refresh planner statistics and release free pages.
+/// Returns false, without doing anything, while a scan is running here or in
+/// another watcher process, so the caller can try again shortly.
+pub fn maintain_database(db: &Database) -> bool {
+    match db.scan_in_progress() {
+        Ok(false) => {}
+        Ok(true) => return false,
+        Err(e) => {
+            log(&format!("[ERROR] Database maintenance failed: {}", e));
+            return true;
+        }
+    }
+
+    let options = MaintainOptions {
+        analyze: false,
+        full_vacuum: false,
+    };
+    match db.maintain(&options) {
+        Ok(report) => {
+            log(&format!(
+                "[MAINTAIN] Reclaimed {} bytes (WAL {} -> {} bytes, {} free page(s) released) in {}ms",
+                report.reclaimed_bytes,
+                report.wal_bytes_before,
+                report.wal_bytes_after,
+                report.freelist_pages_before - report.freelist_pages_after,
+                report.duration_ms
+            ));
+            if report.checkpoint_busy {
+                log("[MAINTAIN] WAL checkpoint was blocked by an open reader");
+            }
+        }
+        Err(e) => log(&format!("[ERROR] Database maintenance failed: {}", e)),
+    }
+    true
+}
+
+/// Record that `source` started or finished a scan so maintenance (here or
+/// from `db maintain`) waits for it. Failures only affect maintenance timing.
+pub fn set_scan_marker(db: &Database, source: &str, running: bool) {
+    let result = if running {
+        db.begin_scan(source)
+    } else {
+        db.end_scan(source)
+    };
+    if let Err(e) = result {
+        log(&format!("[ERROR] Updating scan marker failed: {}", e));
+    }
+}
+
+/// Watch `library_path` until `shutdown` is set. Pass `housekeeping: None`
+/// when another watcher sharing `db` already purges and maintains it.
 pub fn run(
     library_path: PathBuf,
     covers_path: PathBuf,
-    db: Database,
+    db: &Database,
+    housekeeping: Option<Housekeeping>,
     shutdown: Arc<AtomicBool>,
 ) -> anyhow::Result<()> {
     let (tx, rx) = mpsc::channel();
@@ -84,6 +207,14 @@ pub fn run(
     let mut initial_scan_done = false;
     let stability_threshold = Duration::from_secs(2);
     let poll_interval = Duration::from_millis(500);
+    let mut last_housekeeping = Instant::now();
+    let mut last_maintenance = Instant::now();
+    if let Some(housekeeping) = &housekeeping {
+        purge_missing_books(db, housekeeping.trash_retention_secs);
+        compact_events(db);
+    }
+    set_scan_marker(db, "local", true);
+    let mut last_scan_marker = Instant::now();
 
     let mut startup_files = Vec::new();
     collect_target_files(&library_path, &mut startup_files)?;
@@ -102,8 +233,9 @@ pub fn run(
 
     if pending.is_empty() {
         initial_scan_done = true;
+        set_scan_marker(db, "local", false);
         log("[SCAN] Initial scan complete -- 0 file(s) found.");
-        if let Err(e) = remove_orphaned_books(&db) {
+        if let Err(e) = remove_orphaned_books(db) {
             log(&format!("[ERROR] Orphan cleanup failed: {}", e));
         }
     }
@@ -117,7 +249,7 @@ pub fn run(
             Ok(event) => {
                 let event: notify::Event = event;
                 for path in &event.paths {
-                    if !is_target(path) {
+                    if !is_target(path) && !is_sidecar_cover_name(path) {
                         continue;
                     }
 
@@ -172,9 +304,24 @@ pub fn run(
         for path in to_dispatch {
             let entry = pending.remove(&path).unwrap();
 
+            if !is_target(&path) {
+                // Sidecar cover images pin the cover of the book(s) they sit next to.
+                // Removing one leaves the pinned cover in place.
+                if entry.kind == PendingKind::AddOrModify
+                    && let Err(e) = handle_sidecar_cover(db, &path, &covers_path)
+                {
+                    log(&format!(
+                        "[ERROR] Failed to apply sidecar cover {}: {}",
+                        path.display(),
+                        e
+                    ));
+                }
+                continue;
+            }
+
             match entry.kind {
                 PendingKind::Remove => {
-                    if let Err(e) = handle_delete(&db, &path) {
+                    if let Err(e) = handle_delete(db, &path) {
                         log(&format!(
                             "[ERROR] Failed to handle deletion of {}: {}",
                             path.display(),
@@ -185,7 +332,7 @@ pub fn run(
                 PendingKind::AddOrModify => {
                     if !initial_scan_done {
                         scan_count += 1;
-                        if scan_count % 10 == 0 {
+                        if scan_count.is_multiple_of(10) {
                             log(&format!("[SCAN] Processed {} files...", scan_count));
                         }
                     }
@@ -194,14 +341,14 @@ pub fn run(
                     let is_existing = db.find_by_path(&file_path_str).ok().flatten().is_some();
 
                     if is_existing {
-                        if let Err(e) = handle_change_with_covers_dir(&db, &path, &covers_path) {
+                        if let Err(e) = handle_change_with_covers_dir(db, &path, &covers_path) {
                             log(&format!(
                                 "[ERROR] Failed to process change for {}: {}",
                                 path.display(),
                                 e
                             ));
                         }
-                    } else if let Err(e) = handle_add_with_covers_dir(&db, &path, &covers_path) {
+                    } else if let Err(e) = handle_add_with_covers_dir(db, &path, &covers_path) {
                         log(&format!(
                             "[ERROR] Failed to process {}: {}",
                             path.display(),
@@ -212,20 +359,46 @@ pub fn run(
             }
         }
 
+        if !initial_scan_done && last_scan_marker.elapsed() >= SCAN_MARKER_REFRESH {
+            set_scan_marker(db, "local", true);
+            last_scan_marker = Instant::now();
+        }
+
         // Detect initial scan completion: pending map empties after processing files
         if !initial_scan_done && pending.is_empty() && scan_count > 0 {
             initial_scan_done = true;
+            set_scan_marker(db, "local", false);
             log(&format!(
                 "[SCAN] Initial scan complete -- {} file(s) found.",
                 scan_count
             ));
-            if let Err(e) = remove_orphaned_books(&db) {
+            if let Err(e) = remove_orphaned_books(db) {
                 log(&format!("[ERROR] Orphan cleanup failed: {}", e));
             }
         }
+
+        if let Some(housekeeping) = &housekeeping
+            && last_housekeeping.elapsed() >= HOUSEKEEPING_INTERVAL
+        {
+            last_housekeeping = Instant::now();
+            purge_missing_books(db, housekeeping.trash_retention_secs);
+            compact_events(db);
+        }
+
+        if let Some(interval) = housekeeping.as_ref().and_then(|h| h.maintenance_interval)
+            && initial_scan_done
+            && pending.is_empty()
+            && last_maintenance.elapsed() >= interval
+            && maintain_database(db)
+        {
+            last_maintenance = Instant::now();
+        }
     }
 
     log("Shutting down...");
+    if !initial_scan_done {
+        set_scan_marker(db, "local", false);
+    }
     drop(watcher);
     log("Watcher closed.");
     Ok(())
@@ -8,7 +8,14 @@ use watcher_rs::covers::{
     render_pdf_cover_primary,
 };
 
+/// PDF jobs run in a `watcher-rs pdfium-worker` process; this test binary
+/// can't be one.
+fn use_built_pdf_worker() {
+    watcher_rs::covers::renderer::set_worker_program(env!("CARGO_BIN_EXE_watcher-rs"));
+}
+
 fn create_sample_pdf(path: &Path) {
+    use_built_pdf_worker();
     let pdf = b"%PDF-1.4
 1 0 obj
 << /Type /Catalog /Pages 2 0 R >>
@@ -38,6 +45,7 @@ startxref
 }
 
 fn create_corrupt_pdf(path: &Path) {
+    use_built_pdf_worker();
     fs::write(path, b"not a pdf").unwrap();
 }
 
@@ -136,6 +144,106 @@ fn create_epub_without_cover(path: &Path) {
     zip.finish().unwrap();
 }
 
+fn create_epub_with_entries(path: &Path, opf: &str, entries: &[(&str, &[u8])]) {
+    let file = fs::File::create(path).unwrap();
+    let mut zip = zip::ZipWriter::new(file);
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    zip.start_file("mimetype", options).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+
+    zip.start_file("META-INF/container.xml", options).unwrap();
+    zip.write_all(
+        br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#,
+    )
+    .unwrap();
+
+    zip.start_file("OEBPS/content.opf", options).unwrap();
+    zip.write_all(opf.as_bytes()).unwrap();
+
+    for (name, bytes) in entries {
+        zip.start_file(*name, options).unwrap();
+        zip.write_all(bytes).unwrap();
+    }
+
+    zip.finish().unwrap();
+}
+
+fn create_epub_with_svg_cover(path: &Path) {
+    create_epub_with_entries(
+        path,
+        r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>SVG Cover EPUB</dc:title>
+  </metadata>
+  <manifest>
+    <item id="cover" href="images/cover.svg" media-type="image/svg+xml" properties="cover-image"/>
+  </manifest>
+  <spine/>
+</package>"#,
+        &[(
+            "OEBPS/images/cover.svg",
+            br##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 600 900">
+  <rect width="600" height="900" fill="#c81414"/>
+  <text x="300" y="450" font-size="60" text-anchor="middle" fill="#ffffff">Title</text>
+</svg>"##,
+        )],
+    );
+}
+
+fn create_epub_with_xhtml_wrapped_svg_cover(path: &Path) {
+    let mut png_bytes = Vec::new();
+    image::RgbImage::from_fn(40, 60, |_, _| image::Rgb([20, 20, 200]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut png_bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+
+    create_epub_with_entries(
+        path,
+        r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>Wrapped Cover EPUB</dc:title>
+  </metadata>
+  <manifest>
+    <item id="cover-page" href="Text/cover.xhtml" media-type="application/xhtml+xml"/>
+    <item id="cover-image" href="Images/cover.png" media-type="image/png"/>
+  </manifest>
+  <spine>
+    <itemref idref="cover-page"/>
+  </spine>
+  <guide>
+    <reference type="cover" title="Cover" href="Text/cover.xhtml"/>
+  </guide>
+</package>"#,
+        &[
+            (
+                "OEBPS/Text/cover.xhtml",
+                br#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:xlink="http://www.w3.org/1999/xlink">
+<head><title>Cover</title></head>
+<body>
+  <svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="100%" height="100%"
+       viewBox="0 0 400 600" preserveAspectRatio="xMidYMid meet">
+    <image width="400" height="600" xlink:href="../Images/cover.png"/>
+  </svg>
+</body>
+</html>"#,
+            ),
+            ("OEBPS/Images/cover.png", &png_bytes),
+        ],
+    );
+}
+
 #[test]
 fn test_pdf_render_primary_generates_jpeg_with_dimensions() {
     let tmp = TempDir::new().unwrap();
@@ -262,3 +370,47 @@ fn test_generate_epub_cover_uses_fallback_when_no_embedded_cover() {
 
     assert!(cover.is_some());
 }
+
+#[test]
+fn test_epub_svg_cover_is_rasterized_to_cover_size() {
+    let tmp = TempDir::new().unwrap();
+    let epub_path = tmp.path().join("svg-cover.epub");
+    let covers_dir = tmp.path().join("covers");
+    create_epub_with_svg_cover(&epub_path);
+
+    let cover = extract_epub_cover(&epub_path, "book-8", &covers_dir);
+    assert!(cover.is_some());
+
+    let img = ImageReader::open(cover.unwrap())
+        .unwrap()
+        .decode()
+        .unwrap()
+        .into_rgb8();
+    assert_eq!(img.width(), 400);
+    assert_eq!(img.height(), 600);
+
+    let [r, g, b] = img.get_pixel(20, 20).0;
+    assert!(r > 150 && g < 80 && b < 80);
+}
+
+#[test]
+fn test_epub_xhtml_wrapped_svg_cover_resolves_linked_image() {
+    let tmp = TempDir::new().unwrap();
+    let epub_path = tmp.path().join("wrapped-cover.epub");
+    let covers_dir = tmp.path().join("covers");
+    create_epub_with_xhtml_wrapped_svg_cover(&epub_path);
+
+    let cover = extract_epub_cover(&epub_path, "book-9", &covers_dir);
+    assert!(cover.is_some());
+
+    let img = ImageReader::open(cover.unwrap())
+        .unwrap()
+        .decode()
+        .unwrap()
+        .into_rgb8();
+    assert_eq!(img.width(), 400);
+    assert_eq!(img.height(), 600);
+
+    let [r, g, b] = img.get_pixel(200, 300).0;
+    assert!(b > 150 && r < 80 && g < 80);
+}
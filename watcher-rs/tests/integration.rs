@@ -2,9 +2,10 @@ use std::fs;
 use std::io::Write;
 use std::path::Path;
 use tempfile::TempDir;
-use watcher_rs::db::Database;
+use watcher_rs::db::{COVER_SOURCE_GENERATED, Database, EventKind, NewBook, unix_now};
 use watcher_rs::handlers::{
-    handle_add_with_covers_dir, handle_change_with_covers_dir, handle_delete, remove_orphaned_books,
+    handle_add_with_covers_dir, handle_change_with_covers_dir, handle_delete,
+    remove_orphaned_books, set_user_cover,
 };
 
 fn create_test_db() -> (TempDir, Database) {
@@ -20,7 +21,14 @@ fn create_covers_dir() -> TempDir {
 }
 
 /// Create a minimal valid PDF with Title and Author in the Info dictionary.
+/// PDF jobs run in a `watcher-rs pdfium-worker` process; this test binary
+/// can't be one.
+fn use_built_pdf_worker() {
+    watcher_rs::covers::renderer::set_worker_program(env!("CARGO_BIN_EXE_watcher-rs"));
+}
+
 fn create_sample_pdf(path: &Path) {
+    use_built_pdf_worker();
     let pdf = b"%PDF-1.4
 1 0 obj
 << /Type /Catalog /Pages 2 0 R >>
@@ -107,6 +115,18 @@ fn create_sample_epub(path: &Path) {
     zip.finish().unwrap();
 }
 
+/// Write a solid-colour PNG to use as a user-supplied cover.
+fn create_cover_image(path: &Path, color: [u8; 3]) {
+    image::RgbImage::from_fn(40, 60, |_, _| image::Rgb(color))
+        .save_with_format(path, image::ImageFormat::Png)
+        .unwrap();
+}
+
+fn cover_center_pixel(path: &str) -> [u8; 3] {
+    let img = image::open(path).unwrap().into_rgb8();
+    img.get_pixel(img.width() / 2, img.height() / 2).0
+}
+
 #[test]
 fn test_add_pdf() {
     let (_db_dir, db) = create_test_db();
@@ -216,6 +236,67 @@ fn test_orphan_cleanup() {
     assert_eq!(db.all_books().unwrap().len(), 0);
 }
 
+#[test]
+fn test_deleted_book_is_restored_when_file_returns() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    let moved_path = lib_dir.path().join("moved.pdf");
+    create_sample_pdf(&pdf_path);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+
+    fs::rename(&pdf_path, &moved_path).unwrap();
+    handle_delete(&db, &pdf_path).unwrap();
+    assert!(db.find_by_id(&book.id).unwrap().is_none());
+
+    handle_add_with_covers_dir(&db, &moved_path, covers_dir.path()).unwrap();
+
+    let restored = db
+        .find_by_path(moved_path.to_str().unwrap())
+        .unwrap()
+        .expect("book should be restored");
+    assert_eq!(restored.id, book.id);
+    assert!(restored.missing_at.is_none());
+    let last = db.events_since(0, 100).unwrap().pop().unwrap();
+    assert_eq!(last.kind, "restored");
+    assert_eq!(last.fields, vec!["file_path"]);
+}
+
+#[test]
+fn test_missing_book_is_purged_after_retention() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    handle_delete(&db, &pdf_path).unwrap();
+
+    assert!(db.purge_missing(60).unwrap().is_empty());
+    // A negative window puts the book past its retention.
+    let purged = db.purge_missing(-1).unwrap();
+    assert_eq!(purged.len(), 1);
+    assert_eq!(purged[0].id, book.id);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let readded = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_ne!(readded.id, book.id);
+}
+
 #[test]
 fn test_library_version_incremented() {
     let (_db_dir, db) = create_test_db();
@@ -236,6 +317,276 @@ fn test_library_version_incremented() {
     assert!(v2 >= v1);
 }
 
+#[test]
+fn test_shared_database_serializes_events_across_threads() {
+    let (_db_dir, db) = create_test_db();
+    let db = std::sync::Arc::new(db);
+    let started = unix_now();
+
+    let writers: Vec<_> = ["local", "s3"]
+        .into_iter()
+        .map(|watcher| {
+            let db = std::sync::Arc::clone(&db);
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    db.record_event(EventKind::Added, &format!("{watcher}-{i}"), &[])
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let events = db.events_since(0, 1000).unwrap();
+    assert_eq!(events.len(), 100);
+    assert_eq!(db.latest_event_seq().unwrap(), 100);
+    // Every event bumped the version once.
+    assert!(db.get_library_version().unwrap().unwrap() >= started + 99);
+}
+
+#[test]
+fn test_change_feed_records_each_handler() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let mut content = fs::read(&pdf_path).unwrap();
+    content.extend_from_slice(b"\n% modified");
+    fs::write(&pdf_path, &content).unwrap();
+    handle_change_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    handle_delete(&db, &pdf_path).unwrap();
+
+    let events = db.events_since(0, 100).unwrap();
+    let kinds: Vec<&str> = events.iter().map(|e| e.kind.as_str()).collect();
+    assert_eq!(kinds, vec!["added", "updated", "deleted"]);
+    assert!(events.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+    assert!(events.iter().all(|e| e.book_id == events[0].book_id));
+    assert!(events[1].fields.contains(&"file_hash".to_string()));
+    assert!(!events[1].fields.contains(&"title".to_string()));
+
+    let later = db.events_since(events[0].seq, 100).unwrap();
+    assert_eq!(later.len(), 2);
+    assert_eq!(db.latest_event_seq().unwrap(), events[2].seq);
+}
+
+#[test]
+fn test_moved_file_keeps_book_and_records_move() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let old_path = lib_dir.path().join("book.pdf");
+    let new_path = lib_dir.path().join("renamed.pdf");
+    create_sample_pdf(&old_path);
+
+    handle_add_with_covers_dir(&db, &old_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(old_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+
+    fs::rename(&old_path, &new_path).unwrap();
+    handle_add_with_covers_dir(&db, &new_path, covers_dir.path()).unwrap();
+
+    let moved = db
+        .find_by_path(new_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(moved.id, book.id);
+    let events = db.events_since(0, 100).unwrap();
+    assert_eq!(events.last().unwrap().kind, "moved");
+    assert_eq!(events.last().unwrap().fields, vec!["file_path"]);
+}
+
+#[test]
+fn test_synced_copy_is_recorded_on_the_local_book() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+
+    db.set_s3_copy(
+        &book.id,
+        "fiction",
+        "books",
+        "lib/book.pdf",
+        Some("\"etag\""),
+        Some(&book.file_hash),
+    )
+    .unwrap();
+
+    // The S3 watcher sees the object as a synced copy, not a new book.
+    let s3_books = db.find_s3_books("fiction").unwrap();
+    assert_eq!(s3_books.len(), 1);
+    assert_eq!(s3_books[0].file_path, "lib/book.pdf");
+    assert!(s3_books[0].synced);
+    assert!(
+        db.find_s3_book("fiction", "lib/book.pdf")
+            .unwrap()
+            .is_some()
+    );
+    assert!(db.find_s3_books("other").unwrap().is_empty());
+
+    let file = db.find_book_file(&book.id).unwrap().unwrap();
+    assert_eq!(file.source, "local");
+    assert_eq!(file.s3_key.as_deref(), Some("lib/book.pdf"));
+}
+
+#[test]
+fn test_local_change_keeps_the_synced_etag() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    db.set_s3_copy(
+        &book.id,
+        "fiction",
+        "books",
+        "lib/book.pdf",
+        Some("\"etag\""),
+        Some(&book.file_hash),
+    )
+    .unwrap();
+
+    let mut content = fs::read(&pdf_path).unwrap();
+    content.extend_from_slice(b"\n% modified");
+    fs::write(&pdf_path, &content).unwrap();
+    handle_change_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+
+    // Sync compares the ETag to tell whether the bucket copy changed too.
+    let s3_books = db.find_s3_books("fiction").unwrap();
+    assert_eq!(s3_books[0].s3_etag.as_deref(), Some("\"etag\""));
+}
+
+#[test]
+fn test_downloaded_s3_book_becomes_local_and_keeps_its_key() {
+    let (_db_dir, db) = create_test_db();
+    db.insert_book(&NewBook {
+        id: "s3-book",
+        title: "Remote",
+        author: None,
+        description: None,
+        file_type: "pdf",
+        file_path: "lib/remote.pdf",
+        file_size: 10,
+        file_hash: "fingerprint",
+        cover_path: None,
+        cover_source: COVER_SOURCE_GENERATED,
+        page_count: None,
+        added_at: 1,
+        updated_at: 1,
+        source: "s3",
+        s3_source: Some("fiction"),
+        s3_bucket: Some("books"),
+        s3_etag: Some("\"etag\""),
+    })
+    .unwrap();
+
+    let changes = db
+        .adopt_local_copy("s3-book", "/data/library/remote.pdf", "sha256")
+        .unwrap();
+    assert_eq!(changes, 1);
+
+    let file = db.find_book_file("s3-book").unwrap().unwrap();
+    assert_eq!(file.source, "local");
+    assert_eq!(file.file_path, "/data/library/remote.pdf");
+    assert_eq!(file.file_hash, "sha256");
+    assert_eq!(file.s3_key.as_deref(), Some("lib/remote.pdf"));
+
+    let row = db.sync_rows().unwrap().pop().unwrap();
+    assert_eq!(row.synced_hash.as_deref(), Some("sha256"));
+    assert_eq!(row.s3_etag.as_deref(), Some("\"etag\""));
+    let s3_books = db.find_s3_books("fiction").unwrap();
+    assert!(s3_books[0].synced);
+
+    // Only S3 books are adopted.
+    assert_eq!(
+        db.adopt_local_copy("s3-book", "/elsewhere.pdf", "x")
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn test_books_without_a_source_are_claimed_by_prefix() {
+    let (_db_dir, db) = create_test_db();
+    for (id, key, bucket) in [
+        ("fiction-book", "fiction/dune.epub", "books"),
+        ("poetry-book", "poetry/odes.epub", "books"),
+        ("other-bucket", "fiction/elsewhere.epub", "archive"),
+    ] {
+        db.insert_book(&NewBook {
+            id,
+            title: id,
+            author: None,
+            description: None,
+            file_type: "epub",
+            file_path: key,
+            file_size: 10,
+            file_hash: id,
+            cover_path: None,
+            cover_source: COVER_SOURCE_GENERATED,
+            page_count: None,
+            added_at: 1,
+            updated_at: 1,
+            source: "s3",
+            s3_source: None,
+            s3_bucket: Some(bucket),
+            s3_etag: Some("\"etag\""),
+        })
+        .unwrap();
+    }
+
+    let claimed = db
+        .claim_s3_books("fiction", "books", &["fiction/".to_string()])
+        .unwrap();
+    assert_eq!(claimed, 1);
+    let fiction = db.find_s3_books("fiction").unwrap();
+    assert_eq!(fiction.len(), 1);
+    assert_eq!(fiction[0].id, "fiction-book");
+
+    // A source watching the whole bucket takes what is left in it, and
+    // books already claimed stay where they are.
+    assert_eq!(db.claim_s3_books("rest", "books", &[]).unwrap(), 1);
+    assert_eq!(db.find_s3_books("rest").unwrap()[0].id, "poetry-book");
+    assert_eq!(db.find_s3_books("fiction").unwrap().len(), 1);
+}
+
+#[test]
+fn test_event_compaction_marks_compacted_range() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+    handle_delete(&db, &pdf_path).unwrap();
+    let latest = db.latest_event_seq().unwrap();
+
+    // A negative age puts every existing event past the cutoff.
+    assert_eq!(db.compact_events(-1).unwrap(), 2);
+    assert!(db.events_since(0, 100).unwrap().is_empty());
+    assert_eq!(db.compacted_event_seq().unwrap(), latest);
+    assert_eq!(db.latest_event_seq().unwrap(), latest);
+}
+
 #[test]
 fn test_handle_change_updates_metadata() {
     let (_db_dir, db) = create_test_db();
@@ -279,3 +630,111 @@ fn test_handle_change_hash_unchanged() {
     assert_eq!(book_before.file_hash, book_after.file_hash);
     assert_eq!(book_before.title, book_after.title);
 }
+
+#[test]
+fn test_sidecar_cover_is_pinned_and_survives_change() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let pdf_path = lib_dir.path().join("book.pdf");
+    create_sample_pdf(&pdf_path);
+    create_cover_image(&lib_dir.path().join("book.png"), [0, 200, 0]);
+
+    handle_add_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert!(book.has_user_cover());
+    let [r, g, b] = cover_center_pixel(book.cover_path.as_deref().unwrap());
+    assert!(g > 150 && r < 80 && b < 80);
+
+    fs::remove_file(lib_dir.path().join("book.png")).unwrap();
+    let mut content = fs::read(&pdf_path).unwrap();
+    content.extend_from_slice(b"\n% modified");
+    fs::write(&pdf_path, &content).unwrap();
+
+    handle_change_with_covers_dir(&db, &pdf_path, covers_dir.path()).unwrap();
+
+    let book = db
+        .find_by_path(pdf_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert!(book.has_user_cover());
+    let [r, g, b] = cover_center_pixel(book.cover_path.as_deref().unwrap());
+    assert!(g > 150 && r < 80 && b < 80);
+}
+
+#[test]
+fn test_directory_sidecar_applied_to_existing_book() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let epub_path = lib_dir.path().join("book.epub");
+    create_sample_epub(&epub_path);
+
+    handle_add_with_covers_dir(&db, &epub_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(epub_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert!(!book.has_user_cover());
+
+    create_cover_image(&lib_dir.path().join("cover.jpg"), [200, 0, 0]);
+    handle_change_with_covers_dir(&db, &epub_path, covers_dir.path()).unwrap();
+
+    let book = db
+        .find_by_path(epub_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert!(book.has_user_cover());
+    let [r, g, b] = cover_center_pixel(book.cover_path.as_deref().unwrap());
+    assert!(r > 150 && g < 80 && b < 80);
+}
+
+#[test]
+fn test_set_user_cover_survives_change() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let lib_dir = TempDir::new().unwrap();
+    let epub_path = lib_dir.path().join("book.epub");
+    let image_dir = TempDir::new().unwrap();
+    let image_path = image_dir.path().join("pinned.png");
+    create_sample_epub(&epub_path);
+    create_cover_image(&image_path, [0, 0, 200]);
+
+    handle_add_with_covers_dir(&db, &epub_path, covers_dir.path()).unwrap();
+    let book = db
+        .find_by_path(epub_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+
+    set_user_cover(&db, &book.id, &image_path, covers_dir.path()).unwrap();
+
+    let mut content = fs::read(&epub_path).unwrap();
+    content.extend_from_slice(b"trailing bytes");
+    fs::write(&epub_path, &content).unwrap();
+    handle_change_with_covers_dir(&db, &epub_path, covers_dir.path()).unwrap();
+
+    let after = db
+        .find_by_path(epub_path.to_str().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_ne!(after.file_hash, book.file_hash);
+    assert!(after.has_user_cover());
+    let [r, g, b] = cover_center_pixel(after.cover_path.as_deref().unwrap());
+    assert!(b > 150 && r < 80 && g < 80);
+}
+
+#[test]
+fn test_set_user_cover_unknown_book_fails() {
+    let (_db_dir, db) = create_test_db();
+    let covers_dir = create_covers_dir();
+    let image_dir = TempDir::new().unwrap();
+    let image_path = image_dir.path().join("pinned.png");
+    create_cover_image(&image_path, [0, 0, 200]);
+
+    let result = set_user_cover(&db, "missing-id", &image_path, covers_dir.path());
+    assert!(result.is_err());
+}